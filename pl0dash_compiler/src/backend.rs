@@ -0,0 +1,112 @@
+// Pieces shared by the code generators: the walk over the declarations of a
+// block, which enters their names in the table as the semantic checker did,
+// and the helpers every generator needs while translating the statements.
+
+use crate::tokenizer::*;
+use crate::symbol::*;
+use crate::parser::*;
+use crate::table::*;
+
+/// Size of the stack the program may use before reporting a stack overflow,
+/// just short of the usual limit of 8 MiB. The runtimes linked with libc
+/// take 7/8 of the stack limit of the process instead, which is the same for
+/// that limit, and fall back to this when there is none; RISC-V, without
+/// libc, always uses it.
+pub const STACK_LIMIT: usize = 7 << 20;
+
+/// A function or procedure declaration, once `enter_func` opened its block.
+pub struct FuncDecl<'a> {
+    /// entry of the function or procedure
+    pub func: usize,
+    /// entries of the parameters, with the identifiers declaring them
+    pub pars: Vec<(usize, &'a SyntaxNode)>,
+    pub body: &'a SyntaxNode,
+}
+
+/// Enters the constants of a `const` declaration.
+pub fn enter_consts(table: &mut Table, node: &SyntaxNode) {
+    let children = node.get_ref_children();
+    for i in (1..children.len()).step_by(4) {
+        if let Some(Token::Number(value)) = children[i + 2].get_ref_token() {
+            table.enter_const(children[i].get_ref_identifier().unwrap(), *value);
+        }
+    }
+}
+
+/// Enters the variables and arrays of a `var` declaration, giving their
+/// entries with the identifiers declaring them.
+pub fn enter_vars<'a>(table: &mut Table, node: &'a SyntaxNode) -> Vec<(usize, &'a SyntaxNode)> {
+    let children = node.get_ref_children();
+    let mut vars = vec![];
+    for (i, child) in children.iter().enumerate() {
+        if let Some(name) = child.get_ref_identifier() {
+            match children[i + 1].get_ref_token() {
+                Some(Token::Symbol(Symbol::Lbracket)) => {
+                    if let Some(Token::Number(size)) = children[i + 2].get_ref_token() {
+                        vars.push((table.enter_array(name, *size), child));
+                    }
+                },
+                _ => {
+                    vars.push((table.enter_var(name), child));
+                },
+            }
+        }
+    }
+    vars
+}
+
+/// Enters the function or procedure of a declaration with address `addr`,
+/// then opens its block and enters the parameters in it. The caller
+/// generates the body and closes the block.
+pub fn enter_func<'a>(table: &mut Table, node: &'a SyntaxNode, addr: i32) -> FuncDecl<'a> {
+    let children = node.get_ref_children();
+    let name = children[1].get_ref_identifier().unwrap();
+    let func = match node.get_ref_syntax() {
+        Syntax::ProcDecl => table.enter_proc(name, addr),
+        _ => table.enter_func(name, addr),
+    };
+    table.block_begin(FIRST_ADDR);
+    let mut pars = vec![];
+    for par in &children[3..children.len() - 3] {
+        if let Some(name) = par.get_ref_identifier() {
+            pars.push((table.enter_par(name), par));
+        }
+    }
+    table.end_par();
+    FuncDecl { func, pars, body: &children[children.len() - 2] }
+}
+
+/// Entry of `name`, which the semantic checker made sure is declared.
+pub fn lookup(table: &Table, name: &str) -> Entry {
+    let i = table.search(name).expect("unchecked syntax tree");
+    table.get(i).clone()
+}
+
+/// Index of `s` among `strings`, which holds each string once.
+pub fn intern(strings: &mut Vec<String>, s: &str) -> usize {
+    match strings.iter().position(|t| t == s) {
+        Some(i) => i,
+        None => {
+            strings.push(s.to_string());
+            strings.len() - 1
+        }
+    }
+}
+
+/// Name of the function or procedure `e` in the generated code, after
+/// `sigil`, keeping its own name where it is plain ASCII.
+pub fn func_name(sigil: &str, e: &Entry) -> String {
+    if e.name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_') {
+        format!("{}f{}_{}", sigil, e.addr, e.name)
+    } else {
+        format!("{}f{}", sigil, e.addr)
+    }
+}
+
+/// Word for the kind of `e` in comments of the generated code.
+pub fn kind_name(e: &Entry) -> &'static str {
+    match e.kind {
+        Kind::Proc => "procedure",
+        _ => "function",
+    }
+}
@@ -0,0 +1,728 @@
+// C backend: translates a checked syntax tree into a C program.
+//
+// The variables of every block live in a struct, its environment, which is a
+// local of the C function the block becomes (`main` for the main block).
+// Functions take a pointer to the environment of the block they are declared
+// in, so that the names of enclosing blocks are reached through the `up`
+// links of the environments. Arithmetic goes through helpers that wrap
+// around as the stack machine does, and operands are evaluated from left to
+// right wherever C would leave the order open and it could be told apart.
+
+use std::fmt::Write;
+use crate::tokenizer::*;
+use crate::keyword::*;
+use crate::symbol::*;
+use crate::parser::*;
+use crate::table::*;
+use crate::semantic::self_tail_call;
+use crate::backend::*;
+
+/// Helpers of the generated code, writing the same runtime errors as the
+/// stack machine.
+const RUNTIME: &str = r#"#include <errno.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <sys/resource.h>
+
+static uintptr_t pl0_stack_limit;
+
+/* recursion without end is the program's business; the stack check reports
+   it at runtime */
+#if defined(__GNUC__) && !defined(__clang__) && __GNUC__ >= 12
+#pragma GCC diagnostic ignored "-Winfinite-recursion"
+#endif
+
+static void pl0_fail(const char *message)
+{
+    fflush(stdout);
+    fprintf(stderr, "runtime error: %s\n", message);
+    exit(1);
+}
+
+/* the stack grows downwards from the environment of the main block, and may
+   take 7/8 of the limit of the process, or `size` bytes when it has none */
+static void pl0_init_stack(const void *env, uintptr_t size)
+{
+    struct rlimit r;
+    if (getrlimit(RLIMIT_STACK, &r) == 0 && r.rlim_cur != RLIM_INFINITY && r.rlim_cur < (uintptr_t)env)
+        size = r.rlim_cur - r.rlim_cur / 8;
+    pl0_stack_limit = (uintptr_t)env - size;
+}
+
+static inline void pl0_check_stack(const void *env)
+{
+    if ((uintptr_t)env < pl0_stack_limit)
+        pl0_fail("stack overflow");
+}
+
+static inline int32_t pl0_add(int32_t a, int32_t b) { return (int32_t)((uint32_t)a + (uint32_t)b); }
+static inline int32_t pl0_sub(int32_t a, int32_t b) { return (int32_t)((uint32_t)a - (uint32_t)b); }
+static inline int32_t pl0_mul(int32_t a, int32_t b) { return (int32_t)((uint32_t)a * (uint32_t)b); }
+static inline int32_t pl0_neg(int32_t a) { return (int32_t)(0u - (uint32_t)a); }
+static inline int32_t pl0_abs(int32_t a) { return a < 0 ? pl0_neg(a) : a; }
+static inline int32_t pl0_min(int32_t a, int32_t b) { return a < b ? a : b; }
+static inline int32_t pl0_max(int32_t a, int32_t b) { return a > b ? a : b; }
+
+static inline int32_t pl0_div(int32_t a, int32_t b)
+{
+    if (b == 0)
+        pl0_fail("division by zero");
+    return b == -1 ? pl0_neg(a) : a / b;
+}
+
+static inline int32_t pl0_mod(int32_t a, int32_t b)
+{
+    if (b == 0)
+        pl0_fail("division by zero");
+    return b == -1 ? 0 : a % b;
+}
+
+static inline int32_t pl0_index(int32_t index, int32_t size)
+{
+    if (index < 0 || index >= size) {
+        fflush(stdout);
+        fprintf(stderr, "runtime error: index %d is out of bounds for an array of size %d\n", (int)index, (int)size);
+        exit(1);
+    }
+    return index;
+}
+
+static inline void pl0_write(int32_t v)
+{
+    printf("%d ", (int)v);
+}
+
+static inline void pl0_writeln(void)
+{
+    putchar('\n');
+}
+
+/* reads the next whitespace separated word of stdin as an integer */
+static inline int32_t pl0_read(void)
+{
+    char word[64], *end;
+    long v;
+    fflush(stdout);
+    if (scanf("%63s", word) != 1)
+        pl0_fail("`read` reached the end of the input");
+    errno = 0;
+    v = strtol(word, &end, 10);
+    if (end == word || *end != '\0' || errno != 0 || v < INT32_MIN || v > INT32_MAX) {
+        fflush(stdout);
+        fprintf(stderr, "runtime error: `read` expected an integer, found `%s`\n", word);
+        exit(1);
+    }
+    return (int32_t)v;
+}
+"#;
+
+/// A translated expression, with what its evaluation may do besides
+/// reading variables.
+struct Expr {
+    text: String,
+    /// calls a function, which may write output or change variables
+    calls: bool,
+    /// may stop the program with a runtime error
+    fails: bool,
+}
+
+impl Expr {
+    fn pure(text: String) -> Self {
+        Expr {
+            text,
+            calls: false,
+            fails: false,
+        }
+    }
+}
+
+/// Translates a checked syntax tree into C. Functions are entered in the
+/// table with the number of their environment as address, the main block
+/// having 0.
+pub struct CGenerator {
+    table: Table,
+    /// forward declarations of the environments and functions
+    decls: String,
+    /// definitions of the environments
+    envs: String,
+    /// finished functions
+    funcs: String,
+    /// body of the function being generated
+    text: String,
+    indent: usize,
+    /// temporaries of the function being generated
+    temps: usize,
+    envs_count: usize,
+    /// whether the block being generated calls its function in tail position
+    tail_calls: bool,
+}
+
+impl CGenerator {
+    pub fn new() -> Self {
+        CGenerator {
+            table: Table::new(),
+            decls: String::new(),
+            envs: String::new(),
+            funcs: String::new(),
+            text: String::new(),
+            indent: 1,
+            temps: 0,
+            envs_count: 0,
+            tail_calls: false,
+        }
+    }
+
+    pub fn generate(mut self, tree: &SyntaxTree) -> String {
+        let program = tree.get_ref_root();
+        self.table.block_begin(FIRST_ADDR);
+        self.decls += "\nstruct env0;\n";
+        self.gen_block(&program.get_ref_children()[0], None);
+        format!("{}{}{}{}", RUNTIME, self.decls, self.envs, self.funcs)
+    }
+
+    fn gen_block(&mut self, node: &SyntaxNode, func: Option<usize>) {
+        for child in node.get_ref_children() {
+            match child.get_ref_syntax() {
+                Syntax::ConstDecl => enter_consts(&mut self.table, child),
+                Syntax::VarDecl => {
+                    enter_vars(&mut self.table, child);
+                },
+                Syntax::FuncDecl | Syntax::ProcDecl => self.gen_func_decl(child),
+                Syntax::Statement => {
+                    self.temps = 0;
+                    self.gen_statement(child);
+                },
+                _ => unreachable!(),
+            }
+        }
+        let body = std::mem::take(&mut self.text);
+        let tail_calls = std::mem::take(&mut self.tail_calls);
+        // the environment is known once the statement had its temporaries
+        let env = self.env_id();
+        let up = match func {
+            Some(_) => format!("struct env{} *", self.env_of_level(self.table.level() - 1)),
+            None => "void *".to_string(),
+        };
+        writeln!(self.envs, "\nstruct env{} {{\n    {}up;", env, up).unwrap();
+        for e in self.table.block_entries() {
+            match e.kind {
+                Kind::Var | Kind::Par => writeln!(self.envs, "    int32_t {};", var_name(e)).unwrap(),
+                Kind::Array => writeln!(self.envs, "    int32_t {}[{}];", var_name(e), e.size).unwrap(),
+                _ => (),
+            }
+        }
+        self.envs += "};\n";
+
+        match func {
+            Some(f) => {
+                let e = self.table.get(f).clone();
+                writeln!(self.funcs, "\n/* {} {} */\n{}\n{{", kind_name(&e), e.name, self.signature(&e)).unwrap();
+                write!(self.funcs, "    struct env{} e = {{ .up = up", env).unwrap();
+                for (i, par) in self.table.block_entries()[..e.pars].iter().enumerate() {
+                    write!(self.funcs, ", .{} = a{}", var_name(par), i).unwrap();
+                }
+                self.funcs += " };\n";
+            },
+            None => {
+                writeln!(self.funcs, "\nint main(void)\n{{\n    struct env{} e = {{ .up = NULL }};", env).unwrap();
+                writeln!(self.funcs, "    pl0_init_stack(&e, {});", STACK_LIMIT).unwrap();
+            },
+        }
+        if self.temps > 0 {
+            let temps: Vec<String> = (1..=self.temps).map(|i| format!("tmp{}", i)).collect();
+            writeln!(self.funcs, "    int32_t {};", temps.join(", ")).unwrap();
+        }
+        self.funcs += "    pl0_check_stack(&e);\n";
+        if tail_calls {
+            self.funcs += "body:\n";
+        }
+        self.funcs += &body;
+        if func.is_none() {
+            self.funcs += "    return 0;\n";
+        }
+        self.funcs += "}\n";
+        self.table.block_end();
+    }
+
+    fn gen_func_decl(&mut self, node: &SyntaxNode) {
+        self.envs_count += 1;
+        let id = self.envs_count as i32;
+        let decl = enter_func(&mut self.table, node, id);
+        // nested functions may call it before its definition
+        let signature = self.signature(&self.table.get(decl.func).clone());
+        writeln!(self.decls, "struct env{};\n{};", id, signature).unwrap();
+        self.gen_block(decl.body, Some(decl.func));
+    }
+
+    /// Prototype of the function or procedure `e`, whose parameters are the
+    /// innermost block's.
+    fn signature(&self, e: &Entry) -> String {
+        let result = if e.kind == Kind::Proc { "void" } else { "int32_t" };
+        let mut pars = format!("struct env{} *up", self.env_of_level(e.level));
+        for i in 0..e.pars {
+            write!(pars, ", int32_t a{}", i).unwrap();
+        }
+        format!("static {} {}({})", result, func_name("", e), pars)
+    }
+
+    fn gen_statement(&mut self, node: &SyntaxNode) {
+        let children = node.get_ref_children();
+        let first = match children.first() {
+            Some(first) => first,
+            None => return, // empty statement
+        };
+        match first.get_ref_token().unwrap() {
+            Token::Identifier(name) if children.len() > 1 && children[1].get_ref_token() == Some(&Token::Symbol(Symbol::Lparen)) => {
+                let e = lookup(&self.table, name);
+                let call = self.gen_call(&e, &children[1..]);
+                self.line(&format!("{};", call.text));
+            },
+            Token::Keyword(Keyword::Call) => {
+                let e = lookup(&self.table, children[1].get_ref_identifier().unwrap());
+                let call = self.gen_call(&e, &children[2..]);
+                self.line(&format!("{};", call.text));
+            },
+            Token::Identifier(name) => {
+                let e = lookup(&self.table, name);
+                if e.kind == Kind::Array {
+                    // ident [ index ] := value
+                    let index = self.gen_index(&e, &children[2]);
+                    let value = self.gen_expression(&children[5]);
+                    let element = if value.calls || value.fails {
+                        // the index is checked before the value is evaluated
+                        let t = self.new_temp();
+                        self.line(&format!("{} = {};", t, index.text));
+                        format!("{}[{}]", self.var(&e), t)
+                    } else {
+                        format!("{}[{}]", self.var(&e), index.text)
+                    };
+                    self.line(&format!("{} = {};", element, value.text));
+                } else {
+                    let value = self.gen_expression(&children[2]);
+                    let var = self.var(&e);
+                    self.line(&format!("{} = {};", var, value.text));
+                }
+            },
+            Token::Keyword(Keyword::Begin) => {
+                for child in children {
+                    if let Syntax::Statement = child.get_ref_syntax() {
+                        self.gen_statement(child);
+                    }
+                }
+            },
+            Token::Keyword(Keyword::If) => {
+                let cond = self.gen_condition(&children[1]);
+                self.line(&format!("if {} {{", cond.text));
+                self.gen_nested(&children[3]);
+                if children.len() > 4 {
+                    self.line("} else {");
+                    self.gen_nested(&children[5]);
+                }
+                self.line("}");
+            },
+            Token::Keyword(Keyword::While) => {
+                let cond = self.gen_condition(&children[1]);
+                self.line(&format!("while {} {{", cond.text));
+                self.gen_nested(&children[3]);
+                self.line("}");
+            },
+            Token::Keyword(Keyword::For) => {
+                let var = lookup(&self.table, children[1].get_ref_identifier().unwrap());
+                let var = self.var(&var);
+                let temp = self.table.enter_temp();
+                let limit = self.var(&self.table.get(temp).clone());
+                let (cmp, last, step) = match children[4].get_ref_token() {
+                    Some(Token::Keyword(Keyword::To)) => ("<=", "<", "pl0_add"),
+                    _ => (">=", ">", "pl0_sub"),
+                };
+                let from = self.gen_expression(&children[3]);
+                let to = self.gen_expression(&children[5]);
+                self.line(&format!("{} = {};", var, from.text));
+                self.line(&format!("{} = {};", limit, to.text));
+                // `continue` goes to the test of the do-while, which steps
+                self.line(&format!("if ({v} {} {l}) do {{", cmp, v = var, l = limit));
+                self.gen_nested(&children[7]);
+                self.line(&format!("}} while ({v} {} {l} && ({v} = {}({v}, 1), 1));", last, step, v = var, l = limit));
+            },
+            Token::Keyword(Keyword::Repeat) => {
+                self.line("do {");
+                self.indent += 1;
+                for child in children {
+                    if let Syntax::Statement = child.get_ref_syntax() {
+                        self.gen_statement(child);
+                    }
+                }
+                self.indent -= 1;
+                let cond = self.gen_condition(children.last().unwrap());
+                self.line(&format!("}} while (!{});", cond.text));
+            },
+            Token::Keyword(Keyword::Break) => {
+                self.line("break;");
+            },
+            Token::Keyword(Keyword::Continue) => {
+                self.line("continue;");
+            },
+            Token::Keyword(Keyword::Ret) => {
+                if let Some(args) = self_tail_call(&self.table, &children[1]) {
+                    self.gen_tail_call(&args);
+                    return;
+                }
+                let value = self.gen_expression(&children[1]);
+                self.line(&format!("return {};", value.text));
+            },
+            Token::Keyword(Keyword::Write) => {
+                for arg in &children[1..] {
+                    if let Some(Token::String(s)) = arg.get_ref_token() {
+                        self.line(&format!("fputs(\"{}\", stdout);", c_string(s)));
+                    } else if let Syntax::Expression = arg.get_ref_syntax() {
+                        let value = self.gen_expression(arg);
+                        self.line(&format!("pl0_write({});", value.text));
+                    }
+                }
+            },
+            Token::Keyword(Keyword::WriteLn) => {
+                self.line("pl0_writeln();");
+            },
+            Token::Keyword(Keyword::Read) => {
+                let e = lookup(&self.table, children[1].get_ref_identifier().unwrap());
+                if e.kind == Kind::Array {
+                    // read ident [ index ]; the index is checked before reading
+                    let index = self.gen_index(&e, &children[3]);
+                    let t = self.new_temp();
+                    self.line(&format!("{} = {};", t, index.text));
+                    let var = self.var(&e);
+                    self.line(&format!("{}[{}] = pl0_read();", var, t));
+                } else {
+                    let var = self.var(&e);
+                    self.line(&format!("{} = pl0_read();", var));
+                }
+            },
+            t => unreachable!("statement starting with {:?}", t),
+        }
+    }
+
+    /// Generates the body of a compound C statement.
+    fn gen_nested(&mut self, node: &SyntaxNode) {
+        self.indent += 1;
+        self.gen_statement(node);
+        self.indent -= 1;
+    }
+
+    /// Translates a condition into a C expression, always parenthesized.
+    fn gen_condition(&mut self, node: &SyntaxNode) -> Expr {
+        let children = node.get_ref_children();
+        match children[0].get_ref_token() {
+            Some(Token::Keyword(Keyword::Not)) => {
+                let c = self.gen_condition(&children[1]);
+                return Expr { text: format!("(!{})", c.text), ..c };
+            },
+            Some(Token::Keyword(Keyword::Odd)) => {
+                let e = self.gen_expression(&children[1]);
+                return Expr { text: format!("(({} & 1) != 0)", e.text), ..e };
+            },
+            Some(Token::Symbol(Symbol::Lparen)) => {
+                return self.gen_condition(&children[1]);
+            },
+            _ => (),
+        }
+        if let Syntax::Condition = children[0].get_ref_syntax() {
+            // `&&` and `||` evaluate their operands in order, as far as needed
+            let op = match children[1].get_ref_token() {
+                Some(Token::Keyword(Keyword::Or)) => " || ",
+                _ => " && ",
+            };
+            let operands: Vec<Expr> = children.iter().step_by(2).map(|c| self.gen_condition(c)).collect();
+            let texts: Vec<&str> = operands.iter().map(|c| c.text.as_str()).collect();
+            return Expr {
+                text: format!("({})", texts.join(op)),
+                calls: operands.iter().any(|c| c.calls),
+                fails: operands.iter().any(|c| c.fails),
+            };
+        }
+        let op = match children[1].get_ref_token() {
+            Some(Token::Symbol(Symbol::Equal)) => "==",
+            Some(Token::Symbol(Symbol::Lss))   => "<",
+            Some(Token::Symbol(Symbol::Gtr))   => ">",
+            Some(Token::Symbol(Symbol::NotEq)) => "!=",
+            Some(Token::Symbol(Symbol::LssEq)) => "<=",
+            Some(Token::Symbol(Symbol::GtrEq)) => ">=",
+            t => unreachable!("relational operator {:?}", t),
+        };
+        let l = self.gen_expression(&children[0]);
+        let r = self.gen_expression(&children[2]);
+        self.binary(l, r, |l, r| format!("({} {} {})", l, op, r))
+    }
+
+    fn gen_expression(&mut self, node: &SyntaxNode) -> Expr {
+        let children = node.get_ref_children();
+        let mut i = 0;
+        let mut negate = false;
+        if let Some(Token::Symbol(sym)) = children[0].get_ref_token() {
+            negate = *sym == Symbol::Minus;
+            i = 1;
+        }
+        let mut e = self.gen_term(&children[i]);
+        if negate {
+            e.text = format!("pl0_neg({})", e.text);
+        }
+        for pair in children[i + 1..].chunks(2) {
+            let f = match pair[0].get_ref_token() {
+                Some(Token::Symbol(Symbol::Plus))  => "pl0_add",
+                Some(Token::Symbol(Symbol::Minus)) => "pl0_sub",
+                t => unreachable!("additive operator {:?}", t),
+            };
+            let r = self.gen_term(&pair[1]);
+            e = self.binary(e, r, |l, r| format!("{}({}, {})", f, l, r));
+        }
+        e
+    }
+
+    fn gen_term(&mut self, node: &SyntaxNode) -> Expr {
+        let children = node.get_ref_children();
+        let mut e = self.gen_factor(&children[0]);
+        for pair in children[1..].chunks(2) {
+            let f = match pair[0].get_ref_token() {
+                Some(Token::Symbol(Symbol::Mult)) => "pl0_mul",
+                Some(Token::Symbol(Symbol::Div))  => "pl0_div",
+                Some(Token::Symbol(Symbol::Mod)) | Some(Token::Keyword(Keyword::Mod)) => "pl0_mod",
+                t => unreachable!("multiplicative operator {:?}", t),
+            };
+            let r = self.gen_factor(&pair[1]);
+            e = self.binary(e, r, |l, r| format!("{}({}, {})", f, l, r));
+            e.fails |= f != "pl0_mul";
+        }
+        e
+    }
+
+    fn gen_factor(&mut self, node: &SyntaxNode) -> Expr {
+        let children = node.get_ref_children();
+        match children[0].get_ref_token() {
+            Some(Token::Identifier(name)) => {
+                if let Some(f) = self.table.search_intrinsic(name) {
+                    let x = self.gen_expression(&children[2]);
+                    return match f {
+                        Intrinsic::Abs => Expr { text: format!("pl0_abs({})", x.text), ..x },
+                        Intrinsic::Min | Intrinsic::Max => {
+                            let y = self.gen_expression(&children[4]);
+                            let f = if f == Intrinsic::Min { "pl0_min" } else { "pl0_max" };
+                            self.binary(x, y, |x, y| format!("{}({}, {})", f, x, y))
+                        },
+                    };
+                }
+                let e = lookup(&self.table, name);
+                match e.kind {
+                    Kind::Var | Kind::Par => Expr::pure(self.var(&e)),
+                    Kind::Array => {
+                        let index = self.gen_index(&e, &children[2]);
+                        Expr { text: format!("{}[{}]", self.var(&e), index.text), ..index }
+                    },
+                    Kind::Const => Expr::pure(e.value.to_string()),
+                    Kind::Func => self.gen_call(&e, &children[1..]),
+                    Kind::Proc => unreachable!("unchecked syntax tree"),
+                }
+            },
+            Some(Token::Number(value)) => Expr::pure(value.to_string()),
+            _ => {
+                // ( expression )
+                self.gen_expression(&children[1])
+            },
+        }
+    }
+
+    /// Combines two operands with `f`, evaluating the left one first if
+    /// the order could make a difference.
+    fn binary(&mut self, l: Expr, r: Expr, f: impl Fn(&str, &str) -> String) -> Expr {
+        let text = if l.calls || r.calls || (l.fails && r.fails) {
+            let t = self.new_temp();
+            format!("({} = {}, {})", t, l.text, f(&t, &r.text))
+        } else {
+            f(&l.text, &r.text)
+        };
+        Expr {
+            text,
+            calls: l.calls || r.calls,
+            fails: l.fails || r.fails,
+        }
+    }
+
+    /// Translates the index of an element of the array `e`, checked to be
+    /// within bounds.
+    fn gen_index(&mut self, e: &Entry, index: &SyntaxNode) -> Expr {
+        let index = self.gen_expression(index);
+        Expr {
+            text: format!("pl0_index({}, {})", index.text, e.size),
+            fails: true,
+            ..index
+        }
+    }
+
+    /// Translates a call of `e` with the arguments among `args`, which are
+    /// evaluated from left to right.
+    fn gen_call(&mut self, e: &Entry, args: &[SyntaxNode]) -> Expr {
+        let args: Vec<Expr> = args
+            .iter()
+            .filter(|arg| matches!(arg.get_ref_syntax(), Syntax::Expression))
+            .map(|arg| self.gen_expression(arg))
+            .collect();
+        let ordered = args.iter().any(|a| a.calls) || args.iter().filter(|a| a.fails).count() > 1;
+        let mut saved = vec![];
+        let mut texts = vec![self.link(e.level)];
+        for (i, arg) in args.iter().enumerate() {
+            if ordered && i + 1 < args.len() {
+                let t = self.new_temp();
+                saved.push(format!("{} = {}", t, arg.text));
+                texts.push(t);
+            } else {
+                texts.push(arg.text.clone());
+            }
+        }
+        let call = format!("{}({})", func_name("", e), texts.join(", "));
+        Expr {
+            text: if saved.is_empty() { call } else { format!("({}, {})", saved.join(", "), call) },
+            calls: true,
+            fails: args.iter().any(|a| a.fails),
+        }
+    }
+
+    /// Calls the current function again in its own environment: the
+    /// arguments replace the parameters once all of them are known, the
+    /// other fields are zeroed again, and the body starts over.
+    fn gen_tail_call(&mut self, args: &[&SyntaxNode]) {
+        let mut values = vec![];
+        for (i, arg) in args.iter().enumerate() {
+            let value = self.gen_expression(arg);
+            if i + 1 < args.len() {
+                let t = self.new_temp();
+                self.line(&format!("{} = {};", t, value.text));
+                values.push(t);
+            } else {
+                values.push(value.text);
+            }
+        }
+        let mut fields = "up = e.up".to_string();
+        for (par, value) in self.table.block_entries()[..args.len()].iter().zip(&values) {
+            write!(fields, ", .{} = {}", var_name(par), value).unwrap();
+        }
+        self.line(&format!("e = (struct env{}){{ .{} }};", self.env_id(), fields));
+        self.line("goto body;");
+        self.tail_calls = true;
+    }
+
+    /// The environment of the block at `level`, as a pointer, which a
+    /// function declared in that block is called with.
+    fn link(&self, level: usize) -> String {
+        match self.table.level() - level {
+            0 => "&e".to_string(),
+            hops => format!("e.up{}", "->up".repeat(hops - 1)),
+        }
+    }
+
+    /// The variable, parameter or array `e` as a C lvalue.
+    fn var(&self, e: &Entry) -> String {
+        match self.table.level() - e.level {
+            0 => format!("e.{}", var_name(e)),
+            hops => format!("e.up{}->{}", "->up".repeat(hops - 1), var_name(e)),
+        }
+    }
+
+    /// Number of the environment of the innermost block.
+    fn env_id(&self) -> i32 {
+        self.env_of_level(self.table.level())
+    }
+
+    /// Number of the environment of the open block at `level`.
+    fn env_of_level(&self, level: usize) -> i32 {
+        self.table.block_func(level).map_or(0, |f| self.table.get(f).addr)
+    }
+
+    fn new_temp(&mut self) -> String {
+        self.temps += 1;
+        format!("tmp{}", self.temps)
+    }
+
+    fn line(&mut self, line: &str) {
+        writeln!(self.text, "{}{}", "    ".repeat(self.indent), line).unwrap();
+    }
+}
+
+impl Default for CGenerator {
+    fn default() -> Self {
+        CGenerator::new()
+    }
+}
+
+fn is_c_identifier(name: &str) -> bool {
+    name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_')
+}
+
+/// Field of the environment holding a variable, parameter or array, named
+/// after it if C allows.
+fn var_name(e: &Entry) -> String {
+    if e.name.is_empty() {
+        format!("tmp{}", e.addr)
+    } else if is_c_identifier(&e.name) {
+        format!("v_{}", e.name)
+    } else {
+        format!("v{}", e.addr)
+    }
+}
+
+/// Escapes `s` for a C string literal.
+fn c_string(s: &str) -> String {
+    let mut escaped = String::new();
+    for b in s.bytes() {
+        match b {
+            b'"' => escaped += "\\\"",
+            b'\\' => escaped += "\\\\",
+            // keeps `??` from starting a trigraph
+            b'?' => escaped += "\\?",
+            b'\n' => escaped += "\\n",
+            b'\t' => escaped += "\\t",
+            0x20..=0x7e => escaped.push(b as char),
+            _ => write!(escaped, "\\{:03o}", b).unwrap(),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compile::*;
+
+    fn generate(src: &'static str) -> String {
+        let tree = analyze(src.as_bytes(), TokenizerOptions::default()).unwrap();
+        CGenerator::new().generate(&tree)
+    }
+
+    #[test]
+    fn test_environments() {
+        let c = generate("\
+            var x;
+            function f(a)
+                function g(b) return a + b + x;
+                return g(1);
+            begin x := 1; write f(2) end.");
+        assert!(c.contains("struct env1 {\n    struct env0 *up;\n    int32_t v_a;\n};\n"));
+        assert!(c.contains("struct env2 {\n    struct env1 *up;\n    int32_t v_b;\n};\n"));
+        assert!(c.contains("return pl0_add(pl0_add(e.up->v_a, e.v_b), e.up->up->v_x);"));
+        assert!(c.contains("return f2_g(&e, 1);"));
+        assert!(c.contains("pl0_write(f1_f(&e, 2));"));
+    }
+
+    #[test]
+    fn test_evaluation_order() {
+        let c = generate("\
+            var x;
+            function f() begin x := x + 1; return x end;
+            write x + f(), f() - x.");
+        assert!(c.contains("pl0_write((tmp1 = e.v_x, pl0_add(tmp1, f1_f(&e))));"));
+        assert!(c.contains("pl0_write((tmp2 = f1_f(&e), pl0_sub(tmp2, e.v_x)));"));
+    }
+
+    #[test]
+    fn test_c_string() {
+        assert_eq!(c_string("a \"b\"\\\n??=\r"), "a \\\"b\\\"\\\\\\n\\?\\?=\\015");
+    }
+}
@@ -1,5 +1,3 @@
-use std::fmt;
-
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum CharClass {
     Digit,
@@ -8,8 +6,11 @@ pub enum CharClass {
     Minus,
     Aster,
     Slash,
+    Percent,
     Lparen,
     Rparen,
+    Lbracket,
+    Rbracket,
     Equal,
     Lss,
     Gtr,
@@ -17,39 +18,50 @@ pub enum CharClass {
     Period,
     SemiColon,
     Colon,
+    Quote,
     Other
 }
 
 impl CharClass {
-    pub fn from_u8(b: u8) -> Self {
-        match b {
-            b'0'..=b'9' => {
+    pub fn from_char(c: char) -> Self {
+        match c {
+            '0'..='9' => {
                 CharClass::Digit
             },
-            b'a'..=b'z' | b'A'..=b'Z' => {
+            'a'..='z' | 'A'..='Z' => {
                 CharClass::Letter
             },
-            b'+' => CharClass::Plus,
-            b'-' => CharClass::Minus,
-            b'*' => CharClass::Aster,
-            b'/' => CharClass::Slash,
-            b'(' => CharClass::Lparen,
-            b')' => CharClass::Rparen,
-            b'=' => CharClass::Equal,
-            b'<' => CharClass::Lss,
-            b'>' => CharClass::Gtr,
-            b',' => CharClass::Comma,
-            b'.' => CharClass::Period,
-            b';' => CharClass::SemiColon,
-            b':' => CharClass::Colon,
-               _ => CharClass::Other
+            '+' => CharClass::Plus,
+            '-' => CharClass::Minus,
+            '*' => CharClass::Aster,
+            '/' => CharClass::Slash,
+            '%' => CharClass::Percent,
+            '(' => CharClass::Lparen,
+            ')' => CharClass::Rparen,
+            '[' => CharClass::Lbracket,
+            ']' => CharClass::Rbracket,
+            '=' => CharClass::Equal,
+            '<' => CharClass::Lss,
+            '>' => CharClass::Gtr,
+            ',' => CharClass::Comma,
+            '.' => CharClass::Period,
+            ';' => CharClass::SemiColon,
+            ':' => CharClass::Colon,
+            '"' => CharClass::Quote,
+              _ => CharClass::Other
         }
     }
 
     pub fn is_reserved(&self) -> bool {
-        match self {
-            &CharClass::Other => false,
-                       _ => true
-        }
+        !matches!(self, CharClass::Other)
     }
-}
\ No newline at end of file
+}
+// Identifiers follow Unicode Standard Annex #31, using the XID_Start and
+// XID_Continue tables of the unicode-ident crate.
+pub fn is_xid_start(c: char) -> bool {
+    unicode_ident::is_xid_start(c)
+}
+
+pub fn is_xid_continue(c: char) -> bool {
+    unicode_ident::is_xid_continue(c)
+}
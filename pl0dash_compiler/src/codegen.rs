@@ -0,0 +1,603 @@
+use std::fmt;
+use crate::tokenizer::*;
+use crate::keyword::*;
+use crate::symbol::*;
+use crate::parser::*;
+use crate::table::*;
+use crate::backend::*;
+use crate::semantic::self_tail_call;
+
+/// Operators of the `opr` instruction.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Operator {
+    Neg,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Abs,
+    Min,
+    Max,
+    Odd,
+    Eq,
+    Ls,
+    Gr,
+    Neq,
+    LsEq,
+    GrEq,
+    Wrt,
+    Wrl,
+    Red,
+    /// push a copy of the top of the stack, which only the peephole pass
+    /// generates
+    Dup,
+}
+
+impl fmt::Display for Operator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            Operator::Neg  => "neg",
+            Operator::Add  => "add",
+            Operator::Sub  => "sub",
+            Operator::Mul  => "mul",
+            Operator::Div  => "div",
+            Operator::Mod  => "mod",
+            Operator::Abs  => "abs",
+            Operator::Min  => "min",
+            Operator::Max  => "max",
+            Operator::Odd  => "odd",
+            Operator::Eq   => "eq",
+            Operator::Ls   => "ls",
+            Operator::Gr   => "gr",
+            Operator::Neq  => "neq",
+            Operator::LsEq => "lseq",
+            Operator::GrEq => "greq",
+            Operator::Wrt  => "wrt",
+            Operator::Wrl  => "wrl",
+            Operator::Red  => "red",
+            Operator::Dup  => "dup",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// A (block level, relative address) pair.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RelAddr {
+    pub level: usize,
+    pub addr: i32,
+}
+
+/// Instructions of the PL/0' stack machine.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Inst {
+    /// push a constant
+    Lit(i32),
+    /// apply an operator to the top of the stack
+    Opr(Operator),
+    /// push a variable
+    Lod(RelAddr),
+    /// pop into a variable
+    Sto(RelAddr),
+    /// pop an index and push that element of an array of the given size
+    Lda(RelAddr, i32),
+    /// pop a value and an index and store into that element of an array
+    Sta(RelAddr, i32),
+    /// call: level of the function's declaration, code address
+    Cal(RelAddr),
+    /// return: level of the returning block, number of parameters to drop
+    Ret(RelAddr),
+    /// allocate a frame of the given size, or pop with a negative one
+    Ict(i32),
+    Jmp(usize),
+    /// jump if the popped value is 0
+    Jpc(usize),
+    /// write a string of the constant pool
+    Wrs(usize),
+}
+
+impl fmt::Display for Inst {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Inst::Lit(v)  => write!(f, "lit {}", v),
+            Inst::Opr(op) => write!(f, "opr {}", op),
+            Inst::Lod(a)  => write!(f, "lod {},{}", a.level, a.addr),
+            Inst::Sto(a)  => write!(f, "sto {},{}", a.level, a.addr),
+            Inst::Lda(a, size) => write!(f, "lda {},{},{}", a.level, a.addr, size),
+            Inst::Sta(a, size) => write!(f, "sta {},{},{}", a.level, a.addr, size),
+            Inst::Cal(a)  => write!(f, "cal {},{}", a.level, a.addr),
+            Inst::Ret(a)  => write!(f, "ret {},{}", a.level, a.addr),
+            Inst::Ict(v)  => write!(f, "ict {}", v),
+            Inst::Jmp(a)  => write!(f, "jmp {}", a),
+            Inst::Jpc(a)  => write!(f, "jpc {}", a),
+            Inst::Wrs(i)  => write!(f, "wrs {}", i),
+        }
+    }
+}
+
+/// Stack machine code together with its constant pool, the strings that
+/// `wrs` writes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Program {
+    pub code: Vec<Inst>,
+    pub strings: Vec<String>,
+}
+
+/// Jumps out of a loop being generated, patched once its end is known.
+#[derive(Default)]
+struct Loop {
+    breaks: Vec<usize>,
+    continues: Vec<usize>,
+}
+
+/// Translates a checked syntax tree into stack machine code, the same way
+/// the one-pass compiler of the book does while parsing.
+pub struct CodeGenerator {
+    table: Table,
+    code: Vec<Inst>,
+    strings: Vec<String>,
+    loops: Vec<Loop>,
+}
+
+impl CodeGenerator {
+    pub fn new() -> Self {
+        CodeGenerator {
+            table: Table::new(),
+            code: vec![],
+            strings: vec![],
+            loops: vec![],
+        }
+    }
+
+    pub fn generate(mut self, tree: &SyntaxTree) -> Program {
+        let program = tree.get_ref_root();
+        self.table.block_begin(FIRST_ADDR);
+        self.gen_block(&program.get_ref_children()[0], None);
+        Program {
+            code: self.code,
+            strings: self.strings,
+        }
+    }
+
+    fn gen_block(&mut self, node: &SyntaxNode, func: Option<usize>) {
+        let back_p = self.emit(Inst::Jmp(0));
+        for child in node.get_ref_children() {
+            match child.get_ref_syntax() {
+                Syntax::ConstDecl => enter_consts(&mut self.table, child),
+                Syntax::VarDecl => {
+                    enter_vars(&mut self.table, child);
+                },
+                Syntax::FuncDecl | Syntax::ProcDecl => self.gen_func_decl(child),
+                Syntax::Statement => {
+                    // the jump over nested functions lands here
+                    let start = self.next_code();
+                    self.back_patch(back_p, start);
+                    if let Some(f) = func {
+                        self.table.change_addr(f, start as i32);
+                    }
+                    // the frame may grow by temporaries of the statement
+                    let ict = self.emit(Inst::Ict(0));
+                    self.gen_statement(child);
+                    self.code[ict] = Inst::Ict(self.table.frame_size());
+                },
+                _ => unreachable!(),
+            }
+        }
+        if let Some(f) = func {
+            if self.table.get(f).kind == Kind::Proc {
+                // every `ret` hands a value back; the caller drops this one
+                self.emit(Inst::Lit(0));
+            }
+        }
+        self.emit_ret();
+        self.table.block_end();
+    }
+
+    fn gen_func_decl(&mut self, node: &SyntaxNode) {
+        // until its body is generated, a function is called through the jump
+        // over its nested functions, as nested functions may call it already
+        let addr = self.next_code() as i32;
+        let decl = enter_func(&mut self.table, node, addr);
+        self.gen_block(decl.body, Some(decl.func));
+    }
+
+    fn gen_statement(&mut self, node: &SyntaxNode) {
+        let children = node.get_ref_children();
+        let first = match children.first() {
+            Some(first) => first,
+            None => return, // empty statement
+        };
+        match first.get_ref_token().unwrap() {
+            Token::Identifier(name) if children.len() > 1 && children[1].get_ref_token() == Some(&Token::Symbol(Symbol::Lparen)) => {
+                let e = lookup(&self.table, name);
+                self.gen_call(&e, &children[1..]);
+                self.emit(Inst::Ict(-1));
+            },
+            Token::Keyword(Keyword::Call) => {
+                let e = lookup(&self.table, children[1].get_ref_identifier().unwrap());
+                self.gen_call(&e, &children[2..]);
+                self.emit(Inst::Ict(-1));
+            },
+            Token::Identifier(name) => {
+                let e = lookup(&self.table, name);
+                let addr = RelAddr { level: e.level, addr: e.addr };
+                if e.kind == Kind::Array {
+                    // ident [ index ] := value
+                    self.gen_expression(&children[2]);
+                    self.gen_expression(&children[5]);
+                    self.emit(Inst::Sta(addr, e.size));
+                } else {
+                    self.gen_expression(&children[2]);
+                    self.emit(Inst::Sto(addr));
+                }
+            },
+            Token::Keyword(Keyword::Begin) => {
+                for child in children {
+                    if let Syntax::Statement = child.get_ref_syntax() {
+                        self.gen_statement(child);
+                    }
+                }
+            },
+            Token::Keyword(Keyword::If) => {
+                let back_ps = self.gen_condition(&children[1], false);
+                self.gen_statement(&children[3]);
+                if children.len() > 4 {
+                    // jump over the else-branch at the end of the then-branch
+                    let back_p2 = self.emit(Inst::Jmp(0));
+                    let else_start = self.next_code();
+                    self.back_patch_all(back_ps, else_start);
+                    self.gen_statement(&children[5]);
+                    let next = self.next_code();
+                    self.back_patch(back_p2, next);
+                } else {
+                    let next = self.next_code();
+                    self.back_patch_all(back_ps, next);
+                }
+            },
+            Token::Keyword(Keyword::While) => {
+                self.loops.push(Loop::default());
+                let back_p2 = self.next_code();
+                let back_ps = self.gen_condition(&children[1], false);
+                self.gen_statement(&children[3]);
+                self.emit(Inst::Jmp(back_p2));
+                let next = self.next_code();
+                self.back_patch_all(back_ps, next);
+                self.loop_end(back_p2, next);
+            },
+            Token::Keyword(Keyword::For) => {
+                let e = lookup(&self.table, children[1].get_ref_identifier().unwrap());
+                let var = RelAddr { level: e.level, addr: e.addr };
+                let temp = self.table.enter_temp();
+                let limit = RelAddr { level: self.table.level(), addr: self.table.get(temp).addr };
+                let (cmp, last, step) = match children[4].get_ref_token() {
+                    Some(Token::Keyword(Keyword::To)) => (Operator::LsEq, Operator::Ls, Operator::Add),
+                    _ => (Operator::GrEq, Operator::Gr, Operator::Sub),
+                };
+                // both bounds are evaluated once, before the loop
+                self.gen_expression(&children[3]);
+                self.emit(Inst::Sto(var));
+                self.gen_expression(&children[5]);
+                self.emit(Inst::Sto(limit));
+                let back_p2 = self.next_code();
+                self.emit(Inst::Lod(var));
+                self.emit(Inst::Lod(limit));
+                self.emit(Inst::Opr(cmp));
+                let back_p = self.emit(Inst::Jpc(0));
+                self.loops.push(Loop::default());
+                self.gen_statement(&children[7]);
+                // the variable reaching the limit ends the loop before the
+                // step, which could overflow past it
+                let step_start = self.next_code();
+                self.emit(Inst::Lod(var));
+                self.emit(Inst::Lod(limit));
+                self.emit(Inst::Opr(last));
+                let back_p3 = self.emit(Inst::Jpc(0));
+                self.emit(Inst::Lod(var));
+                self.emit(Inst::Lit(1));
+                self.emit(Inst::Opr(step));
+                self.emit(Inst::Sto(var));
+                self.emit(Inst::Jmp(back_p2));
+                let next = self.next_code();
+                self.back_patch(back_p, next);
+                self.back_patch(back_p3, next);
+                self.loop_end(step_start, next);
+            },
+            Token::Keyword(Keyword::Repeat) => {
+                self.loops.push(Loop::default());
+                let start = self.next_code();
+                for child in children {
+                    if let Syntax::Statement = child.get_ref_syntax() {
+                        self.gen_statement(child);
+                    }
+                }
+                let cond_start = self.next_code();
+                let back_ps = self.gen_condition(children.last().unwrap(), false);
+                self.back_patch_all(back_ps, start);
+                let next = self.next_code();
+                self.loop_end(cond_start, next);
+            },
+            Token::Keyword(Keyword::Break) => {
+                let back_p = self.emit(Inst::Jmp(0));
+                self.loops.last_mut().unwrap().breaks.push(back_p);
+            },
+            Token::Keyword(Keyword::Continue) => {
+                let back_p = self.emit(Inst::Jmp(0));
+                self.loops.last_mut().unwrap().continues.push(back_p);
+            },
+            Token::Keyword(Keyword::Ret) => {
+                if let Some(args) = self_tail_call(&self.table, &children[1]) {
+                    self.gen_tail_call(&args);
+                    return;
+                }
+                self.gen_expression(&children[1]);
+                self.emit_ret();
+            },
+            Token::Keyword(Keyword::Write) => {
+                for arg in &children[1..] {
+                    if let Some(Token::String(s)) = arg.get_ref_token() {
+                        let i = intern(&mut self.strings, s);
+                        self.emit(Inst::Wrs(i));
+                    } else if let Syntax::Expression = arg.get_ref_syntax() {
+                        self.gen_expression(arg);
+                        self.emit(Inst::Opr(Operator::Wrt));
+                    }
+                }
+            },
+            Token::Keyword(Keyword::WriteLn) => {
+                self.emit(Inst::Opr(Operator::Wrl));
+            },
+            Token::Keyword(Keyword::Read) => {
+                let e = lookup(&self.table, children[1].get_ref_identifier().unwrap());
+                let addr = RelAddr { level: e.level, addr: e.addr };
+                if e.kind == Kind::Array {
+                    // read ident [ index ]
+                    self.gen_expression(&children[3]);
+                    self.emit(Inst::Opr(Operator::Red));
+                    self.emit(Inst::Sta(addr, e.size));
+                } else {
+                    self.emit(Inst::Opr(Operator::Red));
+                    self.emit(Inst::Sto(addr));
+                }
+            },
+            t => unreachable!("statement starting with {:?}", t),
+        }
+    }
+
+    /// Generates a condition as jumps: the code falls through when the
+    /// condition holds, or when it does not if `negate` is set, and
+    /// otherwise takes one of the returned jumps, left for the caller to
+    /// patch. Operands of `and` and `or` are evaluated only as far as needed.
+    fn gen_condition(&mut self, node: &SyntaxNode, negate: bool) -> Vec<usize> {
+        let children = node.get_ref_children();
+        match children[0].get_ref_token() {
+            Some(Token::Keyword(Keyword::Not)) => {
+                return self.gen_condition(&children[1], !negate);
+            },
+            Some(Token::Keyword(Keyword::Odd)) => {
+                self.gen_expression(&children[1]);
+                self.emit(Inst::Opr(Operator::Odd));
+                if negate {
+                    self.emit(Inst::Lit(0));
+                    self.emit(Inst::Opr(Operator::Eq));
+                }
+                return vec![self.emit(Inst::Jpc(0))];
+            },
+            Some(Token::Symbol(Symbol::Lparen)) => {
+                return self.gen_condition(&children[1], negate);
+            },
+            _ => (),
+        }
+        if let Syntax::Condition = children[0].get_ref_syntax() {
+            // c { and c } or c { or c }; a negated `and` is an `or` of the
+            // negated operands and the other way round
+            let is_or = children[1].get_ref_token() == Some(&Token::Keyword(Keyword::Or));
+            let operands: Vec<&SyntaxNode> = children.iter().step_by(2).collect();
+            if is_or == negate {
+                return operands
+                    .into_iter()
+                    .flat_map(|c| self.gen_condition(c, negate))
+                    .collect();
+            }
+            let (last, init) = operands.split_last().unwrap();
+            let mut to_true = vec![];
+            for c in init {
+                to_true.extend(self.gen_condition(c, !negate));
+            }
+            let to_false = self.gen_condition(last, negate);
+            let next = self.next_code();
+            self.back_patch_all(to_true, next);
+            return to_false;
+        }
+        self.gen_expression(&children[0]);
+        self.gen_expression(&children[2]);
+        let op = match children[1].get_ref_token() {
+            Some(Token::Symbol(Symbol::Equal)) => Operator::Eq,
+            Some(Token::Symbol(Symbol::Lss))   => Operator::Ls,
+            Some(Token::Symbol(Symbol::Gtr))   => Operator::Gr,
+            Some(Token::Symbol(Symbol::NotEq)) => Operator::Neq,
+            Some(Token::Symbol(Symbol::LssEq)) => Operator::LsEq,
+            Some(Token::Symbol(Symbol::GtrEq)) => Operator::GrEq,
+            t => unreachable!("relational operator {:?}", t),
+        };
+        let op = if negate {
+            match op {
+                Operator::Eq   => Operator::Neq,
+                Operator::Neq  => Operator::Eq,
+                Operator::Ls   => Operator::GrEq,
+                Operator::GrEq => Operator::Ls,
+                Operator::Gr   => Operator::LsEq,
+                Operator::LsEq => Operator::Gr,
+                _ => unreachable!(),
+            }
+        } else {
+            op
+        };
+        self.emit(Inst::Opr(op));
+        vec![self.emit(Inst::Jpc(0))]
+    }
+
+    fn gen_expression(&mut self, node: &SyntaxNode) {
+        let children = node.get_ref_children();
+        let mut i = 0;
+        let mut negate = false;
+        if let Some(Token::Symbol(sym)) = children[0].get_ref_token() {
+            negate = *sym == Symbol::Minus;
+            i = 1;
+        }
+        self.gen_term(&children[i]);
+        if negate {
+            self.emit(Inst::Opr(Operator::Neg));
+        }
+        for pair in children[i + 1..].chunks(2) {
+            let op = match pair[0].get_ref_token() {
+                Some(Token::Symbol(Symbol::Plus))  => Operator::Add,
+                Some(Token::Symbol(Symbol::Minus)) => Operator::Sub,
+                t => unreachable!("additive operator {:?}", t),
+            };
+            self.gen_term(&pair[1]);
+            self.emit(Inst::Opr(op));
+        }
+    }
+
+    fn gen_term(&mut self, node: &SyntaxNode) {
+        let children = node.get_ref_children();
+        self.gen_factor(&children[0]);
+        for pair in children[1..].chunks(2) {
+            let op = match pair[0].get_ref_token() {
+                Some(Token::Symbol(Symbol::Mult)) => Operator::Mul,
+                Some(Token::Symbol(Symbol::Div))  => Operator::Div,
+                Some(Token::Symbol(Symbol::Mod)) | Some(Token::Keyword(Keyword::Mod)) => Operator::Mod,
+                t => unreachable!("multiplicative operator {:?}", t),
+            };
+            self.gen_factor(&pair[1]);
+            self.emit(Inst::Opr(op));
+        }
+    }
+
+    fn gen_factor(&mut self, node: &SyntaxNode) {
+        let children = node.get_ref_children();
+        match children[0].get_ref_token() {
+            Some(Token::Identifier(name)) => {
+                if let Some(f) = self.table.search_intrinsic(name) {
+                    for arg in &children[1..] {
+                        if let Syntax::Expression = arg.get_ref_syntax() {
+                            self.gen_expression(arg);
+                        }
+                    }
+                    let op = match f {
+                        Intrinsic::Abs => Operator::Abs,
+                        Intrinsic::Min => Operator::Min,
+                        Intrinsic::Max => Operator::Max,
+                    };
+                    self.emit(Inst::Opr(op));
+                    return;
+                }
+                let e = lookup(&self.table, name);
+                match e.kind {
+                    Kind::Var | Kind::Par => {
+                        self.emit(Inst::Lod(RelAddr { level: e.level, addr: e.addr }));
+                    },
+                    Kind::Array => {
+                        self.gen_expression(&children[2]);
+                        self.emit(Inst::Lda(RelAddr { level: e.level, addr: e.addr }, e.size));
+                    },
+                    Kind::Const => {
+                        self.emit(Inst::Lit(e.value));
+                    },
+                    Kind::Func => {
+                        self.gen_call(&e, &children[1..]);
+                    },
+                    Kind::Proc => unreachable!("unchecked syntax tree"),
+                }
+            },
+            Some(Token::Number(value)) => {
+                self.emit(Inst::Lit(*value));
+            },
+            _ => {
+                // ( expression )
+                self.gen_expression(&children[1]);
+            },
+        }
+    }
+
+    /// Pushes the arguments among `args` and calls `e`, which leaves its
+    /// value on the stack.
+    fn gen_call(&mut self, e: &Entry, args: &[SyntaxNode]) {
+        for arg in args {
+            if let Syntax::Expression = arg.get_ref_syntax() {
+                self.gen_expression(arg);
+            }
+        }
+        self.emit(Inst::Cal(RelAddr { level: e.level, addr: e.addr }));
+    }
+
+    /// Calls the current function again in its own frame: the arguments
+    /// replace the parameters and the variables are zeroed again, and the body
+    /// starts over past the `ict` that has already made room for the frame.
+    /// The return address stays that of the first call.
+    fn gen_tail_call(&mut self, args: &[&SyntaxNode]) {
+        for arg in args {
+            self.gen_expression(arg);
+        }
+        let level = self.table.level();
+        let pars: Vec<i32> = self.table.block_entries()[..args.len()].iter().map(|e| e.addr).collect();
+        for addr in pars.into_iter().rev() {
+            self.emit(Inst::Sto(RelAddr { level, addr }));
+        }
+        for addr in FIRST_ADDR..self.table.frame_size() {
+            self.emit(Inst::Lit(0));
+            self.emit(Inst::Sto(RelAddr { level, addr }));
+        }
+        let f = self.table.func_index().unwrap();
+        let start = self.table.get(f).addr as usize;
+        self.emit(Inst::Jmp(start + 1));
+    }
+
+    fn emit(&mut self, inst: Inst) -> usize {
+        self.code.push(inst);
+        self.code.len() - 1
+    }
+
+    // a `ret` right after another one is left out unless a jump lands on it
+    fn emit_ret(&mut self) {
+        let next = self.next_code();
+        let is_target = self.code.iter().any(|inst| matches!(inst, Inst::Jmp(a) | Inst::Jpc(a) if *a == next));
+        if let (Some(Inst::Ret(_)), false) = (self.code.last(), is_target) {
+            return;
+        }
+        let level = self.table.level();
+        let pars = self.table.func_pars() as i32;
+        self.emit(Inst::Ret(RelAddr { level, addr: pars }));
+    }
+
+    fn next_code(&self) -> usize {
+        self.code.len()
+    }
+
+    fn back_patch(&mut self, i: usize, target: usize) {
+        match &mut self.code[i] {
+            Inst::Jmp(a) | Inst::Jpc(a) => *a = target,
+            _ => unreachable!(),
+        }
+    }
+
+    /// Closes the innermost loop, patching its `continue` and `break` jumps.
+    fn loop_end(&mut self, continue_target: usize, break_target: usize) {
+        let l = self.loops.pop().unwrap();
+        self.back_patch_all(l.continues, continue_target);
+        self.back_patch_all(l.breaks, break_target);
+    }
+
+    fn back_patch_all(&mut self, jumps: Vec<usize>, target: usize) {
+        for i in jumps {
+            self.back_patch(i, target);
+        }
+    }
+}
+
+impl Default for CodeGenerator {
+    fn default() -> Self {
+        CodeGenerator::new()
+    }
+}
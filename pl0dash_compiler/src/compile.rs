@@ -0,0 +1,478 @@
+use std::io::Read;
+use std::fmt;
+use std::str::FromStr;
+use crate::tokenizer::*;
+use crate::parser::*;
+use crate::semantic::*;
+use crate::fold::*;
+use crate::codegen::*;
+use crate::x86_64::*;
+use crate::c::*;
+use crate::llvm::*;
+use crate::wat::*;
+use crate::riscv64::*;
+use crate::ir::Module;
+use crate::lower::*;
+
+/// Any error that keeps a program from being compiled.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CompileError {
+    Lex(LexError),
+    Parse(ParserError),
+    Semantic(SemanticError),
+}
+
+impl CompileError {
+    pub fn pos(&self) -> Option<Position> {
+        match self {
+            CompileError::Lex(e)      => Some(e.pos),
+            CompileError::Parse(e)    => e.pos(),
+            CompileError::Semantic(e) => Some(e.pos),
+        }
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CompileError::Lex(e)      => write!(f, "{}", e),
+            CompileError::Parse(e)    => write!(f, "{}", e),
+            CompileError::Semantic(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// Parses and checks a program, returning its syntax tree or every error
+/// found. Checking is skipped if the program could not be parsed cleanly.
+pub fn analyze<R: Read + 'static>(r: R, options: TokenizerOptions) -> Result<SyntaxTree, Vec<CompileError>> {
+    let mut p = Parser::new(Tokenizer::with_options(r, options));
+    let parsed = p.parse();
+    let mut errors: Vec<CompileError> = p.lex_errors()
+        .iter()
+        .cloned()
+        .map(CompileError::Lex)
+        .collect();
+    let tree = match parsed {
+        Ok(tree) => tree,
+        Err(e) => {
+            errors.push(CompileError::Parse(e));
+            errors.sort_by_key(|e| e.pos());
+            return Err(errors);
+        }
+    };
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    let errors: Vec<CompileError> = Checker::new()
+        .check(&tree)
+        .into_iter()
+        .map(CompileError::Semantic)
+        .collect();
+    if errors.is_empty() {
+        Ok(tree)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Parses and checks a program like `analyze`, then folds its constants.
+/// Divisions by a constant 0 are errors.
+pub fn analyze_folded<R: Read + 'static>(r: R, options: TokenizerOptions) -> Result<SyntaxTree, Vec<CompileError>> {
+    let tree = analyze(r, options)?;
+    Folder::new()
+        .fold(&tree)
+        .map_err(|errors| errors.into_iter().map(CompileError::Semantic).collect())
+}
+
+/// Compiles a program into stack machine code.
+pub fn compile<R: Read + 'static>(r: R, options: TokenizerOptions) -> Result<Program, Vec<CompileError>> {
+    let tree = analyze(r, options)?;
+    Ok(CodeGenerator::new().generate(&tree))
+}
+
+/// Lowers a program into the three-address IR.
+pub fn lower<R: Read + 'static>(r: R, options: TokenizerOptions) -> Result<Module, Vec<CompileError>> {
+    let tree = analyze(r, options)?;
+    Ok(IrGenerator::new().generate(&tree))
+}
+
+/// Languages a program can be translated into besides stack machine code.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Target {
+    /// x86-64 assembly for the GNU assembler, linked with `cc`
+    X86_64,
+    /// C source, compiled by `cc`
+    C,
+    /// textual LLVM IR, compiled by `llc` or `clang`
+    Llvm,
+    /// WebAssembly text format, run by a host providing the input and output
+    Wat,
+    /// RV64IM assembly for the GNU assembler, calling Linux directly
+    Riscv64,
+    /// dump of the three-address IR
+    Ir,
+}
+
+#[derive(Debug, Clone)]
+pub struct UndefinedTarget;
+
+impl fmt::Display for UndefinedTarget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Undefined target (expected x86_64, c, llvm, wat, riscv64 or ir)")
+    }
+}
+
+impl FromStr for Target {
+    type Err = UndefinedTarget;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "x86_64" | "x86-64"   => Ok(Target::X86_64),
+            "c"                   => Ok(Target::C),
+            "llvm" | "ll"         => Ok(Target::Llvm),
+            "wat" | "wasm"        => Ok(Target::Wat),
+            "riscv64" | "rv64"    => Ok(Target::Riscv64),
+            "ir"                  => Ok(Target::Ir),
+                                _ => Err(UndefinedTarget),
+        }
+    }
+}
+
+impl Target {
+    /// Extension of the files holding programs in the language.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Target::X86_64  => "s",
+            Target::C       => "c",
+            Target::Llvm    => "ll",
+            Target::Wat     => "wat",
+            Target::Riscv64 => "s",
+            Target::Ir      => "ir",
+        }
+    }
+}
+
+/// Compiles a program into the source text of `target`.
+pub fn emit<R: Read + 'static>(r: R, options: TokenizerOptions, target: Target) -> Result<String, Vec<CompileError>> {
+    let tree = analyze(r, options)?;
+    Ok(translate(&tree, target))
+}
+
+/// Translates a checked syntax tree into the source text of `target`.
+pub fn translate(tree: &SyntaxTree, target: Target) -> String {
+    match target {
+        Target::X86_64  => X86Generator::new().generate(tree),
+        Target::C       => CGenerator::new().generate(tree),
+        Target::Llvm    => LlvmGenerator::new().generate(tree),
+        Target::Wat     => WatGenerator::new().generate(tree),
+        Target::Riscv64 => RiscvGenerator::new().generate(tree),
+        Target::Ir      => IrGenerator::new().generate(tree).to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::*;
+
+    fn run(src: &'static str) -> String {
+        let code = compile(src.as_bytes(), TokenizerOptions::default()).unwrap();
+        let mut out = vec![];
+        Vm::new(&code).run(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_run() {
+        assert_eq!(run("var x; begin x := -3 + 4 * (5 - 2) / 2; write x; writeln end."), "3 \n");
+        assert_eq!(run("\
+            function gcd(a, b)
+            begin
+                if b = 0 then return a;
+                return gcd(b, a - a / b * b)
+            end;
+            write gcd(84, 36)."), "12 ");
+        assert_eq!(run("\
+            var i, s;
+            begin
+                i := 0; s := 0;
+                while i < 10 do begin i := i + 1; if odd i then s := s + i end;
+                write s
+            end."), "25 ");
+        assert_eq!(run("\
+            function sign(n)
+                if n < 0 then return -1 else if n = 0 then return 0 else return 1;
+            begin write sign(-5); write sign(0); write sign(7) end."), "-1 0 1 ");
+    }
+
+    #[test]
+    fn test_arrays() {
+        assert_eq!(run("\
+            var a[5], i;
+            function sum(n)
+                var s[1];
+                begin
+                    s[0] := 0;
+                    while n > 0 do begin n := n - 1; s[0] := s[0] + a[n] end;
+                    return s[0]
+                end;
+            begin
+                i := 0;
+                while i < 5 do begin a[i] := i * i; i := i + 1 end;
+                write sum(5); write a[4]
+            end."), "30 16 ");
+    }
+
+    #[test]
+    fn test_index_out_of_bounds() {
+        let code = compile("var a[3]; a[3] := 1.".as_bytes(), TokenizerOptions::default()).unwrap();
+        let e = Vm::new(&code).run(&mut vec![]).unwrap_err();
+        assert_eq!(e.to_string(), "runtime error: index 3 is out of bounds for an array of size 3");
+    }
+
+    #[test]
+    fn test_boolean_conditions() {
+        assert_eq!(run("\
+            var a, b;
+            begin
+                a := 0;
+                while a < 2 do begin
+                    b := 0;
+                    while b < 2 do begin
+                        if a = 1 and b = 1 then write 1 else write 0;
+                        if a = 1 or b = 1 then write 1 else write 0;
+                        if not (a = 1 and b = 1) then write 1 else write 0;
+                        if not a = 1 or not odd b then write 1 else write 0;
+                        writeln;
+                        b := b + 1
+                    end;
+                    a := a + 1
+                end
+            end."), "0 0 1 1 \n0 1 1 1 \n0 1 1 1 \n1 1 0 0 \n");
+        // the right operand is evaluated only when needed
+        assert_eq!(run("\
+            function f(x) begin write x; return x end;
+            var z;
+            begin
+                z := 0;
+                if z <> 0 and 10 / z > 1 then write 99;
+                if f(1) = 1 or f(2) = 2 then writeln;
+                if f(3) = 0 and f(4) = 4 then write 99 else writeln;
+                if not (f(5) = 5 and f(6) = 0) then writeln
+            end."), "1 \n3 \n5 6 \n");
+    }
+
+    #[test]
+    fn test_loops() {
+        assert_eq!(run("var i; for i := 1 to 5 do write i."), "1 2 3 4 5 ");
+        assert_eq!(run("var i; for i := 3 downto -1 do write i."), "3 2 1 0 -1 ");
+        assert_eq!(run("var i; begin for i := 5 to 4 do write i; write i end."), "5 ");
+        // the bounds are evaluated once
+        assert_eq!(run("\
+            var i, n;
+            begin
+                n := 3;
+                for i := 1 to n do begin n := n + 1; write i end;
+                write n
+            end."), "1 2 3 6 ");
+        assert_eq!(run("\
+            function sum(n)
+                var i, j, s;
+                begin
+                    s := 0;
+                    for i := 1 to n do for j := 1 to i do s := s + j;
+                    return s
+                end;
+            write sum(4)."), "20 ");
+        // the variable stops at the limit, with no step past the largest or
+        // smallest integer
+        assert_eq!(run("var i; for i := 2147483645 to 2147483647 do write i."), "2147483645 2147483646 2147483647 ");
+        assert_eq!(run("var i; for i := -2147483646 downto -2147483647 - 1 do write i."), "-2147483646 -2147483647 -2147483648 ");
+        assert_eq!(run("var i; begin for i := 1 to 3 do continue; write i end."), "3 ");
+        assert_eq!(run("var i; begin i := 0; repeat write i; i := i + 1 until i >= 3 end."), "0 1 2 ");
+        // the body of `repeat` runs at least once
+        assert_eq!(run("var i; begin i := 10; repeat i := i + 1 until i > 0; write i end."), "11 ");
+    }
+
+    #[test]
+    fn test_break_continue() {
+        assert_eq!(run("\
+            var i;
+            begin
+                i := 0;
+                while 1 = 1 do begin
+                    i := i + 1;
+                    if odd i then continue;
+                    if i > 8 then break;
+                    write i
+                end;
+                writeln;
+                for i := 1 to 10 do begin
+                    if i = 3 then continue;
+                    if i = 6 then break;
+                    write i
+                end;
+                writeln;
+                i := 0;
+                repeat
+                    i := i + 1;
+                    if i < 3 then continue;
+                    write i;
+                    if i = 4 then break
+                until i = 10
+            end."), "2 4 6 8 \n1 2 4 5 \n3 4 ");
+        // `break` leaves the innermost loop only
+        assert_eq!(run("\
+            var i, j;
+            for i := 1 to 3 do begin
+                j := 0;
+                while 1 = 1 do begin j := j + 1; if j = i then break end;
+                write j
+            end."), "1 2 3 ");
+    }
+
+    #[test]
+    fn test_procedures() {
+        assert_eq!(run("\
+            var total;
+            procedure add(n)
+                total := total + n;
+            procedure show()
+                begin write total; writeln end;
+            function twice(n)
+                begin add(n); return 2 * n end;
+            begin
+                total := 0;
+                call add(3); add(4); call show;
+                twice(5); show();
+                write twice(1)
+            end."), "7 \n12 \n2 ");
+        // dropped values do not pile up on the stack
+        let code = compile("\
+            var i;
+            procedure p() ;
+            for i := 1 to 10000 do p().".as_bytes(), TokenizerOptions::default()).unwrap();
+        Vm::with_stack_size(&code, 100).run(&mut vec![]).unwrap();
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        assert_eq!(run("begin write 7 mod 3; write -7 mod 3; write 7 % (-3); write -7 % (-3) end."), "1 -1 1 -1 ");
+        assert_eq!(run("begin write 7 / 2; write -7 / 2; write 7 / (-2) end."), "3 -3 -3 ");
+        assert_eq!(run("begin write 2 * 7 mod 4; write 17 mod 5 * 2; write 1 + 9 % 4 end."), "2 4 2 ");
+        assert_eq!(run("begin write abs(-4); write abs(4); write min(3, -2); write max(3, -2) end."), "4 4 -2 3 ");
+        assert_eq!(run("begin write max(abs(-9), min(2 * 3, 7)) end."), "9 ");
+        // a declaration hides an intrinsic
+        assert_eq!(run("function max(a, b) return a; write max(1, 2)."), "1 ");
+
+        for src in ["var z; begin z := 0; write 1 / z end.", "var z; begin z := 0; write 1 mod z end."] {
+            let code = compile(src.as_bytes(), TokenizerOptions::default()).unwrap();
+            let e = Vm::new(&code).run(&mut vec![]).unwrap_err();
+            assert_eq!(e.to_string(), "runtime error: division by zero");
+        }
+    }
+
+    #[test]
+    fn test_strings() {
+        assert_eq!(run("var x; begin x := 6; write \"x = \", x, \"\\n\"; write \"\\\"done\\\"\" end."), "x = 6 \n\"done\"");
+        let code = compile("begin write \"a\", 1, \"b\"; write \"a\" end.".as_bytes(), TokenizerOptions::default()).unwrap();
+        assert_eq!(code.strings, vec!["a", "b"]);
+    }
+
+    #[test]
+    fn test_nested_functions() {
+        // inner functions read and write the locals and parameters of every
+        // enclosing block, and call enclosing functions before their bodies
+        assert_eq!(run("\
+            function power(b, e)
+                var r;
+                function step(i)
+                    function mult(x)
+                    begin r := r * x; return i - 1 + 0 * power(b, 0) end;
+                begin
+                    if i = 0 then return r;
+                    return step(mult(b))
+                end;
+            begin r := 1; return step(e) end;
+            write power(2, 10)."), "1024 ");
+        // each activation sees its own copy of an enclosing local
+        assert_eq!(run("\
+            function f(n)
+                var x;
+                function g()
+                begin x := x + n; return x end;
+            begin
+                x := 10 * n;
+                if n > 1 then write f(n - 1);
+                return g() + g()
+            end;
+            write f(3)."), "23 46 69 ");
+        // an inner declaration hides an outer one
+        assert_eq!(run("\
+            var x;
+            function f()
+                var x;
+                function g()
+                begin x := 2; return x end;
+            begin x := 1; return g() * 10 + x end;
+            begin x := 5; write f(); write x end."), "22 5 ");
+    }
+
+    #[test]
+    fn test_read() {
+        use std::collections::VecDeque;
+
+        let code = compile("var a[2], x; begin read x; read a[1]; write x + a[1] end.".as_bytes(), TokenizerOptions::default()).unwrap();
+        let mut out = vec![];
+        Vm::new(&code).run_with_input(&mut VecDeque::from(vec![3, 4]), &mut out).unwrap();
+        assert_eq!(out, b"7 ");
+
+        let mut out = vec![];
+        Vm::new(&code).run_with_input(&mut TextInput::new(" -12\n\t30 ".as_bytes()), &mut out).unwrap();
+        assert_eq!(out, b"18 ");
+
+        let e = Vm::new(&code).run_with_input(&mut TextInput::new("5".as_bytes()), &mut vec![]).unwrap_err();
+        assert_eq!(e.to_string(), "runtime error: `read` reached the end of the input");
+        let e = Vm::new(&code).run_with_input(&mut TextInput::new("5 x6".as_bytes()), &mut vec![]).unwrap_err();
+        assert_eq!(e.to_string(), "runtime error: `read` expected an integer, found `x6`");
+        let e = Vm::new(&code).run(&mut vec![]).unwrap_err();
+        assert!(matches!(e, RuntimeError::EndOfInput));
+    }
+
+    #[test]
+    fn test_stack_overflow() {
+        let code = compile("function f(n) return 1 + f(n + 1); write f(0).".as_bytes(), TokenizerOptions::default()).unwrap();
+        let mut out = vec![];
+        let e = Vm::with_stack_size(&code, 1000).run(&mut out).unwrap_err();
+        assert_eq!(e.to_string(), "runtime error: stack overflow");
+    }
+
+    #[test]
+    fn test_tail_calls() {
+        // a call in tail position reuses the frame, however deep it goes
+        let src = "\
+            function f(n, acc) if n = 0 then return acc else return f(n - 1, acc + n mod 3);
+            write f(1000000, 0).";
+        let code = compile(src.as_bytes(), TokenizerOptions::default()).unwrap();
+        let mut out = vec![];
+        Vm::with_stack_size(&code, 1000).run(&mut out).unwrap();
+        assert_eq!(out, b"1000000 ");
+    }
+
+    #[test]
+    fn test_errors_are_collected() {
+        let errors = compile("var x; begin x := 1 @ 2 end.".as_bytes(), TokenizerOptions::default()).unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert!(matches!(errors[0], CompileError::Lex(_)));
+        assert!(matches!(errors[1], CompileError::Parse(_)));
+
+        // lexical errors past the syntax error are reported as well
+        let errors = compile("begin x := 1 @ 2; y # 3 end.".as_bytes(), TokenizerOptions::default()).unwrap_err();
+        let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(messages, vec![
+            "1:14: undefined character '@'",
+            "1:16: expected `end`, found `2`",
+            "1:21: undefined character '#'",
+        ]);
+    }
+}
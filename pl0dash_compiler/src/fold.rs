@@ -0,0 +1,476 @@
+// Constant folding over a checked syntax tree. Named constants are replaced
+// by their values, and operations on values known at compile time by their
+// results, computed as the stack machine would. An `if` with a constant
+// condition becomes the branch taken, and a `while` whose condition is
+// constantly false disappears.
+//
+// Only operands without side effects are ever folded away: `0 * f(x)` still
+// calls `f`, and `f(x) > 0 and 1 = 0` still evaluates `f(x) > 0`. A division
+// by a constant 0 would fail whenever it runs, so it is reported instead.
+
+use crate::tokenizer::*;
+use crate::keyword::*;
+use crate::symbol::*;
+use crate::parser::*;
+use crate::table::*;
+use crate::semantic::SemanticError;
+use crate::ir::{UnaryOp, BinaryOp};
+use crate::interp::{unary, binary};
+
+/// Folds the constants of a tree that passed the checker, returning the
+/// folded tree or the divisions by zero found.
+pub struct Folder {
+    table: Table,
+    errors: Vec<SemanticError>,
+}
+
+impl Folder {
+    pub fn new() -> Self {
+        Folder {
+            table: Table::new(),
+            errors: vec![],
+        }
+    }
+
+    pub fn fold(mut self, tree: &SyntaxTree) -> Result<SyntaxTree, Vec<SemanticError>> {
+        let program = tree.get_ref_root();
+        let children = program.get_ref_children();
+        self.table.block_begin(FIRST_ADDR);
+        let block = self.fold_block(&children[0]);
+        self.table.block_end();
+        if !self.errors.is_empty() {
+            return Err(self.errors);
+        }
+        let mut root = SyntaxNode::new(Syntax::Program);
+        root.append_child(block);
+        root.append_child(children[1].clone());
+        Ok(SyntaxTree::new(root))
+    }
+
+    fn fold_block(&mut self, node: &SyntaxNode) -> SyntaxNode {
+        let mut block = SyntaxNode::new(Syntax::Block);
+        for child in node.get_ref_children() {
+            let child = match child.get_ref_syntax() {
+                Syntax::ConstDecl => {
+                    let children = child.get_ref_children();
+                    for i in (1..children.len()).step_by(4) {
+                        if let Some(Token::Number(value)) = children[i + 2].get_ref_token() {
+                            self.table.enter_const(children[i].get_ref_identifier().unwrap(), *value);
+                        }
+                    }
+                    child.clone()
+                },
+                Syntax::VarDecl => {
+                    let children = child.get_ref_children();
+                    for (i, c) in children.iter().enumerate() {
+                        if let Some(name) = c.get_ref_identifier() {
+                            match (children[i + 1].get_ref_token(), children.get(i + 2).and_then(|c| c.get_ref_token())) {
+                                (Some(Token::Symbol(Symbol::Lbracket)), Some(Token::Number(size))) => {
+                                    self.table.enter_array(name, *size);
+                                },
+                                _ => {
+                                    self.table.enter_var(name);
+                                },
+                            }
+                        }
+                    }
+                    child.clone()
+                },
+                Syntax::FuncDecl | Syntax::ProcDecl => self.fold_func_decl(child),
+                Syntax::Statement => self.fold_statement(child),
+                _ => unreachable!(),
+            };
+            block.append_child(child);
+        }
+        block
+    }
+
+    fn fold_func_decl(&mut self, node: &SyntaxNode) -> SyntaxNode {
+        let children = node.get_ref_children();
+        let name = children[1].get_ref_identifier().unwrap();
+        match node.get_ref_syntax() {
+            Syntax::ProcDecl => self.table.enter_proc(name, 0),
+            _ => self.table.enter_func(name, 0),
+        };
+        self.table.block_begin(FIRST_ADDR);
+        for par in &children[3..children.len() - 3] {
+            if let Some(name) = par.get_ref_identifier() {
+                self.table.enter_par(name);
+            }
+        }
+        self.table.end_par();
+        let body = children.len() - 2;
+        let mut decl = SyntaxNode::new(node.get_ref_syntax().clone());
+        for (i, child) in children.iter().enumerate() {
+            decl.append_child(if i == body { self.fold_block(child) } else { child.clone() });
+        }
+        self.table.block_end();
+        decl
+    }
+
+    fn fold_statement(&mut self, node: &SyntaxNode) -> SyntaxNode {
+        let children = node.get_ref_children();
+        let mut condition = None;
+        match children.first().and_then(|c| c.get_ref_token()) {
+            Some(Token::Keyword(Keyword::If)) => {
+                // only the branch taken is kept, and folded
+                match self.fold_condition(&children[1]) {
+                    (_, Some(true)) => return self.fold_statement(&children[3]),
+                    (_, Some(false)) if children.len() > 4 => return self.fold_statement(&children[5]),
+                    (_, Some(false)) => return SyntaxNode::new(Syntax::Statement),
+                    (c, None) => condition = Some(c),
+                }
+            },
+            Some(Token::Keyword(Keyword::While)) => {
+                match self.fold_condition(&children[1]) {
+                    (_, Some(false)) => return SyntaxNode::new(Syntax::Statement),
+                    (c, _) => condition = Some(c),
+                }
+            },
+            _ => (),
+        }
+        let mut statement = SyntaxNode::new(Syntax::Statement);
+        for child in children {
+            statement.append_child(match child.get_ref_syntax() {
+                Syntax::Statement => self.fold_statement(child),
+                Syntax::Condition => condition.take().unwrap_or_else(|| self.fold_condition(child).0),
+                Syntax::Expression => self.fold_expression(child).0,
+                _ => child.clone(),
+            });
+        }
+        statement
+    }
+
+    /// Folds a condition, returning it with its value if that is constant.
+    fn fold_condition(&mut self, node: &SyntaxNode) -> (SyntaxNode, Option<bool>) {
+        let children = node.get_ref_children();
+        let value = match children[0].get_ref_token() {
+            Some(Token::Keyword(Keyword::Not)) => {
+                let (c, value) = self.fold_condition(&children[1]);
+                match value {
+                    Some(value) => Some(!value),
+                    None => return (rebuild(node, vec![children[0].clone(), c]), None),
+                }
+            },
+            Some(Token::Keyword(Keyword::Odd)) => {
+                let (e, value) = self.fold_expression(&children[1]);
+                match value {
+                    Some(value) => Some(unary(UnaryOp::Odd, value) != 0),
+                    None => return (rebuild(node, vec![children[0].clone(), e]), None),
+                }
+            },
+            Some(Token::Symbol(Symbol::Lparen)) => {
+                let (c, value) = self.fold_condition(&children[1]);
+                match value {
+                    Some(value) => Some(value),
+                    None => return (rebuild(node, vec![children[0].clone(), c, children[2].clone()]), None),
+                }
+            },
+            _ if *children[0].get_ref_syntax() == Syntax::Condition => {
+                return self.fold_logical(node);
+            },
+            _ => {
+                let (lhs, l) = self.fold_expression(&children[0]);
+                let (rhs, r) = self.fold_expression(&children[2]);
+                match (l, r) {
+                    (Some(l), Some(r)) => Some(binary(binary_op(&children[1]), l, r).unwrap() != 0),
+                    _ => return (rebuild(node, vec![lhs, children[1].clone(), rhs]), None),
+                }
+            },
+        };
+        (literal_condition(value.unwrap(), node.get_pos()), value)
+    }
+
+    /// Folds `c { and c }` or `c { or c }`. Operands that cannot change the
+    /// outcome are dropped, and so are those after one that decides it,
+    /// which never run.
+    fn fold_logical(&mut self, node: &SyntaxNode) -> (SyntaxNode, Option<bool>) {
+        let children = node.get_ref_children();
+        let is_or = children[1].get_ref_token() == Some(&Token::Keyword(Keyword::Or));
+        let mut kept = vec![];
+        for operand in children.iter().step_by(2) {
+            let (c, value) = self.fold_condition(operand);
+            match value {
+                Some(value) if value != is_or => (),
+                Some(value) if kept.is_empty() => {
+                    return (literal_condition(value, node.get_pos()), Some(value));
+                },
+                Some(_) => {
+                    kept.push(c);
+                    break;
+                },
+                None => kept.push(c),
+            }
+        }
+        match kept.len() {
+            0 => (literal_condition(!is_or, node.get_pos()), Some(!is_or)),
+            1 => (kept.pop().unwrap(), None),
+            _ => {
+                let mut condition = SyntaxNode::new(Syntax::Condition);
+                for (i, c) in kept.into_iter().enumerate() {
+                    if i > 0 {
+                        condition.append_child(children[1].clone());
+                    }
+                    condition.append_child(c);
+                }
+                (condition, None)
+            },
+        }
+    }
+
+    /// Folds an expression, returning it with its value if that is constant.
+    /// A constant first part, as in `2 * 3 + x`, is folded on its own.
+    fn fold_expression(&mut self, node: &SyntaxNode) -> (SyntaxNode, Option<i32>) {
+        let children = node.get_ref_children();
+        let mut i = 0;
+        let mut negate = false;
+        if let Some(Token::Symbol(sym)) = children[0].get_ref_token() {
+            negate = *sym == Symbol::Minus;
+            i = 1;
+        }
+        let (first, mut value) = self.fold_term(&children[i]);
+        if negate {
+            value = value.map(|v| unary(UnaryOp::Neg, v));
+        }
+        let mut rest = vec![];
+        for pair in children[i + 1..].chunks(2) {
+            let (term, v) = self.fold_term(&pair[1]);
+            match (value, v) {
+                (Some(l), Some(r)) if rest.is_empty() => {
+                    value = Some(binary(binary_op(&pair[0]), l, r).unwrap());
+                },
+                _ => {
+                    rest.push(pair[0].clone());
+                    rest.push(term);
+                },
+            }
+        }
+        let mut expression = SyntaxNode::new(Syntax::Expression);
+        match value {
+            Some(v) => expression.append_child(literal_term(v, node.get_pos())),
+            None => {
+                if i == 1 {
+                    expression.append_child(children[0].clone());
+                }
+                expression.append_child(first);
+            },
+        }
+        let value = if rest.is_empty() { value } else { None };
+        for child in rest {
+            expression.append_child(child);
+        }
+        (expression, value)
+    }
+
+    fn fold_term(&mut self, node: &SyntaxNode) -> (SyntaxNode, Option<i32>) {
+        let children = node.get_ref_children();
+        let (first, mut value) = self.fold_factor(&children[0]);
+        let mut rest = vec![];
+        for pair in children[1..].chunks(2) {
+            let op = binary_op(&pair[0]);
+            let (factor, v) = self.fold_factor(&pair[1]);
+            if matches!(op, BinaryOp::Div | BinaryOp::Mod) && v == Some(0) {
+                self.errors.push(SemanticError {
+                    pos: pair[0].get_pos(),
+                    message: "division by zero".to_string(),
+                });
+            }
+            let folded = match (value, v) {
+                (Some(l), Some(r)) if rest.is_empty() => binary(op, l, r).ok(),
+                _ => None,
+            };
+            match folded {
+                Some(v) => value = Some(v),
+                None => {
+                    rest.push(pair[0].clone());
+                    rest.push(factor);
+                },
+            }
+        }
+        let mut term = SyntaxNode::new(Syntax::Term);
+        term.append_child(match value {
+            Some(v) => literal_factor(v, node.get_pos()),
+            None => first,
+        });
+        let value = if rest.is_empty() { value } else { None };
+        for child in rest {
+            term.append_child(child);
+        }
+        (term, value)
+    }
+
+    fn fold_factor(&mut self, node: &SyntaxNode) -> (SyntaxNode, Option<i32>) {
+        let children = node.get_ref_children();
+        let value = match children[0].get_ref_token() {
+            Some(Token::Identifier(name)) => {
+                let is_call = children.get(1).and_then(|c| c.get_ref_token()) == Some(&Token::Symbol(Symbol::Lparen));
+                let mut folded = SyntaxNode::new(Syntax::Factor);
+                let mut args = vec![];
+                for child in children {
+                    folded.append_child(match child.get_ref_syntax() {
+                        Syntax::Expression => {
+                            let (e, v) = self.fold_expression(child);
+                            args.push(v);
+                            e
+                        },
+                        _ => child.clone(),
+                    });
+                }
+                let intrinsic = if is_call { self.table.search_intrinsic(name) } else { None };
+                let value = match intrinsic {
+                    Some(Intrinsic::Abs) => args[0].map(|x| unary(UnaryOp::Abs, x)),
+                    Some(f) => match (args[0], args[1]) {
+                        (Some(x), Some(y)) => {
+                            let op = if f == Intrinsic::Min { BinaryOp::Min } else { BinaryOp::Max };
+                            Some(binary(op, x, y).unwrap())
+                        },
+                        _ => None,
+                    },
+                    None => {
+                        let e = self.table.get(self.table.search(name).expect("unchecked syntax tree"));
+                        if e.kind == Kind::Const { Some(e.value) } else { None }
+                    },
+                };
+                match value {
+                    Some(value) => value,
+                    None => return (folded, None),
+                }
+            },
+            Some(Token::Number(value)) => {
+                return (node.clone(), Some(*value));
+            },
+            _ => {
+                // ( expression )
+                let (e, value) = self.fold_expression(&children[1]);
+                match value {
+                    Some(value) => value,
+                    None => return (rebuild(node, vec![children[0].clone(), e, children[2].clone()]), None),
+                }
+            },
+        };
+        (literal_factor(value, node.get_pos()), Some(value))
+    }
+}
+
+impl Default for Folder {
+    fn default() -> Self {
+        Folder::new()
+    }
+}
+
+/// A node of the same kind as `node` with other children.
+fn rebuild(node: &SyntaxNode, children: Vec<SyntaxNode>) -> SyntaxNode {
+    let mut rebuilt = SyntaxNode::new(node.get_ref_syntax().clone());
+    for child in children {
+        rebuilt.append_child(child);
+    }
+    rebuilt
+}
+
+fn binary_op(op: &SyntaxNode) -> BinaryOp {
+    match op.get_ref_token() {
+        Some(Token::Symbol(Symbol::Plus))  => BinaryOp::Add,
+        Some(Token::Symbol(Symbol::Minus)) => BinaryOp::Sub,
+        Some(Token::Symbol(Symbol::Mult))  => BinaryOp::Mul,
+        Some(Token::Symbol(Symbol::Div))   => BinaryOp::Div,
+        Some(Token::Symbol(Symbol::Mod)) | Some(Token::Keyword(Keyword::Mod)) => BinaryOp::Mod,
+        Some(Token::Symbol(Symbol::Equal)) => BinaryOp::Eq,
+        Some(Token::Symbol(Symbol::NotEq)) => BinaryOp::Ne,
+        Some(Token::Symbol(Symbol::Lss))   => BinaryOp::Lt,
+        Some(Token::Symbol(Symbol::LssEq)) => BinaryOp::Le,
+        Some(Token::Symbol(Symbol::Gtr))   => BinaryOp::Gt,
+        Some(Token::Symbol(Symbol::GtrEq)) => BinaryOp::Ge,
+        t => unreachable!("binary operator {:?}", t),
+    }
+}
+
+fn literal_factor(value: i32, pos: Position) -> SyntaxNode {
+    let mut factor = SyntaxNode::new(Syntax::Factor);
+    factor.append_child(SyntaxNode::new_token(Token::Number(value), pos, value.to_string()));
+    factor
+}
+
+fn literal_term(value: i32, pos: Position) -> SyntaxNode {
+    let mut term = SyntaxNode::new(Syntax::Term);
+    term.append_child(literal_factor(value, pos));
+    term
+}
+
+fn literal_expression(value: i32, pos: Position) -> SyntaxNode {
+    let mut expression = SyntaxNode::new(Syntax::Expression);
+    expression.append_child(literal_term(value, pos));
+    expression
+}
+
+/// A condition with a constant value, `0 = 0` or `0 = 1`, for where the
+/// grammar still needs one, as in `while 1 = 1 do`.
+fn literal_condition(value: bool, pos: Position) -> SyntaxNode {
+    let mut condition = SyntaxNode::new(Syntax::Condition);
+    condition.append_child(literal_expression(0, pos));
+    condition.append_child(SyntaxNode::new_token(Token::Symbol(Symbol::Equal), pos, "=".to_string()));
+    condition.append_child(literal_expression(if value { 0 } else { 1 }, pos));
+    condition
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compile::*;
+
+    /// The statement of the main block, folded, as source text.
+    fn fold(src: &'static str) -> Result<String, Vec<String>> {
+        fn render(node: &SyntaxNode) -> String {
+            if node.get_ref_token().is_some() {
+                return node.get_ref_lexeme().to_string();
+            }
+            let inner: Vec<String> = node.get_ref_children().iter().map(render).filter(|s| !s.is_empty()).collect();
+            inner.join(" ")
+        }
+        let tree = analyze(src.as_bytes(), TokenizerOptions::default()).unwrap();
+        match Folder::new().fold(&tree) {
+            Ok(tree) => Ok(render(tree.get_ref_root().get_ref_children()[0].get_ref_children().last().unwrap())),
+            Err(errors) => Err(errors.iter().map(|e| e.to_string()).collect()),
+        }
+    }
+
+    #[test]
+    fn test_fold_expressions() {
+        assert_eq!(fold("const m = 7, n = 85; var x; x := m * n + (2 - 5) * 3."), Ok("x := 586".to_string()));
+        assert_eq!(fold("const m = 7; var x; x := -m + 1 + x * (m - 6) + x * 2 * 3."), Ok("x := -6 + x * 1 + x * 2 * 3".to_string()));
+        assert_eq!(fold("var x; x := abs(-4) + min(x, 3 * 3) + max(1, 2) / 2 mod 5."), Ok("x := 4 + min ( x , 9 ) + 1".to_string()));
+        assert_eq!(fold("var a[3]; a[1 + 1] := a[2 - 2]."), Ok("a [ 2 ] := a [ 0 ]".to_string()));
+        // as the stack machine computes them
+        assert_eq!(fold("var x; x := 2147483647 + 1 + (-7) / 2 + (-7) mod 2."), Ok("x := 2147483644".to_string()));
+    }
+
+    #[test]
+    fn test_fold_conditions() {
+        assert_eq!(fold("if odd 5 then write 1 else write 2."), Ok("write 1".to_string()));
+        assert_eq!(fold("const m = 7; if m > 10 then write 1 else write 2."), Ok("write 2".to_string()));
+        assert_eq!(fold("const m = 7; if not (m = 7) then write 1."), Ok("".to_string()));
+        assert_eq!(fold("var x; while 1 > 2 and x = 0 do x := x + 1."), Ok("".to_string()));
+        assert_eq!(fold("var x; while 1 < 2 do x := x + 1."), Ok("while 0 = 0 do x := x + 1".to_string()));
+        assert_eq!(fold("var x; if x = 1 or 2 = 3 or odd x then write x."), Ok("if x = 1 or odd x then write x".to_string()));
+        assert_eq!(fold("var x; if x = 1 and 2 = 3 and odd x then write x."), Ok("if x = 1 and 0 = 1 then write x".to_string()));
+        assert_eq!(fold("var x; repeat x := x + 1 until 1 = 1 or x = 5."), Ok("repeat x := x + 1 until 0 = 0".to_string()));
+    }
+
+    #[test]
+    fn test_fold_scopes() {
+        // a constant hidden by a variable is not substituted
+        assert_eq!(fold("\
+            const c = 3;
+            procedure p(c) write c;
+            var x;
+            begin p(c); x := c * 2 end."), Ok("begin p ( 3 ) ; x := 6 end".to_string()));
+    }
+
+    #[test]
+    fn test_division_by_zero() {
+        assert_eq!(fold("const z = 0; var x; begin x := 1 / 0; x := x mod (z + z); if z = 1 then x := x / z end."), Err(vec![
+            "1:34: division by zero".to_string(),
+            "1:46: division by zero".to_string(),
+        ]));
+        assert_eq!(fold("var x; x := 0 / x * 0."), Ok("x := 0 / x * 0".to_string()));
+    }
+}
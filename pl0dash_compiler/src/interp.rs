@@ -0,0 +1,269 @@
+use std::io::Write;
+use std::collections::VecDeque;
+use crate::ir::*;
+use crate::vm::{Input, RuntimeError, STACK_SIZE};
+
+/// An activation of a function.
+struct Frame {
+    func: FuncId,
+    /// frame of the function whose body declares this one
+    link: Option<usize>,
+    vars: Vec<i32>,
+    temps: Vec<i32>,
+    block: BlockId,
+    /// next instruction of `block`
+    pc: usize,
+    /// temporary of the caller taking the value returned
+    dst: Option<Temp>,
+}
+
+/// Interpreter of the IR, with the behaviour of the stack machine: the same
+/// output and runtime errors, and a stack overflow once the frames would take
+/// more words than the stack of the machine holds.
+pub struct Interpreter<'a> {
+    module: &'a Module,
+    stack_size: usize,
+    /// for every function, where each variable starts in its frame, and the
+    /// size of the frame
+    layouts: Vec<(Vec<usize>, usize)>,
+}
+
+impl<'a> Interpreter<'a> {
+    pub fn new(module: &'a Module) -> Self {
+        Interpreter::with_stack_size(module, STACK_SIZE)
+    }
+
+    pub fn with_stack_size(module: &'a Module, stack_size: usize) -> Self {
+        let layouts = module.funcs.iter().map(|f| {
+            let mut offsets = vec![];
+            let mut size = 0;
+            for v in &f.vars {
+                offsets.push(size);
+                size += v.size.unwrap_or(1) as usize;
+            }
+            (offsets, size)
+        }).collect();
+        Interpreter {
+            module,
+            stack_size,
+            layouts,
+        }
+    }
+
+    /// Runs the program to its end, sending `write` and `writeln` output to
+    /// `out`. Any `read` fails as the program is given no input.
+    pub fn run<W: Write>(&mut self, out: &mut W) -> Result<(), RuntimeError> {
+        self.run_with_input(&mut VecDeque::new(), out)
+    }
+
+    /// Runs the program to its end, taking the integers of `read` from
+    /// `input` and sending `write` and `writeln` output to `out`.
+    pub fn run_with_input<I: Input, W: Write>(&mut self, input: &mut I, out: &mut W) -> Result<(), RuntimeError> {
+        let result = self.execute(input, out);
+        out.flush().map_err(RuntimeError::CannotWrite)?;
+        result
+    }
+
+    fn execute<I: Input, W: Write>(&self, input: &mut I, out: &mut W) -> Result<(), RuntimeError> {
+        let mut frames = vec![];
+        let mut used = 0;
+        self.push_frame(&mut frames, &mut used, 0, None, vec![], None)?;
+        loop {
+            let top = frames.len() - 1;
+            let frame = &frames[top];
+            let block = &self.module.funcs[frame.func].blocks[frame.block];
+            if frame.pc == block.insts.len() {
+                match &block.term {
+                    Terminator::Jump(b) => {
+                        self.enter(&mut frames[top], *b);
+                    },
+                    Terminator::Branch { cond, then_block, else_block } => {
+                        let b = if value(frame, *cond) != 0 { *then_block } else { *else_block };
+                        self.enter(&mut frames[top], b);
+                    },
+                    Terminator::Return(v) => {
+                        let v = v.map(|v| value(frame, v));
+                        let frame = frames.pop().unwrap();
+                        used -= self.frame_words(frame.func);
+                        match frames.last_mut() {
+                            Some(caller) => {
+                                if let (Some(t), Some(v)) = (frame.dst, v) {
+                                    caller.temps[t] = v;
+                                }
+                            },
+                            None => return Ok(()),
+                        }
+                    },
+                    Terminator::Unreachable => unreachable!("reached the end of {}", self.module.funcs[frame.func].name),
+                }
+                continue;
+            }
+            let inst = &block.insts[frame.pc];
+            frames[top].pc += 1;
+            match inst {
+                Inst::Copy { dst, src } => {
+                    let v = value(&frames[top], *src);
+                    frames[top].temps[*dst] = v;
+                },
+                Inst::Unary { dst, op, src } => {
+                    let v = unary(*op, value(&frames[top], *src));
+                    frames[top].temps[*dst] = v;
+                },
+                Inst::Binary { dst, op, lhs, rhs } => {
+                    let v = binary(*op, value(&frames[top], *lhs), value(&frames[top], *rhs))?;
+                    frames[top].temps[*dst] = v;
+                },
+                Inst::Load { dst, var } => {
+                    let v = *self.slot(&mut frames, *var, 0);
+                    frames[top].temps[*dst] = v;
+                },
+                Inst::Store { var, src } => {
+                    let v = value(&frames[top], *src);
+                    *self.slot(&mut frames, *var, 0) = v;
+                },
+                Inst::LoadElement { dst, array, index } => {
+                    let index = self.check_index(*array, value(&frames[top], *index))?;
+                    let v = *self.slot(&mut frames, *array, index);
+                    frames[top].temps[*dst] = v;
+                },
+                Inst::StoreElement { array, index, src } => {
+                    let index = self.check_index(*array, value(&frames[top], *index))?;
+                    let v = value(&frames[top], *src);
+                    *self.slot(&mut frames, *array, index) = v;
+                },
+                Inst::Call { dst, func, args } => {
+                    let args = args.iter().map(|a| value(&frames[top], *a)).collect();
+                    // the callee links to the frame of the function declaring it
+                    let parent = self.module.funcs[*func].parent.unwrap();
+                    let link = frame_of(&frames, parent);
+                    self.push_frame(&mut frames, &mut used, *func, Some(link), args, *dst)?;
+                },
+                Inst::Read { dst } => {
+                    // let the prompt of a preceding `write` show up first
+                    out.flush().map_err(RuntimeError::CannotWrite)?;
+                    frames[top].temps[*dst] = input.read_int()?;
+                },
+                Inst::Write { src } => {
+                    write!(out, "{} ", value(&frames[top], *src)).map_err(RuntimeError::CannotWrite)?;
+                },
+                Inst::WriteString(s) => {
+                    write!(out, "{}", self.module.strings[*s]).map_err(RuntimeError::CannotWrite)?;
+                },
+                Inst::WriteLn => {
+                    writeln!(out).map_err(RuntimeError::CannotWrite)?;
+                },
+                Inst::Phi { .. } => unreachable!("phi after other instructions"),
+            }
+        }
+    }
+
+    /// Goes on to the block `b`, taking the values of its phis from the
+    /// current one, all at once.
+    fn enter(&self, frame: &mut Frame, b: BlockId) {
+        let insts = &self.module.funcs[frame.func].blocks[b].insts;
+        let values: Vec<(Temp, i32)> = insts.iter()
+            .map_while(|inst| match inst {
+                Inst::Phi { dst, args } => {
+                    let (_, v) = args.iter().find(|(p, _)| *p == frame.block).expect("phi without an operand");
+                    Some((*dst, value(frame, *v)))
+                },
+                _ => None,
+            })
+            .collect();
+        frame.pc = values.len();
+        for (t, v) in values {
+            frame.temps[t] = v;
+        }
+        frame.block = b;
+    }
+
+    fn push_frame(&self, frames: &mut Vec<Frame>, used: &mut usize, func: FuncId, link: Option<usize>, args: Vec<i32>, dst: Option<Temp>) -> Result<(), RuntimeError> {
+        *used += self.frame_words(func);
+        if *used > self.stack_size {
+            return Err(RuntimeError::StackOverflow);
+        }
+        let f = &self.module.funcs[func];
+        let mut vars = vec![0; self.layouts[func].1];
+        for (i, a) in args.into_iter().enumerate() {
+            vars[self.layouts[func].0[i]] = a;
+        }
+        frames.push(Frame {
+            func,
+            link,
+            vars,
+            temps: vec![0; f.temps],
+            block: 0,
+            pc: 0,
+            dst,
+        });
+        Ok(())
+    }
+
+    /// Words the stack machine would take for a frame of `func`: the saved
+    /// display entry, the return address and the variables.
+    fn frame_words(&self, func: FuncId) -> usize {
+        2 + self.layouts[func].1
+    }
+
+    fn check_index(&self, array: VarRef, index: i32) -> Result<usize, RuntimeError> {
+        let size = self.module.funcs[array.func].vars[array.index].size.unwrap();
+        if index < 0 || index >= size {
+            return Err(RuntimeError::IndexOutOfBounds { index, size });
+        }
+        Ok(index as usize)
+    }
+
+    /// Element `index` of the variable or array `var`, in the innermost
+    /// frame of its function that the current one reaches.
+    fn slot<'f>(&self, frames: &'f mut [Frame], var: VarRef, index: usize) -> &'f mut i32 {
+        let f = frame_of(frames, var.func);
+        &mut frames[f].vars[self.layouts[var.func].0[var.index] + index]
+    }
+}
+
+/// Follows the static links from the current frame up to the one of `func`.
+fn frame_of(frames: &[Frame], func: FuncId) -> usize {
+    let mut f = frames.len() - 1;
+    while frames[f].func != func {
+        f = frames[f].link.expect("function out of scope");
+    }
+    f
+}
+
+fn value(frame: &Frame, v: Operand) -> i32 {
+    match v {
+        Operand::Temp(t) => frame.temps[t],
+        Operand::Const(c) => c,
+    }
+}
+
+/// Value of a unary operation, as on the stack machine.
+pub fn unary(op: UnaryOp, v: i32) -> i32 {
+    match op {
+        UnaryOp::Neg => v.wrapping_neg(),
+        UnaryOp::Abs => v.wrapping_abs(),
+        UnaryOp::Odd => v & 1,
+    }
+}
+
+/// Value of a binary operation, as on the stack machine.
+pub fn binary(op: BinaryOp, l: i32, r: i32) -> Result<i32, RuntimeError> {
+    Ok(match op {
+        BinaryOp::Add => l.wrapping_add(r),
+        BinaryOp::Sub => l.wrapping_sub(r),
+        BinaryOp::Mul => l.wrapping_mul(r),
+        BinaryOp::Div | BinaryOp::Mod if r == 0 => {
+            return Err(RuntimeError::DivisionByZero);
+        },
+        BinaryOp::Div => l.wrapping_div(r),
+        BinaryOp::Mod => l.wrapping_rem(r),
+        BinaryOp::Min => l.min(r),
+        BinaryOp::Max => l.max(r),
+        BinaryOp::Eq  => (l == r) as i32,
+        BinaryOp::Ne  => (l != r) as i32,
+        BinaryOp::Lt  => (l < r) as i32,
+        BinaryOp::Le  => (l <= r) as i32,
+        BinaryOp::Gt  => (l > r) as i32,
+        BinaryOp::Ge  => (l >= r) as i32,
+    })
+}
@@ -0,0 +1,823 @@
+// Three-address intermediate representation, lowered from the syntax tree by
+// `lower`: every function, procedure and the main block becomes a control
+// flow graph of basic blocks, whose instructions compute into temporaries
+// from temporaries and constants and end in a single jump, branch or return.
+//
+// Variables stay in the frame of the block that declares them and are only
+// reached through `load` and `store`, which keeps nested functions and their
+// static links out of the way of the passes working on temporaries. A module
+// dumps as text:
+//
+//     function f(a) in main
+//     L0:
+//       t0 = a
+//       t1 = t0 < 10
+//       if t1 goto L1 else L2
+//     L1:
+//       t2 = call g(t0, 1)
+//       ret t2
+//     ...
+
+use std::fmt;
+use crate::tokenizer::escape_string;
+
+/// A temporary of a function, `t{n}` in the dump.
+pub type Temp = usize;
+
+/// A basic block of a function, `L{n}` in the dump; the first one is entered
+/// on calls.
+pub type BlockId = usize;
+
+/// A function of a module; the main block is the first one.
+pub type FuncId = usize;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Operand {
+    Temp(Temp),
+    Const(i32),
+}
+
+/// A variable or array in the frame of the function `func`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct VarRef {
+    pub func: FuncId,
+    pub index: usize,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum UnaryOp {
+    Neg,
+    Abs,
+    /// 1 if the operand is odd, 0 otherwise
+    Odd,
+}
+
+/// Binary operators, with the wrapping arithmetic of the stack machine.
+/// Comparisons give 1 or 0.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    /// fails on division by zero
+    Div,
+    /// fails on division by zero
+    Mod,
+    Min,
+    Max,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Inst {
+    Copy {
+        dst: Temp,
+        src: Operand,
+    },
+    Unary {
+        dst: Temp,
+        op: UnaryOp,
+        src: Operand,
+    },
+    Binary {
+        dst: Temp,
+        op: BinaryOp,
+        lhs: Operand,
+        rhs: Operand,
+    },
+    Load {
+        dst: Temp,
+        var: VarRef,
+    },
+    Store {
+        var: VarRef,
+        src: Operand,
+    },
+    /// loads an element of an array, failing if `index` is out of bounds
+    LoadElement {
+        dst: Temp,
+        array: VarRef,
+        index: Operand,
+    },
+    /// stores into an element of an array, failing if `index` is out of
+    /// bounds
+    StoreElement {
+        array: VarRef,
+        index: Operand,
+        src: Operand,
+    },
+    /// calls a function, keeping its value in `dst`, or a procedure
+    Call {
+        dst: Option<Temp>,
+        func: FuncId,
+        args: Vec<Operand>,
+    },
+    Read {
+        dst: Temp,
+    },
+    Write {
+        src: Operand,
+    },
+    /// writes a string of the module
+    WriteString(usize),
+    WriteLn,
+    /// in SSA form, the operand coming from the block control came from; phis
+    /// come first in their block, with an operand for every predecessor
+    Phi {
+        dst: Temp,
+        args: Vec<(BlockId, Operand)>,
+    },
+}
+
+impl Inst {
+    /// The temporary the instruction assigns, if any.
+    pub fn def(&self) -> Option<Temp> {
+        match self {
+            Inst::Copy { dst, .. }
+            | Inst::Unary { dst, .. }
+            | Inst::Binary { dst, .. }
+            | Inst::Load { dst, .. }
+            | Inst::LoadElement { dst, .. }
+            | Inst::Read { dst }
+            | Inst::Phi { dst, .. } => Some(*dst),
+            Inst::Call { dst, .. } => *dst,
+            _ => None,
+        }
+    }
+
+    /// The temporary the instruction assigns, to be renamed.
+    pub fn def_mut(&mut self) -> Option<&mut Temp> {
+        match self {
+            Inst::Copy { dst, .. }
+            | Inst::Unary { dst, .. }
+            | Inst::Binary { dst, .. }
+            | Inst::Load { dst, .. }
+            | Inst::LoadElement { dst, .. }
+            | Inst::Read { dst }
+            | Inst::Phi { dst, .. } => Some(dst),
+            Inst::Call { dst, .. } => dst.as_mut(),
+            _ => None,
+        }
+    }
+
+    /// The operands the instruction reads, in order.
+    pub fn uses(&self) -> Vec<Operand> {
+        match self {
+            Inst::Copy { src, .. } | Inst::Unary { src, .. } | Inst::Store { src, .. } | Inst::Write { src } => vec![*src],
+            Inst::Binary { lhs, rhs, .. } => vec![*lhs, *rhs],
+            Inst::LoadElement { index, .. } => vec![*index],
+            Inst::StoreElement { index, src, .. } => vec![*index, *src],
+            Inst::Call { args, .. } => args.clone(),
+            Inst::Phi { args, .. } => args.iter().map(|(_, v)| *v).collect(),
+            Inst::Load { .. } | Inst::Read { .. } | Inst::WriteString(_) | Inst::WriteLn => vec![],
+        }
+    }
+
+    /// The operands the instruction reads, to be rewritten.
+    pub fn uses_mut(&mut self) -> Vec<&mut Operand> {
+        match self {
+            Inst::Copy { src, .. } | Inst::Unary { src, .. } | Inst::Store { src, .. } | Inst::Write { src } => vec![src],
+            Inst::Binary { lhs, rhs, .. } => vec![lhs, rhs],
+            Inst::LoadElement { index, .. } => vec![index],
+            Inst::StoreElement { index, src, .. } => vec![index, src],
+            Inst::Call { args, .. } => args.iter_mut().collect(),
+            Inst::Phi { args, .. } => args.iter_mut().map(|(_, v)| v).collect(),
+            Inst::Load { .. } | Inst::Read { .. } | Inst::WriteString(_) | Inst::WriteLn => vec![],
+        }
+    }
+}
+
+/// The instruction ending a basic block.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Terminator {
+    Jump(BlockId),
+    /// goes to `then_block` if `cond` is not 0, to `else_block` otherwise
+    Branch {
+        cond: Operand,
+        then_block: BlockId,
+        else_block: BlockId,
+    },
+    /// returns from the function with a value, from a procedure or the main
+    /// block without one
+    Return(Option<Operand>),
+    /// ends a block control never reaches the end of, such as the last one
+    /// of a function
+    Unreachable,
+}
+
+impl Terminator {
+    pub fn successors(&self) -> Vec<BlockId> {
+        match self {
+            Terminator::Jump(b) => vec![*b],
+            Terminator::Branch { then_block, else_block, .. } => vec![*then_block, *else_block],
+            Terminator::Return(_) | Terminator::Unreachable => vec![],
+        }
+    }
+
+    pub fn successors_mut(&mut self) -> Vec<&mut BlockId> {
+        match self {
+            Terminator::Jump(b) => vec![b],
+            Terminator::Branch { then_block, else_block, .. } => vec![then_block, else_block],
+            Terminator::Return(_) | Terminator::Unreachable => vec![],
+        }
+    }
+
+    pub fn uses(&self) -> Vec<Operand> {
+        match self {
+            Terminator::Branch { cond, .. } => vec![*cond],
+            Terminator::Return(Some(v)) => vec![*v],
+            _ => vec![],
+        }
+    }
+
+    pub fn uses_mut(&mut self) -> Vec<&mut Operand> {
+        match self {
+            Terminator::Branch { cond, .. } => vec![cond],
+            Terminator::Return(Some(v)) => vec![v],
+            _ => vec![],
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Block {
+    pub insts: Vec<Inst>,
+    pub term: Terminator,
+}
+
+impl Block {
+    pub fn new() -> Self {
+        Block {
+            insts: vec![],
+            term: Terminator::Unreachable,
+        }
+    }
+}
+
+impl Default for Block {
+    fn default() -> Self {
+        Block::new()
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FuncKind {
+    Main,
+    Proc,
+    Func,
+}
+
+/// A variable of a frame, the parameters first.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Var {
+    pub name: String,
+    /// number of elements of an array, none for a variable or parameter
+    pub size: Option<i32>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Function {
+    pub name: String,
+    pub kind: FuncKind,
+    /// function whose body declares this one, none for the main block
+    pub parent: Option<FuncId>,
+    /// level of the body block, 0 for the main block
+    pub level: usize,
+    pub pars: usize,
+    /// variables and arrays of the frame; variables start out as 0
+    pub vars: Vec<Var>,
+    pub blocks: Vec<Block>,
+    /// number of temporaries, `t0` to `t{temps - 1}`
+    pub temps: usize,
+}
+
+impl Function {
+    /// The blocks jumping to each block, each once.
+    pub fn predecessors(&self) -> Vec<Vec<BlockId>> {
+        let mut preds: Vec<Vec<BlockId>> = vec![vec![]; self.blocks.len()];
+        for (b, block) in self.blocks.iter().enumerate() {
+            for s in block.term.successors() {
+                if preds[s].last() != Some(&b) {
+                    preds[s].push(b);
+                }
+            }
+        }
+        preds
+    }
+
+    /// The blocks reachable from the entry, in reverse postorder: every block
+    /// comes before its successors, but for back edges.
+    pub fn reverse_postorder(&self) -> Vec<BlockId> {
+        let mut visited = vec![false; self.blocks.len()];
+        let mut order = vec![];
+        // (block, successors visited so far)
+        let mut stack = vec![(0, 0)];
+        visited[0] = true;
+        while let Some(&mut (b, ref mut next)) = stack.last_mut() {
+            let succs = self.blocks[b].term.successors();
+            if *next < succs.len() {
+                let s = succs[*next];
+                *next += 1;
+                if !visited[s] {
+                    visited[s] = true;
+                    stack.push((s, 0));
+                }
+            } else {
+                order.push(b);
+                stack.pop();
+            }
+        }
+        order.reverse();
+        order
+    }
+
+    /// Drops the blocks control cannot reach, renumbering the others in their
+    /// order.
+    pub fn remove_unreachable_blocks(&mut self) {
+        let mut reachable = vec![false; self.blocks.len()];
+        for b in self.reverse_postorder() {
+            reachable[b] = true;
+        }
+        let mut renumbered = vec![0; self.blocks.len()];
+        let mut n = 0;
+        for (b, r) in reachable.iter().enumerate() {
+            if *r {
+                renumbered[b] = n;
+                n += 1;
+            }
+        }
+        let blocks = std::mem::take(&mut self.blocks);
+        for (b, mut block) in blocks.into_iter().enumerate() {
+            if reachable[b] {
+                for s in block.term.successors_mut() {
+                    *s = renumbered[*s];
+                }
+                for inst in &mut block.insts {
+                    if let Inst::Phi { args, .. } = inst {
+                        args.retain(|(p, _)| reachable[*p]);
+                        for (p, _) in args {
+                            *p = renumbered[*p];
+                        }
+                    }
+                }
+                self.blocks.push(block);
+            }
+        }
+    }
+
+    /// Allocates a fresh temporary.
+    pub fn new_temp(&mut self) -> Temp {
+        self.temps += 1;
+        self.temps - 1
+    }
+}
+
+/// A whole program: its functions, the main block first, and the strings it
+/// writes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Module {
+    pub funcs: Vec<Function>,
+    pub strings: Vec<String>,
+}
+
+/// Something wrong with a module, found by `Module::verify`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IrError {
+    pub func: String,
+    pub block: Option<BlockId>,
+    pub message: String,
+}
+
+impl fmt::Display for IrError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.block {
+            Some(b) => write!(f, "{}, L{}: {}", self.func, b, self.message),
+            None => write!(f, "{}: {}", self.func, self.message),
+        }
+    }
+}
+
+impl Module {
+    /// Whether `inner` is `outer` or declared inside it, so that its code may
+    /// reach the frame of `outer`.
+    pub fn encloses(&self, outer: FuncId, inner: FuncId) -> bool {
+        let mut f = Some(inner);
+        while let Some(g) = f {
+            if g == outer {
+                return true;
+            }
+            f = self.funcs[g].parent;
+        }
+        false
+    }
+
+    /// Checks that the module is well formed: every jump goes to a block of
+    /// its function, variables and functions are in scope where they are
+    /// used, calls pass as many arguments as the function has parameters,
+    /// returns agree with the kind of function, and every temporary is
+    /// assigned on every path to where it is read. Returns every problem
+    /// found.
+    pub fn verify(&self) -> Result<(), Vec<IrError>> {
+        let mut errors = vec![];
+        if self.funcs.first().map(|f| f.kind) != Some(FuncKind::Main) {
+            errors.push(IrError { func: "module".to_string(), block: None, message: "the first function is not the main block".to_string() });
+        }
+        for id in 0..self.funcs.len() {
+            self.verify_func(id, &mut errors);
+        }
+        if errors.is_empty() { Ok(()) } else { Err(errors) }
+    }
+
+    fn verify_func(&self, id: FuncId, errors: &mut Vec<IrError>) {
+        let func = &self.funcs[id];
+        let error = |errors: &mut Vec<IrError>, block: Option<BlockId>, message: String| {
+            errors.push(IrError { func: func.name.clone(), block, message });
+        };
+        if func.blocks.is_empty() {
+            return error(errors, None, "no entry block".to_string());
+        }
+        if (func.kind == FuncKind::Main) != func.parent.is_none() || func.parent.is_some_and(|p| p >= self.funcs.len()) {
+            return error(errors, None, "bad enclosing function".to_string());
+        }
+        if func.pars > func.vars.len() || func.vars[..func.pars].iter().any(|v| v.size.is_some()) {
+            error(errors, None, "parameters are not scalar variables".to_string());
+        }
+        let mut targets_ok = true;
+        for (b, block) in func.blocks.iter().enumerate() {
+            for s in block.term.successors() {
+                if s >= func.blocks.len() {
+                    error(errors, Some(b), format!("jump to undefined block L{}", s));
+                    targets_ok = false;
+                }
+            }
+        }
+        if !targets_ok {
+            return;
+        }
+        let preds = func.predecessors();
+        if !preds[0].is_empty() {
+            error(errors, Some(0), "jump to the entry block".to_string());
+        }
+        for (b, block) in func.blocks.iter().enumerate() {
+            let check_operands = |errors: &mut Vec<IrError>, operands: Vec<Operand>| {
+                for v in operands {
+                    if let Operand::Temp(t) = v {
+                        if t >= func.temps {
+                            error(errors, Some(b), format!("undefined temporary t{}", t));
+                        }
+                    }
+                }
+            };
+            let phis = block.insts.iter().take_while(|inst| matches!(inst, Inst::Phi { .. })).count();
+            if block.insts[phis..].iter().any(|inst| matches!(inst, Inst::Phi { .. })) {
+                error(errors, Some(b), "phi after other instructions".to_string());
+            }
+            for inst in &block.insts {
+                if let Inst::Phi { args, .. } = inst {
+                    let mut from: Vec<BlockId> = args.iter().map(|(p, _)| *p).collect();
+                    from.sort();
+                    if from != preds[b] {
+                        error(errors, Some(b), "phi operands do not match the predecessors".to_string());
+                    }
+                }
+                check_operands(errors, inst.uses());
+                if let Some(t) = inst.def() {
+                    if t >= func.temps {
+                        error(errors, Some(b), format!("undefined temporary t{}", t));
+                    }
+                }
+                match inst {
+                    Inst::Load { var, .. } | Inst::Store { var, .. } => {
+                        if let Err(message) = self.check_var(id, *var, false) {
+                            error(errors, Some(b), message);
+                        }
+                    },
+                    Inst::LoadElement { array, .. } | Inst::StoreElement { array, .. } => {
+                        if let Err(message) = self.check_var(id, *array, true) {
+                            error(errors, Some(b), message);
+                        }
+                    },
+                    Inst::Call { dst, func: callee, args } => {
+                        match self.funcs.get(*callee) {
+                            Some(g) if g.kind != FuncKind::Main && self.encloses(g.parent.unwrap(), id) => {
+                                if g.pars != args.len() {
+                                    error(errors, Some(b), format!("`{}` takes {} argument{} but {} {} given",
+                                        g.name, g.pars, if g.pars == 1 { "" } else { "s" },
+                                        args.len(), if args.len() == 1 { "was" } else { "were" }));
+                                }
+                                if dst.is_some() && g.kind == FuncKind::Proc {
+                                    error(errors, Some(b), format!("procedure `{}` has no value", g.name));
+                                }
+                            },
+                            _ => error(errors, Some(b), format!("call of a function out of scope: {}", callee)),
+                        }
+                    },
+                    Inst::WriteString(s) if *s >= self.strings.len() => {
+                        error(errors, Some(b), format!("undefined string s{}", s));
+                    },
+                    _ => (),
+                }
+            }
+            check_operands(errors, block.term.uses());
+            match (&block.term, func.kind) {
+                (Terminator::Return(None), FuncKind::Func) => {
+                    error(errors, Some(b), "function returns no value".to_string());
+                },
+                (Terminator::Return(Some(_)), FuncKind::Main | FuncKind::Proc) => {
+                    error(errors, Some(b), "value returned from a procedure".to_string());
+                },
+                _ => (),
+            }
+        }
+        if errors.is_empty() {
+            self.verify_assignments(func, errors);
+        }
+    }
+
+    fn check_var(&self, id: FuncId, var: VarRef, array: bool) -> Result<(), String> {
+        let v = self.funcs.get(var.func)
+            .filter(|_| self.encloses(var.func, id))
+            .and_then(|f| f.vars.get(var.index))
+            .ok_or_else(|| format!("variable out of scope: {}.{}", var.func, var.index))?;
+        match (v.size.is_some(), array) {
+            (true, false) => Err(format!("array `{}` used as a variable", v.name)),
+            (false, true) => Err(format!("variable `{}` used as an array", v.name)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks that every temporary is assigned on every path from the entry
+    /// to where it is read.
+    fn verify_assignments(&self, func: &Function, errors: &mut Vec<IrError>) {
+        let preds = func.predecessors();
+        let order = func.reverse_postorder();
+        // temporaries surely assigned at the start of each block, none for
+        // the blocks not reached yet
+        let mut assigned: Vec<Option<Vec<bool>>> = vec![None; func.blocks.len()];
+        let out = |b: BlockId, start: &[bool]| {
+            let mut out = start.to_vec();
+            for inst in &func.blocks[b].insts {
+                if let Some(t) = inst.def() {
+                    out[t] = true;
+                }
+            }
+            out
+        };
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &b in &order {
+                let start = if b == 0 {
+                    Some(vec![false; func.temps])
+                } else {
+                    preds[b].iter()
+                        .filter_map(|&p| assigned[p].as_ref().map(|a| out(p, a)))
+                        .reduce(|a, o| a.iter().zip(o).map(|(x, y)| *x && y).collect())
+                };
+                if start.is_some() && start != assigned[b] {
+                    assigned[b] = start;
+                    changed = true;
+                }
+            }
+        }
+        let unassigned = |errors: &mut Vec<IrError>, b: BlockId, t: Temp| {
+            errors.push(IrError {
+                func: func.name.clone(),
+                block: Some(b),
+                message: format!("t{} may be read before it is assigned", t),
+            });
+        };
+        for &b in &order {
+            let mut defined = assigned[b].clone().unwrap();
+            let block = &func.blocks[b];
+            for inst in &block.insts {
+                if let Inst::Phi { dst, args } = inst {
+                    // the operands are read on the way from their block
+                    for (p, v) in args {
+                        if let (Operand::Temp(t), Some(a)) = (v, &assigned[*p]) {
+                            if !out(*p, a)[*t] {
+                                unassigned(errors, b, *t);
+                            }
+                        }
+                    }
+                    defined[*dst] = true;
+                    continue;
+                }
+                for v in inst.uses() {
+                    if let Operand::Temp(t) = v {
+                        if !defined[t] {
+                            unassigned(errors, b, t);
+                            defined[t] = true;
+                        }
+                    }
+                }
+                if let Some(t) = inst.def() {
+                    defined[t] = true;
+                }
+            }
+            for v in block.term.uses() {
+                if let Operand::Temp(t) = v {
+                    if !defined[t] {
+                        unassigned(errors, b, t);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Spelling of `var` in the code of the function `id`: names of outer
+    /// frames are qualified with the name of their function.
+    fn var_name(&self, id: FuncId, var: VarRef) -> String {
+        let name = &self.funcs[var.func].vars[var.index].name;
+        if var.func == id {
+            name.clone()
+        } else {
+            format!("{}.{}", self.funcs[var.func].name, name)
+        }
+    }
+
+    fn write_inst(&self, f: &mut fmt::Formatter, id: FuncId, inst: &Inst) -> fmt::Result {
+        match inst {
+            Inst::Copy { dst, src } => write!(f, "t{} = {}", dst, src),
+            Inst::Unary { dst, op, src } => match op {
+                UnaryOp::Neg => write!(f, "t{} = -{}", dst, src),
+                UnaryOp::Abs => write!(f, "t{} = abs({})", dst, src),
+                UnaryOp::Odd => write!(f, "t{} = odd {}", dst, src),
+            },
+            Inst::Binary { dst, op: op @ (BinaryOp::Min | BinaryOp::Max), lhs, rhs } => {
+                write!(f, "t{} = {}({}, {})", dst, op, lhs, rhs)
+            },
+            Inst::Binary { dst, op, lhs, rhs } => write!(f, "t{} = {} {} {}", dst, lhs, op, rhs),
+            Inst::Load { dst, var } => write!(f, "t{} = {}", dst, self.var_name(id, *var)),
+            Inst::Store { var, src } => write!(f, "{} = {}", self.var_name(id, *var), src),
+            Inst::LoadElement { dst, array, index } => {
+                write!(f, "t{} = {}[{}]", dst, self.var_name(id, *array), index)
+            },
+            Inst::StoreElement { array, index, src } => {
+                write!(f, "{}[{}] = {}", self.var_name(id, *array), index, src)
+            },
+            Inst::Call { dst, func, args } => {
+                if let Some(t) = dst {
+                    write!(f, "t{} = ", t)?;
+                }
+                let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+                write!(f, "call {}({})", self.funcs[*func].name, args.join(", "))
+            },
+            Inst::Read { dst } => write!(f, "t{} = read", dst),
+            Inst::Write { src } => write!(f, "write {}", src),
+            Inst::WriteString(s) => write!(f, "write \"{}\"", escape_string(&self.strings[*s])),
+            Inst::WriteLn => write!(f, "writeln"),
+            Inst::Phi { dst, args } => {
+                let args: Vec<String> = args.iter().map(|(b, v)| format!("L{}: {}", b, v)).collect();
+                write!(f, "t{} = phi({})", dst, args.join(", "))
+            },
+        }
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operand::Temp(t) => write!(f, "t{}", t),
+            Operand::Const(v) => write!(f, "{}", v),
+        }
+    }
+}
+
+impl fmt::Display for BinaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Mod => "mod",
+            BinaryOp::Min => "min",
+            BinaryOp::Max => "max",
+            BinaryOp::Eq  => "=",
+            BinaryOp::Ne  => "<>",
+            BinaryOp::Lt  => "<",
+            BinaryOp::Le  => "<=",
+            BinaryOp::Gt  => ">",
+            BinaryOp::Ge  => ">=",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl fmt::Display for Module {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (id, func) in self.funcs.iter().enumerate() {
+            if id > 0 {
+                writeln!(f)?;
+            }
+            let pars: Vec<&str> = func.vars[..func.pars].iter().map(|v| v.name.as_str()).collect();
+            match (func.kind, func.parent) {
+                (FuncKind::Main, _) | (_, None) => writeln!(f, "main")?,
+                (kind, Some(parent)) => {
+                    let kind = if kind == FuncKind::Proc { "procedure" } else { "function" };
+                    writeln!(f, "{} {}({}) in {}", kind, func.name, pars.join(", "), self.funcs[parent].name)?;
+                },
+            }
+            let vars: Vec<String> = func.vars[func.pars..]
+                .iter()
+                .map(|v| match v.size {
+                    Some(size) => format!("{}[{}]", v.name, size),
+                    None => v.name.clone(),
+                })
+                .collect();
+            if !vars.is_empty() {
+                writeln!(f, "  var {}", vars.join(", "))?;
+            }
+            for (b, block) in func.blocks.iter().enumerate() {
+                writeln!(f, "L{}:", b)?;
+                for inst in &block.insts {
+                    write!(f, "  ")?;
+                    self.write_inst(f, id, inst)?;
+                    writeln!(f)?;
+                }
+                match &block.term {
+                    Terminator::Jump(b) => writeln!(f, "  goto L{}", b)?,
+                    Terminator::Branch { cond, then_block, else_block } => {
+                        writeln!(f, "  if {} goto L{} else L{}", cond, then_block, else_block)?
+                    },
+                    Terminator::Return(Some(v)) => writeln!(f, "  ret {}", v)?,
+                    Terminator::Return(None) => writeln!(f, "  ret")?,
+                    Terminator::Unreachable => writeln!(f, "  unreachable")?,
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokenizer::TokenizerOptions;
+    use crate::compile::*;
+
+    fn generate(src: &'static str) -> Module {
+        lower(src.as_bytes(), TokenizerOptions::default()).unwrap()
+    }
+
+    fn messages(module: &Module) -> Vec<String> {
+        module.verify().unwrap_err().iter().map(|e| e.to_string()).collect()
+    }
+
+    #[test]
+    fn test_verify() {
+        let module = generate("function f(a) return a; var x; begin x := f(1); write x end.");
+        assert_eq!(module.verify(), Ok(()));
+
+        let mut m = module.clone();
+        m.funcs[0].blocks[0].term = Terminator::Jump(5);
+        m.funcs[1].blocks[0].term = Terminator::Return(None);
+        assert_eq!(messages(&m), ["main, L0: jump to undefined block L5", "f, L0: function returns no value"]);
+
+        let mut m = module.clone();
+        m.funcs[0].blocks[0].term = Terminator::Jump(0);
+        m.funcs[0].blocks[0].insts.push(Inst::Phi { dst: 0, args: vec![] });
+        assert_eq!(messages(&m), [
+            "main, L0: jump to the entry block",
+            "main, L0: phi after other instructions",
+            "main, L0: phi operands do not match the predecessors",
+        ]);
+
+        let mut m = module.clone();
+        m.funcs[1].blocks[0].insts.insert(0, Inst::Write { src: Operand::Temp(0) });
+        assert_eq!(messages(&m), ["f, L0: t0 may be read before it is assigned"]);
+
+        let mut m = module.clone();
+        m.funcs[0].blocks[0].insts[0] = Inst::Call { dst: Some(0), func: 1, args: vec![] };
+        m.funcs[0].blocks[0].insts.push(Inst::Load { dst: 0, var: VarRef { func: 1, index: 0 } });
+        assert_eq!(messages(&m), ["main, L0: `f` takes 1 argument but 0 were given", "main, L0: variable out of scope: 1.0"]);
+    }
+
+    #[test]
+    fn test_assigned_on_every_path() {
+        let mut module = generate("var x; begin if x > 0 then x := 1; write x end.");
+        // t3 is assigned when the condition holds only
+        let main = &mut module.funcs[0];
+        assert_eq!(main.blocks.len(), 3);
+        let t = main.new_temp();
+        main.blocks[1].insts.push(Inst::Copy { dst: t, src: Operand::Const(1) });
+        main.blocks[1].insts.push(Inst::Write { src: Operand::Temp(t) });
+        main.blocks[2].insts.insert(0, Inst::Write { src: Operand::Temp(1) });
+        main.blocks[2].insts.push(Inst::Write { src: Operand::Temp(t) });
+        assert_eq!(messages(&module), ["main, L2: t3 may be read before it is assigned"]);
+    }
+
+    #[test]
+    fn test_reverse_postorder() {
+        let module = generate("var i; begin while i < 3 do i := i + 1; write i end.");
+        let main = &module.funcs[0];
+        assert_eq!(main.reverse_postorder(), [0, 1, 3, 2]);
+        assert_eq!(main.predecessors(), [vec![], vec![0, 2], vec![1], vec![1]]);
+    }
+}
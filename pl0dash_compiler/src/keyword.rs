@@ -16,36 +16,149 @@ pub enum Keyword {
     End,
     If,
     Then,
+    Else,
     While,
     Do,
+    For,
+    To,
+    Downto,
+    Repeat,
+    Until,
+    Break,
+    Continue,
     Ret,
     Func,
+    Proc,
+    Call,
     Var,
     Const,
     Odd,
+    Mod,
+    And,
+    Or,
+    Not,
     Write,
     WriteLn,
+    Read,
+}
+
+impl fmt::Display for Keyword {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let spelling = KEYWORDS
+            .iter()
+            .chain(PROCEDURES.iter())
+            .find(|&&(_, kw)| kw == *self)
+            .map(|&(s, _)| s)
+            .unwrap();
+        write!(f, "{}", spelling)
+    }
+}
+
+/// The spellings of the standard PL/0' reserved words.
+pub const KEYWORDS: [(&str, Keyword); 26] = [
+    ("begin",    Keyword::Begin),
+    ("end",      Keyword::End),
+    ("if",       Keyword::If),
+    ("then",     Keyword::Then),
+    ("else",     Keyword::Else),
+    ("while",    Keyword::While),
+    ("do",       Keyword::Do),
+    ("for",      Keyword::For),
+    ("to",       Keyword::To),
+    ("downto",   Keyword::Downto),
+    ("repeat",   Keyword::Repeat),
+    ("until",    Keyword::Until),
+    ("break",    Keyword::Break),
+    ("continue", Keyword::Continue),
+    ("return",   Keyword::Ret),
+    ("function", Keyword::Func),
+    ("var",      Keyword::Var),
+    ("const",    Keyword::Const),
+    ("odd",      Keyword::Odd),
+    ("mod",      Keyword::Mod),
+    ("and",      Keyword::And),
+    ("or",       Keyword::Or),
+    ("not",      Keyword::Not),
+    ("write",    Keyword::Write),
+    ("writeln",  Keyword::WriteLn),
+    ("read",     Keyword::Read),
+];
+
+/// Procedures and their calls, which PL/0' leaves out of Wirth's PL/0.
+/// Disabling them frees the two words for identifiers.
+pub const PROCEDURES: [(&str, Keyword); 2] = [
+    ("procedure", Keyword::Proc),
+    ("call",      Keyword::Call),
+];
+
+/// Wirth's PL/0 writes `! e` for output and `? x` for input.
+pub const CLASSIC_IO: [(&str, Keyword); 2] = [
+    ("!", Keyword::Write),
+    ("?", Keyword::Read),
+];
+
+/// Spelling to keyword table consulted by the tokenizer. Dialects are
+/// expressed by enabling or disabling groups of spellings such as
+/// `PROCEDURES`, which is on by default, and `CLASSIC_IO`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeywordTable {
+    entries: Vec<(String, Keyword)>,
+}
+
+impl KeywordTable {
+    pub fn new() -> Self {
+        let mut table = KeywordTable {
+            entries: vec![],
+        };
+        table.enable(&KEYWORDS);
+        table.enable(&PROCEDURES);
+        table
+    }
+
+    pub fn insert(&mut self, spelling: &str, kw: Keyword) {
+        self.remove(spelling);
+        self.entries.push((spelling.to_string(), kw));
+    }
+
+    pub fn remove(&mut self, spelling: &str) {
+        self.entries.retain(|(s, _)| s != spelling);
+    }
+
+    pub fn enable(&mut self, variant: &[(&str, Keyword)]) {
+        for &(spelling, kw) in variant {
+            self.insert(spelling, kw);
+        }
+    }
+
+    pub fn disable(&mut self, variant: &[(&str, Keyword)]) {
+        for &(spelling, _) in variant {
+            self.remove(spelling);
+        }
+    }
+
+    pub fn lookup(&self, word: &str) -> Option<Keyword> {
+        self.entries
+            .iter()
+            .find(|(s, _)| s == word)
+            .map(|&(_, kw)| kw)
+    }
+}
+
+impl Default for KeywordTable {
+    fn default() -> Self {
+        KeywordTable::new()
+    }
 }
 
 impl TryFrom<&str> for Keyword {
     type Error = UndefinedKeywordError;
 
     fn try_from(s: &str) -> Result<Self, Self::Error> {
-        match s {
-            "begin"    => Ok(Keyword::Begin),
-            "end"      => Ok(Keyword::End),
-            "if"       => Ok(Keyword::If),
-            "then"     => Ok(Keyword::Then),
-            "while"    => Ok(Keyword::While),
-            "do"       => Ok(Keyword::Do),
-            "return"   => Ok(Keyword::Ret),
-            "function" => Ok(Keyword::Func),
-            "var"      => Ok(Keyword::Var),
-            "const"    => Ok(Keyword::Const),
-            "odd"      => Ok(Keyword::Odd),
-            "write"    => Ok(Keyword::Write),
-            "writeln"  => Ok(Keyword::WriteLn),
-                     _ => Err(UndefinedKeywordError),
-        }
+        KEYWORDS
+            .iter()
+            .chain(PROCEDURES.iter())
+            .find(|&&(spelling, _)| spelling == s)
+            .map(|&(_, kw)| kw)
+            .ok_or(UndefinedKeywordError)
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,26 @@
+pub mod tokenizer;
+pub mod keyword;
+pub mod symbol;
+//pub mod engine;
+//pub mod analyzer;
+pub mod char_class;
+pub mod parser;
+pub mod token_dump;
+pub mod table;
+pub mod semantic;
+pub mod fold;
+pub mod codegen;
+pub mod vm;
+pub mod peephole;
+pub mod ir;
+pub mod lower;
+pub mod interp;
+pub mod ssa;
+pub mod opt;
+pub mod backend;
+pub mod x86_64;
+pub mod c;
+pub mod llvm;
+pub mod wat;
+pub mod riscv64;
+pub mod compile;
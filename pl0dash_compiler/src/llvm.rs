@@ -0,0 +1,801 @@
+// LLVM backend: translates a checked syntax tree into textual LLVM IR, to be
+// compiled by `llc` or `clang` and linked with the C library.
+//
+// As in the C backend, the variables of every block live in an environment,
+// here a struct `%envN = type { ptr, [K x i32] }` allocated on entry: a link
+// to the environment of the enclosing block, then a slot per parameter,
+// variable and array element, addressed by relative address. Pointers are
+// opaque, as in LLVM 15 and later.
+
+use std::fmt::Write;
+use crate::tokenizer::*;
+use crate::keyword::*;
+use crate::symbol::*;
+use crate::parser::*;
+use crate::table::*;
+use crate::semantic::self_tail_call;
+use crate::backend::*;
+
+/// Constant strings of the runtime, by name.
+const RUNTIME_STRINGS: [(&str, &str); 8] = [
+    ("fmt_int", "%d "),
+    ("fmt_string", "%s"),
+    ("fmt_word", "%63s"),
+    ("msg_malformed", "runtime error: `read` expected an integer, found `%s`\n"),
+    ("msg_end_of_input", "runtime error: `read` reached the end of the input\n"),
+    ("msg_division_by_zero", "runtime error: division by zero\n"),
+    ("msg_index_out_of_bounds", "runtime error: index %d is out of bounds for an array of size %d\n"),
+    ("msg_stack_overflow", "runtime error: stack overflow\n"),
+];
+
+/// Functions the generated code calls for arithmetic that may fail, input,
+/// output and runtime errors, writing the same messages as the stack machine.
+const RUNTIME: &str = r#"
+@pl0_stack_limit = internal global i64 0
+
+declare i32 @printf(ptr, ...)
+declare i32 @scanf(ptr, ...)
+declare i64 @strtol(ptr, ptr, i32)
+declare i32 @getrlimit(i32, ptr)
+declare i32 @dprintf(i32, ptr, ...)
+declare i32 @putchar(i32)
+declare i32 @fflush(ptr)
+declare void @exit(i32) noreturn
+declare void @llvm.memset.p0.i64(ptr, i8, i64, i1)
+
+define internal void @pl0_fail(ptr %message) noreturn {
+  call i32 @fflush(ptr null)
+  call i32 (i32, ptr, ...) @dprintf(i32 2, ptr %message)
+  call void @exit(i32 1)
+  unreachable
+}
+
+; the stack grows downwards from the environment of the main block, and may
+; take 7/8 of the limit of the process, or %size bytes when it has none
+define internal void @pl0_init_stack(ptr %env, i64 %size) {
+entry:
+  %r = alloca [2 x i64]
+  %sp = ptrtoint ptr %env to i64
+  ; RLIMIT_STACK is 3
+  %got = call i32 @getrlimit(i32 3, ptr %r)
+  %ok = icmp eq i32 %got, 0
+  br i1 %ok, label %limited, label %set
+limited:
+  ; no limit is larger than any address
+  %cur = load i64, ptr %r
+  %below = icmp ult i64 %cur, %sp
+  br i1 %below, label %scale, label %set
+scale:
+  %eighth = lshr i64 %cur, 3
+  %part = sub i64 %cur, %eighth
+  br label %set
+set:
+  %s = phi i64 [ %size, %entry ], [ %size, %limited ], [ %part, %scale ]
+  %limit = sub i64 %sp, %s
+  store i64 %limit, ptr @pl0_stack_limit
+  ret void
+}
+
+define internal void @pl0_check_stack(ptr %env) {
+  %sp = ptrtoint ptr %env to i64
+  %limit = load i64, ptr @pl0_stack_limit
+  %over = icmp ult i64 %sp, %limit
+  br i1 %over, label %overflow, label %ok
+overflow:
+  call void @pl0_fail(ptr @.msg_stack_overflow)
+  unreachable
+ok:
+  ret void
+}
+
+; `sdiv` and `srem` of the smallest integer by -1 are undefined, and wrap
+; around in the stack machine
+define internal i32 @pl0_div(i32 %a, i32 %b) {
+  %zero = icmp eq i32 %b, 0
+  br i1 %zero, label %fail, label %nonzero
+fail:
+  call void @pl0_fail(ptr @.msg_division_by_zero)
+  unreachable
+nonzero:
+  %minus_one = icmp eq i32 %b, -1
+  br i1 %minus_one, label %negate, label %divide
+negate:
+  %n = sub i32 0, %a
+  ret i32 %n
+divide:
+  %q = sdiv i32 %a, %b
+  ret i32 %q
+}
+
+define internal i32 @pl0_mod(i32 %a, i32 %b) {
+  %zero = icmp eq i32 %b, 0
+  br i1 %zero, label %fail, label %nonzero
+fail:
+  call void @pl0_fail(ptr @.msg_division_by_zero)
+  unreachable
+nonzero:
+  %minus_one = icmp eq i32 %b, -1
+  br i1 %minus_one, label %none, label %divide
+none:
+  ret i32 0
+divide:
+  %r = srem i32 %a, %b
+  ret i32 %r
+}
+
+define internal i32 @pl0_index(i32 %index, i32 %size) {
+  %in = icmp ult i32 %index, %size
+  br i1 %in, label %ok, label %fail
+ok:
+  ret i32 %index
+fail:
+  call i32 @fflush(ptr null)
+  call i32 (i32, ptr, ...) @dprintf(i32 2, ptr @.msg_index_out_of_bounds, i32 %index, i32 %size)
+  call void @exit(i32 1)
+  unreachable
+}
+
+define internal void @pl0_write(i32 %v) {
+  call i32 (ptr, ...) @printf(ptr @.fmt_int, i32 %v)
+  ret void
+}
+
+define internal void @pl0_write_string(ptr %s) {
+  call i32 (ptr, ...) @printf(ptr @.fmt_string, ptr %s)
+  ret void
+}
+
+define internal void @pl0_writeln() {
+  call i32 @putchar(i32 10)
+  ret void
+}
+
+; reads the next whitespace separated word of stdin as an integer
+define internal i32 @pl0_read() {
+  %word = alloca [64 x i8]
+  %end = alloca ptr
+  call i32 @fflush(ptr null)
+  %n = call i32 (ptr, ...) @scanf(ptr @.fmt_word, ptr %word)
+  %read = icmp eq i32 %n, 1
+  br i1 %read, label %parse, label %end_of_input
+end_of_input:
+  call void @pl0_fail(ptr @.msg_end_of_input)
+  unreachable
+parse:
+  %v = call i64 @strtol(ptr %word, ptr %end, i32 10)
+  %e = load ptr, ptr %end
+  %empty = icmp eq ptr %e, %word
+  br i1 %empty, label %malformed, label %check
+check:
+  %c = load i8, ptr %e
+  %whole = icmp eq i8 %c, 0
+  br i1 %whole, label %range, label %malformed
+range:
+  ; `strtol` saturates on overflow, which is out of range as well
+  %r = trunc i64 %v to i32
+  %back = sext i32 %r to i64
+  %fits = icmp eq i64 %back, %v
+  br i1 %fits, label %done, label %malformed
+done:
+  ret i32 %r
+malformed:
+  call i32 @fflush(ptr null)
+  call i32 (i32, ptr, ...) @dprintf(i32 2, ptr @.msg_malformed, ptr %word)
+  call void @exit(i32 1)
+  unreachable
+}
+"#;
+
+/// Labels of the current loop that `break` and `continue` jump to.
+struct Loop {
+    break_label: usize,
+    continue_label: usize,
+}
+
+/// Translates a checked syntax tree into LLVM IR. Functions are entered in
+/// the table with the number of their environment as address, the main
+/// block having 0.
+pub struct LlvmGenerator {
+    table: Table,
+    /// type definitions of the environments
+    types: String,
+    /// finished functions
+    funcs: String,
+    /// body of the function being generated
+    text: String,
+    /// whether the last instruction of `text` ends its basic block
+    terminated: bool,
+    /// whether the block being generated calls its function in tail position
+    tail_calls: bool,
+    strings: Vec<String>,
+    loops: Vec<Loop>,
+    envs: usize,
+    temps: usize,
+    labels: usize,
+}
+
+impl LlvmGenerator {
+    pub fn new() -> Self {
+        LlvmGenerator {
+            table: Table::new(),
+            types: String::new(),
+            funcs: String::new(),
+            text: String::new(),
+            terminated: false,
+            tail_calls: false,
+            strings: vec![],
+            loops: vec![],
+            envs: 0,
+            temps: 0,
+            labels: 0,
+        }
+    }
+
+    pub fn generate(mut self, tree: &SyntaxTree) -> String {
+        let program = tree.get_ref_root();
+        self.table.block_begin(FIRST_ADDR);
+        self.gen_block(&program.get_ref_children()[0], None);
+        let mut out = self.types;
+        out += "\n";
+        for (name, s) in RUNTIME_STRINGS.iter() {
+            out += &string_constant(name, s);
+        }
+        for (i, s) in self.strings.iter().enumerate() {
+            out += &string_constant(&format!("s{}", i), s);
+        }
+        out += RUNTIME;
+        out += &self.funcs;
+        out
+    }
+
+    fn gen_block(&mut self, node: &SyntaxNode, func: Option<usize>) {
+        for child in node.get_ref_children() {
+            match child.get_ref_syntax() {
+                Syntax::ConstDecl => enter_consts(&mut self.table, child),
+                Syntax::VarDecl => {
+                    enter_vars(&mut self.table, child);
+                },
+                Syntax::FuncDecl | Syntax::ProcDecl => self.gen_func_decl(child),
+                Syntax::Statement => self.gen_statement(child),
+                _ => unreachable!(),
+            }
+        }
+        // procedures and the main block end without a `return`; the end of
+        // a function cannot be reached
+        let e = func.map(|f| self.table.get(f).clone());
+        if !self.terminated {
+            match &e {
+                Some(e) if e.kind == Kind::Func => self.terminate("unreachable"),
+                Some(_) => self.terminate("ret void"),
+                None => self.terminate("ret i32 0"),
+            }
+        }
+        let body = std::mem::take(&mut self.text);
+        let tail_calls = std::mem::take(&mut self.tail_calls);
+        // the environment is known once the statement had its temporaries
+        let env = self.env(self.table.level());
+        let pars = e.as_ref().map_or(0, |e| e.pars);
+        let slots = pars as i32 + self.table.frame_size() - FIRST_ADDR;
+        writeln!(self.types, "%env{} = type {{ ptr, [{} x i32] }}", env, slots).unwrap();
+
+        let clear = format!(
+            "  %size = ptrtoint ptr getelementptr (%env{0}, ptr null, i32 1) to i64\n  \
+             call void @llvm.memset.p0.i64(ptr %e, i8 0, i64 %size, i1 false)\n", env);
+        match &e {
+            Some(e) => {
+                let result = if e.kind == Kind::Proc { "void" } else { "i32" };
+                let mut params = "ptr %up".to_string();
+                for i in 0..e.pars {
+                    write!(params, ", i32 %a{}", i).unwrap();
+                }
+                writeln!(self.funcs, "\n; {} {}\ndefine internal {} {}({}) {{", kind_name(e), e.name, result, func_name("@", e), params).unwrap();
+                writeln!(self.funcs, "  %e = alloca %env{}", env).unwrap();
+                self.funcs += &clear;
+                self.funcs += "  store ptr %up, ptr %e\n";
+                for i in 0..e.pars {
+                    writeln!(self.funcs, "  %p{i} = getelementptr %env{}, ptr %e, i32 0, i32 1, i32 {i}", env, i = i).unwrap();
+                    writeln!(self.funcs, "  store i32 %a{i}, ptr %p{i}", i = i).unwrap();
+                }
+            },
+            None => {
+                writeln!(self.funcs, "\ndefine i32 @main() {{\n  %e = alloca %env{}", env).unwrap();
+                self.funcs += &clear;
+                writeln!(self.funcs, "  call void @pl0_init_stack(ptr %e, i64 {})", STACK_LIMIT).unwrap();
+            },
+        }
+        self.funcs += "  call void @pl0_check_stack(ptr %e)\n";
+        if tail_calls {
+            self.funcs += "  br label %body\nbody:\n";
+        }
+        self.funcs += &body;
+        self.funcs += "}\n";
+        self.terminated = false;
+        self.table.block_end();
+    }
+
+    fn gen_func_decl(&mut self, node: &SyntaxNode) {
+        self.envs += 1;
+        let id = self.envs as i32;
+        let decl = enter_func(&mut self.table, node, id);
+        self.gen_block(decl.body, Some(decl.func));
+    }
+
+    fn gen_statement(&mut self, node: &SyntaxNode) {
+        let children = node.get_ref_children();
+        let first = match children.first() {
+            Some(first) => first,
+            None => return, // empty statement
+        };
+        match first.get_ref_token().unwrap() {
+            Token::Identifier(name) if children.len() > 1 && children[1].get_ref_token() == Some(&Token::Symbol(Symbol::Lparen)) => {
+                let e = lookup(&self.table, name);
+                self.gen_call(&e, &children[1..]);
+            },
+            Token::Keyword(Keyword::Call) => {
+                let e = lookup(&self.table, children[1].get_ref_identifier().unwrap());
+                self.gen_call(&e, &children[2..]);
+            },
+            Token::Identifier(name) => {
+                let e = lookup(&self.table, name);
+                if e.kind == Kind::Array {
+                    // ident [ index ] := value
+                    let index = self.gen_index(&e, &children[2]);
+                    let value = self.gen_expression(&children[5]);
+                    let p = self.element(&e, &index);
+                    self.inst(&format!("store i32 {}, ptr {}", value, p));
+                } else {
+                    let value = self.gen_expression(&children[2]);
+                    self.store(&e, &value);
+                }
+            },
+            Token::Keyword(Keyword::Begin) => {
+                for child in children {
+                    if let Syntax::Statement = child.get_ref_syntax() {
+                        self.gen_statement(child);
+                    }
+                }
+            },
+            Token::Keyword(Keyword::If) => {
+                let (then_label, next) = (self.new_label(), self.new_label());
+                if children.len() > 4 {
+                    let else_label = self.new_label();
+                    self.gen_condition(&children[1], then_label, else_label);
+                    self.label(then_label);
+                    self.gen_statement(&children[3]);
+                    self.jump(next);
+                    self.label(else_label);
+                    self.gen_statement(&children[5]);
+                } else {
+                    self.gen_condition(&children[1], then_label, next);
+                    self.label(then_label);
+                    self.gen_statement(&children[3]);
+                }
+                self.label(next);
+            },
+            Token::Keyword(Keyword::While) => {
+                let (start, body, next) = (self.new_label(), self.new_label(), self.new_label());
+                self.label(start);
+                self.gen_condition(&children[1], body, next);
+                self.label(body);
+                self.loops.push(Loop { break_label: next, continue_label: start });
+                self.gen_statement(&children[3]);
+                self.loops.pop();
+                self.jump(start);
+                self.label(next);
+            },
+            Token::Keyword(Keyword::For) => {
+                let var = lookup(&self.table, children[1].get_ref_identifier().unwrap());
+                let temp = self.table.enter_temp();
+                let limit = self.table.get(temp).clone();
+                let (cmp, last, step) = match children[4].get_ref_token() {
+                    Some(Token::Keyword(Keyword::To)) => ("sle", "slt", "add"),
+                    _ => ("sge", "sgt", "sub"),
+                };
+                let (start, body, step_label, next) = (self.new_label(), self.new_label(), self.new_label(), self.new_label());
+                let inc = self.new_label();
+                let from = self.gen_expression(&children[3]);
+                self.store(&var, &from);
+                let to = self.gen_expression(&children[5]);
+                self.store(&limit, &to);
+                self.label(start);
+                let v = self.load(&var);
+                let l = self.load(&limit);
+                let c = self.value(&format!("icmp {} i32 {}, {}", cmp, v, l));
+                self.terminate(&format!("br i1 {}, label %L{}, label %L{}", c, body, next));
+                self.label(body);
+                self.loops.push(Loop { break_label: next, continue_label: step_label });
+                self.gen_statement(&children[7]);
+                self.loops.pop();
+                self.label(step_label);
+                let v = self.load(&var);
+                let l = self.load(&limit);
+                let c = self.value(&format!("icmp {} i32 {}, {}", last, v, l));
+                self.terminate(&format!("br i1 {}, label %L{}, label %L{}", c, inc, next));
+                self.label(inc);
+                let v = self.load(&var);
+                let v = self.value(&format!("{} i32 {}, 1", step, v));
+                self.store(&var, &v);
+                self.jump(start);
+                self.label(next);
+            },
+            Token::Keyword(Keyword::Repeat) => {
+                let (start, cond, next) = (self.new_label(), self.new_label(), self.new_label());
+                self.label(start);
+                self.loops.push(Loop { break_label: next, continue_label: cond });
+                for child in children {
+                    if let Syntax::Statement = child.get_ref_syntax() {
+                        self.gen_statement(child);
+                    }
+                }
+                self.loops.pop();
+                self.label(cond);
+                self.gen_condition(children.last().unwrap(), next, start);
+                self.label(next);
+            },
+            Token::Keyword(Keyword::Break) => {
+                let target = self.loops.last().unwrap().break_label;
+                self.jump(target);
+            },
+            Token::Keyword(Keyword::Continue) => {
+                let target = self.loops.last().unwrap().continue_label;
+                self.jump(target);
+            },
+            Token::Keyword(Keyword::Ret) => {
+                if let Some(args) = self_tail_call(&self.table, &children[1]) {
+                    self.gen_tail_call(&args);
+                    return;
+                }
+                let value = self.gen_expression(&children[1]);
+                self.terminate(&format!("ret i32 {}", value));
+            },
+            Token::Keyword(Keyword::Write) => {
+                for arg in &children[1..] {
+                    if let Some(Token::String(s)) = arg.get_ref_token() {
+                        let i = intern(&mut self.strings, s);
+                        self.inst(&format!("call void @pl0_write_string(ptr @.s{})", i));
+                    } else if let Syntax::Expression = arg.get_ref_syntax() {
+                        let value = self.gen_expression(arg);
+                        self.inst(&format!("call void @pl0_write(i32 {})", value));
+                    }
+                }
+            },
+            Token::Keyword(Keyword::WriteLn) => {
+                self.inst("call void @pl0_writeln()");
+            },
+            Token::Keyword(Keyword::Read) => {
+                let e = lookup(&self.table, children[1].get_ref_identifier().unwrap());
+                if e.kind == Kind::Array {
+                    // read ident [ index ]
+                    let index = self.gen_index(&e, &children[3]);
+                    let value = self.value("call i32 @pl0_read()");
+                    let p = self.element(&e, &index);
+                    self.inst(&format!("store i32 {}, ptr {}", value, p));
+                } else {
+                    let value = self.value("call i32 @pl0_read()");
+                    self.store(&e, &value);
+                }
+            },
+            t => unreachable!("statement starting with {:?}", t),
+        }
+    }
+
+    /// Generates a condition that branches to `holds` if it holds and to
+    /// `fails` otherwise. Operands of `and` and `or` are evaluated only as
+    /// far as needed.
+    fn gen_condition(&mut self, node: &SyntaxNode, holds: usize, fails: usize) {
+        let children = node.get_ref_children();
+        match children[0].get_ref_token() {
+            Some(Token::Keyword(Keyword::Not)) => {
+                return self.gen_condition(&children[1], fails, holds);
+            },
+            Some(Token::Keyword(Keyword::Odd)) => {
+                let v = self.gen_expression(&children[1]);
+                let bit = self.value(&format!("and i32 {}, 1", v));
+                let c = self.value(&format!("icmp ne i32 {}, 0", bit));
+                return self.terminate(&format!("br i1 {}, label %L{}, label %L{}", c, holds, fails));
+            },
+            Some(Token::Symbol(Symbol::Lparen)) => {
+                return self.gen_condition(&children[1], holds, fails);
+            },
+            _ => (),
+        }
+        if let Syntax::Condition = children[0].get_ref_syntax() {
+            let is_or = children[1].get_ref_token() == Some(&Token::Keyword(Keyword::Or));
+            let operands: Vec<&SyntaxNode> = children.iter().step_by(2).collect();
+            let (last, init) = operands.split_last().unwrap();
+            for c in init {
+                let next = self.new_label();
+                if is_or {
+                    self.gen_condition(c, holds, next);
+                } else {
+                    self.gen_condition(c, next, fails);
+                }
+                self.label(next);
+            }
+            return self.gen_condition(last, holds, fails);
+        }
+        let op = match children[1].get_ref_token() {
+            Some(Token::Symbol(Symbol::Equal)) => "eq",
+            Some(Token::Symbol(Symbol::Lss))   => "slt",
+            Some(Token::Symbol(Symbol::Gtr))   => "sgt",
+            Some(Token::Symbol(Symbol::NotEq)) => "ne",
+            Some(Token::Symbol(Symbol::LssEq)) => "sle",
+            Some(Token::Symbol(Symbol::GtrEq)) => "sge",
+            t => unreachable!("relational operator {:?}", t),
+        };
+        let l = self.gen_expression(&children[0]);
+        let r = self.gen_expression(&children[2]);
+        let c = self.value(&format!("icmp {} i32 {}, {}", op, l, r));
+        self.terminate(&format!("br i1 {}, label %L{}, label %L{}", c, holds, fails));
+    }
+
+    /// Generates an expression, returning the operand that holds its value.
+    fn gen_expression(&mut self, node: &SyntaxNode) -> String {
+        let children = node.get_ref_children();
+        let mut i = 0;
+        let mut negate = false;
+        if let Some(Token::Symbol(sym)) = children[0].get_ref_token() {
+            negate = *sym == Symbol::Minus;
+            i = 1;
+        }
+        let mut v = self.gen_term(&children[i]);
+        if negate {
+            v = self.value(&format!("sub i32 0, {}", v));
+        }
+        for pair in children[i + 1..].chunks(2) {
+            let op = match pair[0].get_ref_token() {
+                Some(Token::Symbol(Symbol::Plus))  => "add",
+                Some(Token::Symbol(Symbol::Minus)) => "sub",
+                t => unreachable!("additive operator {:?}", t),
+            };
+            let r = self.gen_term(&pair[1]);
+            v = self.value(&format!("{} i32 {}, {}", op, v, r));
+        }
+        v
+    }
+
+    fn gen_term(&mut self, node: &SyntaxNode) -> String {
+        let children = node.get_ref_children();
+        let mut v = self.gen_factor(&children[0]);
+        for pair in children[1..].chunks(2) {
+            let r = self.gen_factor(&pair[1]);
+            v = match pair[0].get_ref_token() {
+                Some(Token::Symbol(Symbol::Mult)) => self.value(&format!("mul i32 {}, {}", v, r)),
+                Some(Token::Symbol(Symbol::Div))  => self.value(&format!("call i32 @pl0_div(i32 {}, i32 {})", v, r)),
+                Some(Token::Symbol(Symbol::Mod)) | Some(Token::Keyword(Keyword::Mod)) => {
+                    self.value(&format!("call i32 @pl0_mod(i32 {}, i32 {})", v, r))
+                },
+                t => unreachable!("multiplicative operator {:?}", t),
+            };
+        }
+        v
+    }
+
+    fn gen_factor(&mut self, node: &SyntaxNode) -> String {
+        let children = node.get_ref_children();
+        match children[0].get_ref_token() {
+            Some(Token::Identifier(name)) => {
+                if let Some(f) = self.table.search_intrinsic(name) {
+                    let x = self.gen_expression(&children[2]);
+                    let (c, a, b) = match f {
+                        Intrinsic::Abs => {
+                            let c = self.value(&format!("icmp slt i32 {}, 0", x));
+                            let n = self.value(&format!("sub i32 0, {}", x));
+                            (c, n, x)
+                        },
+                        Intrinsic::Min | Intrinsic::Max => {
+                            let y = self.gen_expression(&children[4]);
+                            let cmp = if f == Intrinsic::Min { "slt" } else { "sgt" };
+                            (self.value(&format!("icmp {} i32 {}, {}", cmp, x, y)), x, y)
+                        },
+                    };
+                    return self.value(&format!("select i1 {}, i32 {}, i32 {}", c, a, b));
+                }
+                let e = lookup(&self.table, name);
+                match e.kind {
+                    Kind::Var | Kind::Par => self.load(&e),
+                    Kind::Array => {
+                        let index = self.gen_index(&e, &children[2]);
+                        let p = self.element(&e, &index);
+                        self.value(&format!("load i32, ptr {}", p))
+                    },
+                    Kind::Const => e.value.to_string(),
+                    Kind::Func => self.gen_call(&e, &children[1..]),
+                    Kind::Proc => unreachable!("unchecked syntax tree"),
+                }
+            },
+            Some(Token::Number(value)) => value.to_string(),
+            _ => {
+                // ( expression )
+                self.gen_expression(&children[1])
+            },
+        }
+    }
+
+    /// Generates the index of an element of the array `e`, checked to be
+    /// within bounds.
+    fn gen_index(&mut self, e: &Entry, index: &SyntaxNode) -> String {
+        let index = self.gen_expression(index);
+        self.value(&format!("call i32 @pl0_index(i32 {}, i32 {})", index, e.size))
+    }
+
+    /// Calls `e` with the arguments among `args`, returning the operand that
+    /// holds its value; procedures have none.
+    fn gen_call(&mut self, e: &Entry, args: &[SyntaxNode]) -> String {
+        let mut operands = vec![];
+        for arg in args {
+            if let Syntax::Expression = arg.get_ref_syntax() {
+                let v = self.gen_expression(arg);
+                operands.push(format!("i32 {}", v));
+            }
+        }
+        // the callee is declared in the block whose environment it links to
+        let link = self.frame(e.level);
+        operands.insert(0, format!("ptr {}", link));
+        let call = format!("{}({})", func_name("@", e), operands.join(", "));
+        if e.kind == Kind::Proc {
+            self.inst(&format!("call void {}", call));
+            String::new()
+        } else {
+            self.value(&format!("call i32 {}", call))
+        }
+    }
+
+    /// Calls the current function again in its own environment: the
+    /// arguments replace the parameters once all of them are known, the
+    /// locals are zeroed again, and the body starts over.
+    fn gen_tail_call(&mut self, args: &[&SyntaxNode]) {
+        let values: Vec<String> = args.iter().map(|arg| self.gen_expression(arg)).collect();
+        let env = self.env(self.table.level());
+        let size = self.value(&format!("ptrtoint ptr getelementptr (%env{}, ptr null, i32 1) to i64", env));
+        self.inst(&format!("call void @llvm.memset.p0.i64(ptr %e, i8 0, i64 {}, i1 false)", size));
+        self.inst("store ptr %up, ptr %e");
+        let pars = self.table.block_entries()[..args.len()].to_vec();
+        for (par, value) in pars.iter().zip(&values) {
+            self.store(par, value);
+        }
+        self.terminate("br label %body");
+        self.tail_calls = true;
+    }
+
+    /// Follows the links up to the environment of the block at `level`.
+    fn frame(&mut self, level: usize) -> String {
+        let mut frame = "%e".to_string();
+        for _ in level..self.table.level() {
+            frame = self.value(&format!("load ptr, ptr {}", frame));
+        }
+        frame
+    }
+
+    /// Pointer to the slot of the variable or parameter `e`, or of the
+    /// element `index` of the array `e`.
+    fn slot(&mut self, e: &Entry, index: Option<&str>) -> String {
+        let frame = self.frame(e.level);
+        let pars = self.block_func(e.level).map_or(0, |f| f.pars) as i32;
+        let slot = if e.addr < 0 { e.addr + pars } else { pars + e.addr - FIRST_ADDR };
+        let slot = match index {
+            Some(index) => self.value(&format!("add i32 {}, {}", index, slot)),
+            None => slot.to_string(),
+        };
+        let env = self.env(e.level);
+        self.value(&format!("getelementptr %env{}, ptr {}, i32 0, i32 1, i32 {}", env, frame, slot))
+    }
+
+    fn element(&mut self, e: &Entry, index: &str) -> String {
+        self.slot(e, Some(index))
+    }
+
+    fn load(&mut self, e: &Entry) -> String {
+        let p = self.slot(e, None);
+        self.value(&format!("load i32, ptr {}", p))
+    }
+
+    fn store(&mut self, e: &Entry, value: &str) {
+        let p = self.slot(e, None);
+        self.inst(&format!("store i32 {}, ptr {}", value, p));
+    }
+
+    /// The function or procedure whose body is the open block at `level`.
+    fn block_func(&self, level: usize) -> Option<&Entry> {
+        self.table.block_func(level).map(|f| self.table.get(f))
+    }
+
+    /// Number of the environment of the open block at `level`.
+    fn env(&self, level: usize) -> i32 {
+        self.block_func(level).map_or(0, |f| f.addr)
+    }
+
+    fn new_label(&mut self) -> usize {
+        self.labels += 1;
+        self.labels
+    }
+
+    /// Starts the basic block `label`, falling through into it.
+    fn label(&mut self, label: usize) {
+        if !self.terminated {
+            writeln!(self.text, "  br label %L{}", label).unwrap();
+        }
+        writeln!(self.text, "L{}:", label).unwrap();
+        self.terminated = false;
+    }
+
+    fn jump(&mut self, label: usize) {
+        self.terminate(&format!("br label %L{}", label));
+    }
+
+    /// Emits an instruction, in a basic block of its own if the current
+    /// one has ended: the code after a `return` or `break` is unreachable.
+    fn inst(&mut self, inst: &str) {
+        if self.terminated {
+            let label = self.new_label();
+            writeln!(self.text, "L{}:", label).unwrap();
+            self.terminated = false;
+        }
+        writeln!(self.text, "  {}", inst).unwrap();
+    }
+
+    fn terminate(&mut self, inst: &str) {
+        self.inst(inst);
+        self.terminated = true;
+    }
+
+    /// Emits an instruction with a result, returning the temporary that
+    /// holds it.
+    fn value(&mut self, inst: &str) -> String {
+        self.temps += 1;
+        let t = format!("%t{}", self.temps);
+        self.inst(&format!("{} = {}", t, inst));
+        t
+    }
+}
+
+impl Default for LlvmGenerator {
+    fn default() -> Self {
+        LlvmGenerator::new()
+    }
+}
+
+/// A private global holding `s`, null terminated.
+fn string_constant(name: &str, s: &str) -> String {
+    let mut escaped = String::new();
+    for b in s.bytes() {
+        match b {
+            b'"' | b'\\' => write!(escaped, "\\{:02X}", b).unwrap(),
+            0x20..=0x7e => escaped.push(b as char),
+            _ => write!(escaped, "\\{:02X}", b).unwrap(),
+        }
+    }
+    format!("@.{} = private unnamed_addr constant [{} x i8] c\"{}\\00\"\n", name, s.len() + 1, escaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compile::*;
+
+    fn generate(src: &'static str) -> String {
+        let tree = analyze(src.as_bytes(), TokenizerOptions::default()).unwrap();
+        LlvmGenerator::new().generate(&tree)
+    }
+
+    #[test]
+    fn test_environments() {
+        let ir = generate("\
+            var x;
+            function f(a)
+                function g(b) return a + b + x;
+                return g(1);
+            begin x := 1; write f(2) end.");
+        assert!(ir.contains("%env0 = type { ptr, [1 x i32] }\n"));
+        assert!(ir.contains("%env2 = type { ptr, [1 x i32] }\n"));
+        assert!(ir.contains("define internal i32 @f2_g(ptr %up, i32 %a0) {\n  %e = alloca %env2\n"));
+        // g reaches `a` through one link and `x` through two
+        assert!(ir.contains("  %t1 = load ptr, ptr %e\n  %t2 = getelementptr %env1, ptr %t1, i32 0, i32 1, i32 0\n"));
+        assert!(ir.contains("  %t7 = load ptr, ptr %e\n  %t8 = load ptr, ptr %t7\n  %t9 = getelementptr %env0, ptr %t8, i32 0, i32 1, i32 0\n"));
+    }
+
+    #[test]
+    fn test_string_constant() {
+        assert_eq!(string_constant("s0", "a\"\n"), "@.s0 = private unnamed_addr constant [4 x i8] c\"a\\22\\0A\\00\"\n");
+    }
+}
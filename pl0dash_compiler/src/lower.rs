@@ -0,0 +1,636 @@
+// Lowering of a checked syntax tree into the three-address IR of `ir`.
+//
+// Every variable, parameter and array gets the index of its entry in the
+// frame of its function, which the table keeps as the address of the name.
+// Functions are numbered as they are declared, the main block being 0, and
+// the table keeps that number as their address.
+
+use crate::tokenizer::*;
+use crate::keyword::*;
+use crate::symbol::*;
+use crate::parser::*;
+use crate::table::*;
+use crate::backend::*;
+use crate::semantic::self_tail_call;
+use crate::ir::*;
+
+/// Targets of `break` and `continue` in the loop being lowered.
+struct Loop {
+    break_block: BlockId,
+    continue_block: BlockId,
+}
+
+pub struct IrGenerator {
+    table: Table,
+    funcs: Vec<Function>,
+    strings: Vec<String>,
+    loops: Vec<Loop>,
+    /// function being lowered
+    func: FuncId,
+    /// block instructions go to
+    current: BlockId,
+    /// whether the current block has its terminator
+    terminated: bool,
+}
+
+impl IrGenerator {
+    pub fn new() -> Self {
+        IrGenerator {
+            table: Table::new(),
+            funcs: vec![],
+            strings: vec![],
+            loops: vec![],
+            func: 0,
+            current: 0,
+            terminated: false,
+        }
+    }
+
+    pub fn generate(mut self, tree: &SyntaxTree) -> Module {
+        let program = tree.get_ref_root();
+        self.funcs.push(new_function("main", FuncKind::Main, None, 0));
+        self.table.block_begin(FIRST_ADDR);
+        self.gen_block(&program.get_ref_children()[0], 0);
+        Module {
+            funcs: self.funcs,
+            strings: self.strings,
+        }
+    }
+
+    fn gen_block(&mut self, node: &SyntaxNode, id: FuncId) {
+        for child in node.get_ref_children() {
+            match child.get_ref_syntax() {
+                Syntax::ConstDecl => enter_consts(&mut self.table, child),
+                Syntax::VarDecl => self.gen_var_decl(child, id),
+                Syntax::FuncDecl | Syntax::ProcDecl => self.gen_func_decl(child),
+                Syntax::Statement => {
+                    // nested functions are done with by now
+                    self.func = id;
+                    self.current = self.new_block();
+                    self.terminated = false;
+                    self.gen_statement(child);
+                },
+                _ => unreachable!(),
+            }
+        }
+        // procedures and the main block end without a `return`; the end of
+        // a function cannot be reached
+        if !self.terminated {
+            let term = match self.funcs[id].kind {
+                FuncKind::Func => Terminator::Unreachable,
+                _ => Terminator::Return(None),
+            };
+            self.terminate(term);
+        }
+        let func = &mut self.funcs[id];
+        if func.blocks.iter().any(|b| b.term.successors().contains(&0)) {
+            // tail calls jump back to the start of the body, which moves out
+            // of the entry block as nothing may jump there
+            let start = func.blocks.len();
+            let body = std::mem::take(&mut func.blocks[0]);
+            func.blocks.push(body);
+            func.blocks[0].term = Terminator::Jump(start);
+            for block in &mut func.blocks[1..] {
+                for s in block.term.successors_mut() {
+                    if *s == 0 {
+                        *s = start;
+                    }
+                }
+            }
+        }
+        func.remove_unreachable_blocks();
+        self.table.block_end();
+    }
+
+    fn gen_var_decl(&mut self, node: &SyntaxNode, id: FuncId) {
+        for (e, ident) in enter_vars(&mut self.table, node) {
+            let size = match self.table.get(e).kind {
+                Kind::Array => Some(self.table.get(e).size),
+                _ => None,
+            };
+            self.enter_local(id, e, ident, size);
+        }
+    }
+
+    fn gen_func_decl(&mut self, node: &SyntaxNode) {
+        let name = node.get_ref_children()[1].get_ref_lexeme();
+        let id = self.funcs.len();
+        let kind = match node.get_ref_syntax() {
+            Syntax::ProcDecl => FuncKind::Proc,
+            _ => FuncKind::Func,
+        };
+        let parent = self.env(self.table.level());
+        let level = self.table.level() + 1;
+        self.funcs.push(new_function(name, kind, Some(parent), level));
+        let decl = enter_func(&mut self.table, node, id as i32);
+        for (e, ident) in decl.pars {
+            self.enter_local(id, e, ident, None);
+        }
+        self.funcs[id].pars = self.table.get(decl.func).pars;
+        self.gen_block(decl.body, id);
+    }
+
+    /// Makes the entry `e` a variable of the function `id`, numbered in the
+    /// order of declaration.
+    fn enter_local(&mut self, id: FuncId, e: usize, ident: &SyntaxNode, size: Option<i32>) {
+        let vars = &mut self.funcs[id].vars;
+        self.table.change_addr(e, vars.len() as i32);
+        vars.push(Var { name: ident.get_ref_lexeme().to_string(), size });
+    }
+
+    fn gen_statement(&mut self, node: &SyntaxNode) {
+        let children = node.get_ref_children();
+        let first = match children.first() {
+            Some(first) => first,
+            None => return, // empty statement
+        };
+        match first.get_ref_token().unwrap() {
+            Token::Identifier(name) if children.len() > 1 && children[1].get_ref_token() == Some(&Token::Symbol(Symbol::Lparen)) => {
+                let e = lookup(&self.table, name);
+                self.gen_call(&e, &children[1..]);
+            },
+            Token::Keyword(Keyword::Call) => {
+                let e = lookup(&self.table, children[1].get_ref_identifier().unwrap());
+                self.gen_call(&e, &children[2..]);
+            },
+            Token::Identifier(name) => {
+                let e = lookup(&self.table, name);
+                if e.kind == Kind::Array {
+                    // ident [ index ] := value
+                    let index = self.gen_expression(&children[2]);
+                    let src = self.gen_expression(&children[5]);
+                    self.inst(Inst::StoreElement { array: self.var(&e), index, src });
+                } else {
+                    let src = self.gen_expression(&children[2]);
+                    self.inst(Inst::Store { var: self.var(&e), src });
+                }
+            },
+            Token::Keyword(Keyword::Begin) => {
+                for child in children {
+                    if let Syntax::Statement = child.get_ref_syntax() {
+                        self.gen_statement(child);
+                    }
+                }
+            },
+            Token::Keyword(Keyword::If) => {
+                let (then_block, next) = (self.new_block(), self.new_block());
+                if children.len() > 4 {
+                    let else_block = self.new_block();
+                    self.gen_condition(&children[1], then_block, else_block);
+                    self.start(then_block);
+                    self.gen_statement(&children[3]);
+                    self.jump(next);
+                    self.start(else_block);
+                    self.gen_statement(&children[5]);
+                } else {
+                    self.gen_condition(&children[1], then_block, next);
+                    self.start(then_block);
+                    self.gen_statement(&children[3]);
+                }
+                self.jump(next);
+                self.start(next);
+            },
+            Token::Keyword(Keyword::While) => {
+                let (start, body, next) = (self.new_block(), self.new_block(), self.new_block());
+                self.jump(start);
+                self.start(start);
+                self.gen_condition(&children[1], body, next);
+                self.start(body);
+                self.loops.push(Loop { break_block: next, continue_block: start });
+                self.gen_statement(&children[3]);
+                self.loops.pop();
+                self.jump(start);
+                self.start(next);
+            },
+            Token::Keyword(Keyword::For) => {
+                let e = lookup(&self.table, children[1].get_ref_identifier().unwrap());
+                let var = self.var(&e);
+                let (op, last, step) = match children[4].get_ref_token() {
+                    Some(Token::Keyword(Keyword::To)) => (BinaryOp::Le, BinaryOp::Lt, BinaryOp::Add),
+                    _ => (BinaryOp::Ge, BinaryOp::Gt, BinaryOp::Sub),
+                };
+                let (start, body, step_block, next) = (self.new_block(), self.new_block(), self.new_block(), self.new_block());
+                let inc = self.new_block();
+                let from = self.gen_expression(&children[3]);
+                self.inst(Inst::Store { var, src: from });
+                let to = self.gen_expression(&children[5]);
+                let limit = self.value(|dst| Inst::Copy { dst, src: to });
+                self.jump(start);
+                self.start(start);
+                let v = self.value(|dst| Inst::Load { dst, var });
+                let cond = self.value(|dst| Inst::Binary { dst, op, lhs: v, rhs: limit });
+                self.terminate(Terminator::Branch { cond, then_block: body, else_block: next });
+                self.start(body);
+                self.loops.push(Loop { break_block: next, continue_block: step_block });
+                self.gen_statement(&children[7]);
+                self.loops.pop();
+                self.jump(step_block);
+                self.start(step_block);
+                let v = self.value(|dst| Inst::Load { dst, var });
+                let cond = self.value(|dst| Inst::Binary { dst, op: last, lhs: v, rhs: limit });
+                self.terminate(Terminator::Branch { cond, then_block: inc, else_block: next });
+                self.start(inc);
+                let v = self.value(|dst| Inst::Load { dst, var });
+                let v = self.value(|dst| Inst::Binary { dst, op: step, lhs: v, rhs: Operand::Const(1) });
+                self.inst(Inst::Store { var, src: v });
+                self.jump(start);
+                self.start(next);
+            },
+            Token::Keyword(Keyword::Repeat) => {
+                let (start, cond, next) = (self.new_block(), self.new_block(), self.new_block());
+                self.jump(start);
+                self.start(start);
+                self.loops.push(Loop { break_block: next, continue_block: cond });
+                for child in children {
+                    if let Syntax::Statement = child.get_ref_syntax() {
+                        self.gen_statement(child);
+                    }
+                }
+                self.loops.pop();
+                self.jump(cond);
+                self.start(cond);
+                self.gen_condition(children.last().unwrap(), next, start);
+                self.start(next);
+            },
+            Token::Keyword(Keyword::Break) => {
+                let target = self.loops.last().unwrap().break_block;
+                self.jump(target);
+            },
+            Token::Keyword(Keyword::Continue) => {
+                let target = self.loops.last().unwrap().continue_block;
+                self.jump(target);
+            },
+            Token::Keyword(Keyword::Ret) => {
+                if let Some(args) = self_tail_call(&self.table, &children[1]) {
+                    // the arguments replace the parameters, the variables
+                    // start at zero again and the body starts over, from the
+                    // entry block as `gen_block` leaves it
+                    let args: Vec<Operand> = args.into_iter().map(|a| self.gen_expression(a)).collect();
+                    let pars = args.len();
+                    for (index, src) in args.into_iter().enumerate() {
+                        self.inst(Inst::Store { var: VarRef { func: self.func, index }, src });
+                    }
+                    let sizes: Vec<Option<i32>> = self.funcs[self.func].vars[pars..].iter().map(|v| v.size).collect();
+                    for (index, size) in (pars..).zip(sizes) {
+                        let var = VarRef { func: self.func, index };
+                        match size {
+                            Some(size) => {
+                                for i in 0..size {
+                                    self.inst(Inst::StoreElement { array: var, index: Operand::Const(i), src: Operand::Const(0) });
+                                }
+                            },
+                            None => self.inst(Inst::Store { var, src: Operand::Const(0) }),
+                        }
+                    }
+                    self.terminate(Terminator::Jump(0));
+                    return;
+                }
+                let v = self.gen_expression(&children[1]);
+                self.terminate(Terminator::Return(Some(v)));
+            },
+            Token::Keyword(Keyword::Write) => {
+                for arg in &children[1..] {
+                    if let Some(Token::String(s)) = arg.get_ref_token() {
+                        let i = intern(&mut self.strings, s);
+                        self.inst(Inst::WriteString(i));
+                    } else if let Syntax::Expression = arg.get_ref_syntax() {
+                        let src = self.gen_expression(arg);
+                        self.inst(Inst::Write { src });
+                    }
+                }
+            },
+            Token::Keyword(Keyword::WriteLn) => {
+                self.inst(Inst::WriteLn);
+            },
+            Token::Keyword(Keyword::Read) => {
+                let e = lookup(&self.table, children[1].get_ref_identifier().unwrap());
+                if e.kind == Kind::Array {
+                    // read ident [ index ]
+                    let index = self.gen_expression(&children[3]);
+                    let src = self.value(|dst| Inst::Read { dst });
+                    self.inst(Inst::StoreElement { array: self.var(&e), index, src });
+                } else {
+                    let src = self.value(|dst| Inst::Read { dst });
+                    self.inst(Inst::Store { var: self.var(&e), src });
+                }
+            },
+            t => unreachable!("statement starting with {:?}", t),
+        }
+    }
+
+    /// Lowers a condition that branches to `holds` if it holds and to `fails`
+    /// otherwise. Operands of `and` and `or` are evaluated only as far as
+    /// needed.
+    fn gen_condition(&mut self, node: &SyntaxNode, holds: BlockId, fails: BlockId) {
+        let children = node.get_ref_children();
+        match children[0].get_ref_token() {
+            Some(Token::Keyword(Keyword::Not)) => {
+                return self.gen_condition(&children[1], fails, holds);
+            },
+            Some(Token::Keyword(Keyword::Odd)) => {
+                let v = self.gen_expression(&children[1]);
+                let cond = self.value(|dst| Inst::Unary { dst, op: UnaryOp::Odd, src: v });
+                return self.terminate(Terminator::Branch { cond, then_block: holds, else_block: fails });
+            },
+            Some(Token::Symbol(Symbol::Lparen)) => {
+                return self.gen_condition(&children[1], holds, fails);
+            },
+            _ => (),
+        }
+        if let Syntax::Condition = children[0].get_ref_syntax() {
+            let is_or = children[1].get_ref_token() == Some(&Token::Keyword(Keyword::Or));
+            let operands: Vec<&SyntaxNode> = children.iter().step_by(2).collect();
+            let (last, init) = operands.split_last().unwrap();
+            for c in init {
+                let next = self.new_block();
+                if is_or {
+                    self.gen_condition(c, holds, next);
+                } else {
+                    self.gen_condition(c, next, fails);
+                }
+                self.start(next);
+            }
+            return self.gen_condition(last, holds, fails);
+        }
+        let op = match children[1].get_ref_token() {
+            Some(Token::Symbol(Symbol::Equal)) => BinaryOp::Eq,
+            Some(Token::Symbol(Symbol::Lss))   => BinaryOp::Lt,
+            Some(Token::Symbol(Symbol::Gtr))   => BinaryOp::Gt,
+            Some(Token::Symbol(Symbol::NotEq)) => BinaryOp::Ne,
+            Some(Token::Symbol(Symbol::LssEq)) => BinaryOp::Le,
+            Some(Token::Symbol(Symbol::GtrEq)) => BinaryOp::Ge,
+            t => unreachable!("relational operator {:?}", t),
+        };
+        let lhs = self.gen_expression(&children[0]);
+        let rhs = self.gen_expression(&children[2]);
+        let cond = self.value(|dst| Inst::Binary { dst, op, lhs, rhs });
+        self.terminate(Terminator::Branch { cond, then_block: holds, else_block: fails });
+    }
+
+    /// Lowers an expression, returning the operand that holds its value.
+    fn gen_expression(&mut self, node: &SyntaxNode) -> Operand {
+        let children = node.get_ref_children();
+        let mut i = 0;
+        let mut negate = false;
+        if let Some(Token::Symbol(sym)) = children[0].get_ref_token() {
+            negate = *sym == Symbol::Minus;
+            i = 1;
+        }
+        let mut v = self.gen_term(&children[i]);
+        if negate {
+            let src = v;
+            v = self.value(|dst| Inst::Unary { dst, op: UnaryOp::Neg, src });
+        }
+        for pair in children[i + 1..].chunks(2) {
+            let op = match pair[0].get_ref_token() {
+                Some(Token::Symbol(Symbol::Plus))  => BinaryOp::Add,
+                Some(Token::Symbol(Symbol::Minus)) => BinaryOp::Sub,
+                t => unreachable!("additive operator {:?}", t),
+            };
+            let (lhs, rhs) = (v, self.gen_term(&pair[1]));
+            v = self.value(|dst| Inst::Binary { dst, op, lhs, rhs });
+        }
+        v
+    }
+
+    fn gen_term(&mut self, node: &SyntaxNode) -> Operand {
+        let children = node.get_ref_children();
+        let mut v = self.gen_factor(&children[0]);
+        for pair in children[1..].chunks(2) {
+            let op = match pair[0].get_ref_token() {
+                Some(Token::Symbol(Symbol::Mult)) => BinaryOp::Mul,
+                Some(Token::Symbol(Symbol::Div))  => BinaryOp::Div,
+                Some(Token::Symbol(Symbol::Mod)) | Some(Token::Keyword(Keyword::Mod)) => BinaryOp::Mod,
+                t => unreachable!("multiplicative operator {:?}", t),
+            };
+            let (lhs, rhs) = (v, self.gen_factor(&pair[1]));
+            v = self.value(|dst| Inst::Binary { dst, op, lhs, rhs });
+        }
+        v
+    }
+
+    fn gen_factor(&mut self, node: &SyntaxNode) -> Operand {
+        let children = node.get_ref_children();
+        match children[0].get_ref_token() {
+            Some(Token::Identifier(name)) => {
+                if let Some(f) = self.table.search_intrinsic(name) {
+                    let x = self.gen_expression(&children[2]);
+                    return match f {
+                        Intrinsic::Abs => self.value(|dst| Inst::Unary { dst, op: UnaryOp::Abs, src: x }),
+                        Intrinsic::Min | Intrinsic::Max => {
+                            let y = self.gen_expression(&children[4]);
+                            let op = if f == Intrinsic::Min { BinaryOp::Min } else { BinaryOp::Max };
+                            self.value(|dst| Inst::Binary { dst, op, lhs: x, rhs: y })
+                        },
+                    };
+                }
+                let e = lookup(&self.table, name);
+                match e.kind {
+                    Kind::Var | Kind::Par => {
+                        let var = self.var(&e);
+                        self.value(|dst| Inst::Load { dst, var })
+                    },
+                    Kind::Array => {
+                        let index = self.gen_expression(&children[2]);
+                        let array = self.var(&e);
+                        self.value(|dst| Inst::LoadElement { dst, array, index })
+                    },
+                    Kind::Const => Operand::Const(e.value),
+                    Kind::Func => self.gen_call(&e, &children[1..]).unwrap(),
+                    Kind::Proc => unreachable!("unchecked syntax tree"),
+                }
+            },
+            Some(Token::Number(value)) => Operand::Const(*value),
+            _ => {
+                // ( expression )
+                self.gen_expression(&children[1])
+            },
+        }
+    }
+
+    /// Calls `e` with the arguments among `args`, returning the operand that
+    /// holds its value; procedures have none.
+    fn gen_call(&mut self, e: &Entry, args: &[SyntaxNode]) -> Option<Operand> {
+        let mut operands = vec![];
+        for arg in args {
+            if let Syntax::Expression = arg.get_ref_syntax() {
+                operands.push(self.gen_expression(arg));
+            }
+        }
+        let func = e.addr as FuncId;
+        if e.kind == Kind::Proc {
+            self.inst(Inst::Call { dst: None, func, args: operands });
+            None
+        } else {
+            Some(self.value(|dst| Inst::Call { dst: Some(dst), func, args: operands }))
+        }
+    }
+
+    /// The variable or array `e` of the frame it is declared in.
+    fn var(&self, e: &Entry) -> VarRef {
+        VarRef { func: self.env(e.level), index: e.addr as usize }
+    }
+
+    /// The function whose body is the open block at `level`.
+    fn env(&self, level: usize) -> FuncId {
+        self.table.block_func(level).map_or(0, |f| self.table.get(f).addr as FuncId)
+    }
+
+    fn new_block(&mut self) -> BlockId {
+        let blocks = &mut self.funcs[self.func].blocks;
+        blocks.push(Block::new());
+        blocks.len() - 1
+    }
+
+    /// Continues in the block `b`. The current one must have ended.
+    fn start(&mut self, b: BlockId) {
+        self.current = b;
+        self.terminated = false;
+    }
+
+    fn jump(&mut self, b: BlockId) {
+        if !self.terminated {
+            self.terminate(Terminator::Jump(b));
+        }
+    }
+
+    /// Appends an instruction, in a block of its own if the current one has
+    /// ended: the code after a `return` or `break` is unreachable.
+    fn inst(&mut self, inst: Inst) {
+        if self.terminated {
+            let b = self.new_block();
+            self.start(b);
+        }
+        self.funcs[self.func].blocks[self.current].insts.push(inst);
+    }
+
+    fn terminate(&mut self, term: Terminator) {
+        if self.terminated {
+            let b = self.new_block();
+            self.start(b);
+        }
+        self.funcs[self.func].blocks[self.current].term = term;
+        self.terminated = true;
+    }
+
+    /// Appends the instruction `make` builds around a fresh temporary,
+    /// returning the temporary.
+    fn value(&mut self, make: impl FnOnce(Temp) -> Inst) -> Operand {
+        let t = self.funcs[self.func].new_temp();
+        self.inst(make(t));
+        Operand::Temp(t)
+    }
+}
+
+impl Default for IrGenerator {
+    fn default() -> Self {
+        IrGenerator::new()
+    }
+}
+
+fn new_function(name: &str, kind: FuncKind, parent: Option<FuncId>, level: usize) -> Function {
+    Function {
+        name: name.to_string(),
+        kind,
+        parent,
+        level,
+        pars: 0,
+        vars: vec![],
+        blocks: vec![],
+        temps: 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compile::*;
+
+    fn generate(src: &'static str) -> Module {
+        let tree = analyze(src.as_bytes(), TokenizerOptions::default()).unwrap();
+        IrGenerator::new().generate(&tree)
+    }
+
+    #[test]
+    fn test_loops() {
+        let module = generate("\
+            var i, a[3];
+            begin
+                for i := 0 to 2 do begin
+                    if i > 1 and not odd i then break;
+                    a[i] := i * i
+                end;
+                write a[1], \"done\"
+            end.");
+        assert_eq!(module.verify(), Ok(()));
+        assert_eq!(module.to_string(), "\
+main
+  var i, a[3]
+L0:
+  i = 0
+  t0 = 2
+  goto L1
+L1:
+  t1 = i
+  t2 = t1 <= t0
+  if t2 goto L2 else L4
+L2:
+  t3 = i
+  t4 = t3 > 1
+  if t4 goto L8 else L7
+L3:
+  t11 = i
+  t12 = t11 < t0
+  if t12 goto L5 else L4
+L4:
+  t15 = a[1]
+  write t15
+  write \"done\"
+  ret
+L5:
+  t13 = i
+  t14 = t13 + 1
+  i = t14
+  goto L1
+L6:
+  goto L4
+L7:
+  t7 = i
+  t8 = i
+  t9 = i
+  t10 = t8 * t9
+  a[t7] = t10
+  goto L3
+L8:
+  t5 = i
+  t6 = odd t5
+  if t6 goto L7 else L6
+");
+    }
+
+    #[test]
+    fn test_frames() {
+        let module = generate("\
+            var x;
+            function f(a)
+                var b[2];
+                function g(c) return a + c + x;
+                begin b[0] := g(1); return b[0] end;
+            procedure p() write f(2);
+            begin x := 1; p() end.");
+        assert_eq!(module.verify(), Ok(()));
+        let names: Vec<(&str, Option<FuncId>, usize)> = module.funcs.iter().map(|f| (f.name.as_str(), f.parent, f.pars)).collect();
+        assert_eq!(names, [("main", None, 0), ("f", Some(0), 1), ("g", Some(1), 1), ("p", Some(0), 0)]);
+        assert_eq!(module.funcs[1].vars, [
+            Var { name: "a".to_string(), size: None },
+            Var { name: "b".to_string(), size: Some(2) },
+        ]);
+        // g reaches `a` in the frame of f and `x` in the one of the main block
+        let g = &module.funcs[2].blocks[0].insts;
+        assert_eq!(g[0], Inst::Load { dst: 0, var: VarRef { func: 1, index: 0 } });
+        assert_eq!(g[3], Inst::Load { dst: 3, var: VarRef { func: 0, index: 0 } });
+        // code after a `return` is dropped
+        assert_eq!(module.funcs[1].blocks.len(), 1);
+        assert_eq!(module.funcs[3].blocks[0].insts[0], Inst::Call { dst: Some(0), func: 1, args: vec![Operand::Const(2)] });
+    }
+}
@@ -1,17 +1,146 @@
-mod tokenizer;
-mod keyword;
-mod symbol;
-//mod engine;
-//mod analyzer;
-mod char_class;
-mod parser;
+use pl0dash_compiler::tokenizer::*;
+use pl0dash_compiler::keyword::*;
+use pl0dash_compiler::token_dump::*;
+use pl0dash_compiler::compile::*;
+use pl0dash_compiler::codegen::CodeGenerator;
+use pl0dash_compiler::vm::*;
+use pl0dash_compiler::peephole::*;
+use pl0dash_compiler::lower::IrGenerator;
+use pl0dash_compiler::opt::*;
 
 use std::env;
+use std::fs;
+use std::io::{self, BufReader, Cursor, Write};
 use std::path::Path;
+use std::process;
+
+enum Mode {
+    Tokens(DumpFormat),
+    Tree,
+    Code,
+    Emit(Target),
+    Run,
+}
+
+/// Ends the program quietly when the reader of stdout has gone away, as
+/// `head` does once it has read enough.
+fn written(result: io::Result<()>, what: &str) {
+    match result {
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => process::exit(0),
+        r => r.unwrap_or_else(|e| panic!("cannot write {}: {}", what, e)),
+    }
+}
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 2 { panic!("usage: jackc <filename>.jack | <dirname>"); }
-    let arg_path = Path::new(&args[1]);
-    //analyzer::Analyzer::run(arg_path);
-}
\ No newline at end of file
+    let mut options = TokenizerOptions::default();
+    let mut filename = None;
+    let mut mode = Mode::Run;
+    let mut input_file = None;
+    let mut passes = Passes::none();
+    let mut fold = false;
+    let mut optimize_code = false;
+    let mut stats = false;
+    for arg in env::args().skip(1) {
+        if let Some(format) = arg.strip_prefix("--tokens=") {
+            mode = Mode::Tokens(format.parse::<DumpFormat>().unwrap_or_else(|e| panic!("{}", e)));
+            continue;
+        }
+        if let Some(target) = arg.strip_prefix("--emit=") {
+            mode = Mode::Emit(target.parse::<Target>().unwrap_or_else(|e| panic!("{}", e)));
+            continue;
+        }
+        if let Some(list) = arg.strip_prefix("--opt=") {
+            passes = list.parse::<Passes>().unwrap_or_else(|e| panic!("{}", e));
+            continue;
+        }
+        if let Some(path) = arg.strip_prefix("--input=") {
+            input_file = Some(path.to_string());
+            continue;
+        }
+        match arg.as_str() {
+            "--unicode-identifiers" => options.unicode_identifiers = true,
+            "--case-insensitive" => options.case_insensitive = true,
+            "--classic-io" => options.keywords.enable(&CLASSIC_IO),
+            "--no-procedures" => options.keywords.disable(&PROCEDURES),
+            "--tree" => mode = Mode::Tree,
+            "--code" => mode = Mode::Code,
+            "--fold" => fold = true,
+            "--peephole" => optimize_code = true,
+            "--stats" => stats = true,
+            _ => filename = Some(arg),
+        }
+    }
+    let filename = filename.expect("usage: pl0dash_compiler [--unicode-identifiers] [--case-insensitive] [--classic-io] [--no-procedures] [--tokens=xml|json|text | --tree | --code | --emit=x86_64|c|llvm|wat|riscv64|ir] [--fold] [--peephole [--stats]] [--opt=sccp,gvn,dce] [--input=<file>] <filename>.pl0");
+    let arg_path = Path::new(&filename);
+    let source = fs::read(arg_path).expect("cannot read source file");
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+
+    if let Mode::Tokens(format) = mode {
+        let mut t = Tokenizer::with_options(Cursor::new(source), options);
+        written(dump_tokens(&mut t, format, &mut out), "tokens");
+        return;
+    }
+
+    let report = |errors: Vec<CompileError>| -> ! {
+        for e in errors {
+            eprintln!("{}: {}", arg_path.display(), e);
+        }
+        process::exit(1);
+    };
+    let tree = if fold {
+        analyze_folded(Cursor::new(source), options)
+    } else {
+        analyze(Cursor::new(source), options)
+    }.unwrap_or_else(|e| report(e));
+    let generate_code = || {
+        let mut code = CodeGenerator::new().generate(&tree);
+        if optimize_code {
+            let before = code.code.len();
+            peephole(&mut code);
+            if stats {
+                eprintln!("{}: peephole: {} instructions before, {} after", arg_path.display(), before, code.code.len());
+            }
+        }
+        code
+    };
+    match mode {
+        Mode::Tree => {
+            written(tree.write_xml(&mut out), "tree");
+        },
+        Mode::Code => {
+            let code = generate_code();
+            for (i, inst) in code.code.iter().enumerate() {
+                written(writeln!(out, "{:4}: {}", i, inst), "code");
+            }
+            for (i, s) in code.strings.iter().enumerate() {
+                written(writeln!(out, "{:>4}: \"{}\"", format!("s{}", i), escape_string(s)), "code");
+            }
+        },
+        Mode::Emit(Target::Ir) if passes != Passes::none() => {
+            let mut module = IrGenerator::new().generate(&tree);
+            optimize(&mut module, passes);
+            written(write!(out, "{}", module), "code");
+        },
+        Mode::Emit(target) => {
+            written(out.write_all(translate(&tree, target).as_bytes()), "code");
+        },
+        _ => {
+            let code = generate_code();
+            let result = match input_file {
+                Some(path) => {
+                    let file = fs::File::open(path).expect("cannot open input file");
+                    Vm::new(&code).run_with_input(&mut TextInput::new(BufReader::new(file)), &mut out)
+                },
+                None => {
+                    let stdin = io::stdin();
+                    Vm::new(&code).run_with_input(&mut TextInput::new(stdin.lock()), &mut out)
+                },
+            };
+            if let Err(e) = result {
+                eprintln!("{}: {}", arg_path.display(), e);
+                process::exit(1);
+            }
+        },
+    }
+}
@@ -0,0 +1,478 @@
+// Optimizations of the IR in SSA form:
+//
+//   - sparse conditional constant propagation (Wegman and Zadeck), which
+//     folds what is constant on the paths control can take and drops the
+//     branches it cannot,
+//   - global value numbering over the dominator tree, which reuses an
+//     operation already computed on every path to a repeat of it,
+//   - dead code elimination, which drops the operations whose values are
+//     never used.
+//
+// None of them changes what a program writes or the runtime error it stops
+// with: a division that may fail is only folded when it does not, and stays
+// even when its value is unused.
+
+use std::fmt;
+use std::str::FromStr;
+use std::collections::{HashMap, HashSet};
+use crate::ir::*;
+use crate::ssa::*;
+use crate::interp::{unary, binary};
+
+/// The passes to run, each of which can be turned off on its own.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Passes {
+    pub sccp: bool,
+    pub gvn: bool,
+    pub dce: bool,
+}
+
+impl Passes {
+    pub fn all() -> Self {
+        Passes { sccp: true, gvn: true, dce: true }
+    }
+
+    pub fn none() -> Self {
+        Passes { sccp: false, gvn: false, dce: false }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct UndefinedPass(pub String);
+
+impl fmt::Display for UndefinedPass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Undefined pass `{}` (expected sccp, gvn, dce or all)", self.0)
+    }
+}
+
+/// Reads a comma separated list of passes, such as `sccp,dce`.
+impl FromStr for Passes {
+    type Err = UndefinedPass;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut passes = Passes::none();
+        for name in s.split(',').filter(|name| !name.is_empty()) {
+            match name {
+                "sccp"                => passes.sccp = true,
+                "gvn"                 => passes.gvn = true,
+                "dce"                 => passes.dce = true,
+                "all"                 => passes = Passes::all(),
+                                    _ => return Err(UndefinedPass(name.to_string())),
+            }
+        }
+        Ok(passes)
+    }
+}
+
+/// Runs `passes` on every function of `module`, in SSA form in between.
+pub fn optimize(module: &mut Module, passes: Passes) {
+    to_ssa(module);
+    let sizes: Vec<Vec<Option<i32>>> = module.funcs.iter()
+        .map(|f| f.vars.iter().map(|v| v.size).collect())
+        .collect();
+    for func in &mut module.funcs {
+        if passes.sccp {
+            sccp(func);
+        }
+        if passes.gvn {
+            gvn(func);
+        }
+        if passes.dce {
+            dce(func, &sizes);
+        }
+    }
+    from_ssa(module);
+}
+
+/// What constant propagation knows of a temporary.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Value {
+    /// not computed on any path taken so far
+    Unknown,
+    Const(i32),
+    Varying,
+}
+
+fn meet(a: Value, b: Value) -> Value {
+    match (a, b) {
+        (Value::Unknown, v) | (v, Value::Unknown) => v,
+        (Value::Const(x), Value::Const(y)) if x == y => a,
+        _ => Value::Varying,
+    }
+}
+
+/// Sparse conditional constant propagation of a function in SSA form.
+pub fn sccp(func: &mut Function) {
+    let n = func.blocks.len();
+    let mut values = vec![Value::Unknown; func.temps];
+    let mut reached = vec![false; n];
+    let mut edges: HashSet<(BlockId, BlockId)> = HashSet::new();
+    // where every temporary is read: (block, instruction, none for the
+    // terminator)
+    let mut uses: Vec<Vec<(BlockId, Option<usize>)>> = vec![vec![]; func.temps];
+    for (b, block) in func.blocks.iter().enumerate() {
+        for (i, inst) in block.insts.iter().enumerate() {
+            for v in inst.uses() {
+                if let Operand::Temp(t) = v {
+                    uses[t].push((b, Some(i)));
+                }
+            }
+        }
+        for v in block.term.uses() {
+            if let Operand::Temp(t) = v {
+                uses[t].push((b, None));
+            }
+        }
+    }
+    let mut flow = vec![(None, 0)];
+    let mut work: Vec<(BlockId, Option<usize>)> = vec![];
+    loop {
+        let visits: Vec<(BlockId, Option<usize>)> = if let Some((from, b)) = flow.pop() {
+            if let Some(from) = from {
+                if !edges.insert((from, b)) {
+                    continue;
+                }
+            }
+            let insts = &func.blocks[b].insts;
+            if reached[b] {
+                // a new way in only changes the phis
+                (0..insts.len()).take_while(|&i| matches!(insts[i], Inst::Phi { .. })).map(|i| (b, Some(i))).collect()
+            } else {
+                reached[b] = true;
+                (0..insts.len()).map(|i| (b, Some(i))).chain(std::iter::once((b, None))).collect()
+            }
+        } else if let Some(visit) = work.pop() {
+            if !reached[visit.0] {
+                continue;
+            }
+            vec![visit]
+        } else {
+            break;
+        };
+        for (b, i) in visits {
+            let i = match i {
+                Some(i) => i,
+                None => {
+                    match &func.blocks[b].term {
+                        Terminator::Jump(s) => flow.push((Some(b), *s)),
+                        Terminator::Branch { cond, then_block, else_block } => match operand_value(&values, *cond) {
+                            Value::Unknown => (),
+                            Value::Const(c) => flow.push((Some(b), if c != 0 { *then_block } else { *else_block })),
+                            Value::Varying => {
+                                flow.push((Some(b), *then_block));
+                                flow.push((Some(b), *else_block));
+                            },
+                        },
+                        Terminator::Return(_) | Terminator::Unreachable => (),
+                    }
+                    continue;
+                },
+            };
+            let inst = &func.blocks[b].insts[i];
+            let dst = match inst.def() {
+                Some(dst) => dst,
+                None => continue,
+            };
+            let v = match inst {
+                Inst::Phi { args, .. } => args.iter()
+                    .filter(|(p, _)| edges.contains(&(*p, b)))
+                    .fold(Value::Unknown, |acc, (_, v)| meet(acc, operand_value(&values, *v))),
+                Inst::Copy { src, .. } => operand_value(&values, *src),
+                Inst::Unary { op, src, .. } => match operand_value(&values, *src) {
+                    Value::Const(c) => Value::Const(unary(*op, c)),
+                    v => v,
+                },
+                Inst::Binary { op, lhs, rhs, .. } => match (operand_value(&values, *lhs), operand_value(&values, *rhs)) {
+                    (Value::Varying, _) | (_, Value::Varying) => Value::Varying,
+                    (Value::Const(l), Value::Const(r)) => match binary(*op, l, r) {
+                        Ok(v) => Value::Const(v),
+                        // left for the program to fail on
+                        Err(_) => Value::Varying,
+                    },
+                    _ => Value::Unknown,
+                },
+                _ => Value::Varying,
+            };
+            let v = meet(values[dst], v);
+            if v != values[dst] {
+                values[dst] = v;
+                work.extend(&uses[dst]);
+            }
+        }
+    }
+
+    // constants for temporaries, jumps for branches that go one way
+    let constant = |v: Operand| match v {
+        Operand::Temp(t) => match values[t] {
+            Value::Const(c) => Operand::Const(c),
+            _ => v,
+        },
+        c => c,
+    };
+    for block in &mut func.blocks {
+        block.insts.retain(|inst| {
+            !(matches!(inst, Inst::Copy { .. } | Inst::Unary { .. } | Inst::Binary { .. } | Inst::Phi { .. })
+                && matches!(values[inst.def().unwrap()], Value::Const(_)))
+        });
+        for inst in &mut block.insts {
+            for v in inst.uses_mut() {
+                *v = constant(*v);
+            }
+        }
+        for v in block.term.uses_mut() {
+            *v = constant(*v);
+        }
+        if let Terminator::Branch { cond: Operand::Const(c), then_block, else_block } = block.term {
+            block.term = Terminator::Jump(if c != 0 { then_block } else { else_block });
+        }
+    }
+    prune_phis(func);
+    func.remove_unreachable_blocks();
+}
+
+fn operand_value(values: &[Value], v: Operand) -> Value {
+    match v {
+        Operand::Temp(t) => values[t],
+        Operand::Const(c) => Value::Const(c),
+    }
+}
+
+/// Drops the operands of phis for the blocks that no longer jump to theirs.
+fn prune_phis(func: &mut Function) {
+    let preds = func.predecessors();
+    for (b, block) in func.blocks.iter_mut().enumerate() {
+        for inst in &mut block.insts {
+            if let Inst::Phi { args, .. } = inst {
+                args.retain(|(p, _)| preds[b].contains(p));
+            }
+        }
+    }
+}
+
+/// An operation, its operands numbered by the temporaries holding them.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum Expr {
+    Unary(UnaryOp, Operand),
+    Binary(BinaryOp, Operand, Operand),
+    Phi(BlockId, Vec<(BlockId, Operand)>),
+}
+
+/// Spells `op lhs rhs` the same way as any equivalent operation.
+fn binary_expr(op: BinaryOp, lhs: Operand, rhs: Operand) -> Expr {
+    let rank = |v: Operand| match v {
+        Operand::Const(c) => (0, c as i64),
+        Operand::Temp(t) => (1, t as i64),
+    };
+    match op {
+        BinaryOp::Gt => binary_expr(BinaryOp::Lt, rhs, lhs),
+        BinaryOp::Ge => binary_expr(BinaryOp::Le, rhs, lhs),
+        BinaryOp::Add | BinaryOp::Mul | BinaryOp::Min | BinaryOp::Max | BinaryOp::Eq | BinaryOp::Ne
+            if rank(rhs) < rank(lhs) => Expr::Binary(op, rhs, lhs),
+        _ => Expr::Binary(op, lhs, rhs),
+    }
+}
+
+/// Global value numbering of a function in SSA form: walking down the
+/// dominator tree, an operation repeating one that dominates it is replaced
+/// by its value, and so are copies and phis whose operands are all the same.
+pub fn gvn(func: &mut Function) {
+    let dom = Dominators::new(func);
+    let mut replaced: HashMap<Temp, Operand> = HashMap::new();
+    let resolve = |replaced: &HashMap<Temp, Operand>, mut v: Operand| {
+        while let Operand::Temp(t) = v {
+            match replaced.get(&t) {
+                Some(w) => v = *w,
+                None => break,
+            }
+        }
+        v
+    };
+    // the operations available in the block being visited
+    let mut available: HashMap<Expr, Temp> = HashMap::new();
+    let mut added: Vec<Vec<Expr>> = vec![vec![]; func.blocks.len()];
+    let mut work = vec![(0, false)];
+    while let Some((b, leaving)) = work.pop() {
+        if leaving {
+            for e in added[b].drain(..) {
+                available.remove(&e);
+            }
+            continue;
+        }
+        work.push((b, true));
+        let mut insts = vec![];
+        for mut inst in std::mem::take(&mut func.blocks[b].insts) {
+            for v in inst.uses_mut() {
+                *v = resolve(&replaced, *v);
+            }
+            let (dst, expr) = match &inst {
+                Inst::Copy { dst, src } => {
+                    replaced.insert(*dst, *src);
+                    continue;
+                },
+                Inst::Phi { dst, args } => {
+                    let mut values = args.iter().map(|(_, v)| *v).filter(|v| *v != Operand::Temp(*dst));
+                    let first = values.next();
+                    if let Some(v) = first.filter(|v| values.all(|w| w == *v)) {
+                        replaced.insert(*dst, v);
+                        continue;
+                    }
+                    let mut args = args.clone();
+                    args.sort_by_key(|(p, _)| *p);
+                    (*dst, Expr::Phi(b, args))
+                },
+                Inst::Unary { dst, op, src } => (*dst, Expr::Unary(*op, *src)),
+                Inst::Binary { dst, op, lhs, rhs } => (*dst, binary_expr(*op, *lhs, *rhs)),
+                _ => {
+                    insts.push(inst);
+                    continue;
+                },
+            };
+            match available.get(&expr) {
+                Some(t) => {
+                    replaced.insert(dst, Operand::Temp(*t));
+                },
+                None => {
+                    available.insert(expr.clone(), dst);
+                    added[b].push(expr);
+                    insts.push(inst);
+                },
+            }
+        }
+        func.blocks[b].insts = insts;
+        work.extend(dom.children(b).iter().rev().map(|&c| (c, false)));
+    }
+    // operands of phis may come from blocks visited later
+    for block in &mut func.blocks {
+        for inst in &mut block.insts {
+            for v in inst.uses_mut() {
+                *v = resolve(&replaced, *v);
+            }
+        }
+        for v in block.term.uses_mut() {
+            *v = resolve(&replaced, *v);
+        }
+    }
+}
+
+/// Dead code elimination of a function in SSA form: operations without side
+/// effects whose values nothing needs go. `sizes` holds the sizes of the
+/// arrays of every function, for the elements surely within bounds.
+pub fn dce(func: &mut Function, sizes: &[Vec<Option<i32>>]) {
+    let removable = |inst: &Inst| match inst {
+        Inst::Copy { .. } | Inst::Unary { .. } | Inst::Load { .. } | Inst::Phi { .. } => true,
+        Inst::Binary { op: BinaryOp::Div | BinaryOp::Mod, rhs, .. } => matches!(rhs, Operand::Const(c) if *c != 0),
+        Inst::Binary { .. } => true,
+        Inst::LoadElement { array, index: Operand::Const(i), .. } => {
+            (0..sizes[array.func][array.index].unwrap()).contains(i)
+        },
+        _ => false,
+    };
+    let mut defs = vec![None; func.temps];
+    for (b, block) in func.blocks.iter().enumerate() {
+        for (i, inst) in block.insts.iter().enumerate() {
+            if let Some(t) = inst.def() {
+                defs[t] = Some((b, i));
+            }
+        }
+    }
+    // mark what the side effects and the control flow need
+    let mut live: Vec<Vec<bool>> = func.blocks.iter().map(|block| vec![false; block.insts.len()]).collect();
+    let mut work: Vec<Operand> = vec![];
+    for (b, block) in func.blocks.iter().enumerate() {
+        for (i, inst) in block.insts.iter().enumerate() {
+            if !removable(inst) {
+                live[b][i] = true;
+                work.extend(inst.uses());
+            }
+        }
+        work.extend(block.term.uses());
+    }
+    while let Some(v) = work.pop() {
+        if let Operand::Temp(t) = v {
+            if let Some((b, i)) = defs[t] {
+                if !live[b][i] {
+                    live[b][i] = true;
+                    work.extend(func.blocks[b].insts[i].uses());
+                }
+            }
+        }
+    }
+    for (block, live) in func.blocks.iter_mut().zip(live) {
+        let mut live = live.into_iter();
+        block.insts.retain(|_| live.next().unwrap());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokenizer::TokenizerOptions;
+    use crate::compile::*;
+
+    fn optimized(src: &'static str, passes: &str) -> String {
+        let mut module = lower(src.as_bytes(), TokenizerOptions::default()).unwrap();
+        optimize(&mut module, passes.parse().unwrap());
+        assert_eq!(module.verify(), Ok(()));
+        module.to_string()
+    }
+
+    #[test]
+    fn test_passes() {
+        assert_eq!("sccp,dce".parse::<Passes>().unwrap(), Passes { sccp: true, gvn: false, dce: true });
+        assert_eq!("all".parse::<Passes>().unwrap(), Passes::all());
+        assert_eq!("sccp,licm".parse::<Passes>().unwrap_err().to_string(), "Undefined pass `licm` (expected sccp, gvn, dce or all)");
+    }
+
+    #[test]
+    fn test_sccp() {
+        let src = "var x, y; begin x := 2 + 3; if x > 4 then y := x * 2 else y := 0; write y end.";
+        assert_eq!(optimized(src, "sccp"), "\
+main
+  var x, y
+L0:
+  goto L1
+L1:
+  goto L2
+L2:
+  write 10
+  ret
+");
+    }
+
+    #[test]
+    fn test_gvn() {
+        let src = "var x; begin read x; write x * 2 + 1; if x > 0 then write x * 2 end.";
+        assert_eq!(optimized(src, "gvn"), "\
+main
+  var x
+L0:
+  t0 = read
+  t2 = t0 * 2
+  t3 = t2 + 1
+  write t3
+  t5 = t0 > 0
+  if t5 goto L1 else L2
+L1:
+  write t2
+  goto L2
+L2:
+  ret
+");
+    }
+
+    #[test]
+    fn test_dce() {
+        let src = "var x, y, a[2]; begin read x; y := x * 3; y := x / 0; y := a[1]; y := a[x]; write x end.";
+        assert_eq!(optimized(src, "dce"), "\
+main
+  var x, y, a[2]
+L0:
+  t0 = read
+  t4 = t0 / 0
+  t7 = a[t0]
+  write t0
+  ret
+");
+    }
+}
@@ -1,9 +1,9 @@
-use std::io::{BufWriter, Write};
-use std::fs::File;
+use std::io::{self, Write};
+use std::fmt;
 use crate::tokenizer::*;
 use crate::keyword::*;
 use crate::symbol::*;
-use crate::char_class::*;
+use crate::token_dump::xml_token;
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum Syntax {
@@ -12,6 +12,7 @@ pub enum Syntax {
     ConstDecl,
     VarDecl,
     FuncDecl,
+    ProcDecl,
     Statement,
     Condition,
     Expression,
@@ -23,32 +24,77 @@ pub enum Syntax {
 #[derive(Clone, Debug, PartialEq)]
 pub struct SyntaxNode {
     syntax: Syntax,
+    pos: Position,
+    lexeme: String,
     children: Vec<SyntaxNode>,
 }
 
 impl SyntaxNode {
-    fn new(syntax: Syntax) -> Self {
+    pub(crate) fn new(syntax: Syntax) -> Self {
         SyntaxNode {
             syntax,
+            pos: Position::default(),
+            lexeme: String::new(),
             children: vec![],
         }
     }
 
-    fn get_ref_syntax(&self) -> &Syntax {
+    pub fn get_ref_syntax(&self) -> &Syntax {
         &self.syntax
     }
 
-    fn append_child(&mut self, child: SyntaxNode) {
+    /// Where the node starts in the source; for a non-terminal, the position
+    /// of its first token.
+    pub fn get_pos(&self) -> Position {
+        self.pos
+    }
+
+    /// Original spelling of a token node, which may differ from the token
+    /// itself when identifiers are case-folded. Empty for non-terminals.
+    pub fn get_ref_lexeme(&self) -> &str {
+        &self.lexeme
+    }
+
+    /// A token node spelt `lexeme`, for passes that rewrite a tree.
+    pub(crate) fn new_token(token: Token, pos: Position, lexeme: String) -> Self {
+        SyntaxNode {
+            syntax: Syntax::Token(token),
+            pos,
+            lexeme,
+            children: vec![],
+        }
+    }
+
+    pub(crate) fn append_child(&mut self, child: SyntaxNode) {
+        if self.children.is_empty() {
+            self.pos = child.pos;
+        }
         self.children.push(child);
     }
 
-    fn has_child(&self) -> bool {
+    pub fn has_child(&self) -> bool {
         !self.children.is_empty()
     }
 
-    fn get_ref_children(&self) -> &Vec<SyntaxNode> {
+    pub fn get_ref_children(&self) -> &Vec<SyntaxNode> {
         &self.children
     }
+
+    /// The token of a token node.
+    pub fn get_ref_token(&self) -> Option<&Token> {
+        match &self.syntax {
+            Syntax::Token(t) => Some(t),
+            _ => None,
+        }
+    }
+
+    /// The (canonical) name of an identifier node.
+    pub fn get_ref_identifier(&self) -> Option<&str> {
+        match &self.syntax {
+            Syntax::Token(Token::Identifier(s)) => Some(s),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -57,61 +103,158 @@ pub struct SyntaxTree {
 }
 
 impl SyntaxTree {
-    fn new(root: SyntaxNode) -> Self {
+    pub(crate) fn new(root: SyntaxNode) -> Self {
         SyntaxTree {
             root
         }
     }
 
-    fn get_ref_root(&self) -> &SyntaxNode {
+    pub fn get_ref_root(&self) -> &SyntaxNode {
         &self.root
     }
+
+    /// Writes the tree as nested XML elements, one token per line in the
+    /// format of the token dump.
+    pub fn write_xml<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        write_xml_node(&self.root, 0, w)
+    }
 }
 
-#[derive(Debug)]
+fn write_xml_node<W: Write>(node: &SyntaxNode, depth: usize, w: &mut W) -> io::Result<()> {
+    let indent = "  ".repeat(depth);
+    let tag = match node.get_ref_syntax() {
+        Syntax::Program    => "program",
+        Syntax::Block      => "block",
+        Syntax::ConstDecl  => "constDecl",
+        Syntax::VarDecl    => "varDecl",
+        Syntax::FuncDecl   => "funcDecl",
+        Syntax::ProcDecl   => "procDecl",
+        Syntax::Statement  => "statement",
+        Syntax::Condition  => "condition",
+        Syntax::Expression => "expression",
+        Syntax::Term       => "term",
+        Syntax::Factor     => "factor",
+        Syntax::Token(t)   => {
+            return writeln!(w, "{}{}", indent, xml_token(t));
+        },
+    };
+    if node.has_child() {
+        writeln!(w, "{}<{}>", indent, tag)?;
+        for child in node.get_ref_children() {
+            write_xml_node(child, depth + 1, w)?;
+        }
+        writeln!(w, "{}</{}>", indent, tag)
+    } else {
+        writeln!(w, "{}<{} />", indent, tag)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum ParserError {
-    ReachedEOF,
-    ExpectedEOF,
+    ReachedEOF(Position),
+    ExpectedEOF(Position),
+    Unexpected {
+        expected: String,
+        found: String,
+        pos: Position,
+    },
     Unrecoverable,
 }
 
+impl ParserError {
+    pub fn pos(&self) -> Option<Position> {
+        match self {
+            ParserError::ReachedEOF(pos) | ParserError::ExpectedEOF(pos) => Some(*pos),
+            ParserError::Unexpected { pos, .. } => Some(*pos),
+            ParserError::Unrecoverable => None,
+        }
+    }
+}
+
+impl fmt::Display for ParserError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParserError::ReachedEOF(pos) => {
+                write!(f, "{}: unexpected end of file", pos)
+            },
+            ParserError::ExpectedEOF(pos) => {
+                write!(f, "{}: unexpected token after the final `.`", pos)
+            },
+            ParserError::Unexpected { expected, found, pos } => {
+                write!(f, "{}: expected {}, found `{}`", pos, expected, found)
+            },
+            ParserError::Unrecoverable => {
+                write!(f, "cannot read the source")
+            },
+        }
+    }
+}
+
+/// What a parenthesized part of a condition turned out to be.
+enum Parenthesized {
+    Condition(SyntaxNode),
+    Expression(SyntaxNode),
+}
+
 pub struct Parser {
     tokenizer: Tokenizer,
     current_token: Token,
+    reached_eof: bool,
+    lex_errors: Vec<LexError>,
 }
 
 impl Parser {
-    pub fn new(mut t: Tokenizer) -> Self {
-        let token = t.get_next_token().unwrap();
+    pub fn new(t: Tokenizer) -> Self {
         Parser {
             tokenizer: t,
-            current_token: token,
+            current_token: Token::Symbol(Symbol::Period),
+            reached_eof: false,
+            lex_errors: vec![],
         }
     }
-    
-    pub fn parse(&mut self) -> SyntaxTree {
-        SyntaxTree::new(self.parse_program())
+
+    /// Lexical errors skipped over while parsing, in source order.
+    pub fn lex_errors(&self) -> &[LexError] {
+        &self.lex_errors
     }
 
-    fn parse_program(&mut self) -> SyntaxNode {
+    /// Parses a whole program. Parsing stops at the first syntax error;
+    /// lexical errors do not stop it and are collected in `lex_errors`.
+    pub fn parse(&mut self) -> Result<SyntaxTree, ParserError> {
+        self.read_next_token()?;
+        let parsed = self.parse_program().and_then(|root| {
+            if self.reached_eof {
+                Ok(SyntaxTree::new(root))
+            } else {
+                Err(ParserError::ExpectedEOF(self.tokenizer.token_pos()))
+            }
+        });
+        if parsed.is_err() {
+            // read on so that every lexical error gets reported
+            while !self.reached_eof && self.read_next_token().is_ok() {}
+        }
+        parsed
+    }
+
+    fn parse_program(&mut self) -> Result<SyntaxNode, ParserError> {
         let mut node = SyntaxNode::new(Syntax::Program);
-        node.append_child(self.parse_block());
-        node.append_child(self.parse_token_expect(Token::Symbol(Symbol::Period)));
-        node
+        node.append_child(self.parse_block()?);
+        node.append_child(self.parse_token_expect(Token::Symbol(Symbol::Period))?);
+        Ok(node)
     }
 
-    fn parse_block(&mut self) -> SyntaxNode {
+    fn parse_block(&mut self) -> Result<SyntaxNode, ParserError> {
         let mut node = SyntaxNode::new(Syntax::Block);
         loop {
             let child = match self.current_token {
-                Token::Keyword(Keyword::Const) => {
-                    self.parse_const_decl()
+                Token::Keyword(Keyword::Const) if !self.reached_eof => {
+                    self.parse_const_decl()?
                 },
-                Token::Keyword(Keyword::Var) => {
-                    self.parse_var_decl()
+                Token::Keyword(Keyword::Var) if !self.reached_eof => {
+                    self.parse_var_decl()?
                 },
-                Token::Keyword(Keyword::Func) => {
-                    self.parse_func_decl()
+                Token::Keyword(Keyword::Func | Keyword::Proc) if !self.reached_eof => {
+                    self.parse_func_decl()?
                 },
                 _ => {
                     break;
@@ -119,259 +262,595 @@ impl Parser {
             };
             node.append_child(child);
         }
-        node.append_child(self.parse_statement());
-        node
+        node.append_child(self.parse_statement()?);
+        Ok(node)
     }
-    
-    fn parse_const_decl(&mut self) -> SyntaxNode {
+
+    fn parse_const_decl(&mut self) -> Result<SyntaxNode, ParserError> {
         let mut node = SyntaxNode::new(Syntax::ConstDecl);
-        node.append_child(self.parse_token()); // const
+        node.append_child(self.parse_token()?); // const
         loop {
-            node.append_child(self.parse_token()); // ident
-            node.append_child(self.parse_token_expect(Token::Symbol(Symbol::Equal)));
-            node.append_child(self.parse_token()); // number
+            node.append_child(self.parse_identifier()?);
+            node.append_child(self.parse_token_expect(Token::Symbol(Symbol::Equal))?);
+            match self.current_token {
+                Token::Number(_) => {
+                    node.append_child(self.parse_token()?); // number
+                },
+                _ => {
+                    return Err(self.unexpected("a number"));
+                }
+            }
             if Token::Symbol(Symbol::Comma) == self.current_token {
-                node.append_child(self.parse_token()); // ,
+                node.append_child(self.parse_token()?); // ,
             } else {
                 break;
             }
         }
-        node.append_child(self.parse_token_expect(Token::Symbol(Symbol::SemiColon)));
-        node
+        node.append_child(self.parse_token_expect(Token::Symbol(Symbol::SemiColon))?);
+        Ok(node)
     }
 
-    fn parse_var_decl(&mut self) -> SyntaxNode {
+    fn parse_var_decl(&mut self) -> Result<SyntaxNode, ParserError> {
         let mut node = SyntaxNode::new(Syntax::VarDecl);
-        node.append_child(self.parse_token()); // var
+        node.append_child(self.parse_token()?); // var
         loop {
-            node.append_child(self.parse_token()); // ident
+            node.append_child(self.parse_identifier()?);
+            if Token::Symbol(Symbol::Lbracket) == self.current_token {
+                node.append_child(self.parse_token()?); // [
+                match self.current_token {
+                    Token::Number(_) if !self.reached_eof => {
+                        node.append_child(self.parse_token()?); // array size
+                    },
+                    _ => {
+                        return Err(self.unexpected("an array size"));
+                    }
+                }
+                node.append_child(self.parse_token_expect(Token::Symbol(Symbol::Rbracket))?);
+            }
             if Token::Symbol(Symbol::Comma) == self.current_token {
-                node.append_child(self.parse_token()); // ,
+                node.append_child(self.parse_token()?); // ,
             } else {
                 break;
             }
         }
-        node.append_child(self.parse_token_expect(Token::Symbol(Symbol::SemiColon)));
-        node
+        node.append_child(self.parse_token_expect(Token::Symbol(Symbol::SemiColon))?);
+        Ok(node)
     }
 
-    fn parse_func_decl(&mut self) -> SyntaxNode {
-        let mut node = SyntaxNode::new(Syntax::FuncDecl);
-        node.append_child(self.parse_token()); // function
-        node.append_child(self.parse_token()); // ident
-        node.append_child(self.parse_token_expect(Token::Symbol(Symbol::Lparen)));
+    fn parse_func_decl(&mut self) -> Result<SyntaxNode, ParserError> {
+        let mut node = match self.current_token {
+            Token::Keyword(Keyword::Proc) => SyntaxNode::new(Syntax::ProcDecl),
+            _ => SyntaxNode::new(Syntax::FuncDecl),
+        };
+        node.append_child(self.parse_token()?); // function or procedure
+        node.append_child(self.parse_identifier()?);
+        node.append_child(self.parse_token_expect(Token::Symbol(Symbol::Lparen))?);
         while let Token::Identifier(_) = self.current_token {
-            node.append_child(self.parse_token()); // ident
+            node.append_child(self.parse_token()?); // ident
             if Token::Symbol(Symbol::Comma) == self.current_token {
-                node.append_child(self.parse_token()); // ,
+                node.append_child(self.parse_token()?); // ,
             } else {
                 break;
             }
         }
-        node.append_child(self.parse_token_expect(Token::Symbol(Symbol::Rparen)));
-        node.append_child(self.parse_block());
-        node.append_child(self.parse_token_expect(Token::Symbol(Symbol::SemiColon)));
-        node
+        node.append_child(self.parse_token_expect(Token::Symbol(Symbol::Rparen))?);
+        node.append_child(self.parse_block()?);
+        node.append_child(self.parse_token_expect(Token::Symbol(Symbol::SemiColon))?);
+        Ok(node)
     }
 
-    fn parse_statement(&mut self) -> SyntaxNode {
+    fn parse_statement(&mut self) -> Result<SyntaxNode, ParserError> {
         let mut node = SyntaxNode::new(Syntax::Statement);
+        if self.reached_eof {
+            return Ok(node);
+        }
         match self.current_token {
             Token::Identifier(_) => {
-                node.append_child(self.parse_token()); // ident
-                node.append_child(self.parse_token_expect(Token::Symbol(Symbol::Assign)));
-                node.append_child(self.parse_expression());
+                node.append_child(self.parse_token()?); // ident
+                if Token::Symbol(Symbol::Lparen) == self.current_token {
+                    // a call for its side effects
+                    self.parse_arguments(&mut node)?;
+                    return Ok(node);
+                }
+                if Token::Symbol(Symbol::Lbracket) == self.current_token {
+                    node.append_child(self.parse_token()?); // [
+                    node.append_child(self.parse_expression()?);
+                    node.append_child(self.parse_token_expect(Token::Symbol(Symbol::Rbracket))?);
+                }
+                node.append_child(self.parse_token_expect(Token::Symbol(Symbol::Assign))?);
+                node.append_child(self.parse_expression()?);
+            },
+            Token::Keyword(Keyword::Call) => {
+                node.append_child(self.parse_token()?); // call
+                node.append_child(self.parse_identifier()?);
+                if Token::Symbol(Symbol::Lparen) == self.current_token {
+                    self.parse_arguments(&mut node)?;
+                }
             },
             Token::Keyword(Keyword::Begin) => {
-                node.append_child(self.parse_token()); // begin
+                node.append_child(self.parse_token()?); // begin
                 loop {
-                    node.append_child(self.parse_statement());
+                    node.append_child(self.parse_statement()?);
                     if Token::Symbol(Symbol::SemiColon) == self.current_token {
-                        node.append_child(self.parse_token()); // ;
+                        node.append_child(self.parse_token()?); // ;
                     } else {
                         break;
                     }
                 }
-                node.append_child(self.parse_token_expect(Token::Keyword(Keyword::End)));
+                node.append_child(self.parse_token_expect(Token::Keyword(Keyword::End))?);
             },
             Token::Keyword(Keyword::If) => {
-                node.append_child(self.parse_token()); // if
-                node.append_child(self.parse_condition());
-                node.append_child(self.parse_token_expect(Token::Keyword(Keyword::Then)));
-                node.append_child(self.parse_statement());
+                node.append_child(self.parse_token()?); // if
+                node.append_child(self.parse_condition()?);
+                node.append_child(self.parse_token_expect(Token::Keyword(Keyword::Then))?);
+                node.append_child(self.parse_statement()?);
+                // a dangling `else` belongs to the innermost `if`, which is
+                // the one whose then-branch has just been parsed
+                if Token::Keyword(Keyword::Else) == self.current_token && !self.reached_eof {
+                    node.append_child(self.parse_token()?); // else
+                    node.append_child(self.parse_statement()?);
+                }
             },
             Token::Keyword(Keyword::While) => {
-                node.append_child(self.parse_token()); // while
-                node.append_child(self.parse_condition());
-                node.append_child(self.parse_token_expect(Token::Keyword(Keyword::Do)));
-                node.append_child(self.parse_statement());
+                node.append_child(self.parse_token()?); // while
+                node.append_child(self.parse_condition()?);
+                node.append_child(self.parse_token_expect(Token::Keyword(Keyword::Do))?);
+                node.append_child(self.parse_statement()?);
+            },
+            Token::Keyword(Keyword::For) => {
+                node.append_child(self.parse_token()?); // for
+                node.append_child(self.parse_identifier()?);
+                node.append_child(self.parse_token_expect(Token::Symbol(Symbol::Assign))?);
+                node.append_child(self.parse_expression()?);
+                match self.current_token {
+                    Token::Keyword(Keyword::To | Keyword::Downto) if !self.reached_eof => {
+                        node.append_child(self.parse_token()?); // to or downto
+                    },
+                    _ => {
+                        return Err(self.unexpected("`to` or `downto`"));
+                    }
+                }
+                node.append_child(self.parse_expression()?);
+                node.append_child(self.parse_token_expect(Token::Keyword(Keyword::Do))?);
+                node.append_child(self.parse_statement()?);
+            },
+            Token::Keyword(Keyword::Repeat) => {
+                node.append_child(self.parse_token()?); // repeat
+                loop {
+                    node.append_child(self.parse_statement()?);
+                    if Token::Symbol(Symbol::SemiColon) == self.current_token {
+                        node.append_child(self.parse_token()?); // ;
+                    } else {
+                        break;
+                    }
+                }
+                node.append_child(self.parse_token_expect(Token::Keyword(Keyword::Until))?);
+                node.append_child(self.parse_condition()?);
+            },
+            Token::Keyword(Keyword::Break | Keyword::Continue) => {
+                node.append_child(self.parse_token()?); // break or continue
             },
             Token::Keyword(Keyword::Ret) => {
-                node.append_child(self.parse_token()); // return
-                node.append_child(self.parse_expression());
+                node.append_child(self.parse_token()?); // return
+                node.append_child(self.parse_expression()?);
             },
             Token::Keyword(Keyword::Write) => {
-                node.append_child(self.parse_token()); // write
-                node.append_child(self.parse_expression());
+                node.append_child(self.parse_token()?); // write
+                loop {
+                    match self.current_token {
+                        Token::String(_) if !self.reached_eof => {
+                            node.append_child(self.parse_token()?); // string
+                        },
+                        _ => {
+                            node.append_child(self.parse_expression()?);
+                        }
+                    }
+                    if Token::Symbol(Symbol::Comma) == self.current_token {
+                        node.append_child(self.parse_token()?); // ,
+                    } else {
+                        break;
+                    }
+                }
             },
             Token::Keyword(Keyword::WriteLn) => {
-                node.append_child(self.parse_token()); // writeln
+                node.append_child(self.parse_token()?); // writeln
+            },
+            Token::Keyword(Keyword::Read) => {
+                node.append_child(self.parse_token()?); // read
+                node.append_child(self.parse_identifier()?);
+                if Token::Symbol(Symbol::Lbracket) == self.current_token {
+                    node.append_child(self.parse_token()?); // [
+                    node.append_child(self.parse_expression()?);
+                    node.append_child(self.parse_token_expect(Token::Symbol(Symbol::Rbracket))?);
+                }
             },
             _ => (),
         }
-        node
+        Ok(node)
+    }
+
+    // condition    = conjunction { "or" conjunction }
+    // conjunction  = negation { "and" negation }
+    // negation     = "not" negation | "odd" expression | "(" condition ")"
+    //              | expression relational-op expression
+    //
+    // A `(` opening a negation may as well open the first factor of an
+    // expression, so the parenthesized part is parsed first and then taken
+    // as one or the other. A single negation or conjunction is not wrapped
+    // in a node of its own.
+    fn parse_condition(&mut self) -> Result<SyntaxNode, ParserError> {
+        let first = self.parse_negation()?;
+        self.parse_condition_from(first)
+    }
+
+    fn parse_condition_from(&mut self, first: SyntaxNode) -> Result<SyntaxNode, ParserError> {
+        let first = self.parse_conjunction_from(first)?;
+        if !self.at_keyword(Keyword::Or) {
+            return Ok(first);
+        }
+        let mut node = SyntaxNode::new(Syntax::Condition);
+        node.append_child(first);
+        while self.at_keyword(Keyword::Or) {
+            node.append_child(self.parse_token()?); // or
+            let operand = self.parse_negation()?;
+            node.append_child(self.parse_conjunction_from(operand)?);
+        }
+        Ok(node)
     }
 
-    fn parse_condition(&mut self) -> SyntaxNode {
+    fn parse_conjunction_from(&mut self, first: SyntaxNode) -> Result<SyntaxNode, ParserError> {
+        if !self.at_keyword(Keyword::And) {
+            return Ok(first);
+        }
         let mut node = SyntaxNode::new(Syntax::Condition);
-        if Token::Keyword(Keyword::Odd) == self.current_token {
-            node.append_child(self.parse_token()); // odd
-            node.append_child(self.parse_expression());
+        node.append_child(first);
+        while self.at_keyword(Keyword::And) {
+            node.append_child(self.parse_token()?); // and
+            node.append_child(self.parse_negation()?);
+        }
+        Ok(node)
+    }
+
+    fn parse_negation(&mut self) -> Result<SyntaxNode, ParserError> {
+        if self.at_keyword(Keyword::Not) {
+            let mut node = SyntaxNode::new(Syntax::Condition);
+            node.append_child(self.parse_token()?); // not
+            node.append_child(self.parse_negation()?);
+            Ok(node)
+        } else if self.at_keyword(Keyword::Odd) {
+            let mut node = SyntaxNode::new(Syntax::Condition);
+            node.append_child(self.parse_token()?); // odd
+            node.append_child(self.parse_expression()?);
+            Ok(node)
+        } else if Token::Symbol(Symbol::Lparen) == self.current_token && !self.reached_eof {
+            match self.parse_parenthesized()? {
+                Parenthesized::Condition(node) => Ok(node),
+                Parenthesized::Expression(lhs) => self.parse_relation_from(lhs),
+            }
         } else {
-            node.append_child(self.parse_expression());
-            node.append_child(self.parse_token()); // bool op.
-            node.append_child(self.parse_expression());
+            let lhs = self.parse_expression()?;
+            self.parse_relation_from(lhs)
         }
-        node
     }
 
-    fn parse_expression(&mut self) -> SyntaxNode {
-        let mut node = SyntaxNode::new(Syntax::Expression);
-        if let Token::Symbol(sym) = self.current_token {
-            match sym {
-                Symbol::Plus | Symbol::Minus => {
-                    node.append_child(self.parse_token()); // + or -
+    fn parse_relation_from(&mut self, lhs: SyntaxNode) -> Result<SyntaxNode, ParserError> {
+        let mut node = SyntaxNode::new(Syntax::Condition);
+        node.append_child(lhs);
+        match self.current_token {
+            Token::Symbol(Symbol::Equal | Symbol::NotEq | Symbol::Lss | Symbol::Gtr | Symbol::LssEq | Symbol::GtrEq) if !self.reached_eof => {
+                node.append_child(self.parse_token()?); // bool op.
+            },
+            _ => {
+                return Err(self.unexpected("a relational operator"));
+            }
+        }
+        node.append_child(self.parse_expression()?);
+        Ok(node)
+    }
+
+    /// Parses `( ... )` where either a condition or an expression is
+    /// allowed. An expression is continued to its end, as in `(a + b) * c`.
+    fn parse_parenthesized(&mut self) -> Result<Parenthesized, ParserError> {
+        let open = self.parse_token()?; // (
+        let inner = if self.at_keyword(Keyword::Not) || self.at_keyword(Keyword::Odd) {
+            Parenthesized::Condition(self.parse_condition()?)
+        } else {
+            let first = if Token::Symbol(Symbol::Lparen) == self.current_token && !self.reached_eof {
+                self.parse_parenthesized()?
+            } else {
+                Parenthesized::Expression(self.parse_expression()?)
+            };
+            match first {
+                Parenthesized::Condition(c) => {
+                    Parenthesized::Condition(self.parse_condition_from(c)?)
+                },
+                Parenthesized::Expression(e) if self.at_relational_operator() => {
+                    let relation = self.parse_relation_from(e)?;
+                    Parenthesized::Condition(self.parse_condition_from(relation)?)
                 },
-                _ => (),
+                e => e,
             }
+        };
+        let close = self.parse_token_expect(Token::Symbol(Symbol::Rparen))?;
+        match inner {
+            Parenthesized::Condition(c) => {
+                let mut node = SyntaxNode::new(Syntax::Condition);
+                node.append_child(open);
+                node.append_child(c);
+                node.append_child(close);
+                Ok(Parenthesized::Condition(node))
+            },
+            Parenthesized::Expression(e) => {
+                let mut factor = SyntaxNode::new(Syntax::Factor);
+                factor.append_child(open);
+                factor.append_child(e);
+                factor.append_child(close);
+                let term = self.parse_term_from(factor)?;
+                Ok(Parenthesized::Expression(self.parse_expression_from(term)?))
+            },
         }
-        node.append_child(self.parse_term());
+    }
+
+    fn at_keyword(&self, kw: Keyword) -> bool {
+        Token::Keyword(kw) == self.current_token && !self.reached_eof
+    }
+
+    fn at_relational_operator(&self) -> bool {
+        matches!(self.current_token, Token::Symbol(Symbol::Equal | Symbol::NotEq | Symbol::Lss | Symbol::Gtr | Symbol::LssEq | Symbol::GtrEq))
+            && !self.reached_eof
+    }
+
+    fn parse_expression(&mut self) -> Result<SyntaxNode, ParserError> {
+        let mut node = SyntaxNode::new(Syntax::Expression);
+        if let Token::Symbol(Symbol::Plus | Symbol::Minus) = self.current_token {
+            node.append_child(self.parse_token()?); // + or -
+        }
+        node.append_child(self.parse_term()?);
+        self.parse_expression_rest(node)
+    }
+
+    /// Continues an expression whose first term has been parsed as `first`.
+    fn parse_expression_from(&mut self, first: SyntaxNode) -> Result<SyntaxNode, ParserError> {
+        let mut node = SyntaxNode::new(Syntax::Expression);
+        node.append_child(first);
+        self.parse_expression_rest(node)
+    }
+
+    fn parse_expression_rest(&mut self, mut node: SyntaxNode) -> Result<SyntaxNode, ParserError> {
         while let Token::Symbol(sym) = self.current_token {
             match sym {
                 Symbol::Plus | Symbol::Minus => {
-                    node.append_child(self.parse_token()); // + or -
-                    node.append_child(self.parse_term());
+                    node.append_child(self.parse_token()?); // + or -
+                    node.append_child(self.parse_term()?);
                 },
                 _ => {
                     break;
                 }
             }
         }
-        node
+        Ok(node)
     }
 
-    fn parse_term(&mut self) -> SyntaxNode {
+    fn parse_term(&mut self) -> Result<SyntaxNode, ParserError> {
+        let first = self.parse_factor()?;
+        self.parse_term_from(first)
+    }
+
+    /// Continues a term whose first factor has been parsed as `first`.
+    fn parse_term_from(&mut self, first: SyntaxNode) -> Result<SyntaxNode, ParserError> {
         let mut node = SyntaxNode::new(Syntax::Term);
-        node.append_child(self.parse_factor());
-        while let Token::Symbol(sym) = self.current_token {
-            match sym {
-                Symbol::Mult | Symbol::Div => {
-                    node.append_child(self.parse_token()); // * or /
-                    node.append_child(self.parse_factor());
-                },
+        node.append_child(first);
+        loop {
+            match self.current_token {
+                Token::Symbol(Symbol::Mult | Symbol::Div | Symbol::Mod) => (),
+                Token::Keyword(Keyword::Mod) if !self.reached_eof => (),
                 _ => {
                     break;
                 }
             }
+            node.append_child(self.parse_token()?); // *, /, % or mod
+            node.append_child(self.parse_factor()?);
         }
-        node
+        Ok(node)
     }
 
-    fn parse_factor(&mut self) -> SyntaxNode {
+    fn parse_factor(&mut self) -> Result<SyntaxNode, ParserError> {
         let mut node = SyntaxNode::new(Syntax::Factor);
+        if self.reached_eof {
+            return Err(ParserError::ReachedEOF(self.tokenizer.token_pos()));
+        }
         match self.current_token {
             Token::Identifier(_) => {
-                node.append_child(self.parse_token()); // ident
-                if Token::Symbol(Symbol::Lparen) == self.current_token {
-                    node.append_child(self.parse_token()); // (
-                    while Token::Symbol(Symbol::Rparen) != self.current_token {
-                        node.append_child(self.parse_expression());
-                        if Token::Symbol(Symbol::Comma) == self.current_token {
-                            node.append_child(self.parse_token()); // ,
-                        } else {
-                            break;
-                        }
-                    }
-                    node.append_child(self.parse_token_expect(Token::Symbol(Symbol::Rparen)));
+                node.append_child(self.parse_token()?); // ident
+                if Token::Symbol(Symbol::Lbracket) == self.current_token {
+                    node.append_child(self.parse_token()?); // [
+                    node.append_child(self.parse_expression()?);
+                    node.append_child(self.parse_token_expect(Token::Symbol(Symbol::Rbracket))?);
+                } else if Token::Symbol(Symbol::Lparen) == self.current_token {
+                    self.parse_arguments(&mut node)?;
                 }
             },
             Token::Number(_) => {
-                node.append_child(self.parse_token()); // number
+                node.append_child(self.parse_token()?); // number
             },
             Token::Symbol(Symbol::Lparen) => {
-                node.append_child(self.parse_token()); // (
-                node.append_child(self.parse_expression());
-                node.append_child(self.parse_token_expect(Token::Symbol(Symbol::Rparen)));
+                node.append_child(self.parse_token()?); // (
+                node.append_child(self.parse_expression()?);
+                node.append_child(self.parse_token_expect(Token::Symbol(Symbol::Rparen))?);
+            },
+            _ => {
+                return Err(self.unexpected("an expression"));
+            }
+        }
+        Ok(node)
+    }
+
+    /// Parses `( [expression { , expression }] )` into `node`.
+    fn parse_arguments(&mut self, node: &mut SyntaxNode) -> Result<(), ParserError> {
+        node.append_child(self.parse_token()?); // (
+        while Token::Symbol(Symbol::Rparen) != self.current_token {
+            node.append_child(self.parse_expression()?);
+            if Token::Symbol(Symbol::Comma) == self.current_token {
+                node.append_child(self.parse_token()?); // ,
+            } else {
+                break;
+            }
+        }
+        node.append_child(self.parse_token_expect(Token::Symbol(Symbol::Rparen))?);
+        Ok(())
+    }
+
+    fn parse_identifier(&mut self) -> Result<SyntaxNode, ParserError> {
+        match self.current_token {
+            Token::Identifier(_) if !self.reached_eof => {
+                self.parse_token()
             },
             _ => {
-                panic!("syntax error");
+                Err(self.unexpected("an identifier"))
             }
         }
-        node
     }
 
-    fn parse_token_expect(&mut self, token: Token) -> SyntaxNode {
-        if token == self.current_token {
+    fn parse_token_expect(&mut self, token: Token) -> Result<SyntaxNode, ParserError> {
+        if token == self.current_token && !self.reached_eof {
             self.parse_token()
         } else {
-            panic!("{:?} expected, found {:?}", token, self.current_token);
+            Err(self.unexpected(&format!("`{}`", token)))
         }
     }
 
-    fn parse_token(&mut self) -> SyntaxNode {
+    fn parse_token(&mut self) -> Result<SyntaxNode, ParserError> {
+        if self.reached_eof {
+            return Err(ParserError::ReachedEOF(self.tokenizer.token_pos()));
+        }
         let mut node = SyntaxNode::new(Syntax::Token(self.current_token.clone()));
-        match self.tokenizer.get_next_token() {
-            Ok(t) => {
-                self.current_token = t;
-            },
-            Err(TokenizerError::ReachedEOF) => (),
-            _ => {
-                panic!("unexpected error");
+        node.pos = self.tokenizer.token_pos();
+        node.lexeme = self.tokenizer.lexeme().to_string();
+        self.read_next_token()?;
+        Ok(node)
+    }
+
+    fn unexpected(&self, expected: &str) -> ParserError {
+        if self.reached_eof {
+            ParserError::ReachedEOF(self.tokenizer.token_pos())
+        } else {
+            ParserError::Unexpected {
+                expected: expected.to_string(),
+                found: self.tokenizer.lexeme().to_string(),
+                pos: self.tokenizer.token_pos(),
+            }
+        }
+    }
+
+    fn read_next_token(&mut self) -> Result<(), ParserError> {
+        loop {
+            match self.tokenizer.get_next_token() {
+                Ok(Token::Error(e)) => {
+                    self.lex_errors.push(e);
+                },
+                Ok(t) => {
+                    self.current_token = t;
+                    return Ok(());
+                },
+                Err(TokenizerError::ReachedEOF) => {
+                    self.reached_eof = true;
+                    return Ok(());
+                },
+                Err(_) => {
+                    return Err(ParserError::Unrecoverable);
+                }
             }
         }
-        node
     }
 }
 
 #[cfg(test)]
 mod tests {
     #[test]
-    fn test_parse() {
+    fn test_syntax_errors() {
         use super::*;
-        use std::path::Path;
-        use std::fs::File;
-        use std::io::{BufWriter, Write};
-        use std::process::Command;
-        use crate::tokenizer::*;
-
-        // pair list of full path of *.pl0
-        let mut filenames_input = vec![]; 
-        let src_path = Path::new("/workspace/pl0dash-compiler/pl0dash_compiler/pl0/");
-        for f in src_path.read_dir().expect("read_dir call failed") {
-            if let Ok(f) = f {
-                if f.path().extension().unwrap() == "pl0" {
-                    let input_filename = f.path().to_string_lossy().into_owned();
-                    filenames_input.push(input_filename);
-                }
+
+        let parse = |src: &'static str| Parser::new(Tokenizer::new(src.as_bytes())).parse();
+        assert_eq!(parse("var x; begin x := 1; x 2 end.").unwrap_err().to_string(),
+                   "1:24: expected `:=`, found `2`");
+        assert_eq!(parse("var x; begin x := end.").unwrap_err().to_string(),
+                   "1:19: expected an expression, found `end`");
+        assert_eq!(parse("var x; begin if x # 1 then x := 1 end.").unwrap_err().to_string(),
+                   "1:21: expected a relational operator, found `1`");
+        assert_eq!(parse("var x; begin x := (1 + 2").unwrap_err().to_string(),
+                   "1:24: unexpected end of file");
+        assert_eq!(parse("var x; x := 1. x").unwrap_err().to_string(),
+                   "1:16: unexpected token after the final `.`");
+
+        let mut p = Parser::new(Tokenizer::new("var x; begin x := 1 # end.".as_bytes()));
+        assert!(p.parse().is_ok());
+        assert_eq!(p.lex_errors().len(), 1);
+    }
+
+    #[test]
+    fn test_parse_keeps_spelling() {
+        use super::*;
+
+        let options = TokenizerOptions { case_insensitive: true, ..Default::default() };
+        let t = Tokenizer::with_options("VAR Total;\nBEGIN Total := 1 END.".as_bytes(), options);
+        let syn_tree = Parser::new(t).parse().unwrap();
+        let block = &syn_tree.get_ref_root().get_ref_children()[0];
+        let var_decl = &block.get_ref_children()[0];
+        let ident = &var_decl.get_ref_children()[1];
+        assert_eq!(ident.get_ref_syntax(), &Syntax::Token(Token::Identifier("total".to_string())));
+        assert_eq!(ident.get_ref_lexeme(), "Total");
+        assert_eq!(ident.get_pos(), Position::new(1, 5));
+        let stmt = &block.get_ref_children()[1];
+        assert_eq!(stmt.get_pos(), Position::new(2, 1));
+    }
+
+    #[test]
+    fn test_dangling_else() {
+        use super::*;
+
+        let t = Tokenizer::new("var x; if x = 0 then if x = 1 then x := 1 else x := 2.".as_bytes());
+        let syn_tree = Parser::new(t).parse().unwrap();
+        let block = &syn_tree.get_ref_root().get_ref_children()[0];
+        let outer_if = &block.get_ref_children()[1];
+        assert_eq!(outer_if.get_ref_children().len(), 4);
+        let inner_if = &outer_if.get_ref_children()[3];
+        assert_eq!(inner_if.get_ref_children().len(), 6);
+        assert_eq!(inner_if.get_ref_children()[4].get_ref_token(), Some(&Token::Keyword(Keyword::Else)));
+    }
+
+    #[test]
+    fn test_boolean_conditions() {
+        use super::*;
+
+        // renders every condition node in braces and everything else as its spelling
+        fn render(node: &SyntaxNode) -> String {
+            if node.get_ref_token().is_some() {
+                return node.get_ref_lexeme().to_string();
+            }
+            let inner: Vec<String> = node.get_ref_children().iter().map(render).collect();
+            match node.get_ref_syntax() {
+                Syntax::Condition => format!("{{{}}}", inner.join(" ")),
+                _ => inner.join(" "),
             }
         }
+        fn condition(src: &str) -> String {
+            let src = format!("var a, b, c; if {} then a := 1.", src);
+            let t = Tokenizer::new(std::io::Cursor::new(src.into_bytes()));
+            let syn_tree = Parser::new(t).parse().unwrap();
+            let block = &syn_tree.get_ref_root().get_ref_children()[0];
+            render(&block.get_ref_children()[1].get_ref_children()[1])
+        }
 
-        // parse *.pl0 and show the syntax trees
-        for fin in filenames_input.iter() {
-            // tokenize
-            let input_file = File::open(fin).expect("cannot open input file");
-            let mut t = Tokenizer::new(input_file);
-            
-            // parse
-            let mut p = Parser::new(t);
-            let syn_tree = p.parse();
-            println!("parse finished. printing syn_tree...");
-            println!("{:?}", syn_tree);
+        assert_eq!(condition("a = 1"), "{a = 1}");
+        assert_eq!(condition("a = 1 or b = 2 and c = 3"), "{{a = 1} or {{b = 2} and {c = 3}}}");
+        assert_eq!(condition("not a = 1 and b = 2"), "{{not {a = 1}} and {b = 2}}");
+        assert_eq!(condition("(a = 1 or b = 2) and not odd c"), "{{( {{a = 1} or {b = 2}} )} and {not {odd c}}}");
+        assert_eq!(condition("(a + 1) * 2 < c"), "{( a + 1 ) * 2 < c}");
+        assert_eq!(condition("((a)) = b or ((a < b))"), "{{( ( a ) ) = b} or {( {( {a < b} )} )}}");
 
-            // compare two files
-            //let forg = Path::new(fout).with_extension("xml.org").to_string_lossy().into_owned();
-            //let diff_status = Command::new("diff").args(["-b", "-u", "-w", &fout, &forg]).status().expect("failed to execute process");
-            //assert!(diff_status.success());
-        }
+        let t = Tokenizer::new("var a; if (a + 1) then a := 1.".as_bytes());
+        assert_eq!(Parser::new(t).parse().unwrap_err().to_string(), "1:19: expected a relational operator, found `then`");
+        let t = Tokenizer::new("var a; if (a = 1 then a := 1.".as_bytes());
+        assert_eq!(Parser::new(t).parse().unwrap_err().to_string(), "1:18: expected `)`, found `then`");
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,209 @@
+// Peephole optimization of stack machine code. The code generator works one
+// syntax tree node at a time and leaves patterns that a look at neighbouring
+// instructions can tidy up:
+//
+//   - `lit 0; opr add` and `lit 0; opr sub`, `lit 1; opr mul` and
+//     `lit 1; opr div` leave the value below as it is, and go,
+//   - a `jmp` to the next instruction goes,
+//   - a jump to a `jmp` goes straight to where that one leads,
+//   - `sto x; lod x` becomes `opr dup; sto x`, which keeps the value without
+//     reading it back.
+//
+// An instruction some jump lands on starts a different path through the
+// code, so no pattern spans one. Removing instructions moves the rest, and
+// every jump and call is pointed at where its target went; a target removed
+// stands for the instruction after it.
+
+use crate::codegen::*;
+
+/// Rewrites the code of `program` until no pattern is left.
+pub fn peephole(program: &mut Program) {
+    let code = &mut program.code;
+    loop {
+        let mut changed = false;
+        for i in 0..code.len() {
+            if let Inst::Jmp(a) | Inst::Jpc(a) = code[i] {
+                let b = final_target(code, a);
+                if b != a {
+                    set_target(&mut code[i], b);
+                    changed = true;
+                }
+            }
+        }
+
+        let targets = jump_targets(code);
+        let mut removed = vec![false; code.len()];
+        let mut i = 0;
+        while i < code.len() {
+            let next = code.get(i + 1).copied();
+            let landed_on = i + 1 < code.len() && targets[i + 1];
+            match (code[i], next) {
+                (Inst::Lit(0), Some(Inst::Opr(Operator::Add | Operator::Sub)))
+                | (Inst::Lit(1), Some(Inst::Opr(Operator::Mul | Operator::Div))) if !landed_on => {
+                    removed[i] = true;
+                    removed[i + 1] = true;
+                    i += 1;
+                },
+                (Inst::Sto(a), Some(Inst::Lod(b))) if a == b && !landed_on => {
+                    code[i] = Inst::Opr(Operator::Dup);
+                    code[i + 1] = Inst::Sto(a);
+                    changed = true;
+                    i += 1;
+                },
+                // the machine stops when control comes back to address 0,
+                // which has to stay where it is
+                (Inst::Jmp(a), _) if a == i + 1 && i > 0 => {
+                    removed[i] = true;
+                },
+                _ => (),
+            }
+            i += 1;
+        }
+        if removed.contains(&true) {
+            remove(code, &removed);
+            changed = true;
+        }
+        if !changed {
+            break;
+        }
+    }
+}
+
+/// Where control ends up after jumping to `a` and any `jmp` found there.
+fn final_target(code: &[Inst], mut a: usize) -> usize {
+    // a cycle of jumps has no end; stop after going around it
+    for _ in 0..code.len() {
+        match code.get(a) {
+            Some(Inst::Jmp(b)) if *b != a => a = *b,
+            _ => break,
+        }
+    }
+    a
+}
+
+fn set_target(inst: &mut Inst, target: usize) {
+    match inst {
+        Inst::Jmp(a) | Inst::Jpc(a) => *a = target,
+        Inst::Cal(a) => a.addr = target as i32,
+        _ => unreachable!(),
+    }
+}
+
+/// For every address, whether a jump or call lands there.
+fn jump_targets(code: &[Inst]) -> Vec<bool> {
+    let mut targets = vec![false; code.len() + 1];
+    for inst in code {
+        match inst {
+            Inst::Jmp(a) | Inst::Jpc(a) => targets[*a] = true,
+            Inst::Cal(a) => targets[a.addr as usize] = true,
+            _ => (),
+        }
+    }
+    targets
+}
+
+/// Drops the instructions marked in `removed`, pointing every jump and call
+/// at where its target went.
+fn remove(code: &mut Vec<Inst>, removed: &[bool]) {
+    // new address of every instruction, and of the end of the code
+    let mut moved = Vec::with_capacity(code.len() + 1);
+    let mut kept = 0;
+    for &r in removed {
+        moved.push(kept);
+        if !r {
+            kept += 1;
+        }
+    }
+    moved.push(kept);
+    let mut i = 0;
+    code.retain(|_| {
+        i += 1;
+        !removed[i - 1]
+    });
+    for inst in code.iter_mut() {
+        match *inst {
+            Inst::Jmp(a) | Inst::Jpc(a) => set_target(inst, moved[a]),
+            Inst::Cal(a) => set_target(inst, moved[a.addr as usize]),
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokenizer::TokenizerOptions;
+    use crate::compile::*;
+    use crate::vm::*;
+
+    fn optimized(code: Vec<Inst>) -> Vec<Inst> {
+        let mut program = Program { code, strings: vec![] };
+        peephole(&mut program);
+        program.code
+    }
+
+    #[test]
+    fn test_patterns() {
+        let x = RelAddr { level: 0, addr: 2 };
+        assert_eq!(optimized(vec![
+            Inst::Jmp(1),
+            Inst::Ict(3),
+            Inst::Lod(x),
+            Inst::Lit(0),
+            Inst::Opr(Operator::Add),
+            Inst::Lit(1),
+            Inst::Opr(Operator::Mul),
+            Inst::Sto(x),
+            Inst::Lod(x),
+            Inst::Jpc(10),
+            Inst::Jmp(11),
+            Inst::Jmp(12),
+            Inst::Ret(RelAddr { level: 0, addr: 0 }),
+        ]), vec![
+            Inst::Jmp(1),
+            Inst::Ict(3),
+            Inst::Lod(x),
+            Inst::Opr(Operator::Dup),
+            Inst::Sto(x),
+            Inst::Jpc(6),
+            Inst::Ret(RelAddr { level: 0, addr: 0 }),
+        ]);
+    }
+
+    #[test]
+    fn test_jump_targets_are_kept() {
+        let x = RelAddr { level: 0, addr: 2 };
+        // a loop lands between the instructions of each pattern
+        let code = vec![
+            Inst::Jmp(1),
+            Inst::Ict(3),
+            Inst::Sto(x),
+            Inst::Lod(x),
+            Inst::Lit(0),
+            Inst::Opr(Operator::Add),
+            Inst::Jpc(3),
+            Inst::Jmp(5),
+            Inst::Ret(RelAddr { level: 0, addr: 0 }),
+        ];
+        assert_eq!(optimized(code.clone()), code);
+    }
+
+    #[test]
+    fn test_programs() {
+        let src = "\
+            var i, s;
+            function f(n) if n = 0 then return 0 else return n + 0 - 0;
+            begin
+                i := 0; s := 0;
+                while i < 10 do begin i := i + 1; s := s + f(i) * 1 end;
+                write s, i / 1
+            end.";
+        let code = compile(src.as_bytes(), TokenizerOptions::default()).unwrap();
+        let mut optimized = code.clone();
+        peephole(&mut optimized);
+        assert!(optimized.code.len() < code.code.len());
+        let mut out = vec![];
+        Vm::new(&optimized).run(&mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "55 10 ");
+    }
+}
@@ -0,0 +1,1034 @@
+// RISC-V backend: translates a checked syntax tree into RV64IM assembly for
+// the GNU assembler. Programs need no C library: they talk to Linux through
+// `ecall`, so they run as they are under a user-mode QEMU or Spike with the
+// proxy kernel.
+//
+// Functions follow the calling convention of the psABI: the static link, the
+// frame of the enclosing block, comes in a0 and the arguments in a1 to a7,
+// further ones on the stack, the first at 0(sp). Results come back in a0.
+// Every block gets a frame of its own, addressed through fp:
+//
+//      16(fp) + 8*k    the stack argument k, the eighth one and on
+//       8(fp)          return address
+//       0(fp)          saved fp of the caller
+//      -8(fp)          static link
+//     -16(fp) - 8*k    slot k: the parameters, then the variables and array
+//                      elements by relative address
+//
+// Values are 32-bit, as in the stack machine, and are kept sign extended in
+// registers, which the `w` instructions preserve. Only RV64 is targeted: RV32
+// would need frame slots of 4 bytes and has none of the `w` instructions.
+
+use std::fmt::Write;
+use crate::tokenizer::*;
+use crate::keyword::*;
+use crate::symbol::*;
+use crate::parser::*;
+use crate::table::*;
+use crate::semantic::self_tail_call;
+use crate::backend::*;
+
+/// Arguments passed in registers, after the static link.
+const ARG_REGS: usize = 7;
+
+/// Routines the generated code calls for input, output and runtime errors.
+/// Output goes through a buffer that is flushed before reading, on errors and
+/// at exit. They keep fp and the saved registers, and write the same messages
+/// as the stack machine.
+const RUNTIME: &str = r#"
+# appends the byte a0 to the output
+pl0_putc:
+	la t0, pl0_out_len
+	lw t1, 0(t0)
+	la t2, pl0_out_buf
+	add t2, t2, t1
+	sb a0, 0(t2)
+	addi t1, t1, 1
+	sw t1, 0(t0)
+	li t2, 4096
+	beq t1, t2, pl0_flush
+	ret
+
+# writes out the output buffer
+pl0_flush:
+	la t0, pl0_out_len
+	lw a2, 0(t0)
+	sw zero, 0(t0)
+	la t0, pl0_out_fd
+	lw a0, 0(t0)
+	la a1, pl0_out_buf
+	li a7, 64
+	ecall
+	ret
+
+# writes the integer a0
+pl0_putint:
+	addi sp, sp, -48
+	sd ra, 40(sp)
+	sd s1, 32(sp)
+	sd s2, 24(sp)
+	mv s1, a0
+	bgez s1, 1f
+	li a0, 45
+	call pl0_putc
+	neg s1, s1
+1:
+	# the digits, last first, below 24(sp)
+	addi s2, sp, 24
+2:
+	li t0, 10
+	remu t1, s1, t0
+	divu s1, s1, t0
+	addi t1, t1, 48
+	addi s2, s2, -1
+	sb t1, 0(s2)
+	bnez s1, 2b
+3:
+	lbu a0, 0(s2)
+	call pl0_putc
+	addi s2, s2, 1
+	addi t0, sp, 24
+	bltu s2, t0, 3b
+	ld ra, 40(sp)
+	ld s1, 32(sp)
+	ld s2, 24(sp)
+	addi sp, sp, 48
+	ret
+
+# writes a0 followed by a space
+pl0_write:
+	addi sp, sp, -16
+	sd ra, 8(sp)
+	call pl0_putint
+	li a0, 32
+	call pl0_putc
+	ld ra, 8(sp)
+	addi sp, sp, 16
+	ret
+
+# writes the string at a0
+pl0_write_string:
+	addi sp, sp, -16
+	sd ra, 8(sp)
+	sd s1, 0(sp)
+	mv s1, a0
+1:
+	lbu a0, 0(s1)
+	beqz a0, 2f
+	call pl0_putc
+	addi s1, s1, 1
+	j 1b
+2:
+	ld ra, 8(sp)
+	ld s1, 0(sp)
+	addi sp, sp, 16
+	ret
+
+pl0_writeln:
+	li a0, 10
+	j pl0_putc
+
+# reads the next byte of stdin into a0, -1 at the end of the input
+pl0_getc:
+	la t0, pl0_in_pos
+	lw t1, 0(t0)
+	la t2, pl0_in_len
+	lw t3, 0(t2)
+	blt t1, t3, 1f
+	li a0, 0
+	la a1, pl0_in_buf
+	li a2, 4096
+	li a7, 63
+	ecall
+	la t0, pl0_in_pos
+	la t2, pl0_in_len
+	sw zero, 0(t0)
+	sw zero, 0(t2)
+	blez a0, 2f
+	sw a0, 0(t2)
+	li t1, 0
+1:
+	la t2, pl0_in_buf
+	add t2, t2, t1
+	lbu a0, 0(t2)
+	addi t1, t1, 1
+	sw t1, 0(t0)
+	ret
+2:
+	li a0, -1
+	ret
+
+# sets a0 when the byte a0 is white space
+pl0_is_space:
+	addi t0, a0, -9
+	sltiu t0, t0, 5
+	addi a0, a0, -32
+	seqz a0, a0
+	or a0, a0, t0
+	ret
+
+# reads the next whitespace separated word of stdin as an integer into a0
+pl0_read:
+	addi sp, sp, -32
+	sd ra, 24(sp)
+	sd s1, 16(sp)
+	sd s2, 8(sp)
+	sd s3, 0(sp)
+	call pl0_flush
+1:
+	call pl0_getc
+	bltz a0, pl0_end_of_input
+	mv s3, a0
+	call pl0_is_space
+	bnez a0, 1b
+	# the word, cut to the size of the buffer
+	la s1, pl0_word
+	mv s2, s1
+2:
+	addi t0, s1, 255
+	bgeu s2, t0, 3f
+	sb s3, 0(s2)
+	addi s2, s2, 1
+3:
+	call pl0_getc
+	bltz a0, 4f
+	mv s3, a0
+	call pl0_is_space
+	beqz a0, 2b
+4:
+	sb zero, 0(s2)
+	# an optional sign, then digits
+	li t2, 0
+	lbu t0, 0(s1)
+	li t1, 45
+	bne t0, t1, 5f
+	li t2, 1
+	addi s1, s1, 1
+	j 6f
+5:
+	li t1, 43
+	bne t0, t1, 6f
+	addi s1, s1, 1
+6:
+	beq s1, s2, pl0_malformed
+	li a0, 0
+	li t3, 10
+	li t4, 2147483648
+7:
+	lbu t0, 0(s1)
+	addi t0, t0, -48
+	bgeu t0, t3, pl0_malformed
+	mul a0, a0, t3
+	add a0, a0, t0
+	bgtu a0, t4, pl0_malformed
+	addi s1, s1, 1
+	bltu s1, s2, 7b
+	beqz t2, 8f
+	neg a0, a0
+	j 9f
+8:
+	beq a0, t4, pl0_malformed
+9:
+	ld ra, 24(sp)
+	ld s1, 16(sp)
+	ld s2, 8(sp)
+	ld s3, 0(sp)
+	addi sp, sp, 32
+	ret
+
+pl0_malformed:
+	call pl0_error
+	la a0, .Lmsg_malformed
+	call pl0_write_string
+	la a0, pl0_word
+	call pl0_write_string
+	li a0, 96
+	call pl0_putc
+	j pl0_error_end
+
+pl0_end_of_input:
+	la s1, .Lmsg_end_of_input
+	j pl0_fail
+
+pl0_division_by_zero:
+	la s1, .Lmsg_division_by_zero
+	j pl0_fail
+
+# the index is in a0, the size of the array in a1
+pl0_index_out_of_bounds:
+	mv s1, a0
+	mv s2, a1
+	call pl0_error
+	la a0, .Lmsg_index
+	call pl0_write_string
+	mv a0, s1
+	call pl0_putint
+	la a0, .Lmsg_size
+	call pl0_write_string
+	mv a0, s2
+	call pl0_putint
+	j pl0_error_end
+
+pl0_stack_overflow:
+	la s1, .Lmsg_stack_overflow
+	j pl0_fail
+
+# writes the message at s1 as a runtime error and exits
+pl0_fail:
+	call pl0_error
+	mv a0, s1
+	call pl0_write_string
+	j pl0_error_end
+
+# flushes the output and starts a runtime error on stderr
+pl0_error:
+	mv s11, ra
+	call pl0_flush
+	li t0, 2
+	la t1, pl0_out_fd
+	sw t0, 0(t1)
+	la a0, .Lmsg_runtime_error
+	call pl0_write_string
+	jr s11
+
+pl0_error_end:
+	li a0, 10
+	call pl0_putc
+	li a0, 1
+	j pl0_exit
+
+# flushes the output and exits with the status a0
+pl0_exit:
+	mv s1, a0
+	call pl0_flush
+	mv a0, s1
+	li a7, 93
+	ecall
+
+	.section .rodata
+.Lmsg_runtime_error:
+	.string "runtime error: "
+.Lmsg_malformed:
+	.string "`read` expected an integer, found `"
+.Lmsg_end_of_input:
+	.string "`read` reached the end of the input"
+.Lmsg_division_by_zero:
+	.string "division by zero"
+.Lmsg_index:
+	.string "index "
+.Lmsg_size:
+	.string " is out of bounds for an array of size "
+.Lmsg_stack_overflow:
+	.string "stack overflow"
+
+	.data
+	.align 2
+pl0_out_fd:
+	.word 1
+
+	.bss
+	.align 3
+pl0_stack_limit:
+	.zero 8
+pl0_out_len:
+	.zero 4
+pl0_in_pos:
+	.zero 4
+pl0_in_len:
+	.zero 4
+pl0_out_buf:
+	.zero 4096
+pl0_in_buf:
+	.zero 4096
+pl0_word:
+	.zero 256
+"#;
+
+/// Labels of the current loop that `break` and `continue` jump to.
+struct Loop {
+    break_label: usize,
+    continue_label: usize,
+}
+
+/// Translates a checked syntax tree into RV64IM assembly. Functions are
+/// entered in the table with the number of their label as address.
+pub struct RiscvGenerator {
+    table: Table,
+    /// finished functions
+    out: String,
+    /// body of the function being generated
+    text: String,
+    strings: Vec<String>,
+    loops: Vec<Loop>,
+    labels: usize,
+}
+
+impl RiscvGenerator {
+    pub fn new() -> Self {
+        RiscvGenerator {
+            table: Table::new(),
+            out: String::new(),
+            text: String::new(),
+            strings: vec![],
+            loops: vec![],
+            labels: 0,
+        }
+    }
+
+    pub fn generate(mut self, tree: &SyntaxTree) -> String {
+        let program = tree.get_ref_root();
+        self.out += "\t.text\n";
+        self.table.block_begin(FIRST_ADDR);
+        self.gen_block(&program.get_ref_children()[0], None);
+        self.out += RUNTIME;
+        if !self.strings.is_empty() {
+            self.out += "\n\t.section .rodata\n";
+            for (i, s) in self.strings.iter().enumerate() {
+                writeln!(self.out, ".Ls{}:\n\t.string \"{}\"", i, asm_string(s)).unwrap();
+            }
+        }
+        self.out
+    }
+
+    fn gen_block(&mut self, node: &SyntaxNode, func: Option<usize>) {
+        for child in node.get_ref_children() {
+            match child.get_ref_syntax() {
+                Syntax::ConstDecl => enter_consts(&mut self.table, child),
+                Syntax::VarDecl => {
+                    enter_vars(&mut self.table, child);
+                },
+                Syntax::FuncDecl | Syntax::ProcDecl => self.gen_func_decl(child),
+                Syntax::Statement => self.gen_statement(child),
+                _ => unreachable!(),
+            }
+        }
+        let body = std::mem::take(&mut self.text);
+        // the frame is known once the statement had its temporaries
+        let pars = func.map_or(0, |f| self.table.get(f).pars) as i32;
+        let size = (8 + 8 * (pars + self.table.frame_size() - FIRST_ADDR) + 15) / 16 * 16;
+        match func {
+            Some(f) => {
+                let e = self.table.get(f);
+                writeln!(self.out, "\n# {} {}\n.Lf{}:", kind_name(e), e.name, e.addr).unwrap();
+            },
+            None => {
+                self.out += "\n\t.globl _start\n_start:\n";
+                writeln!(self.out, "\tli t0, {}\n\tsub t0, sp, t0", STACK_LIMIT).unwrap();
+                self.out += "\tla t1, pl0_stack_limit\n\tsd t0, 0(t1)\n";
+            },
+        }
+        self.out += "\taddi sp, sp, -16\n\tsd ra, 8(sp)\n\tsd fp, 0(sp)\n\tmv fp, sp\n";
+        // the frame is allocated once it is known to fit
+        writeln!(self.out, "\tli t0, {}\n\tsub t0, sp, t0", size).unwrap();
+        self.out += "\tla t1, pl0_stack_limit\n\tld t1, 0(t1)\n";
+        let fits = self.new_label();
+        writeln!(self.out, "\tbgeu t0, t1, .L{}\n\tj pl0_stack_overflow\n.L{}:\n\tmv sp, t0", fits, fits).unwrap();
+        self.out += "\tsd a0, -8(fp)\n";
+        for i in 0..pars {
+            let operand = self.slot_operand(-16 - 8 * i, "fp", "t0");
+            if (i as usize) < ARG_REGS {
+                self.emit(&format!("sw a{}, {}", i + 1, operand));
+            } else {
+                self.emit(&format!("ld t1, {}(fp)", 16 + 8 * (i - ARG_REGS as i32)));
+                self.emit(&format!("sw t1, {}", operand));
+            }
+        }
+        self.out += &std::mem::take(&mut self.text);
+        if let Some(f) = func {
+            // where a self tail call starts the body over
+            writeln!(self.out, ".Lf{}_body:", self.table.get(f).addr).unwrap();
+        }
+        let locals = self.table.frame_size() - FIRST_ADDR;
+        if locals > 0 {
+            // the locals, below the parameters, start at zero on every call
+            let label = self.new_label();
+            writeln!(self.out, "\tli t0, {}\n\tsub t0, fp, t0\n\tli t1, {}\n\tsub t1, fp, t1", 8 + 8 * pars, 8 + 8 * (pars + locals)).unwrap();
+            writeln!(self.out, ".L{}:\n\taddi t0, t0, -8\n\tsd zero, 0(t0)\n\tbne t0, t1, .L{}", label, label).unwrap();
+        }
+        self.out += &body;
+        match func {
+            // procedures end without a `return`, functions usually with one
+            Some(_) => {
+                if !body.ends_with("\tret\n") {
+                    self.out += "\tli a0, 0\n\tmv sp, fp\n\tld ra, 8(sp)\n\tld fp, 0(sp)\n\taddi sp, sp, 16\n\tret\n";
+                }
+            },
+            None => self.out += "\tli a0, 0\n\tj pl0_exit\n",
+        }
+        self.table.block_end();
+    }
+
+    fn gen_func_decl(&mut self, node: &SyntaxNode) {
+        let label = self.new_label() as i32;
+        let decl = enter_func(&mut self.table, node, label);
+        self.gen_block(decl.body, Some(decl.func));
+    }
+
+    fn gen_statement(&mut self, node: &SyntaxNode) {
+        let children = node.get_ref_children();
+        let first = match children.first() {
+            Some(first) => first,
+            None => return, // empty statement
+        };
+        match first.get_ref_token().unwrap() {
+            Token::Identifier(name) if children.len() > 1 && children[1].get_ref_token() == Some(&Token::Symbol(Symbol::Lparen)) => {
+                let e = lookup(&self.table, name);
+                self.gen_call(&e, &children[1..]);
+            },
+            Token::Keyword(Keyword::Call) => {
+                let e = lookup(&self.table, children[1].get_ref_identifier().unwrap());
+                self.gen_call(&e, &children[2..]);
+            },
+            Token::Identifier(name) => {
+                let e = lookup(&self.table, name);
+                if e.kind == Kind::Array {
+                    // ident [ index ] := value
+                    self.gen_index(&e, &children[2]);
+                    self.push();
+                    self.gen_expression(&children[5]);
+                    self.pop("a1");
+                    self.store_element(&e);
+                } else {
+                    self.gen_expression(&children[2]);
+                    self.store(&e);
+                }
+            },
+            Token::Keyword(Keyword::Begin) => {
+                for child in children {
+                    if let Syntax::Statement = child.get_ref_syntax() {
+                        self.gen_statement(child);
+                    }
+                }
+            },
+            Token::Keyword(Keyword::If) => {
+                let else_label = self.new_label();
+                self.gen_condition(&children[1], false, else_label);
+                self.gen_statement(&children[3]);
+                if children.len() > 4 {
+                    let next = self.new_label();
+                    self.emit(&format!("j .L{}", next));
+                    self.label(else_label);
+                    self.gen_statement(&children[5]);
+                    self.label(next);
+                } else {
+                    self.label(else_label);
+                }
+            },
+            Token::Keyword(Keyword::While) => {
+                let start = self.new_label();
+                let next = self.new_label();
+                self.label(start);
+                self.gen_condition(&children[1], false, next);
+                self.loops.push(Loop { break_label: next, continue_label: start });
+                self.gen_statement(&children[3]);
+                self.loops.pop();
+                self.emit(&format!("j .L{}", start));
+                self.label(next);
+            },
+            Token::Keyword(Keyword::For) => {
+                let var = lookup(&self.table, children[1].get_ref_identifier().unwrap());
+                let temp = self.table.enter_temp();
+                let limit = self.table.get(temp).clone();
+                let (exit, last, step) = match children[4].get_ref_token() {
+                    Some(Token::Keyword(Keyword::To)) => ("bgt", "bge", 1),
+                    _ => ("blt", "ble", -1),
+                };
+                let (start, step_label, next) = (self.new_label(), self.new_label(), self.new_label());
+                self.gen_expression(&children[3]);
+                self.store(&var);
+                self.gen_expression(&children[5]);
+                self.store(&limit);
+                self.label(start);
+                self.load(&limit);
+                self.emit("mv a1, a0");
+                self.load(&var);
+                self.branch(exit, next);
+                self.loops.push(Loop { break_label: next, continue_label: step_label });
+                self.gen_statement(&children[7]);
+                self.loops.pop();
+                self.label(step_label);
+                self.load(&limit);
+                self.emit("mv a1, a0");
+                self.load(&var);
+                self.branch(last, next);
+                self.emit(&format!("addiw a0, a0, {}", step));
+                self.store(&var);
+                self.emit(&format!("j .L{}", start));
+                self.label(next);
+            },
+            Token::Keyword(Keyword::Repeat) => {
+                let (start, cond, next) = (self.new_label(), self.new_label(), self.new_label());
+                self.label(start);
+                self.loops.push(Loop { break_label: next, continue_label: cond });
+                for child in children {
+                    if let Syntax::Statement = child.get_ref_syntax() {
+                        self.gen_statement(child);
+                    }
+                }
+                self.loops.pop();
+                self.label(cond);
+                self.gen_condition(children.last().unwrap(), false, start);
+                self.label(next);
+            },
+            Token::Keyword(Keyword::Break) => {
+                let target = self.loops.last().unwrap().break_label;
+                self.emit(&format!("j .L{}", target));
+            },
+            Token::Keyword(Keyword::Continue) => {
+                let target = self.loops.last().unwrap().continue_label;
+                self.emit(&format!("j .L{}", target));
+            },
+            Token::Keyword(Keyword::Ret) => {
+                if let Some(args) = self_tail_call(&self.table, &children[1]) {
+                    self.gen_tail_call(&args);
+                    return;
+                }
+                self.gen_expression(&children[1]);
+                self.emit("mv sp, fp");
+                self.emit("ld ra, 8(sp)");
+                self.emit("ld fp, 0(sp)");
+                self.emit("addi sp, sp, 16");
+                self.emit("ret");
+            },
+            Token::Keyword(Keyword::Write) => {
+                for arg in &children[1..] {
+                    if let Some(Token::String(s)) = arg.get_ref_token() {
+                        let i = intern(&mut self.strings, s);
+                        self.emit(&format!("la a0, .Ls{}", i));
+                        self.emit("call pl0_write_string");
+                    } else if let Syntax::Expression = arg.get_ref_syntax() {
+                        self.gen_expression(arg);
+                        self.emit("call pl0_write");
+                    }
+                }
+            },
+            Token::Keyword(Keyword::WriteLn) => {
+                self.emit("call pl0_writeln");
+            },
+            Token::Keyword(Keyword::Read) => {
+                let e = lookup(&self.table, children[1].get_ref_identifier().unwrap());
+                if e.kind == Kind::Array {
+                    // read ident [ index ]
+                    self.gen_index(&e, &children[3]);
+                    self.push();
+                    self.emit("call pl0_read");
+                    self.pop("a1");
+                    self.store_element(&e);
+                } else {
+                    self.emit("call pl0_read");
+                    self.store(&e);
+                }
+            },
+            t => unreachable!("statement starting with {:?}", t),
+        }
+    }
+
+    /// Generates a condition that falls through when it holds, or when it
+    /// does not if `negate` is set, and otherwise branches to `target`.
+    /// Operands of `and` and `or` are evaluated only as far as needed.
+    fn gen_condition(&mut self, node: &SyntaxNode, negate: bool, target: usize) {
+        let children = node.get_ref_children();
+        match children[0].get_ref_token() {
+            Some(Token::Keyword(Keyword::Not)) => {
+                return self.gen_condition(&children[1], !negate, target);
+            },
+            Some(Token::Keyword(Keyword::Odd)) => {
+                self.gen_expression(&children[1]);
+                self.emit("andi a0, a0, 1");
+                let branch = if negate { "bnez" } else { "beqz" };
+                return self.emit(&format!("{} a0, .L{}", branch, target));
+            },
+            Some(Token::Symbol(Symbol::Lparen)) => {
+                return self.gen_condition(&children[1], negate, target);
+            },
+            _ => (),
+        }
+        if let Syntax::Condition = children[0].get_ref_syntax() {
+            // c { and c } or c { or c }; a negated `and` is an `or` of the
+            // negated operands and the other way round
+            let is_or = children[1].get_ref_token() == Some(&Token::Keyword(Keyword::Or));
+            let operands: Vec<&SyntaxNode> = children.iter().step_by(2).collect();
+            if is_or == negate {
+                for c in operands {
+                    self.gen_condition(c, negate, target);
+                }
+                return;
+            }
+            let (last, init) = operands.split_last().unwrap();
+            let holds = self.new_label();
+            for c in init {
+                self.gen_condition(c, !negate, holds);
+            }
+            self.gen_condition(last, negate, target);
+            return self.label(holds);
+        }
+        self.gen_binary(&children[0], &children[2]);
+        // the branch taken when the relation does not hold
+        let branch = match children[1].get_ref_token() {
+            Some(Token::Symbol(Symbol::Equal)) => "bne",
+            Some(Token::Symbol(Symbol::Lss))   => "bge",
+            Some(Token::Symbol(Symbol::Gtr))   => "ble",
+            Some(Token::Symbol(Symbol::NotEq)) => "beq",
+            Some(Token::Symbol(Symbol::LssEq)) => "bgt",
+            Some(Token::Symbol(Symbol::GtrEq)) => "blt",
+            t => unreachable!("relational operator {:?}", t),
+        };
+        let branch = if negate { complement(branch) } else { branch };
+        self.branch(branch, target);
+    }
+
+    /// Evaluates two operands, the left one into a0 and the right one
+    /// into a1.
+    fn gen_binary(&mut self, left: &SyntaxNode, right: &SyntaxNode) {
+        self.gen_expression(left);
+        self.push();
+        self.gen_expression(right);
+        self.emit("mv a1, a0");
+        self.pop("a0");
+    }
+
+    /// Evaluates an expression into a0.
+    fn gen_expression(&mut self, node: &SyntaxNode) {
+        let children = node.get_ref_children();
+        let mut i = 0;
+        let mut negate = false;
+        if let Some(Token::Symbol(sym)) = children[0].get_ref_token() {
+            negate = *sym == Symbol::Minus;
+            i = 1;
+        }
+        self.gen_term(&children[i]);
+        if negate {
+            self.emit("negw a0, a0");
+        }
+        for pair in children[i + 1..].chunks(2) {
+            let op = match pair[0].get_ref_token() {
+                Some(Token::Symbol(Symbol::Plus))  => "addw",
+                Some(Token::Symbol(Symbol::Minus)) => "subw",
+                t => unreachable!("additive operator {:?}", t),
+            };
+            self.push();
+            self.gen_term(&pair[1]);
+            self.emit("mv a1, a0");
+            self.pop("a0");
+            self.emit(&format!("{} a0, a0, a1", op));
+        }
+    }
+
+    fn gen_term(&mut self, node: &SyntaxNode) {
+        let children = node.get_ref_children();
+        self.gen_factor(&children[0]);
+        for pair in children[1..].chunks(2) {
+            self.push();
+            self.gen_factor(&pair[1]);
+            self.emit("mv a1, a0");
+            self.pop("a0");
+            // `divw` and `remw` wrap around on the quotient of the smallest
+            // integer by -1, as the stack machine does
+            match pair[0].get_ref_token() {
+                Some(Token::Symbol(Symbol::Mult)) => self.emit("mulw a0, a0, a1"),
+                Some(Token::Symbol(Symbol::Div))  => {
+                    self.check("beqz a1", "pl0_division_by_zero");
+                    self.emit("divw a0, a0, a1");
+                },
+                Some(Token::Symbol(Symbol::Mod)) | Some(Token::Keyword(Keyword::Mod)) => {
+                    self.check("beqz a1", "pl0_division_by_zero");
+                    self.emit("remw a0, a0, a1");
+                },
+                t => unreachable!("multiplicative operator {:?}", t),
+            }
+        }
+    }
+
+    fn gen_factor(&mut self, node: &SyntaxNode) {
+        let children = node.get_ref_children();
+        match children[0].get_ref_token() {
+            Some(Token::Identifier(name)) => {
+                if let Some(f) = self.table.search_intrinsic(name) {
+                    match f {
+                        Intrinsic::Abs => {
+                            self.gen_expression(&children[2]);
+                            self.emit("sraiw a1, a0, 31");
+                            self.emit("xor a0, a0, a1");
+                            self.emit("subw a0, a0, a1");
+                        },
+                        Intrinsic::Min | Intrinsic::Max => {
+                            self.gen_binary(&children[2], &children[4]);
+                            let next = self.new_label();
+                            let keep = if f == Intrinsic::Min { "ble" } else { "bge" };
+                            self.emit(&format!("{} a0, a1, .L{}", keep, next));
+                            self.emit("mv a0, a1");
+                            self.label(next);
+                        },
+                    }
+                    return;
+                }
+                let e = lookup(&self.table, name);
+                match e.kind {
+                    Kind::Var | Kind::Par => self.load(&e),
+                    Kind::Array => {
+                        self.gen_index(&e, &children[2]);
+                        self.emit("mv a1, a0");
+                        let operand = self.element(&e);
+                        self.emit(&format!("lw a0, {}", operand));
+                    },
+                    Kind::Const => {
+                        self.emit(&format!("li a0, {}", e.value));
+                    },
+                    Kind::Func => self.gen_call(&e, &children[1..]),
+                    Kind::Proc => unreachable!("unchecked syntax tree"),
+                }
+            },
+            Some(Token::Number(value)) => {
+                self.emit(&format!("li a0, {}", value));
+            },
+            _ => {
+                // ( expression )
+                self.gen_expression(&children[1]);
+            },
+        }
+    }
+
+    /// Evaluates the index of an element of the array `e` into a0,
+    /// checking it is within bounds.
+    fn gen_index(&mut self, e: &Entry, index: &SyntaxNode) {
+        self.gen_expression(index);
+        self.emit(&format!("li a1, {}", e.size));
+        self.check("bgeu a0, a1", "pl0_index_out_of_bounds");
+    }
+
+    /// Calls the current function again in its own frame: the arguments
+    /// replace the parameters once all of them are known, and the body starts
+    /// over past the prologue, which has already set up the frame, where the
+    /// locals are zeroed again.
+    fn gen_tail_call(&mut self, args: &[&SyntaxNode]) {
+        for arg in args {
+            self.gen_expression(arg);
+            self.push();
+        }
+        let pars = self.table.block_entries()[..args.len()].to_vec();
+        for par in pars.iter().rev() {
+            self.pop("a0");
+            self.store(par);
+        }
+        let f = self.table.func_index().unwrap();
+        self.emit(&format!("j .Lf{}_body", self.table.get(f).addr));
+    }
+
+    /// Evaluates the arguments among `args` into their places and calls `e`,
+    /// which leaves its value in a0.
+    fn gen_call(&mut self, e: &Entry, args: &[SyntaxNode]) {
+        let args: Vec<&SyntaxNode> = args.iter().filter(|a| matches!(a.get_ref_syntax(), Syntax::Expression)).collect();
+        // the arguments for registers lie below the ones for the stack, in
+        // the order of evaluation
+        let space = 8 * args.len() as i32;
+        if space > 0 {
+            self.emit(&format!("addi sp, sp, -{}", space));
+        }
+        for (i, arg) in args.iter().enumerate() {
+            self.gen_expression(arg);
+            self.emit(&format!("sd a0, {}(sp)", 8 * i));
+        }
+        // the callee is declared in the block whose frame it links to
+        let link = self.frame(e.level, "t1");
+        self.emit(&format!("mv a0, {}", link));
+        let in_regs = args.len().min(ARG_REGS);
+        for i in 0..in_regs {
+            self.emit(&format!("ld a{}, {}(sp)", i + 1, 8 * i));
+        }
+        if in_regs > 0 {
+            self.emit(&format!("addi sp, sp, {}", 8 * in_regs));
+        }
+        self.emit(&format!("call .Lf{}", e.addr));
+        if args.len() > ARG_REGS {
+            self.emit(&format!("addi sp, sp, {}", 8 * (args.len() - ARG_REGS)));
+        }
+    }
+
+    /// Follows the static links up to the frame of the block at `level`,
+    /// using `reg` unless it is the frame of the current block. Returns the
+    /// register that holds the frame.
+    fn frame(&mut self, level: usize, reg: &'static str) -> &'static str {
+        let hops = self.table.level() - level;
+        if hops == 0 {
+            return "fp";
+        }
+        self.emit(&format!("ld {}, -8(fp)", reg));
+        for _ in 1..hops {
+            self.emit(&format!("ld {}, -8({})", reg, reg));
+        }
+        reg
+    }
+
+    /// Offset from its frame of the variable or parameter `e`, or of the
+    /// first element of the array `e`.
+    fn offset(&self, e: &Entry) -> i32 {
+        let pars = self.table.block_func(e.level).map_or(0, |f| self.table.get(f).pars) as i32;
+        let slot = if e.addr < 0 { e.addr + pars } else { pars + e.addr - FIRST_ADDR };
+        -16 - 8 * slot
+    }
+
+    /// Memory operand for `offset` from the address in `base`, going through
+    /// `scratch` when the offset does not fit an immediate.
+    fn slot_operand(&mut self, offset: i32, base: &str, scratch: &str) -> String {
+        if (-2048..2048).contains(&offset) {
+            return format!("{}({})", offset, base);
+        }
+        self.emit(&format!("li {}, {}", scratch, offset));
+        self.emit(&format!("add {}, {}, {}", scratch, base, scratch));
+        format!("0({})", scratch)
+    }
+
+    /// Memory operand of the variable or parameter `e`.
+    fn operand(&mut self, e: &Entry) -> String {
+        let base = self.frame(e.level, "t1");
+        let offset = self.offset(e);
+        self.slot_operand(offset, base, "t2")
+    }
+
+    /// Memory operand of the element of the array `e` whose index is in a1.
+    fn element(&mut self, e: &Entry) -> String {
+        let base = self.frame(e.level, "t1");
+        self.emit("slli t0, a1, 3");
+        self.emit(&format!("sub t0, {}, t0", base));
+        let offset = self.offset(e);
+        self.slot_operand(offset, "t0", "t2")
+    }
+
+    fn load(&mut self, e: &Entry) {
+        let operand = self.operand(e);
+        self.emit(&format!("lw a0, {}", operand));
+    }
+
+    fn store(&mut self, e: &Entry) {
+        let operand = self.operand(e);
+        self.emit(&format!("sw a0, {}", operand));
+    }
+
+    /// Stores a0 into the element of the array `e` whose index is in a1.
+    fn store_element(&mut self, e: &Entry) {
+        let operand = self.element(e);
+        self.emit(&format!("sw a0, {}", operand));
+    }
+
+    fn push(&mut self) {
+        self.emit("addi sp, sp, -8");
+        self.emit("sd a0, 0(sp)");
+    }
+
+    fn pop(&mut self, reg: &str) {
+        self.emit(&format!("ld {}, 0(sp)", reg));
+        self.emit("addi sp, sp, 8");
+    }
+
+    /// Compares a0 with a1 and branches to `target` as `branch` says. The
+    /// branch skips a jump so that the target may lie beyond its reach.
+    fn branch(&mut self, branch: &str, target: usize) {
+        let next = self.new_label();
+        self.emit(&format!("{} a0, a1, .L{}", complement(branch), next));
+        self.emit(&format!("j .L{}", target));
+        self.label(next);
+    }
+
+    /// Jumps to the runtime error `routine` if `test`, a conditional branch
+    /// without its target, would branch. Like `branch`, it skips a jump, as
+    /// the runtime follows all of the generated code.
+    fn check(&mut self, test: &str, routine: &str) {
+        let (branch, operands) = test.split_once(' ').unwrap();
+        let next = self.new_label();
+        self.emit(&format!("{} {}, .L{}", complement(branch), operands, next));
+        self.emit(&format!("j {}", routine));
+        self.label(next);
+    }
+
+    fn new_label(&mut self) -> usize {
+        self.labels += 1;
+        self.labels
+    }
+
+    fn label(&mut self, label: usize) {
+        writeln!(self.text, ".L{}:", label).unwrap();
+    }
+
+    fn emit(&mut self, inst: &str) {
+        writeln!(self.text, "\t{}", inst).unwrap();
+    }
+}
+
+impl Default for RiscvGenerator {
+    fn default() -> Self {
+        RiscvGenerator::new()
+    }
+}
+
+/// The conditional branch taken exactly when `branch` is not.
+fn complement(branch: &str) -> &'static str {
+    match branch {
+        "beq" => "bne",
+        "bne" => "beq",
+        "blt" => "bge",
+        "bge" => "blt",
+        "bgt" => "ble",
+        "ble" => "bgt",
+        "bltu" => "bgeu",
+        "bgeu" => "bltu",
+        "beqz" => "bnez",
+        "bnez" => "beqz",
+        _ => unreachable!("conditional branch {}", branch),
+    }
+}
+
+/// Escapes `s` for a `.string` directive.
+fn asm_string(s: &str) -> String {
+    let mut escaped = String::new();
+    for b in s.bytes() {
+        match b {
+            b'"' => escaped += "\\\"",
+            b'\\' => escaped += "\\\\",
+            0x20..=0x7e => escaped.push(b as char),
+            _ => write!(escaped, "\\{:03o}", b).unwrap(),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compile::*;
+
+    fn generate(src: &'static str) -> String {
+        let tree = analyze(src.as_bytes(), TokenizerOptions::default()).unwrap();
+        RiscvGenerator::new().generate(&tree)
+    }
+
+    #[test]
+    fn test_static_links() {
+        let asm = generate("\
+            var x;
+            function f(a)
+                function g(b) return a + b + x;
+                return g(1);
+            begin x := 1; write f(2) end.");
+        // f links to the frame of the main block, g to the one of f
+        assert!(asm.contains("\tmv a0, fp\n\tld a1, 0(sp)\n\taddi sp, sp, 8\n\tcall .Lf1\n"));
+        assert!(asm.contains("\tmv a0, fp\n\tld a1, 0(sp)\n\taddi sp, sp, 8\n\tcall .Lf2\n"));
+        // g reaches `a` through one static link and `x` through two
+        assert!(asm.contains("\tld t1, -8(fp)\n\tlw a0, -16(t1)\n"));
+        assert!(asm.contains("\tld t1, -8(fp)\n\tld t1, -8(t1)\n\tlw a0, -16(t1)\n"));
+    }
+
+    #[test]
+    fn test_stack_arguments() {
+        let asm = generate("\
+            function f(a, b, c, d, e, g, h, i, j) return i + j;
+            write f(1, 2, 3, 4, 5, 6, 7, 8, 9).");
+        // the first seven arguments go in a1..a7, the other two on the stack
+        assert!(asm.contains("\tld a7, 48(sp)\n\taddi sp, sp, 56\n\tcall .Lf1\n\taddi sp, sp, 16\n"));
+        assert!(asm.contains("\tsw a7, -64(fp)\n\tld t1, 16(fp)\n\tsw t1, -72(fp)\n\tld t1, 24(fp)\n\tsw t1, -80(fp)\n"));
+    }
+
+    #[test]
+    fn test_asm_string() {
+        assert_eq!(asm_string("a \"b\"\\\n"), "a \\\"b\\\"\\\\\\012");
+    }
+}
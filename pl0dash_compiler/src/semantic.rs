@@ -0,0 +1,567 @@
+use std::fmt;
+use crate::tokenizer::*;
+use crate::keyword::*;
+use crate::symbol::*;
+use crate::parser::*;
+use crate::table::*;
+
+/// An error in an otherwise well-formed program, such as a use of an
+/// undeclared name.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SemanticError {
+    pub pos: Position,
+    pub message: String,
+}
+
+impl fmt::Display for SemanticError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.pos, self.message)
+    }
+}
+
+/// Resolves every name of a syntax tree and checks it is used according to
+/// its kind. The code generators assume a tree that passed the checker.
+pub struct Checker {
+    table: Table,
+    /// number of loops around the statement being checked, within the
+    /// innermost function
+    loop_depth: usize,
+    errors: Vec<SemanticError>,
+}
+
+impl Checker {
+    pub fn new() -> Self {
+        Checker {
+            table: Table::new(),
+            loop_depth: 0,
+            errors: vec![],
+        }
+    }
+
+    /// Checks the whole program and returns every error found, in source order.
+    pub fn check(mut self, tree: &SyntaxTree) -> Vec<SemanticError> {
+        let program = tree.get_ref_root();
+        self.table.block_begin(FIRST_ADDR);
+        self.check_block(&program.get_ref_children()[0]);
+        self.table.block_end();
+        self.errors
+    }
+
+    fn check_block(&mut self, node: &SyntaxNode) {
+        for child in node.get_ref_children() {
+            match child.get_ref_syntax() {
+                Syntax::ConstDecl => self.check_const_decl(child),
+                Syntax::VarDecl => self.check_var_decl(child),
+                Syntax::FuncDecl | Syntax::ProcDecl => self.check_func_decl(child),
+                Syntax::Statement => self.check_statement(child),
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    fn check_const_decl(&mut self, node: &SyntaxNode) {
+        let children = node.get_ref_children();
+        // const ident = number {, ident = number} ;
+        for i in (1..children.len()).step_by(4) {
+            if let Some(Token::Number(value)) = children[i + 2].get_ref_token() {
+                if self.check_redeclaration(&children[i]) {
+                    self.table.enter_const(children[i].get_ref_identifier().unwrap(), *value);
+                }
+            }
+        }
+    }
+
+    fn check_var_decl(&mut self, node: &SyntaxNode) {
+        let children = node.get_ref_children();
+        for (i, child) in children.iter().enumerate() {
+            if let Some(name) = child.get_ref_identifier() {
+                // ident [ '[' number ']' ]
+                let size = match children[i + 1].get_ref_token() {
+                    Some(Token::Symbol(Symbol::Lbracket)) => match children[i + 2].get_ref_token() {
+                        Some(Token::Number(size)) => Some(*size),
+                        _ => unreachable!(),
+                    },
+                    _ => None,
+                };
+                if let Some(size) = size {
+                    if size <= 0 {
+                        self.error(&children[i + 2], format!("array `{}` must have a positive size", child.get_ref_lexeme()));
+                    }
+                }
+                if self.check_redeclaration(child) {
+                    match size {
+                        Some(size) => self.table.enter_array(name, size.max(1)),
+                        None => self.table.enter_var(name),
+                    };
+                }
+            }
+        }
+    }
+
+    fn check_func_decl(&mut self, node: &SyntaxNode) {
+        let children = node.get_ref_children();
+        let name = &children[1];
+        let is_proc = *node.get_ref_syntax() == Syntax::ProcDecl;
+        // keep the body checkable against a fresh entry if the name is taken
+        let spelling = if self.check_redeclaration(name) { name.get_ref_identifier().unwrap() } else { "" };
+        if is_proc {
+            self.table.enter_proc(spelling, 0);
+        } else {
+            self.table.enter_func(spelling, 0);
+        }
+        self.table.block_begin(FIRST_ADDR);
+        for par in &children[3..children.len() - 3] {
+            if let Some(par_name) = par.get_ref_identifier() {
+                if self.check_redeclaration(par) {
+                    self.table.enter_par(par_name);
+                }
+            }
+        }
+        self.table.end_par();
+        // `break` and `continue` cannot leave a function
+        let loop_depth = std::mem::replace(&mut self.loop_depth, 0);
+        let body = &children[children.len() - 2];
+        self.check_block(body);
+        self.loop_depth = loop_depth;
+        self.table.block_end();
+        if !is_proc && !always_returns(body.get_ref_children().last().unwrap(), false) {
+            self.error(name, format!("function `{}` may reach its end without returning a value", name.get_ref_lexeme()));
+        }
+    }
+
+    fn check_redeclaration(&mut self, ident: &SyntaxNode) -> bool {
+        let name = ident.get_ref_identifier().unwrap();
+        if self.table.search_in_block(name).is_some() {
+            self.error(ident, format!("`{}` is already declared in this block", ident.get_ref_lexeme()));
+            false
+        } else {
+            true
+        }
+    }
+
+    fn check_statement(&mut self, node: &SyntaxNode) {
+        let children = node.get_ref_children();
+        let first = match children.first() {
+            Some(first) => first,
+            None => return, // empty statement
+        };
+        let is_loop = matches!(first.get_ref_token(), Some(Token::Keyword(Keyword::While | Keyword::For | Keyword::Repeat)));
+        if is_loop {
+            self.loop_depth += 1;
+        }
+        match first.get_ref_token() {
+            Some(Token::Identifier(_)) if children.len() > 1 && children[1].get_ref_token() == Some(&Token::Symbol(Symbol::Lparen)) => {
+                // ident ( arguments )
+                self.check_call_statement(&children[0], &children[1..]);
+            },
+            Some(Token::Keyword(Keyword::Call)) => {
+                // call ident [ ( arguments ) ]
+                self.check_call_statement(&children[1], &children[2..]);
+            },
+            Some(Token::Identifier(_)) => {
+                // ident [ '[' expression ']' ] := expression
+                self.check_target(&children[..children.len() - 2], "assign to");
+                self.check_expression(children.last().unwrap());
+            },
+            Some(Token::Keyword(Keyword::For)) => {
+                // for ident := expression (to | downto) expression do statement
+                let var = &children[1];
+                if let Some(e) = self.lookup(var) {
+                    if !matches!(e.kind, Kind::Var | Kind::Par) {
+                        self.error(var, format!("the `for` loop variable `{}` must be a variable", var.get_ref_lexeme()));
+                    }
+                }
+                self.check_expression(&children[3]);
+                self.check_expression(&children[5]);
+                self.check_statement(&children[7]);
+            },
+            Some(Token::Keyword(Keyword::Read)) => {
+                // read ident [ '[' expression ']' ]
+                self.check_target(&children[1..], "read into");
+            },
+            Some(Token::Keyword(kw @ (Keyword::Break | Keyword::Continue))) => {
+                if self.loop_depth == 0 {
+                    self.error(first, format!("`{}` outside a loop", kw));
+                }
+            },
+            Some(Token::Keyword(Keyword::Ret)) => {
+                match self.table.func_index().map(|i| self.table.get(i).clone()) {
+                    None => {
+                        self.error(first, "`return` outside a function".to_string());
+                    },
+                    Some(e) if e.kind == Kind::Proc => {
+                        self.error(first, format!("procedure `{}` cannot return a value", e.name));
+                    },
+                    Some(_) => (),
+                }
+                self.check_expression(&children[1]);
+            },
+            _ => {
+                for child in children {
+                    match child.get_ref_syntax() {
+                        Syntax::Statement => self.check_statement(child),
+                        Syntax::Condition => self.check_condition(child),
+                        Syntax::Expression => self.check_expression(child),
+                        _ => (),
+                    }
+                }
+            }
+        }
+        if is_loop {
+            self.loop_depth -= 1;
+        }
+    }
+
+    /// Checks the variable or array element `ident [ '[' expression ']' ]`
+    /// that a statement stores into.
+    fn check_target(&mut self, target: &[SyntaxNode], verb: &str) {
+        let ident = &target[0];
+        let indexed = target.len() > 1;
+        if let Some(e) = self.lookup(ident) {
+            match e.kind {
+                Kind::Var | Kind::Par if indexed => {
+                    self.error(ident, format!("`{}` is not an array", ident.get_ref_lexeme()));
+                },
+                Kind::Var | Kind::Par => (),
+                Kind::Array if !indexed => {
+                    self.error(ident, format!("cannot {} array `{}` as a whole", verb, ident.get_ref_lexeme()));
+                },
+                Kind::Array => (),
+                Kind::Const => {
+                    self.error(ident, format!("cannot {} constant `{}`", verb, ident.get_ref_lexeme()));
+                },
+                Kind::Func => {
+                    self.error(ident, format!("cannot {} function `{}`", verb, ident.get_ref_lexeme()));
+                },
+                Kind::Proc => {
+                    self.error(ident, format!("cannot {} procedure `{}`", verb, ident.get_ref_lexeme()));
+                },
+            }
+        }
+        if indexed {
+            self.check_expression(&target[2]);
+        }
+    }
+
+    /// Checks a call made for its side effects, of a procedure or of a
+    /// function whose value is dropped. `rest` holds the parenthesized
+    /// arguments, if any.
+    fn check_call_statement(&mut self, ident: &SyntaxNode, rest: &[SyntaxNode]) {
+        let args: Vec<&SyntaxNode> = rest
+            .iter()
+            .filter(|c| *c.get_ref_syntax() == Syntax::Expression)
+            .collect();
+        if let Some(e) = self.lookup(ident) {
+            match e.kind {
+                Kind::Func | Kind::Proc => self.check_arity(ident, e.pars, args.len()),
+                _ => {
+                    self.error(ident, format!("`{}` is not a procedure or function", ident.get_ref_lexeme()));
+                },
+            }
+        }
+        for arg in args {
+            self.check_expression(arg);
+        }
+    }
+
+    fn check_arity(&mut self, ident: &SyntaxNode, pars: usize, args: usize) {
+        if pars != args {
+            let message = format!("`{}` takes {} argument{} but {} {} given",
+                ident.get_ref_lexeme(), pars, if pars == 1 { "" } else { "s" },
+                args, if args == 1 { "was" } else { "were" });
+            self.error(ident, message);
+        }
+    }
+
+    fn check_condition(&mut self, node: &SyntaxNode) {
+        for child in node.get_ref_children() {
+            match child.get_ref_syntax() {
+                Syntax::Condition => self.check_condition(child),
+                Syntax::Expression => self.check_expression(child),
+                _ => (),
+            }
+        }
+    }
+
+    fn check_expression(&mut self, node: &SyntaxNode) {
+        for child in node.get_ref_children() {
+            match child.get_ref_syntax() {
+                Syntax::Term | Syntax::Expression => self.check_expression(child),
+                Syntax::Factor => self.check_factor(child),
+                _ => (),
+            }
+        }
+    }
+
+    fn check_factor(&mut self, node: &SyntaxNode) {
+        let children = node.get_ref_children();
+        let first = &children[0];
+        if first.get_ref_identifier().is_none() {
+            for child in children {
+                if let Syntax::Expression = child.get_ref_syntax() {
+                    self.check_expression(child);
+                }
+            }
+            return;
+        }
+
+        let args: Vec<&SyntaxNode> = children
+            .iter()
+            .filter(|c| *c.get_ref_syntax() == Syntax::Expression)
+            .collect();
+        let is_call = matches!(children.get(1).and_then(|c| c.get_ref_token()), Some(Token::Symbol(Symbol::Lparen)));
+        let is_indexed = matches!(children.get(1).and_then(|c| c.get_ref_token()), Some(Token::Symbol(Symbol::Lbracket)));
+        let intrinsic = self.table.search_intrinsic(first.get_ref_identifier().unwrap());
+        if let (Some(f), true) = (intrinsic, is_call) {
+            self.check_arity(first, f.pars(), args.len());
+        } else if let Some(e) = self.lookup(first) {
+            match (e.kind, is_call) {
+                (Kind::Array, _) if !is_indexed => {
+                    self.error(first, format!("array `{}` must be indexed with `[...]`", first.get_ref_lexeme()));
+                },
+                (Kind::Array, _) => (),
+                (_, _) if is_indexed => {
+                    self.error(first, format!("`{}` is not an array", first.get_ref_lexeme()));
+                },
+                (Kind::Proc, _) => {
+                    self.error(first, format!("procedure `{}` does not return a value", first.get_ref_lexeme()));
+                },
+                (Kind::Func, true) => {
+                    self.check_arity(first, e.pars, args.len());
+                },
+                (Kind::Func, false) => {
+                    self.error(first, format!("function `{}` must be called with `(...)`", first.get_ref_lexeme()));
+                },
+                (_, true) => {
+                    self.error(first, format!("`{}` is not a function", first.get_ref_lexeme()));
+                },
+                (_, false) => (),
+            }
+        }
+        for arg in args {
+            self.check_expression(arg);
+        }
+    }
+
+    fn lookup(&mut self, ident: &SyntaxNode) -> Option<Entry> {
+        match self.table.search(ident.get_ref_identifier().unwrap()) {
+            Some(i) => Some(self.table.get(i).clone()),
+            None => {
+                self.error(ident, format!("`{}` is not declared", ident.get_ref_lexeme()));
+                None
+            }
+        }
+    }
+
+    fn error(&mut self, node: &SyntaxNode, message: String) {
+        self.errors.push(SemanticError {
+            pos: node.get_pos(),
+            message,
+        });
+    }
+}
+
+/// Whether every path through `node` ends in a `return`. Loops other than
+/// `repeat` may run their body zero times and so never count, and within
+/// a `repeat`, as `in_repeat` tells, a `break` or `continue` may skip the
+/// statements after it.
+fn always_returns(node: &SyntaxNode, in_repeat: bool) -> bool {
+    let children = node.get_ref_children();
+    let first = children.first().and_then(|c| c.get_ref_token());
+    match first {
+        Some(Token::Keyword(Keyword::Ret)) => true,
+        Some(Token::Keyword(Keyword::Begin | Keyword::Repeat)) => {
+            let in_repeat = in_repeat || first == Some(&Token::Keyword(Keyword::Repeat));
+            for child in children.iter().filter(|c| *c.get_ref_syntax() == Syntax::Statement) {
+                if always_returns(child, in_repeat) {
+                    return true;
+                }
+                if in_repeat && leaves_loop(child) {
+                    return false;
+                }
+            }
+            false
+        },
+        Some(Token::Keyword(Keyword::If)) => {
+            children.len() > 4 && always_returns(&children[3], in_repeat) && always_returns(&children[5], in_repeat)
+        },
+        _ => false,
+    }
+}
+
+/// Whether `node` holds a `break` or `continue` of the loop around it, not
+/// of a loop of its own.
+fn leaves_loop(node: &SyntaxNode) -> bool {
+    let children = node.get_ref_children();
+    match children.first().and_then(|c| c.get_ref_token()) {
+        Some(Token::Keyword(Keyword::Break | Keyword::Continue)) => true,
+        Some(Token::Keyword(Keyword::While | Keyword::For | Keyword::Repeat)) => false,
+        _ => children
+            .iter()
+            .filter(|c| *c.get_ref_syntax() == Syntax::Statement)
+            .any(leaves_loop),
+    }
+}
+
+/// The arguments of `return f(...)` whose expression, `f(...)`, calls the
+/// function whose body holds the statement, as `table` stands there. The
+/// caller has nothing left to do after such a call, whose callee can take
+/// over its frame instead of pushing another.
+pub fn self_tail_call<'a>(table: &Table, expression: &'a SyntaxNode) -> Option<Vec<&'a SyntaxNode>> {
+    let children = match expression.get_ref_children().as_slice() {
+        [term] => match term.get_ref_children().as_slice() {
+            [factor] => factor.get_ref_children(),
+            _ => return None,
+        },
+        _ => return None,
+    };
+    let name = children[0].get_ref_identifier()?;
+    let is_call = children.get(1).and_then(|c| c.get_ref_token()) == Some(&Token::Symbol(Symbol::Lparen));
+    let f = table.func_index()?;
+    if !is_call || table.search(name) != Some(f) {
+        return None;
+    }
+    Some(children.iter().filter(|c| *c.get_ref_syntax() == Syntax::Expression).collect())
+}
+
+impl Default for Checker {
+    fn default() -> Self {
+        Checker::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(src: &'static str) -> Vec<String> {
+        let tree = Parser::new(Tokenizer::new(src.as_bytes())).parse().unwrap();
+        Checker::new().check(&tree).iter().map(|e| e.to_string()).collect()
+    }
+
+    #[test]
+    fn test_check_names() {
+        assert!(check("function f(a) return a; var x; begin x := f(1) end.").is_empty());
+        assert_eq!(check("var x; begin y := x end."), vec![
+            "1:14: `y` is not declared",
+        ]);
+        assert_eq!(check("const c = 1; var x, x; begin c := 2 end."), vec![
+            "1:21: `x` is already declared in this block",
+            "1:30: cannot assign to constant `c`",
+        ]);
+        assert_eq!(check("function f(a, b) return a; var x; begin x := f(1); x := f; x := x(1) end."), vec![
+            "1:46: `f` takes 2 arguments but 1 was given",
+            "1:57: function `f` must be called with `(...)`",
+            "1:65: `x` is not a function",
+        ]);
+        assert_eq!(check("var x; return x."), vec![
+            "1:8: `return` outside a function",
+        ]);
+    }
+
+    #[test]
+    fn test_check_arrays() {
+        assert!(check("var a[3], i; begin i := 1; a[i] := a[i - 1] + 1 end.").is_empty());
+        assert_eq!(check("var a[0], x; begin a := 1; x := a; x[0] := 1; x := x[0] end."), vec![
+            "1:7: array `a` must have a positive size",
+            "1:20: cannot assign to array `a` as a whole",
+            "1:33: array `a` must be indexed with `[...]`",
+            "1:36: `x` is not an array",
+            "1:52: `x` is not an array",
+        ]);
+    }
+
+    #[test]
+    fn test_check_for() {
+        assert!(check("function f(n) var i; begin for i := n downto 1 do n := n; return n end; var i; for i := 1 to 2 do i := f(i).").is_empty());
+        assert_eq!(check("const c = 1; var a[2]; begin for c := 1 to 2 do ; for a := 1 to 2 do ; for k := 1 to j do end."), vec![
+            "1:34: the `for` loop variable `c` must be a variable",
+            "1:55: the `for` loop variable `a` must be a variable",
+            "1:76: `k` is not declared",
+            "1:86: `j` is not declared",
+        ]);
+    }
+
+    #[test]
+    fn test_check_break_continue() {
+        assert!(check("var i; while 1 = 1 do begin if i = 1 then break; repeat continue until 1 = 1 end.").is_empty());
+        assert_eq!(check("var i; begin break; for i := 1 to 2 do ; continue end."), vec![
+            "1:14: `break` outside a loop",
+            "1:42: `continue` outside a loop",
+        ]);
+    }
+
+    #[test]
+    fn test_check_procedures() {
+        assert!(check("procedure p(a) write a; function f() return 1; begin p(1); call p(f()); f() end.").is_empty());
+        assert_eq!(check("procedure p(a) return a; var x; begin x := p(1); p := 1; call x; p(); x(1) end."), vec![
+            "1:16: procedure `p` cannot return a value",
+            "1:44: procedure `p` does not return a value",
+            "1:50: cannot assign to procedure `p`",
+            "1:63: `x` is not a procedure or function",
+            "1:66: `p` takes 1 argument but 0 were given",
+            "1:71: `x` is not a procedure or function",
+        ]);
+    }
+
+    #[test]
+    fn test_missing_return() {
+        assert!(check("\
+            function f(n)
+                if n = 0 then return 1 else begin n := n - 1; return n end;
+            function g(n)
+                repeat n := n - 1; return n until n = 0;
+            write f(1) + g(1).").is_empty());
+        assert_eq!(check("function f(n) if n = 0 then return 1; function g() while 1 = 1 do return 1; write f(1) + g()."), vec![
+            "1:10: function `f` may reach its end without returning a value",
+            "1:48: function `g` may reach its end without returning a value",
+        ]);
+        // `break` skips the `return` after it
+        assert_eq!(check("\
+            function f(n) var a, b; begin a := 77; b := 88; repeat if n > 0 then break; return 1 until 1 = 1 end;
+            function g(n) repeat begin if n > 0 then continue; return 1 end until n > 0;
+            function h(n) repeat begin while n > 0 do break; return 1 end until 1 = 1;
+            write f(1) + g(1) + h(1)."), vec![
+            "1:10: function `f` may reach its end without returning a value",
+            "2:22: function `g` may reach its end without returning a value",
+        ]);
+    }
+
+    #[test]
+    fn test_check_intrinsics() {
+        assert!(check("var x; x := abs(x) + min(x, 1) * max(2, x).").is_empty());
+        assert_eq!(check("var x; x := abs(x, 1) + min(x) + max."), vec![
+            "1:13: `abs` takes 1 argument but 2 were given",
+            "1:25: `min` takes 2 arguments but 1 was given",
+            "1:34: `max` is not declared",
+        ]);
+    }
+
+    #[test]
+    fn test_check_nested_scopes() {
+        assert!(check("\
+            var a;
+            function f(p)
+                var b;
+                function g(q)
+                    function h() return a + b + p + q + g(q) + f(p);
+                return h();
+            return g(b);
+            a := f(a).").is_empty());
+        assert_eq!(check("function f(p) function g() return p; return g(); begin p := 1; p := g() end."), vec![
+            "1:56: `p` is not declared",
+            "1:64: `p` is not declared",
+            "1:69: `g` is not declared",
+        ]);
+    }
+
+    #[test]
+    fn test_check_read() {
+        assert!(check("var a[2], x; begin read x; read a[x] end.").is_empty());
+        assert_eq!(check("const c = 1; var a[2]; begin read c; read a; read y end."), vec![
+            "1:35: cannot read into constant `c`",
+            "1:43: cannot read into array `a` as a whole",
+            "1:51: `y` is not declared",
+        ]);
+    }
+}
@@ -0,0 +1,452 @@
+// Static single assignment form of the IR, after Cytron et al.: phis go on
+// the iterated dominance frontiers of the blocks assigning a name, then a walk
+// down the dominator tree renames every assignment to a fresh temporary.
+//
+// The names renamed are the scalar variables of a function that no nested
+// function reaches, whose loads and stores disappear, and the temporaries
+// assigned more than once. Variables start out as 0 and parameters as their
+// arguments, which are loaded on entry.
+//
+// Out of SSA, every phi gets a temporary of its own, assigned at the end of
+// each predecessor and copied into the phi's at the start of its block; the
+// copies of all the phis of a block then happen at once, as the phis do.
+
+use std::collections::{HashMap, HashSet};
+use crate::ir::*;
+
+/// The dominator tree of a function, after "A Simple, Fast Dominance
+/// Algorithm" by Cooper, Harvey and Kennedy. Blocks control cannot reach
+/// have no place in it.
+pub struct Dominators {
+    /// immediate dominator of every reachable block but the entry
+    idom: Vec<Option<BlockId>>,
+    children: Vec<Vec<BlockId>>,
+}
+
+impl Dominators {
+    pub fn new(func: &Function) -> Self {
+        let n = func.blocks.len();
+        let order = func.reverse_postorder();
+        let mut number = vec![usize::MAX; n];
+        for (i, &b) in order.iter().enumerate() {
+            number[b] = i;
+        }
+        let preds = func.predecessors();
+        let mut idom = vec![None; n];
+        idom[0] = Some(0);
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &b in &order[1..] {
+                let mut new_idom = None;
+                for &p in &preds[b] {
+                    if idom[p].is_none() {
+                        continue;
+                    }
+                    new_idom = Some(match new_idom {
+                        None => p,
+                        Some(mut q) => {
+                            // the nearest common dominator of p and q
+                            let mut p = p;
+                            while p != q {
+                                while number[p] > number[q] {
+                                    p = idom[p].unwrap();
+                                }
+                                while number[q] > number[p] {
+                                    q = idom[q].unwrap();
+                                }
+                            }
+                            p
+                        },
+                    });
+                }
+                if new_idom != idom[b] {
+                    idom[b] = new_idom;
+                    changed = true;
+                }
+            }
+        }
+        idom[0] = None;
+        let mut children = vec![vec![]; n];
+        for &b in &order[1..] {
+            children[idom[b].unwrap()].push(b);
+        }
+        Dominators {
+            idom,
+            children,
+        }
+    }
+
+    /// The immediate dominator of `b`, none for the entry block.
+    pub fn idom(&self, b: BlockId) -> Option<BlockId> {
+        self.idom[b]
+    }
+
+    /// The blocks `b` immediately dominates.
+    pub fn children(&self, b: BlockId) -> &[BlockId] {
+        &self.children[b]
+    }
+
+    /// Whether every path from the entry to `b` goes through `a`.
+    pub fn dominates(&self, a: BlockId, b: BlockId) -> bool {
+        let mut b = Some(b);
+        while let Some(c) = b {
+            if c == a {
+                return true;
+            }
+            b = self.idom[c];
+        }
+        false
+    }
+
+    /// The reachable blocks, every one after its dominators.
+    pub fn preorder(&self) -> Vec<BlockId> {
+        let mut order = vec![];
+        let mut stack = vec![0];
+        while let Some(b) = stack.pop() {
+            order.push(b);
+            stack.extend(self.children[b].iter().rev());
+        }
+        order
+    }
+
+    /// The dominance frontier of every block: the blocks it does not strictly
+    /// dominate but dominates a predecessor of.
+    pub fn frontiers(&self, func: &Function) -> Vec<Vec<BlockId>> {
+        let mut frontiers: Vec<Vec<BlockId>> = vec![vec![]; func.blocks.len()];
+        for (b, preds) in func.predecessors().iter().enumerate() {
+            if preds.len() < 2 || (b != 0 && self.idom[b].is_none()) {
+                continue;
+            }
+            for &p in preds {
+                if p != 0 && self.idom[p].is_none() {
+                    continue; // unreachable
+                }
+                let mut runner = Some(p);
+                while let Some(r) = runner {
+                    if Some(r) == self.idom[b] {
+                        break;
+                    }
+                    if !frontiers[r].contains(&b) {
+                        frontiers[r].push(b);
+                    }
+                    runner = self.idom[r];
+                }
+            }
+        }
+        frontiers
+    }
+}
+
+/// Turns every function of `module` into SSA form, dropping the blocks
+/// control cannot reach.
+pub fn to_ssa(module: &mut Module) {
+    // variables that nested functions reach stay in memory
+    let mut shared = HashSet::new();
+    for (id, func) in module.funcs.iter().enumerate() {
+        for block in &func.blocks {
+            for inst in &block.insts {
+                if let Inst::Load { var, .. } | Inst::Store { var, .. } = inst {
+                    if var.func != id {
+                        shared.insert(*var);
+                    }
+                }
+            }
+        }
+    }
+    for (id, func) in module.funcs.iter_mut().enumerate() {
+        let promoted: Vec<usize> = (0..func.vars.len())
+            .filter(|&index| func.vars[index].size.is_none() && !shared.contains(&VarRef { func: id, index }))
+            .collect();
+        construct(func, id, &promoted);
+    }
+}
+
+fn construct(func: &mut Function, id: FuncId, promoted: &[usize]) {
+    func.remove_unreachable_blocks();
+    let n = func.blocks.len();
+    // names: the promoted variables, then the temporaries assigned more
+    // than once
+    let mut var_name = vec![None; func.vars.len()];
+    let mut temp_name = vec![None; func.temps];
+    let mut names = 0;
+    for &v in promoted {
+        var_name[v] = Some(names);
+        names += 1;
+    }
+    let mut assignments = vec![0; func.temps];
+    for block in &func.blocks {
+        for inst in &block.insts {
+            if let Some(t) = inst.def() {
+                assignments[t] += 1;
+            }
+        }
+    }
+    for (t, &count) in assignments.iter().enumerate() {
+        if count > 1 {
+            temp_name[t] = Some(names);
+            names += 1;
+        }
+    }
+    let name_assigned = |inst: &Inst| match inst {
+        Inst::Store { var, .. } if var.func == id => var_name[var.index],
+        _ => inst.def().and_then(|t| temp_name[t]),
+    };
+
+    // phis for every name on the iterated dominance frontier of the blocks
+    // assigning it
+    let dom = Dominators::new(func);
+    let frontiers = dom.frontiers(func);
+    let mut sites = vec![vec![]; names];
+    for (b, block) in func.blocks.iter().enumerate() {
+        for inst in &block.insts {
+            if let Some(x) = name_assigned(inst) {
+                if sites[x].last() != Some(&b) {
+                    sites[x].push(b);
+                }
+            }
+        }
+    }
+    // (name, temporary) of the phis of every block
+    let mut phis: Vec<Vec<(usize, Temp)>> = vec![vec![]; n];
+    for (x, sites) in sites.into_iter().enumerate() {
+        let mut has_phi = vec![false; n];
+        let mut queued = vec![false; n];
+        for &b in &sites {
+            queued[b] = true;
+        }
+        let mut work = sites;
+        while let Some(b) = work.pop() {
+            for &d in &frontiers[b] {
+                if !has_phi[d] {
+                    has_phi[d] = true;
+                    phis[d].push((x, func.new_temp()));
+                    if !queued[d] {
+                        queued[d] = true;
+                        work.push(d);
+                    }
+                }
+            }
+        }
+    }
+
+    // renaming: the current value of every name, innermost last
+    let mut stacks: Vec<Vec<Operand>> = vec![vec![]; names];
+    let mut entry = vec![];
+    for &v in promoted {
+        let x = var_name[v].unwrap();
+        if v < func.pars {
+            let t = func.new_temp();
+            entry.push(Inst::Load { dst: t, var: VarRef { func: id, index: v } });
+            stacks[x].push(Operand::Temp(t));
+        } else {
+            stacks[x].push(Operand::Const(0));
+        }
+    }
+    // temporaries loaded from promoted variables, by their values
+    let mut loaded: HashMap<Temp, Operand> = HashMap::new();
+    let mut phi_args: Vec<Vec<Vec<(BlockId, Operand)>>> = phis.iter().map(|p| vec![vec![]; p.len()]).collect();
+    // names assigned in each block, to be restored when leaving it
+    let mut pushed: Vec<Vec<usize>> = vec![vec![]; n];
+    let mut work = vec![(0, false)];
+    while let Some((b, leaving)) = work.pop() {
+        if leaving {
+            for x in pushed[b].drain(..) {
+                stacks[x].pop();
+            }
+            continue;
+        }
+        work.push((b, true));
+        let current = |stacks: &[Vec<Operand>], x: usize| *stacks[x].last().unwrap_or(&Operand::Const(0));
+        let rename = |stacks: &[Vec<Operand>], loaded: &HashMap<Temp, Operand>, v: &mut Operand| {
+            if let Operand::Temp(t) = *v {
+                if let Some(x) = temp_name.get(t).copied().flatten() {
+                    *v = current(stacks, x);
+                } else if let Some(value) = loaded.get(&t) {
+                    *v = *value;
+                }
+            }
+        };
+        let mut insts = std::mem::take(&mut entry);
+        for &(x, t) in &phis[b] {
+            insts.push(Inst::Phi { dst: t, args: vec![] });
+            stacks[x].push(Operand::Temp(t));
+            pushed[b].push(x);
+        }
+        for mut inst in std::mem::take(&mut func.blocks[b].insts) {
+            for v in inst.uses_mut() {
+                rename(&stacks, &loaded, v);
+            }
+            match inst {
+                Inst::Load { dst, var } if var.func == id && var_name[var.index].is_some() => {
+                    loaded.insert(dst, current(&stacks, var_name[var.index].unwrap()));
+                    continue;
+                },
+                Inst::Store { var, src } if var.func == id && var_name[var.index].is_some() => {
+                    let x = var_name[var.index].unwrap();
+                    stacks[x].push(src);
+                    pushed[b].push(x);
+                    continue;
+                },
+                _ => (),
+            }
+            if let Some(x) = inst.def().and_then(|t| temp_name[t]) {
+                let t = func.new_temp();
+                *inst.def_mut().unwrap() = t;
+                stacks[x].push(Operand::Temp(t));
+                pushed[b].push(x);
+            }
+            insts.push(inst);
+        }
+        func.blocks[b].insts = insts;
+        for v in func.blocks[b].term.uses_mut() {
+            rename(&stacks, &loaded, v);
+        }
+        let mut succs = func.blocks[b].term.successors();
+        succs.dedup();
+        for s in succs {
+            for (k, &(x, _)) in phis[s].iter().enumerate() {
+                phi_args[s][k].push((b, current(&stacks, x)));
+            }
+        }
+        work.extend(dom.children(b).iter().rev().map(|&c| (c, false)));
+    }
+    for (b, args) in phi_args.into_iter().enumerate() {
+        for (inst, args) in func.blocks[b].insts.iter_mut().zip(args) {
+            if let Inst::Phi { args: a, .. } = inst {
+                *a = args;
+            }
+        }
+    }
+}
+
+/// Replaces the phis of every function of `module` with copies.
+pub fn from_ssa(module: &mut Module) {
+    for func in &mut module.funcs {
+        for b in 0..func.blocks.len() {
+            let phis = func.blocks[b].insts.iter().take_while(|inst| matches!(inst, Inst::Phi { .. })).count();
+            for k in 0..phis {
+                let t = func.new_temp();
+                let (dst, args) = match &func.blocks[b].insts[k] {
+                    Inst::Phi { dst, args } => (*dst, args.clone()),
+                    _ => unreachable!(),
+                };
+                for (p, v) in args {
+                    func.blocks[p].insts.push(Inst::Copy { dst: t, src: v });
+                }
+                func.blocks[b].insts[k] = Inst::Copy { dst, src: Operand::Temp(t) };
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokenizer::TokenizerOptions;
+    use crate::compile::*;
+
+    fn generate(src: &'static str) -> Module {
+        lower(src.as_bytes(), TokenizerOptions::default()).unwrap()
+    }
+
+    const SUM: &str = "\
+        var i, s;
+        begin
+            s := 0;
+            i := 0;
+            while i < 3 do begin
+                if odd i then s := s + i;
+                i := i + 1
+            end;
+            write s
+        end.";
+
+    #[test]
+    fn test_dominators() {
+        let module = generate(SUM);
+        let func = &module.funcs[0];
+        let dom = Dominators::new(func);
+        let idoms: Vec<Option<BlockId>> = (0..func.blocks.len()).map(|b| dom.idom(b)).collect();
+        assert_eq!(idoms, [None, Some(0), Some(1), Some(1), Some(2), Some(2)]);
+        assert!(dom.dominates(1, 5) && !dom.dominates(4, 5));
+        assert_eq!(dom.preorder(), [0, 1, 3, 2, 4, 5]);
+        assert_eq!(dom.frontiers(func), [vec![], vec![1], vec![1], vec![], vec![5], vec![1]]);
+    }
+
+    #[test]
+    fn test_to_ssa() {
+        let mut module = generate(SUM);
+        to_ssa(&mut module);
+        assert_eq!(module.verify(), Ok(()));
+        assert_eq!(module.to_string(), "\
+main
+  var i, s
+L0:
+  goto L1
+L1:
+  t10 = phi(L0: 0, L5: t8)
+  t12 = phi(L0: 0, L5: t11)
+  t1 = t10 < 3
+  if t1 goto L2 else L3
+L2:
+  t3 = odd t10
+  if t3 goto L4 else L5
+L3:
+  write t12
+  ret
+L4:
+  t6 = t12 + t10
+  goto L5
+L5:
+  t11 = phi(L2: t12, L4: t6)
+  t8 = t10 + 1
+  goto L1
+");
+    }
+
+    #[test]
+    fn test_from_ssa() {
+        let mut module = generate("\
+            function f(n)
+                var r;
+            begin
+                r := 1;
+                while n > 1 do begin r := r * n; n := n - 1 end;
+                return r
+            end;
+            write f(5)
+            .");
+        to_ssa(&mut module);
+        from_ssa(&mut module);
+        assert_eq!(module.verify(), Ok(()));
+        assert_eq!(module.to_string(), "\
+main
+L0:
+  t0 = call f(5)
+  write t0
+  ret
+
+function f(n) in main
+  var r
+L0:
+  t10 = n
+  t11 = t10
+  t12 = 1
+  goto L1
+L1:
+  t8 = t11
+  t9 = t12
+  t1 = t8 > 1
+  if t1 goto L2 else L3
+L2:
+  t4 = t9 * t8
+  t6 = t8 - 1
+  t11 = t6
+  t12 = t4
+  goto L1
+L3:
+  ret t9
+");
+    }
+}
@@ -8,8 +8,11 @@ pub enum Symbol {
     Minus,
     Mult,
     Div,
+    Mod,
     Lparen,
     Rparen,
+    Lbracket,
+    Rbracket,
     Equal,
     Lss,
     Gtr,
@@ -22,6 +25,33 @@ pub enum Symbol {
     Assign,
 }
 
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            Symbol::Plus      => "+",
+            Symbol::Minus     => "-",
+            Symbol::Mult      => "*",
+            Symbol::Div       => "/",
+            Symbol::Mod       => "%",
+            Symbol::Lparen    => "(",
+            Symbol::Rparen    => ")",
+            Symbol::Lbracket  => "[",
+            Symbol::Rbracket  => "]",
+            Symbol::Equal     => "=",
+            Symbol::Lss       => "<",
+            Symbol::Gtr       => ">",
+            Symbol::NotEq     => "<>",
+            Symbol::LssEq     => "<=",
+            Symbol::GtrEq     => ">=",
+            Symbol::Comma     => ",",
+            Symbol::Period    => ".",
+            Symbol::SemiColon => ";",
+            Symbol::Assign    => ":=",
+        };
+        write!(f, "{}", s)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct UndefinedSymbol;
 
@@ -40,8 +70,11 @@ impl TryFrom<CharClass> for Symbol {
             CharClass::Minus     => Ok(Symbol::Minus),
             CharClass::Aster     => Ok(Symbol::Mult),
             CharClass::Slash     => Ok(Symbol::Div),
+            CharClass::Percent   => Ok(Symbol::Mod),
             CharClass::Lparen    => Ok(Symbol::Lparen),
             CharClass::Rparen    => Ok(Symbol::Rparen),
+            CharClass::Lbracket  => Ok(Symbol::Lbracket),
+            CharClass::Rbracket  => Ok(Symbol::Rbracket),
             CharClass::Equal     => Ok(Symbol::Equal),
             CharClass::Lss       => Ok(Symbol::Lss),
             CharClass::Gtr       => Ok(Symbol::Gtr),
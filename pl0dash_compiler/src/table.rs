@@ -0,0 +1,282 @@
+// The name table shared by the semantic checker and the code generators,
+// after the one of the PL/0' compiler in the book: names are entered while
+// walking the declarations of a block and dropped again when it ends.
+
+/// Relative address of the first local variable; the two slots below it
+/// hold the saved display entry and the return address.
+pub const FIRST_ADDR: i32 = 2;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Kind {
+    Var,
+    Array,
+    Par,
+    Func,
+    Proc,
+    Const,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry {
+    pub name: String,
+    pub kind: Kind,
+    /// level of the block the name is declared in (0 for the main block)
+    pub level: usize,
+    /// relative address for `Var`, `Array` (of its first element) and `Par`,
+    /// code address for `Func` and `Proc`
+    pub addr: i32,
+    /// value of a `Const`
+    pub value: i32,
+    /// number of parameters of a `Func` or `Proc`
+    pub pars: usize,
+    /// number of elements of an `Array`
+    pub size: i32,
+}
+
+/// Functions built into the language. They are not entered in the table,
+/// so that a program may declare names of its own like `max`, which then
+/// hide them.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Intrinsic {
+    Abs,
+    Min,
+    Max,
+}
+
+impl Intrinsic {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "abs" => Some(Intrinsic::Abs),
+            "min" => Some(Intrinsic::Min),
+            "max" => Some(Intrinsic::Max),
+            _ => None,
+        }
+    }
+
+    pub fn pars(&self) -> usize {
+        match self {
+            Intrinsic::Abs => 1,
+            Intrinsic::Min | Intrinsic::Max => 2,
+        }
+    }
+}
+
+struct BlockInfo {
+    start: usize,
+    local_addr: i32,
+    func: Option<usize>,
+}
+
+pub struct Table {
+    entries: Vec<Entry>,
+    blocks: Vec<BlockInfo>,
+}
+
+impl Table {
+    pub fn new() -> Self {
+        Table {
+            entries: vec![],
+            blocks: vec![],
+        }
+    }
+
+    /// Opens a block. If the last entered name is a function or procedure,
+    /// the block is taken to be its body.
+    pub fn block_begin(&mut self, first_addr: i32) {
+        let func = match self.entries.last() {
+            Some(e) if matches!(e.kind, Kind::Func | Kind::Proc) && e.level + 1 == self.blocks.len() => {
+                Some(self.entries.len() - 1)
+            },
+            _ => None,
+        };
+        self.blocks.push(BlockInfo {
+            start: self.entries.len(),
+            local_addr: first_addr,
+            func,
+        });
+    }
+
+    pub fn block_end(&mut self) {
+        let block = self.blocks.pop().expect("no block to end");
+        self.entries.truncate(block.start);
+    }
+
+    /// Level of the innermost open block.
+    pub fn level(&self) -> usize {
+        self.blocks.len() - 1
+    }
+
+    /// Size of the frame of the innermost block so far.
+    pub fn frame_size(&self) -> i32 {
+        self.blocks.last().unwrap().local_addr
+    }
+
+    /// Index of the function or procedure whose body is the innermost block.
+    pub fn func_index(&self) -> Option<usize> {
+        self.blocks.last().and_then(|b| b.func)
+    }
+
+    /// Index of the function or procedure whose body is the open block at
+    /// `level`, none for the main block.
+    pub fn block_func(&self, level: usize) -> Option<usize> {
+        self.blocks[level].func
+    }
+
+    /// Number of parameters of the function or procedure whose body is the
+    /// innermost block.
+    pub fn func_pars(&self) -> usize {
+        self.func_index().map_or(0, |i| self.entries[i].pars)
+    }
+
+    pub fn enter_func(&mut self, name: &str, addr: i32) -> usize {
+        self.enter(name, Kind::Func, addr, 0)
+    }
+
+    pub fn enter_proc(&mut self, name: &str, addr: i32) -> usize {
+        self.enter(name, Kind::Proc, addr, 0)
+    }
+
+    pub fn enter_par(&mut self, name: &str) -> usize {
+        let i = self.enter(name, Kind::Par, 0, 0);
+        if let Some(f) = self.func_index() {
+            self.entries[f].pars += 1;
+        }
+        i
+    }
+
+    /// Fixes the addresses of the parameters just entered: they sit right
+    /// below the frame, the last one at -1.
+    pub fn end_par(&mut self) {
+        let pars = self.func_pars();
+        let start = self.blocks.last().unwrap().start;
+        for (i, e) in self.entries[start..start + pars].iter_mut().enumerate() {
+            e.addr = i as i32 - pars as i32;
+        }
+    }
+
+    pub fn enter_var(&mut self, name: &str) -> usize {
+        let addr = self.frame_size();
+        self.blocks.last_mut().unwrap().local_addr += 1;
+        self.enter(name, Kind::Var, addr, 0)
+    }
+
+    /// Allocates an unnamed variable in the frame of the innermost block, for
+    /// a value the generated code keeps while a statement runs.
+    pub fn enter_temp(&mut self) -> usize {
+        self.enter_var("")
+    }
+
+    pub fn enter_array(&mut self, name: &str, size: i32) -> usize {
+        let addr = self.frame_size();
+        self.blocks.last_mut().unwrap().local_addr += size;
+        let i = self.enter(name, Kind::Array, addr, 0);
+        self.entries[i].size = size;
+        i
+    }
+
+    pub fn enter_const(&mut self, name: &str, value: i32) -> usize {
+        self.enter(name, Kind::Const, 0, value)
+    }
+
+    fn enter(&mut self, name: &str, kind: Kind, addr: i32, value: i32) -> usize {
+        let level = self.level();
+        self.entries.push(Entry {
+            name: name.to_string(),
+            kind,
+            level,
+            addr,
+            value,
+            pars: 0,
+            size: 0,
+        });
+        self.entries.len() - 1
+    }
+
+    pub fn change_addr(&mut self, i: usize, addr: i32) {
+        self.entries[i].addr = addr;
+    }
+
+    /// Looks `name` up from the innermost block outwards.
+    pub fn search(&self, name: &str) -> Option<usize> {
+        self.entries.iter().rposition(|e| e.name == name)
+    }
+
+    /// Looks `name` up in the innermost block only.
+    pub fn search_in_block(&self, name: &str) -> Option<usize> {
+        let start = self.blocks.last().map_or(0, |b| b.start);
+        self.entries[start..]
+            .iter()
+            .rposition(|e| e.name == name)
+            .map(|i| start + i)
+    }
+
+    /// The intrinsic `name` stands for, unless a declaration hides it.
+    pub fn search_intrinsic(&self, name: &str) -> Option<Intrinsic> {
+        match self.search(name) {
+            Some(_) => None,
+            None => Intrinsic::from_name(name),
+        }
+    }
+
+    /// The names declared in the innermost block so far, parameters first.
+    pub fn block_entries(&self) -> &[Entry] {
+        let start = self.blocks.last().map_or(0, |b| b.start);
+        &self.entries[start..]
+    }
+
+    pub fn get(&self, i: usize) -> &Entry {
+        &self.entries[i]
+    }
+}
+
+impl Default for Table {
+    fn default() -> Self {
+        Table::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scopes() {
+        let mut t = Table::new();
+        t.block_begin(FIRST_ADDR);
+        t.enter_var("x");
+        t.enter_array("a", 10);
+        t.enter_var("y");
+        let f = t.enter_func("f", 0);
+        t.block_begin(FIRST_ADDR);
+        t.enter_par("a");
+        t.enter_par("b");
+        t.end_par();
+        t.enter_var("x");
+        assert_eq!(t.level(), 1);
+        assert_eq!(t.get(f).pars, 2);
+        assert_eq!(t.func_index(), Some(f));
+        assert_eq!((t.block_func(0), t.block_func(1)), (None, Some(f)));
+
+        let a = t.get(t.search("a").unwrap());
+        assert_eq!((a.kind, a.level, a.addr), (Kind::Par, 1, -2));
+        let b = t.get(t.search("b").unwrap());
+        assert_eq!(b.addr, -1);
+        let x = t.get(t.search("x").unwrap());
+        assert_eq!((x.level, x.addr), (1, FIRST_ADDR));
+        assert_eq!(t.search_in_block("f"), None);
+        assert_eq!(t.frame_size(), FIRST_ADDR + 1);
+        let names: Vec<&str> = t.block_entries().iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["a", "b", "x"]);
+
+        t.block_end();
+        let x = t.get(t.search("x").unwrap());
+        assert_eq!((x.level, x.addr), (0, FIRST_ADDR));
+        let a = t.get(t.search("a").unwrap());
+        assert_eq!((a.kind, a.addr, a.size), (Kind::Array, FIRST_ADDR + 1, 10));
+        let y = t.get(t.search("y").unwrap());
+        assert_eq!(y.addr, FIRST_ADDR + 11);
+        assert_eq!(t.frame_size(), FIRST_ADDR + 12);
+        assert_eq!(t.search_in_block("f"), Some(f));
+        assert_eq!(t.func_index(), None);
+    }
+}
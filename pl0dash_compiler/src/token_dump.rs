@@ -0,0 +1,188 @@
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::fmt;
+use crate::tokenizer::*;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DumpFormat {
+    /// `<tokens>` document as in `pl0/*T.xml`
+    Xml,
+    /// one JSON object per token
+    JsonLines,
+    /// `line<TAB>column<TAB>kind<TAB>value<TAB>lexeme`, one token per line
+    Text,
+}
+
+#[derive(Debug, Clone)]
+pub struct UndefinedDumpFormat;
+
+impl fmt::Display for UndefinedDumpFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Undefined dump format (expected xml, json or text)")
+    }
+}
+
+impl FromStr for DumpFormat {
+    type Err = UndefinedDumpFormat;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "xml"                 => Ok(DumpFormat::Xml),
+            "json" | "jsonl"      => Ok(DumpFormat::JsonLines),
+            "text" | "tsv"        => Ok(DumpFormat::Text),
+                                _ => Err(UndefinedDumpFormat),
+        }
+    }
+}
+
+/// Reads `t` to the end and writes every token, lexical errors included,
+/// to `w` in the given format.
+pub fn dump_tokens<W: Write>(t: &mut Tokenizer, format: DumpFormat, w: &mut W) -> io::Result<()> {
+    if format == DumpFormat::Xml {
+        writeln!(w, "<tokens>")?;
+    }
+    loop {
+        let token = match t.get_next_token() {
+            Ok(token) => token,
+            Err(TokenizerError::ReachedEOF) => break,
+            Err(e) => return Err(io::Error::other(e.to_string())),
+        };
+        match format {
+            DumpFormat::Xml => {
+                writeln!(w, "{}", xml_token(&token))?;
+            },
+            DumpFormat::JsonLines => {
+                let pos = t.token_pos();
+                writeln!(w, "{{\"kind\":\"{}\",\"value\":\"{}\",\"lexeme\":\"{}\",\"line\":{},\"column\":{}}}",
+                    token_kind(&token), json_escape(&token_value(&token)), json_escape(t.lexeme()), pos.line, pos.column)?;
+            },
+            DumpFormat::Text => {
+                let pos = t.token_pos();
+                writeln!(w, "{}\t{}\t{}\t{}\t{}",
+                    pos.line, pos.column, token_kind(&token), text_escape(&token_value(&token)), text_escape(t.lexeme()))?;
+            },
+        }
+    }
+    if format == DumpFormat::Xml {
+        writeln!(w, "</tokens>")?;
+    }
+    w.flush()
+}
+
+/// One line of the XML dump, e.g. `<keyword> Begin </keyword>`.
+pub fn xml_token(token: &Token) -> String {
+    let kind = token_kind(token);
+    format!("<{}> {} </{}>", kind, xml_escape(&token_value(token)), kind)
+}
+
+pub fn token_kind(token: &Token) -> &'static str {
+    match token {
+        Token::Keyword(_)    => "keyword",
+        Token::Symbol(_)     => "symbol",
+        Token::Identifier(_) => "identifier",
+        Token::Number(_)     => "number",
+        Token::String(_)     => "string",
+        Token::Error(_)      => "error",
+    }
+}
+
+/// The canonical spelling of a token: the variant name for keywords and
+/// symbols, the (possibly case-folded) name for identifiers, the escaped
+/// contents for strings, and the message for errors.
+pub fn token_value(token: &Token) -> String {
+    match token {
+        Token::Keyword(kw)   => format!("{:?}", kw),
+        Token::Symbol(sym)   => format!("{:?}", sym),
+        Token::Identifier(s) => s.clone(),
+        Token::Number(i)     => i.to_string(),
+        Token::String(s)     => escape_string(s),
+        Token::Error(e)      => e.to_string(),
+    }
+}
+
+pub fn xml_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '<'  => escaped.push_str("&lt;"),
+            '>'  => escaped.push_str("&gt;"),
+            '&'  => escaped.push_str("&amp;"),
+            '"'  => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+               _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+pub fn json_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"'  => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+               _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn text_escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dump(src: &'static str, format: DumpFormat) -> String {
+        let mut t = Tokenizer::new(src.as_bytes());
+        let mut out = vec![];
+        dump_tokens(&mut t, format, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_dump_formats() {
+        let src = "begin\n  x := 10 & y\nend.";
+        assert_eq!(dump(src, DumpFormat::Xml), "\
+<tokens>
+<keyword> Begin </keyword>
+<identifier> x </identifier>
+<symbol> Assign </symbol>
+<number> 10 </number>
+<error> 2:11: undefined character &apos;&amp;&apos; </error>
+<identifier> y </identifier>
+<keyword> End </keyword>
+<symbol> Period </symbol>
+</tokens>
+");
+        assert_eq!(dump(src, DumpFormat::Text), "\
+1\t1\tkeyword\tBegin\tbegin
+2\t3\tidentifier\tx\tx
+2\t5\tsymbol\tAssign\t:=
+2\t8\tnumber\t10\t10
+2\t11\terror\t2:11: undefined character '&'\t&
+2\t13\tidentifier\ty\ty
+3\t1\tkeyword\tEnd\tend
+3\t4\tsymbol\tPeriod\t.
+");
+        let json = dump(src, DumpFormat::JsonLines);
+        let lines: Vec<&str> = json.lines().collect();
+        assert_eq!(lines.len(), 8);
+        assert_eq!(lines[0], r#"{"kind":"keyword","value":"Begin","lexeme":"begin","line":1,"column":1}"#);
+        assert_eq!(lines[2], r#"{"kind":"symbol","value":"Assign","lexeme":":=","line":2,"column":5}"#);
+    }
+
+    #[test]
+    fn test_format_from_str() {
+        assert_eq!("xml".parse::<DumpFormat>().unwrap(), DumpFormat::Xml);
+        assert_eq!("jsonl".parse::<DumpFormat>().unwrap(), DumpFormat::JsonLines);
+        assert_eq!("tsv".parse::<DumpFormat>().unwrap(), DumpFormat::Text);
+        assert!("yaml".parse::<DumpFormat>().is_err());
+    }
+}
@@ -1,6 +1,6 @@
 use std::io::{Read, BufRead, BufReader};
+use std::collections::VecDeque;
 use std::io::ErrorKind;
-use std::fs::File;
 use crate::keyword::*;
 use crate::symbol::*;
 use crate::char_class::*;
@@ -13,20 +13,150 @@ pub enum Token {
     Symbol(Symbol),
     Identifier(String),
     Number(i32),
+    String(String),
+    Error(LexError),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Keyword(kw)   => write!(f, "{}", kw),
+            Token::Symbol(sym)   => write!(f, "{}", sym),
+            Token::Identifier(s) => write!(f, "{}", s),
+            Token::Number(i)     => write!(f, "{}", i),
+            Token::String(s)     => write!(f, "\"{}\"", escape_string(s)),
+            Token::Error(e)      => write!(f, "{}", e),
+        }
+    }
+}
+
+/// Spells `s` the way it is written inside a string literal.
+pub fn escape_string(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"'  => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+               _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// 1-origin line and column of a character in the source.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Position {
+    pub fn new(line: usize, column: usize) -> Self {
+        Position {
+            line,
+            column,
+        }
+    }
+}
+
+impl Default for Position {
+    fn default() -> Self {
+        Position::new(1, 1)
+    }
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum LexErrorKind {
+    UndefinedChar(char),
+    InvalidUtf8(Vec<u8>),
+    LoneColon,
+    CommentNotTerminated,
+    StringNotTerminated,
+    UnknownEscape(char),
+    NumberTooLarge,
+}
+
+/// A lexical error. The tokenizer hands it out as a `Token::Error` and
+/// carries on with the next character.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LexError {
+    pub kind: LexErrorKind,
+    pub pos: Position,
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.kind {
+            LexErrorKind::UndefinedChar(c) => {
+                if c.is_control() || c.is_whitespace() {
+                    write!(f, "{}: undefined character U+{:04X}", self.pos, *c as u32)
+                } else if !c.is_ascii() && is_xid_start(*c) {
+                    write!(f, "{}: undefined character '{}' (non-ASCII identifiers need the unicode identifiers option)", self.pos, c)
+                } else {
+                    write!(f, "{}: undefined character '{}'", self.pos, c)
+                }
+            },
+            LexErrorKind::InvalidUtf8(bytes) => {
+                let hex: Vec<String> = bytes.iter().map(|b| format!("0x{:02x}", b)).collect();
+                write!(f, "{}: invalid UTF-8 sequence {}", self.pos, hex.join(" "))
+            },
+            LexErrorKind::LoneColon => {
+                write!(f, "{}: unexpected ':' (did you mean `:=`?)", self.pos)
+            },
+            LexErrorKind::CommentNotTerminated => {
+                write!(f, "{}: comment not terminated", self.pos)
+            },
+            LexErrorKind::StringNotTerminated => {
+                write!(f, "{}: string literal not terminated", self.pos)
+            },
+            LexErrorKind::UnknownEscape(c) => {
+                write!(f, "{}: unknown escape sequence `\\{}`", self.pos, c)
+            },
+            LexErrorKind::NumberTooLarge => {
+                write!(f, "{}: number too large", self.pos)
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct TokenizerOptions {
+    /// accept identifiers made of Unicode letters (XID_Start XID_Continue*),
+    /// not just ASCII ones.
+    pub unicode_identifiers: bool,
+    /// match keywords regardless of case and fold identifiers to lower case,
+    /// as classic PL/0 programs written in `BEGIN ... END` expect.
+    pub case_insensitive: bool,
+    pub keywords: KeywordTable,
 }
 
 pub struct Tokenizer {
-    reader: BufReader<File>,
-    current_byte: u8,
+    reader: BufReader<Box<dyn Read>>,
+    options: TokenizerOptions,
+    current_char: char,
+    // raw bytes of `current_char` when they are not valid UTF-8
+    current_invalid: Option<Vec<u8>>,
+    reached_eof: bool,
+    pos: Position,
+    token_pos: Position,
+    lexeme: String,
+    // errors found inside a comment, handed out before the next token
+    pending_errors: VecDeque<LexError>,
 }
 
 #[derive(Debug)]
 pub enum TokenizerError {
     ReachedEOF,
-    UndefinedToken,
     CannotReadByte,
-    CommentNotTerminated,
-    Unrecoverable,
 }
 
 impl fmt::Display for TokenizerError {
@@ -35,66 +165,99 @@ impl fmt::Display for TokenizerError {
             TokenizerError::ReachedEOF => {
                 write!(f, "Error: Reached EOF")
             },
-            TokenizerError::UndefinedToken => {
-                write!(f, "Error: Undefined token found")
-            },
             TokenizerError::CannotReadByte => {
                 write!(f, "Error: Cannot read byte")
             },
-            TokenizerError::CommentNotTerminated => {
-                write!(f, "Comment Not Terminated")
-            },
-            TokenizerError::Unrecoverable => {
-                write!(f, "Unexpected error occurred")
-            }
         }
     }
 }
 
 impl Tokenizer {
-    pub fn new(f: File) -> Self {
-        let mut reader = BufReader::new(f);
-        let mut byte = [0; 1];
-        reader.read_exact(&mut byte).unwrap();
+    pub fn new<R: Read + 'static>(r: R) -> Self {
+        Tokenizer::with_options(r, TokenizerOptions::default())
+    }
 
-        Tokenizer {
-            reader: reader,
-            current_byte: byte[0],
-        }
+    pub fn with_options<R: Read + 'static>(r: R, options: TokenizerOptions) -> Self {
+        let mut t = Tokenizer {
+            reader: BufReader::new(Box::new(r) as Box<dyn Read>),
+            options,
+            current_char: '\n',
+            current_invalid: None,
+            reached_eof: false,
+            pos: Position::new(0, 1),
+            token_pos: Position::default(),
+            lexeme: String::new(),
+            pending_errors: VecDeque::new(),
+        };
+        // the '\n' placeholder moves the position to 1:1
+        let _ = t._read_next_char();
+        t
+    }
+
+    /// Position of the first character of the token most recently returned
+    /// by `get_next_token`.
+    pub fn token_pos(&self) -> Position {
+        self.token_pos
+    }
+
+    /// Source text of the token most recently returned by `get_next_token`,
+    /// in its original spelling.
+    pub fn lexeme(&self) -> &str {
+        &self.lexeme
     }
 
     pub fn get_next_token(&mut self) -> Result<Token, TokenizerError> {
-        while self.current_byte.is_ascii_whitespace() || self.current_byte == b'\n' {
-            self._read_next_byte()?;
+        if let Some(e) = self.pending_errors.pop_front() {
+            self.token_pos = e.pos;
+            self.lexeme.clear();
+            return Ok(Token::Error(e));
+        }
+        if self.reached_eof {
+            return Err(TokenizerError::ReachedEOF);
+        }
+        while self.current_char.is_whitespace() {
+            self._read_next_char()?;
         }
-        match CharClass::from_u8(self.current_byte) {
+        self.token_pos = self.pos;
+        self.lexeme.clear();
+        if let Some(bytes) = self.current_invalid.take() {
+            self._advance()?;
+            return Ok(self._error_token(LexErrorKind::InvalidUtf8(bytes)));
+        }
+        match CharClass::from_char(self.current_char) {
             CharClass::Digit => {
                 self._tokenize_number()
             },
             CharClass::Letter => {
                 self._tokenize_identifier()
             },
+            CharClass::Other if self.options.unicode_identifiers && is_xid_start(self.current_char) => {
+                self._tokenize_identifier()
+            },
+            CharClass::Quote => {
+                self._tokenize_string()
+            },
             CharClass::Colon => {
-                self._read_next_byte()?;
-                match CharClass::from_u8(self.current_byte) {
+                self._advance()?;
+                match CharClass::from_char(self.current_char) {
                     CharClass::Equal => {
-                        self._read_next_byte()?;
+                        self._advance()?;
                         Ok(Token::Symbol(Symbol::Assign))
                     },
                     _ => {
-                        Err(TokenizerError::UndefinedToken)
+                        Ok(self._error_token(LexErrorKind::LoneColon))
                     }
                 }
             },
             CharClass::Lss => {
-                self._read_next_byte()?;
-                match CharClass::from_u8(self.current_byte) {
+                self._advance()?;
+                match CharClass::from_char(self.current_char) {
                     CharClass::Equal => {
-                        self._read_next_byte()?;
+                        self._advance()?;
                         Ok(Token::Symbol(Symbol::LssEq))
                     },
                     CharClass::Gtr => {
-                        self._read_next_byte()?;
+                        self._advance()?;
                         Ok(Token::Symbol(Symbol::NotEq))
                     },
                     _ => {
@@ -103,10 +266,10 @@ impl Tokenizer {
                 }
             },
             CharClass::Gtr => {
-                self._read_next_byte()?;
-                match CharClass::from_u8(self.current_byte) {
+                self._advance()?;
+                match CharClass::from_char(self.current_char) {
                     CharClass::Equal => {
-                        self._read_next_byte()?;
+                        self._advance()?;
                         Ok(Token::Symbol(Symbol::GtrEq))
                     },
                     _ => {
@@ -115,11 +278,19 @@ impl Tokenizer {
                 }
             },
             CharClass::Slash => {
-                self._read_next_byte()?;
-                match CharClass::from_u8(self.current_byte) {
+                self._advance()?;
+                match CharClass::from_char(self.current_char) {
                     CharClass::Aster => { /* comment */
-                        self._skip_comment()?;
-                        self.get_next_token() // recursion
+                        if self._skip_comment()? {
+                            self.get_next_token() // recursion
+                        } else {
+                            let e = LexError {
+                                kind: LexErrorKind::CommentNotTerminated,
+                                pos: self.token_pos,
+                            };
+                            self.pending_errors.push_back(e);
+                            self.get_next_token()
+                        }
                     },
                     _ => {
                         Ok(Token::Symbol(Symbol::Div))
@@ -127,30 +298,96 @@ impl Tokenizer {
                 }
             },
             cc => {
+                let c = self.current_char;
+                self._advance()?; // possibly final char (if sym is a period)
                 match Symbol::try_from(cc) {
                     Ok(sym) => {
-                        self._read_next_byte(); // possibly final byte (if sym is a period)
                         Ok(Token::Symbol(sym))
                     },
                     Err(_) => {
-                        Err(TokenizerError::UndefinedToken)
+                        match self.options.keywords.lookup(self.lexeme.as_str()) {
+                            Some(kw) => Ok(Token::Keyword(kw)), // e.g. `!` of classic PL/0
+                            None => Ok(self._error_token(LexErrorKind::UndefinedChar(c))),
+                        }
                     }
                 }
             }
         }
     }
 
-    fn _read_next_byte(&mut self) -> Result<(), TokenizerError> {
+    fn _error_token(&self, kind: LexErrorKind) -> Token {
+        Token::Error(LexError {
+            kind,
+            pos: self.token_pos,
+        })
+    }
+
+    // decodes the next UTF-8 character into `current_char`. A malformed
+    // sequence becomes U+FFFD with its bytes kept in `current_invalid`.
+    fn _read_next_char(&mut self) -> Result<(), TokenizerError> {
+        if self.reached_eof {
+            return Err(TokenizerError::ReachedEOF);
+        }
+        let lead = match self._read_byte()? {
+            Some(b) => b,
+            None => {
+                // '\0' never matches a char class we look ahead for
+                self.reached_eof = true;
+                self.current_char = '\0';
+                self.current_invalid = None;
+                return Err(TokenizerError::ReachedEOF);
+            }
+        };
+        if self.current_char == '\n' {
+            self.pos.line += 1;
+            self.pos.column = 1;
+        } else {
+            self.pos.column += 1;
+        }
+
+        let len = match lead {
+            0x00..=0x7f => 1,
+            0xc2..=0xdf => 2,
+            0xe0..=0xef => 3,
+            0xf0..=0xf4 => 4,
+                      _ => 0,
+        };
+        let mut bytes = vec![lead];
+        while bytes.len() < len {
+            // a continuation byte is only consumed if it really is one, so
+            // that a truncated sequence does not swallow the next character
+            let buf = self.reader.fill_buf().map_err(|_| TokenizerError::CannotReadByte)?;
+            match buf.first() {
+                Some(&b) if b & 0xc0 == 0x80 => {
+                    bytes.push(b);
+                    self.reader.consume(1);
+                },
+                _ => break,
+            }
+        }
+        match std::str::from_utf8(&bytes) {
+            Ok(s) if len != 0 => {
+                self.current_char = s.chars().next().unwrap();
+                self.current_invalid = None;
+            },
+            _ => {
+                self.current_char = char::REPLACEMENT_CHARACTER;
+                self.current_invalid = Some(bytes);
+            }
+        }
+        Ok(())
+    }
+
+    fn _read_byte(&mut self) -> Result<Option<u8>, TokenizerError> {
         let mut byte = [0; 1];
         match self.reader.read_exact(&mut byte) {
             Ok(_) => {
-                self.current_byte = byte[0];
-                Ok(())
+                Ok(Some(byte[0]))
             },
             Err(e) => {
                 match e.kind() {
                     ErrorKind::UnexpectedEof => {
-                        Err(TokenizerError::ReachedEOF)
+                        Ok(None)
                     },
                     _ => {
                         Err(TokenizerError::CannotReadByte)
@@ -160,181 +397,366 @@ impl Tokenizer {
         }
     }
 
-    fn _read_until(&mut self, b: u8) -> Result<(), std::io::Error>{
-        let mut _skip = vec![];
-        self.reader.read_until(b, &mut _skip)?;
-        self.current_byte = b;
-        Ok(())
+    // like `_read_next_char`, but running into EOF is left for the next call
+    // of `get_next_token` to report.
+    fn _advance(&mut self) -> Result<(), TokenizerError> {
+        self.lexeme.push(self.current_char);
+        match self._read_next_char() {
+            Err(TokenizerError::ReachedEOF) => Ok(()),
+            r => r,
+        }
     }
 
     fn _tokenize_number(&mut self) -> Result<Token, TokenizerError> {
-        let mut digits = vec![self.current_byte];
+        let mut digits = vec![self.current_char];
         loop {
-            match self._read_next_byte() {
-                Ok(_) => {
-                    match self.current_byte {
-                        b'0'..=b'9' => {
-                            digits.push(self.current_byte);
-                        },
-                        _ => {
-                            break;
-                        }
-                    }
+            self._advance()?;
+            match self.current_char {
+                '0'..='9' => {
+                    digits.push(self.current_char);
                 },
-                Err(e) => {
-                    match e {
-                        TokenizerError::ReachedEOF => {
-                            break;
-                        },
-                        _ => {
-                            return Err(e);
-                        }
-                    }
+                _ => {
+                    break;
                 }
             }
         }
 
         let num = digits
             .into_iter()
-            .map(|d| (d - b'0') as i32)
-            .fold(0, |acc, d| 10*acc + d);
+            .map(|d| d.to_digit(10).unwrap() as i32)
+            .try_fold(0i32, |acc, d| acc.checked_mul(10)?.checked_add(d));
 
+        // a number too large still stands for an operand, so that the parser
+        // reports nothing more about it
+        let num = num.unwrap_or_else(|| {
+            self.pending_errors.push_back(LexError {
+                kind: LexErrorKind::NumberTooLarge,
+                pos: self.token_pos,
+            });
+            i32::MAX
+        });
         Ok(Token::Number(num))
     }
 
     fn _tokenize_identifier(&mut self) -> Result<Token, TokenizerError> {
-        let mut chars = vec![self.current_byte];
+        let mut word = String::new();
+        word.push(self.current_char);
         loop {
-            match self._read_next_byte() {
-                Ok(_) => {
-                    match CharClass::from_u8(self.current_byte) {
-                        CharClass::Digit | CharClass::Letter => {
-                            chars.push(self.current_byte);
-                        },
-                        _ => {
-                            break;
-                        }
-                    }
-                },
-                Err(e) => {
-                    match e {
-                        TokenizerError::ReachedEOF => {
-                            break;
-                        },
-                        _ => {
-                            return Err(e);
-                        }
-                    }
-                }
+            self._advance()?;
+            let c = self.current_char;
+            let accepted = match CharClass::from_char(c) {
+                CharClass::Digit | CharClass::Letter => true,
+                CharClass::Other => self.options.unicode_identifiers && !c.is_ascii() && is_xid_continue(c),
+                _ => false,
+            };
+            if accepted && self.current_invalid.is_none() {
+                word.push(c);
+            } else {
+                break;
             }
         }
 
-        let word = std::str::from_utf8(&chars).unwrap();
-        match Keyword::try_from(word) {
-            Ok(kw) => {
+        if self.options.case_insensitive {
+            word = word.to_lowercase();
+        }
+        match self.options.keywords.lookup(word.as_str()) {
+            Some(kw) => {
                 Ok(Token::Keyword(kw))
             },
-            Err(_) => {
-                Ok(Token::Identifier(word.to_string()))
+            None => {
+                Ok(Token::Identifier(word))
             }
         }
     }
 
-    fn _skip_comment(&mut self) -> Result<(), TokenizerError> {
+    // reads a string literal up to the closing quote on the same line. The
+    // escapes are `\n`, `\t`, `\r`, `\\` and `\"`.
+    fn _tokenize_string(&mut self) -> Result<Token, TokenizerError> {
+        let mut value = String::new();
+        let mut unknown_escape = None;
+        self._advance()?; // "
         loop {
-            match self._read_until(b'*') {
+            if self.reached_eof || self.current_char == '\n' {
+                return Ok(self._error_token(LexErrorKind::StringNotTerminated));
+            }
+            if let Some(bytes) = self.current_invalid.take() {
+                self.pending_errors.push_back(LexError {
+                    kind: LexErrorKind::InvalidUtf8(bytes),
+                    pos: self.pos,
+                });
+            }
+            match self.current_char {
+                '"' => {
+                    self._advance()?;
+                    break;
+                },
+                '\\' => {
+                    let escape_pos = self.pos;
+                    self._advance()?;
+                    if self.reached_eof || self.current_char == '\n' {
+                        continue; // not terminated
+                    }
+                    let c = match self.current_char {
+                        'n'  => '\n',
+                        't'  => '\t',
+                        'r'  => '\r',
+                        '\\' => '\\',
+                        '"'  => '"',
+                        c => {
+                            unknown_escape.get_or_insert(LexError {
+                                kind: LexErrorKind::UnknownEscape(c),
+                                pos: escape_pos,
+                            });
+                            c
+                        }
+                    };
+                    value.push(c);
+                    self._advance()?;
+                },
+                c => {
+                    value.push(c);
+                    self._advance()?;
+                }
+            }
+        }
+        match unknown_escape {
+            Some(e) => Ok(Token::Error(e)),
+            None => Ok(Token::String(value)),
+        }
+    }
+
+    // skips a comment whose leading `/` has already been consumed.
+    // returns false if EOF is reached before the closing `*/`.
+    fn _skip_comment(&mut self) -> Result<bool, TokenizerError> {
+        let mut prev = '\0';
+        loop {
+            match self._read_next_char() {
                 Ok(()) => {
-                    self._read_next_byte()?;
-                    if self.current_byte == b'/' {
-                        self._read_next_byte()?;
-                        break;
+                    if let Some(bytes) = self.current_invalid.take() {
+                        self.pending_errors.push_back(LexError {
+                            kind: LexErrorKind::InvalidUtf8(bytes),
+                            pos: self.pos,
+                        });
                     }
+                    if prev == '*' && self.current_char == '/' {
+                        self._advance()?;
+                        return Ok(true);
+                    }
+                    prev = self.current_char;
+                },
+                Err(TokenizerError::ReachedEOF) => {
+                    return Ok(false);
                 },
                 Err(e) => {
-                    match e.kind() {
-                        ErrorKind::UnexpectedEof => {
-                            return Err(TokenizerError::CommentNotTerminated)
-                        },
-                        _ => {
-                            return Err(TokenizerError::Unrecoverable)
-                        }
-                    }
+                    return Err(e);
                 }
             }
         }
-        Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
     #[test]
-    fn test_export_xml() {
+    fn test_error_recovery() {
         use super::*;
-        use std::path::Path;
-        use std::fs::File;
-        use std::io::{BufWriter, Write};
-        use std::process::Command;
-
-        // pair list of full path of *.pl0 and *T.xml files
-        let mut filename_pairs_in_out = vec![]; 
-        let dir = Path::new("/workspace/pl0dash-compiler/pl0dash_compiler/pl0");
-        for f in dir.read_dir().expect("read_dir call failed") {
-            if let Ok(f) = f {
-                if f.path().extension().unwrap() == "pl0" {
-                    let input_filename = f.path().to_string_lossy().into_owned();
-                    let output_filename = dir.join(f.path().file_stem().unwrap()).to_string_lossy().into_owned()+"T.xml";
-                    filename_pairs_in_out.push((input_filename, output_filename));
-                }
+
+        let src = "x := 1 @ 2;\ny # z : 3;\nw := 99999999999 + 2147483647;\n";
+        let mut t = Tokenizer::new(src.as_bytes());
+        let mut tokens = vec![];
+        let mut errors = vec![];
+        loop {
+            match t.get_next_token() {
+                Ok(Token::Error(e)) => errors.push(e),
+                Ok(tok) => tokens.push(tok),
+                Err(TokenizerError::ReachedEOF) => break,
+                Err(e) => panic!("{}", e),
             }
         }
-        // tokenize *.pl0, export *T.xml, and compare with *T.xml.org
-        for (fin, fout) in filename_pairs_in_out.iter() {
-            let input_file = File::open(fin).expect("cannot open input file");
-            let mut t = Tokenizer::new(input_file);
-
-            let output_file = File::create(fout).expect("cannot open output file");
-            let mut w = BufWriter::<File>::new(output_file);
-
-            // export xml
-            writeln!(w, "<tokens>").unwrap();
-            'export_xml: loop {
-                match t.get_next_token() {
-                    Ok(t) => {
-                        match t {
-                            Token::Keyword(kw) => {
-                                writeln!(w, "<keyword> {:?} </keyword>", kw).unwrap();
-                            },
-                            Token::Symbol(sym) => {
-                                writeln!(w, "<symbol> {:?} </symbol>", sym).unwrap();
-                            },
-                            Token::Identifier(s) => {
-                                writeln!(w, "<identifier> {} </identifier>", s).unwrap();
-                            },
-                            Token::Number(i) => {
-                                writeln!(w, "<number> {} </number>", i).unwrap();
-                            },
-                        }
-                    },
-                    Err(e) => {
-                        match e {
-                            TokenizerError::ReachedEOF => {
-                                break 'export_xml;
-                            },
-                            _ => {
-                                panic!("{}", e);
-                            }
-                        }
-                    }
-                }
-            }
-            writeln!(w, "</tokens>").unwrap();
-            w.flush().unwrap();
+        assert_eq!(errors, vec![
+            LexError { kind: LexErrorKind::UndefinedChar('@'), pos: Position::new(1, 8) },
+            LexError { kind: LexErrorKind::UndefinedChar('#'), pos: Position::new(2, 3) },
+            LexError { kind: LexErrorKind::LoneColon, pos: Position::new(2, 7) },
+            LexError { kind: LexErrorKind::NumberTooLarge, pos: Position::new(3, 6) },
+        ]);
+        assert_eq!(errors[3].to_string(), "3:6: number too large");
+        assert_eq!(errors[2].to_string(), "2:7: unexpected ':' (did you mean `:=`?)");
+        assert_eq!(tokens, vec![
+            Token::Identifier("x".to_string()),
+            Token::Symbol(Symbol::Assign),
+            Token::Number(1),
+            Token::Number(2),
+            Token::Symbol(Symbol::SemiColon),
+            Token::Identifier("y".to_string()),
+            Token::Identifier("z".to_string()),
+            Token::Number(3),
+            Token::Symbol(Symbol::SemiColon),
+            Token::Identifier("w".to_string()),
+            Token::Symbol(Symbol::Assign),
+            Token::Number(2147483647),
+            Token::Symbol(Symbol::Plus),
+            Token::Number(2147483647),
+            Token::Symbol(Symbol::SemiColon),
+        ]);
+    }
+
+    #[test]
+    fn test_unterminated_comment() {
+        use super::*;
+
+        let mut t = Tokenizer::new("end. /* oops".as_bytes());
+        assert_eq!(t.get_next_token().unwrap(), Token::Keyword(Keyword::End));
+        assert_eq!(t.get_next_token().unwrap(), Token::Symbol(Symbol::Period));
+        assert_eq!(t.get_next_token().unwrap(), Token::Error(LexError {
+            kind: LexErrorKind::CommentNotTerminated,
+            pos: Position::new(1, 6),
+        }));
+        assert!(matches!(t.get_next_token(), Err(TokenizerError::ReachedEOF)));
+    }
+
+    #[test]
+    fn test_non_ascii_source() {
+        use super::*;
 
-            // compare two files
-            //let forg = Path::new(fout).with_extension("xml.org").to_string_lossy().into_owned();
-            //let diff_status = Command::new("diff").args(["-b", "-u", &fout, &forg]).status().expect("failed to execute process");
-            //assert!(diff_status.success());
+        // comments may hold any text, and columns count characters, not bytes
+        let src = "/* 階乗を計算する */ x := 1;\n/* ここも */ 変数 := 2";
+        let mut t = Tokenizer::new(src.as_bytes());
+        assert_eq!(t.get_next_token().unwrap(), Token::Identifier("x".to_string()));
+        assert_eq!(t.token_pos(), Position::new(1, 15));
+        t.get_next_token().unwrap(); // :=
+        t.get_next_token().unwrap(); // 1
+        t.get_next_token().unwrap(); // ;
+        let e = t.get_next_token().unwrap();
+        assert_eq!(e, Token::Error(LexError {
+            kind: LexErrorKind::UndefinedChar('変'),
+            pos: Position::new(2, 11),
+        }));
+        if let Token::Error(e) = e {
+            assert!(e.to_string().contains("unicode identifiers"));
         }
+
+        let options = TokenizerOptions { unicode_identifiers: true, ..Default::default() };
+        let mut t = Tokenizer::with_options(src.as_bytes(), options);
+        for _ in 0..4 {
+            t.get_next_token().unwrap();
+        }
+        assert_eq!(t.get_next_token().unwrap(), Token::Identifier("変数".to_string()));
+        assert_eq!(t.token_pos(), Position::new(2, 11));
+        assert_eq!(t.get_next_token().unwrap(), Token::Symbol(Symbol::Assign));
+        assert_eq!(t.token_pos(), Position::new(2, 14));
+
+        // XID_Continue takes the middle dot and combining marks, but not
+        // every numeric character
+        let options = TokenizerOptions { unicode_identifiers: true, ..Default::default() };
+        let mut t = Tokenizer::with_options("l\u{b7}l e\u{301} x\u{b2}".as_bytes(), options);
+        assert_eq!(t.get_next_token().unwrap(), Token::Identifier("l\u{b7}l".to_string()));
+        assert_eq!(t.get_next_token().unwrap(), Token::Identifier("e\u{301}".to_string()));
+        assert_eq!(t.get_next_token().unwrap(), Token::Identifier("x".to_string()));
+        assert_eq!(t.get_next_token().unwrap(), Token::Error(LexError {
+            kind: LexErrorKind::UndefinedChar('\u{b2}'),
+            pos: Position::new(1, 9),
+        }));
+    }
+
+    #[test]
+    fn test_invalid_utf8() {
+        use super::*;
+
+        let src: &'static [u8] = b"x\n  y \xe3\x81 z /* \xff */ w";
+        let mut t = Tokenizer::new(src);
+        assert_eq!(t.get_next_token().unwrap(), Token::Identifier("x".to_string()));
+        assert_eq!(t.get_next_token().unwrap(), Token::Identifier("y".to_string()));
+        let e = t.get_next_token().unwrap();
+        assert_eq!(e, Token::Error(LexError {
+            kind: LexErrorKind::InvalidUtf8(vec![0xe3, 0x81]),
+            pos: Position::new(2, 5),
+        }));
+        if let Token::Error(e) = e {
+            assert_eq!(e.to_string(), "2:5: invalid UTF-8 sequence 0xe3 0x81");
+        }
+        assert_eq!(t.get_next_token().unwrap(), Token::Identifier("z".to_string()));
+        assert_eq!(t.token_pos(), Position::new(2, 7));
+        assert_eq!(t.get_next_token().unwrap(), Token::Error(LexError {
+            kind: LexErrorKind::InvalidUtf8(vec![0xff]),
+            pos: Position::new(2, 12),
+        }));
+        assert_eq!(t.get_next_token().unwrap(), Token::Identifier("w".to_string()));
+    }
+
+    #[test]
+    fn test_case_insensitive() {
+        use super::*;
+
+        let src = "VAR Count; BEGIN count := 1 End.";
+        let mut t = Tokenizer::new(src.as_bytes());
+        assert_eq!(t.get_next_token().unwrap(), Token::Identifier("VAR".to_string()));
+
+        let options = TokenizerOptions { case_insensitive: true, ..Default::default() };
+        let mut t = Tokenizer::with_options(src.as_bytes(), options);
+        assert_eq!(t.get_next_token().unwrap(), Token::Keyword(Keyword::Var));
+        assert_eq!(t.lexeme(), "VAR");
+        assert_eq!(t.get_next_token().unwrap(), Token::Identifier("count".to_string()));
+        assert_eq!(t.lexeme(), "Count");
+        assert_eq!(t.get_next_token().unwrap(), Token::Symbol(Symbol::SemiColon));
+        assert_eq!(t.get_next_token().unwrap(), Token::Keyword(Keyword::Begin));
+        assert_eq!(t.get_next_token().unwrap(), Token::Identifier("count".to_string()));
+        assert_eq!(t.lexeme(), "count");
+        t.get_next_token().unwrap(); // :=
+        t.get_next_token().unwrap(); // 1
+        assert_eq!(t.get_next_token().unwrap(), Token::Keyword(Keyword::End));
+    }
+
+    #[test]
+    fn test_keyword_table() {
+        use super::*;
+
+        let src = "! x; write y; ? z";
+        let mut t = Tokenizer::new(src.as_bytes());
+        assert!(matches!(t.get_next_token().unwrap(), Token::Error(_)));
+
+        let mut options = TokenizerOptions::default();
+        options.keywords.enable(&CLASSIC_IO);
+        options.keywords.remove("write");
+        let mut t = Tokenizer::with_options(src.as_bytes(), options);
+        assert_eq!(t.get_next_token().unwrap(), Token::Keyword(Keyword::Write));
+        assert_eq!(t.lexeme(), "!");
+        assert_eq!(t.get_next_token().unwrap(), Token::Identifier("x".to_string()));
+        assert_eq!(t.get_next_token().unwrap(), Token::Symbol(Symbol::SemiColon));
+        assert_eq!(t.get_next_token().unwrap(), Token::Identifier("write".to_string()));
+        assert_eq!(t.get_next_token().unwrap(), Token::Identifier("y".to_string()));
+        assert_eq!(t.get_next_token().unwrap(), Token::Symbol(Symbol::SemiColon));
+        assert_eq!(t.get_next_token().unwrap(), Token::Keyword(Keyword::Read));
+        assert_eq!(t.lexeme(), "?");
+
+        let src = "procedure call";
+        let mut t = Tokenizer::new(src.as_bytes());
+        assert_eq!(t.get_next_token().unwrap(), Token::Keyword(Keyword::Proc));
+        assert_eq!(t.get_next_token().unwrap(), Token::Keyword(Keyword::Call));
+        let mut options = TokenizerOptions::default();
+        options.keywords.disable(&PROCEDURES);
+        let mut t = Tokenizer::with_options(src.as_bytes(), options);
+        assert_eq!(t.get_next_token().unwrap(), Token::Identifier("procedure".to_string()));
+        assert_eq!(t.get_next_token().unwrap(), Token::Identifier("call".to_string()));
+    }
+
+    #[test]
+    fn test_string_literals() {
+        use super::*;
+
+        let src = "write \"a \\\"b\\\"\\n\\t\\\\\", \"\";\n\"bad \\q\" \"open\nx";
+        let mut t = Tokenizer::new(src.as_bytes());
+        assert_eq!(t.get_next_token().unwrap(), Token::Keyword(Keyword::Write));
+        let s = t.get_next_token().unwrap();
+        assert_eq!(s, Token::String("a \"b\"\n\t\\".to_string()));
+        assert_eq!(t.lexeme(), "\"a \\\"b\\\"\\n\\t\\\\\"");
+        assert_eq!(s.to_string(), t.lexeme());
+        assert_eq!(t.get_next_token().unwrap(), Token::Symbol(Symbol::Comma));
+        assert_eq!(t.get_next_token().unwrap(), Token::String(String::new()));
+        assert_eq!(t.get_next_token().unwrap(), Token::Symbol(Symbol::SemiColon));
+        let e = t.get_next_token().unwrap();
+        assert_eq!(e.to_string(), "2:6: unknown escape sequence `\\q`");
+        let e = t.get_next_token().unwrap();
+        assert_eq!(e.to_string(), "2:10: string literal not terminated");
+        assert_eq!(t.get_next_token().unwrap(), Token::Identifier("x".to_string()));
     }
 }
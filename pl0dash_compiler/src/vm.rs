@@ -0,0 +1,321 @@
+use std::io::{self, BufRead, Write};
+use std::fmt;
+use std::collections::VecDeque;
+use crate::codegen::*;
+use crate::table::FIRST_ADDR;
+
+/// Default size of the stack in words.
+pub const STACK_SIZE: usize = 1 << 20;
+
+#[derive(Debug)]
+pub enum RuntimeError {
+    StackOverflow,
+    DivisionByZero,
+    IndexOutOfBounds {
+        index: i32,
+        size: i32,
+    },
+    EndOfInput,
+    MalformedInput(String),
+    CannotRead(io::Error),
+    CannotWrite(io::Error),
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RuntimeError::StackOverflow => {
+                write!(f, "runtime error: stack overflow")
+            },
+            RuntimeError::DivisionByZero => {
+                write!(f, "runtime error: division by zero")
+            },
+            RuntimeError::IndexOutOfBounds { index, size } => {
+                write!(f, "runtime error: index {} is out of bounds for an array of size {}", index, size)
+            },
+            RuntimeError::EndOfInput => {
+                write!(f, "runtime error: `read` reached the end of the input")
+            },
+            RuntimeError::MalformedInput(s) => {
+                write!(f, "runtime error: `read` expected an integer, found `{}`", s)
+            },
+            RuntimeError::CannotRead(e) => {
+                write!(f, "runtime error: cannot read input: {}", e)
+            },
+            RuntimeError::CannotWrite(e) => {
+                write!(f, "runtime error: cannot write output: {}", e)
+            },
+        }
+    }
+}
+
+/// Source of the integers taken by `read`.
+pub trait Input {
+    fn read_int(&mut self) -> Result<i32, RuntimeError>;
+}
+
+/// In-memory input, handy for tests.
+impl Input for VecDeque<i32> {
+    fn read_int(&mut self) -> Result<i32, RuntimeError> {
+        self.pop_front().ok_or(RuntimeError::EndOfInput)
+    }
+}
+
+/// Input of whitespace separated decimal integers, such as stdin or a file.
+pub struct TextInput<R: BufRead> {
+    reader: R,
+}
+
+impl<R: BufRead> TextInput<R> {
+    pub fn new(reader: R) -> Self {
+        TextInput { reader }
+    }
+
+    /// Reads the next whitespace separated word, if any.
+    fn read_word(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut word = vec![];
+        loop {
+            let buf = self.reader.fill_buf()?;
+            if buf.is_empty() {
+                break;
+            }
+            let skip = if word.is_empty() {
+                buf.iter().take_while(|b| b.is_ascii_whitespace()).count()
+            } else {
+                0
+            };
+            let len = buf[skip..].iter().take_while(|b| !b.is_ascii_whitespace()).count();
+            word.extend_from_slice(&buf[skip..skip + len]);
+            let at_end = skip + len < buf.len();
+            self.reader.consume(skip + len);
+            if at_end && !word.is_empty() {
+                break;
+            }
+        }
+        Ok(if word.is_empty() { None } else { Some(word) })
+    }
+}
+
+impl<R: BufRead> Input for TextInput<R> {
+    fn read_int(&mut self) -> Result<i32, RuntimeError> {
+        let word = self.read_word()
+            .map_err(RuntimeError::CannotRead)?
+            .ok_or(RuntimeError::EndOfInput)?;
+        let word = String::from_utf8_lossy(&word);
+        word.parse::<i32>().map_err(|_| RuntimeError::MalformedInput(word.into_owned()))
+    }
+}
+
+/// Interpreter of the stack machine code, after `execute` of the book. The
+/// frame of a block at level `l` starts at `display[l]` and holds the saved
+/// display entry, the return address and the local variables, in this order;
+/// the arguments of a call sit just below the frame.
+pub struct Vm<'a> {
+    code: &'a [Inst],
+    strings: &'a [String],
+    stack: Vec<i32>,
+    top: usize,
+    display: Vec<usize>,
+    pc: usize,
+}
+
+impl<'a> Vm<'a> {
+    pub fn new(program: &'a Program) -> Self {
+        Vm::with_stack_size(program, STACK_SIZE)
+    }
+
+    pub fn with_stack_size(program: &'a Program, stack_size: usize) -> Self {
+        Vm {
+            code: &program.code,
+            strings: &program.strings,
+            stack: vec![0; stack_size.max(2)],
+            top: 0,
+            display: vec![0],
+            pc: 0,
+        }
+    }
+
+    /// Runs the program to its end, sending `write` and `writeln` output to
+    /// `out`. Any `read` fails as the program is given no input.
+    pub fn run<W: Write>(&mut self, out: &mut W) -> Result<(), RuntimeError> {
+        self.run_with_input(&mut VecDeque::new(), out)
+    }
+
+    /// Runs the program to its end, taking the integers of `read` from
+    /// `input` and sending `write` and `writeln` output to `out`.
+    pub fn run_with_input<I: Input, W: Write>(&mut self, input: &mut I, out: &mut W) -> Result<(), RuntimeError> {
+        self.stack[0] = 0;
+        self.stack[1] = 0;
+        self.top = 0;
+        self.pc = 0;
+        self.display[0] = 0;
+        loop {
+            let inst = self.code[self.pc];
+            self.pc += 1;
+            match inst {
+                Inst::Lit(v) => {
+                    self.push(v)?;
+                },
+                Inst::Opr(op) => {
+                    self.operate(op, input, out)?;
+                },
+                Inst::Lod(a) => {
+                    let v = self.stack[self.address(a)];
+                    self.push(v)?;
+                },
+                Inst::Sto(a) => {
+                    let v = self.pop();
+                    let i = self.address(a);
+                    self.stack[i] = v;
+                },
+                Inst::Lda(a, size) => {
+                    let index = self.pop();
+                    let i = self.element_address(a, index, size)?;
+                    let v = self.stack[i];
+                    self.push(v)?;
+                },
+                Inst::Sta(a, size) => {
+                    let v = self.pop();
+                    let index = self.pop();
+                    let i = self.element_address(a, index, size)?;
+                    self.stack[i] = v;
+                },
+                Inst::Cal(a) => {
+                    let lev = a.level + 1;
+                    if self.display.len() <= lev {
+                        self.display.resize(lev + 1, 0);
+                    }
+                    if self.top + 2 > self.stack.len() {
+                        return Err(RuntimeError::StackOverflow);
+                    }
+                    self.stack[self.top] = self.display[lev] as i32;
+                    self.stack[self.top + 1] = self.pc as i32;
+                    self.display[lev] = self.top;
+                    self.pc = a.addr as usize;
+                },
+                Inst::Ret(a) => {
+                    let temp = self.pop();
+                    self.top = self.display[a.level];
+                    self.display[a.level] = self.stack[self.top] as usize;
+                    self.pc = self.stack[self.top + 1] as usize;
+                    self.top -= a.addr as usize;
+                    self.push(temp)?;
+                },
+                Inst::Ict(v) => {
+                    let top = (self.top as i64 + v as i64) as usize;
+                    if top >= self.stack.len() {
+                        return Err(RuntimeError::StackOverflow);
+                    }
+                    // the variables of a frame start at zero, above the slots
+                    // `cal` filled
+                    if top > self.top {
+                        let start = (self.top + FIRST_ADDR as usize).min(top);
+                        self.stack[start..top].fill(0);
+                    }
+                    self.top = top;
+                },
+                Inst::Jmp(a) => {
+                    self.pc = a;
+                },
+                Inst::Jpc(a) => {
+                    if self.pop() == 0 {
+                        self.pc = a;
+                    }
+                },
+                Inst::Wrs(i) => {
+                    write!(out, "{}", self.strings[i]).map_err(RuntimeError::CannotWrite)?;
+                },
+            }
+            if self.pc == 0 {
+                break;
+            }
+        }
+        out.flush().map_err(RuntimeError::CannotWrite)
+    }
+
+    fn operate<I: Input, W: Write>(&mut self, op: Operator, input: &mut I, out: &mut W) -> Result<(), RuntimeError> {
+        match op {
+            Operator::Neg => {
+                let v = self.pop();
+                self.push(v.wrapping_neg())?;
+            },
+            Operator::Odd => {
+                let v = self.pop();
+                self.push(v & 1)?;
+            },
+            Operator::Abs => {
+                let v = self.pop();
+                self.push(v.wrapping_abs())?;
+            },
+            Operator::Wrt => {
+                let v = self.pop();
+                write!(out, "{} ", v).map_err(RuntimeError::CannotWrite)?;
+            },
+            Operator::Wrl => {
+                writeln!(out).map_err(RuntimeError::CannotWrite)?;
+            },
+            Operator::Dup => {
+                let v = self.stack[self.top - 1];
+                self.push(v)?;
+            },
+            Operator::Red => {
+                // let the prompt of a preceding `write` show up first
+                out.flush().map_err(RuntimeError::CannotWrite)?;
+                let v = input.read_int()?;
+                self.push(v)?;
+            },
+            _ => {
+                let r = self.pop();
+                let l = self.pop();
+                let v = match op {
+                    Operator::Add  => l.wrapping_add(r),
+                    Operator::Sub  => l.wrapping_sub(r),
+                    Operator::Mul  => l.wrapping_mul(r),
+                    // both truncate towards zero, so that `l mod r` takes
+                    // the sign of `l` and `(l / r) * r + l mod r = l`
+                    Operator::Div | Operator::Mod if r == 0 => {
+                        return Err(RuntimeError::DivisionByZero);
+                    },
+                    Operator::Div  => l.wrapping_div(r),
+                    Operator::Mod  => l.wrapping_rem(r),
+                    Operator::Min  => l.min(r),
+                    Operator::Max  => l.max(r),
+                    Operator::Eq   => (l == r) as i32,
+                    Operator::Ls   => (l < r) as i32,
+                    Operator::Gr   => (l > r) as i32,
+                    Operator::Neq  => (l != r) as i32,
+                    Operator::LsEq => (l <= r) as i32,
+                    Operator::GrEq => (l >= r) as i32,
+                    _ => unreachable!(),
+                };
+                self.push(v)?;
+            },
+        }
+        Ok(())
+    }
+
+    fn address(&self, a: RelAddr) -> usize {
+        (self.display[a.level] as i32 + a.addr) as usize
+    }
+
+    fn element_address(&self, a: RelAddr, index: i32, size: i32) -> Result<usize, RuntimeError> {
+        if index < 0 || index >= size {
+            return Err(RuntimeError::IndexOutOfBounds { index, size });
+        }
+        Ok(self.address(a) + index as usize)
+    }
+
+    fn push(&mut self, v: i32) -> Result<(), RuntimeError> {
+        if self.top >= self.stack.len() {
+            return Err(RuntimeError::StackOverflow);
+        }
+        self.stack[self.top] = v;
+        self.top += 1;
+        Ok(())
+    }
+
+    fn pop(&mut self) -> i32 {
+        self.top -= 1;
+        self.stack[self.top]
+    }
+}
@@ -0,0 +1,752 @@
+// WebAssembly backend: translates a checked syntax tree into a module in the
+// WebAssembly text format.
+//
+// WebAssembly locals cannot be reached from other functions, so the variables
+// of every block live in a frame in linear memory, on a stack that grows
+// downwards from the top of memory:
+//
+//     fp + 0            static link, the frame of the enclosing block
+//     fp + 4 + 4*k      slot k: the parameters, then the variables and
+//                       array elements by relative address
+//
+// Functions take the static link and the arguments as `i32` parameters and
+// keep the address of their frame in the local `$fp`. The strings of `write`
+// sit at the bottom of memory, below the stack.
+//
+// The host provides, in the module `env`:
+//
+//     write (i32)                    writes an integer and a space
+//     writeln ()                     ends the line
+//     write_string (i32 i32)         writes the bytes at an address, of a length
+//     read () -> i32                 reads an integer, stopping on bad input
+//     division_by_zero ()            report the runtime error and stop,
+//     index_out_of_bounds (i32 i32)  by throwing an exception for example
+//     stack_overflow ()
+//
+// and runs the exported function `main`.
+
+use std::fmt::Write;
+use crate::tokenizer::*;
+use crate::keyword::*;
+use crate::symbol::*;
+use crate::parser::*;
+use crate::table::*;
+use crate::backend::*;
+use crate::semantic::self_tail_call;
+
+/// Pages of linear memory; the stack may take up to 4 MiB like the one of
+/// the stack machine.
+const PAGES: usize = 64;
+
+const RUNTIME: &str = r#"  (import "env" "write" (func $write (param i32)))
+  (import "env" "writeln" (func $writeln))
+  (import "env" "write_string" (func $write_string (param i32 i32)))
+  (import "env" "read" (func $read (result i32)))
+  (import "env" "division_by_zero" (func $division_by_zero))
+  (import "env" "index_out_of_bounds" (func $index_out_of_bounds (param i32 i32)))
+  (import "env" "stack_overflow" (func $stack_overflow))
+
+  ;; `i32.div_s` traps on the quotient of the smallest integer by -1, which
+  ;; wraps around in the stack machine
+  (func $pl0_div (param $a i32) (param $b i32) (result i32)
+    local.get $b
+    i32.eqz
+    if
+      call $division_by_zero
+      unreachable
+    end
+    local.get $b
+    i32.const -1
+    i32.eq
+    if
+      i32.const 0
+      local.get $a
+      i32.sub
+      return
+    end
+    local.get $a
+    local.get $b
+    i32.div_s
+  )
+  (func $pl0_mod (param $a i32) (param $b i32) (result i32)
+    local.get $b
+    i32.eqz
+    if
+      call $division_by_zero
+      unreachable
+    end
+    local.get $a
+    local.get $b
+    i32.rem_s
+  )
+  (func $pl0_abs (param $a i32) (result i32)
+    i32.const 0
+    local.get $a
+    i32.sub
+    local.get $a
+    local.get $a
+    i32.const 0
+    i32.lt_s
+    select
+  )
+  (func $pl0_min (param $a i32) (param $b i32) (result i32)
+    local.get $a
+    local.get $b
+    local.get $a
+    local.get $b
+    i32.lt_s
+    select
+  )
+  (func $pl0_max (param $a i32) (param $b i32) (result i32)
+    local.get $a
+    local.get $b
+    local.get $a
+    local.get $b
+    i32.gt_s
+    select
+  )
+  (func $pl0_index (param $index i32) (param $size i32) (result i32)
+    local.get $index
+    local.get $size
+    i32.ge_u
+    if
+      local.get $index
+      local.get $size
+      call $index_out_of_bounds
+      unreachable
+    end
+    local.get $index
+  )
+"#;
+
+/// Labels of the current loop that `break` and `continue` branch to.
+struct Loop {
+    break_label: usize,
+    continue_label: usize,
+}
+
+/// Translates a checked syntax tree into a WebAssembly text module.
+/// Functions are entered in the table with a number, which names them.
+pub struct WatGenerator {
+    table: Table,
+    /// finished functions
+    funcs: String,
+    /// body of the function being generated
+    text: String,
+    indent: usize,
+    /// strings, one after the other from address 0
+    strings: Vec<String>,
+    loops: Vec<Loop>,
+    /// whether the block being generated calls its function in tail position
+    tail_calls: bool,
+    funcs_count: usize,
+    labels: usize,
+}
+
+impl WatGenerator {
+    pub fn new() -> Self {
+        WatGenerator {
+            table: Table::new(),
+            funcs: String::new(),
+            text: String::new(),
+            indent: 2,
+            strings: vec![],
+            loops: vec![],
+            tail_calls: false,
+            funcs_count: 0,
+            labels: 0,
+        }
+    }
+
+    pub fn generate(mut self, tree: &SyntaxTree) -> String {
+        let program = tree.get_ref_root();
+        self.table.block_begin(FIRST_ADDR);
+        self.gen_block(&program.get_ref_children()[0], None);
+        let mut out = "(module\n".to_string();
+        out += RUNTIME;
+        writeln!(out, "\n  (memory (export \"memory\") {})", PAGES).unwrap();
+        writeln!(out, "  (global $sp (mut i32) (i32.const {}))", PAGES * 65536).unwrap();
+        // the stack ends where the strings begin
+        let data_size: usize = self.strings.iter().map(String::len).sum();
+        writeln!(out, "  (global $stack_limit i32 (i32.const {}))", data_size.next_multiple_of(8)).unwrap();
+        let mut addr = 0;
+        for s in &self.strings {
+            writeln!(out, "  (data (i32.const {}) \"{}\")", addr, wat_string(s)).unwrap();
+            addr += s.len();
+        }
+        out += &self.funcs;
+        out += "\n  (export \"main\" (func $main))\n)\n";
+        out
+    }
+
+    fn gen_block(&mut self, node: &SyntaxNode, func: Option<usize>) {
+        for child in node.get_ref_children() {
+            match child.get_ref_syntax() {
+                Syntax::ConstDecl => enter_consts(&mut self.table, child),
+                Syntax::VarDecl => {
+                    enter_vars(&mut self.table, child);
+                },
+                Syntax::FuncDecl | Syntax::ProcDecl => self.gen_func_decl(child),
+                Syntax::Statement => self.gen_statement(child),
+                _ => unreachable!(),
+            }
+        }
+        let e = func.map(|f| self.table.get(f).clone());
+        if std::mem::take(&mut self.tail_calls) {
+            // a self tail call branches back to the start of the statement
+            let statement = std::mem::take(&mut self.text);
+            self.open("loop $body");
+            for line in statement.lines() {
+                writeln!(self.text, "  {}", line).unwrap();
+            }
+            self.close();
+        }
+        match &e {
+            // the end of a function cannot be reached
+            Some(e) if e.kind == Kind::Func => self.inst("unreachable"),
+            _ => self.pop_frame(),
+        }
+        let body = std::mem::take(&mut self.text);
+        // the frame is known once the statement had its temporaries
+        let pars = e.as_ref().map_or(0, |e| e.pars);
+        let size = 4 * (1 + pars as i32 + self.table.frame_size() - FIRST_ADDR);
+        match &e {
+            Some(e) => {
+                write!(self.funcs, "\n  ;; {} {}\n  (func {} (param $up i32)", kind_name(e), e.name, func_name("$", e)).unwrap();
+                for i in 0..e.pars {
+                    write!(self.funcs, " (param $a{} i32)", i).unwrap();
+                }
+                if e.kind == Kind::Func {
+                    self.funcs += " (result i32)";
+                }
+                self.funcs += "\n";
+            },
+            None => self.funcs += "\n  (func $main\n",
+        }
+        self.funcs += "    (local $fp i32) (local $saved i32)\n";
+        self.funcs += "    global.get $sp\n    local.tee $saved\n    global.get $stack_limit\n    i32.sub\n";
+        writeln!(self.funcs, "    i32.const {}\n    i32.lt_u\n    if\n      call $stack_overflow\n      unreachable\n    end", size).unwrap();
+        writeln!(self.funcs, "    local.get $saved\n    i32.const {}\n    i32.sub\n    local.tee $fp\n    global.set $sp", size).unwrap();
+        writeln!(self.funcs, "    local.get $fp\n    i32.const 0\n    i32.const {}\n    memory.fill", size).unwrap();
+        if func.is_some() {
+            self.funcs += "    local.get $fp\n    local.get $up\n    i32.store\n";
+        }
+        for i in 0..pars {
+            writeln!(self.funcs, "    local.get $fp\n    local.get $a{}\n    i32.store offset={}", i, 4 + 4 * i).unwrap();
+        }
+        self.funcs += &body;
+        self.funcs += "  )\n";
+        self.table.block_end();
+    }
+
+    fn gen_func_decl(&mut self, node: &SyntaxNode) {
+        self.funcs_count += 1;
+        let id = self.funcs_count as i32;
+        let decl = enter_func(&mut self.table, node, id);
+        self.gen_block(decl.body, Some(decl.func));
+    }
+
+    fn gen_statement(&mut self, node: &SyntaxNode) {
+        let children = node.get_ref_children();
+        let first = match children.first() {
+            Some(first) => first,
+            None => return, // empty statement
+        };
+        match first.get_ref_token().unwrap() {
+            Token::Identifier(name) if children.len() > 1 && children[1].get_ref_token() == Some(&Token::Symbol(Symbol::Lparen)) => {
+                let e = lookup(&self.table, name);
+                self.gen_call(&e, &children[1..]);
+                if e.kind == Kind::Func {
+                    self.inst("drop");
+                }
+            },
+            Token::Keyword(Keyword::Call) => {
+                let e = lookup(&self.table, children[1].get_ref_identifier().unwrap());
+                self.gen_call(&e, &children[2..]);
+                if e.kind == Kind::Func {
+                    self.inst("drop");
+                }
+            },
+            Token::Identifier(name) => {
+                let e = lookup(&self.table, name);
+                if e.kind == Kind::Array {
+                    // ident [ index ] := value
+                    self.gen_element(&e, &children[2]);
+                    self.gen_expression(&children[5]);
+                } else {
+                    self.frame(e.level);
+                    self.gen_expression(&children[2]);
+                }
+                let offset = self.offset(&e);
+                self.inst(&format!("i32.store offset={}", offset));
+            },
+            Token::Keyword(Keyword::Begin) => {
+                for child in children {
+                    if let Syntax::Statement = child.get_ref_syntax() {
+                        self.gen_statement(child);
+                    }
+                }
+            },
+            Token::Keyword(Keyword::If) => {
+                self.gen_condition(&children[1]);
+                self.open("if");
+                self.gen_statement(&children[3]);
+                if children.len() > 4 {
+                    self.indent -= 1;
+                    self.inst("else");
+                    self.indent += 1;
+                    self.gen_statement(&children[5]);
+                }
+                self.close();
+            },
+            Token::Keyword(Keyword::While) => {
+                let (next, start) = (self.new_label(), self.new_label());
+                self.open(&format!("block $L{}", next));
+                self.open(&format!("loop $L{}", start));
+                self.gen_condition(&children[1]);
+                self.inst("i32.eqz");
+                self.inst(&format!("br_if $L{}", next));
+                self.loops.push(Loop { break_label: next, continue_label: start });
+                self.gen_statement(&children[3]);
+                self.loops.pop();
+                self.inst(&format!("br $L{}", start));
+                self.close();
+                self.close();
+            },
+            Token::Keyword(Keyword::For) => {
+                let var = lookup(&self.table, children[1].get_ref_identifier().unwrap());
+                let temp = self.table.enter_temp();
+                let limit = self.table.get(temp).clone();
+                let (cmp, last, step) = match children[4].get_ref_token() {
+                    Some(Token::Keyword(Keyword::To)) => ("i32.le_s", "i32.ge_s", "i32.add"),
+                    _ => ("i32.ge_s", "i32.le_s", "i32.sub"),
+                };
+                let (next, start, step_label) = (self.new_label(), self.new_label(), self.new_label());
+                self.frame(var.level);
+                self.gen_expression(&children[3]);
+                self.store(&var);
+                self.frame(limit.level);
+                self.gen_expression(&children[5]);
+                self.store(&limit);
+                self.open(&format!("block $L{}", next));
+                self.open(&format!("loop $L{}", start));
+                self.load(&var);
+                self.load(&limit);
+                self.inst(cmp);
+                self.inst("i32.eqz");
+                self.inst(&format!("br_if $L{}", next));
+                self.open(&format!("block $L{}", step_label));
+                self.loops.push(Loop { break_label: next, continue_label: step_label });
+                self.gen_statement(&children[7]);
+                self.loops.pop();
+                self.close();
+                self.load(&var);
+                self.load(&limit);
+                self.inst(last);
+                self.inst(&format!("br_if $L{}", next));
+                self.frame(var.level);
+                self.load(&var);
+                self.inst("i32.const 1");
+                self.inst(step);
+                self.store(&var);
+                self.inst(&format!("br $L{}", start));
+                self.close();
+                self.close();
+            },
+            Token::Keyword(Keyword::Repeat) => {
+                let (next, start, cond) = (self.new_label(), self.new_label(), self.new_label());
+                self.open(&format!("block $L{}", next));
+                self.open(&format!("loop $L{}", start));
+                self.open(&format!("block $L{}", cond));
+                self.loops.push(Loop { break_label: next, continue_label: cond });
+                for child in children {
+                    if let Syntax::Statement = child.get_ref_syntax() {
+                        self.gen_statement(child);
+                    }
+                }
+                self.loops.pop();
+                self.close();
+                self.gen_condition(children.last().unwrap());
+                self.inst("i32.eqz");
+                self.inst(&format!("br_if $L{}", start));
+                self.close();
+                self.close();
+            },
+            Token::Keyword(Keyword::Break) => {
+                let target = self.loops.last().unwrap().break_label;
+                self.inst(&format!("br $L{}", target));
+            },
+            Token::Keyword(Keyword::Continue) => {
+                let target = self.loops.last().unwrap().continue_label;
+                self.inst(&format!("br $L{}", target));
+            },
+            Token::Keyword(Keyword::Ret) => {
+                if let Some(args) = self_tail_call(&self.table, &children[1]) {
+                    self.gen_tail_call(&args);
+                    return;
+                }
+                self.gen_expression(&children[1]);
+                self.pop_frame();
+                self.inst("return");
+            },
+            Token::Keyword(Keyword::Write) => {
+                for arg in &children[1..] {
+                    if let Some(Token::String(s)) = arg.get_ref_token() {
+                        let addr = self.string_addr(s);
+                        self.inst(&format!("i32.const {}", addr));
+                        self.inst(&format!("i32.const {}", s.len()));
+                        self.inst("call $write_string");
+                    } else if let Syntax::Expression = arg.get_ref_syntax() {
+                        self.gen_expression(arg);
+                        self.inst("call $write");
+                    }
+                }
+            },
+            Token::Keyword(Keyword::WriteLn) => {
+                self.inst("call $writeln");
+            },
+            Token::Keyword(Keyword::Read) => {
+                let e = lookup(&self.table, children[1].get_ref_identifier().unwrap());
+                if e.kind == Kind::Array {
+                    // read ident [ index ]
+                    self.gen_element(&e, &children[3]);
+                } else {
+                    self.frame(e.level);
+                }
+                self.inst("call $read");
+                let offset = self.offset(&e);
+                self.inst(&format!("i32.store offset={}", offset));
+            },
+            t => unreachable!("statement starting with {:?}", t),
+        }
+    }
+
+    /// Generates a condition, leaving 1 if it holds and 0 otherwise.
+    /// Operands of `and` and `or` are evaluated only as far as needed.
+    fn gen_condition(&mut self, node: &SyntaxNode) {
+        let children = node.get_ref_children();
+        match children[0].get_ref_token() {
+            Some(Token::Keyword(Keyword::Not)) => {
+                self.gen_condition(&children[1]);
+                return self.inst("i32.eqz");
+            },
+            Some(Token::Keyword(Keyword::Odd)) => {
+                self.gen_expression(&children[1]);
+                self.inst("i32.const 1");
+                return self.inst("i32.and");
+            },
+            Some(Token::Symbol(Symbol::Lparen)) => {
+                return self.gen_condition(&children[1]);
+            },
+            _ => (),
+        }
+        if let Syntax::Condition = children[0].get_ref_syntax() {
+            let is_or = children[1].get_ref_token() == Some(&Token::Keyword(Keyword::Or));
+            let operands: Vec<&SyntaxNode> = children.iter().step_by(2).collect();
+            self.gen_condition(operands[0]);
+            for c in &operands[1..] {
+                self.open("if (result i32)");
+                if is_or {
+                    self.inst("i32.const 1");
+                    self.indent -= 1;
+                    self.inst("else");
+                    self.indent += 1;
+                    self.gen_condition(c);
+                } else {
+                    self.gen_condition(c);
+                    self.indent -= 1;
+                    self.inst("else");
+                    self.indent += 1;
+                    self.inst("i32.const 0");
+                }
+                self.close();
+            }
+            return;
+        }
+        let op = match children[1].get_ref_token() {
+            Some(Token::Symbol(Symbol::Equal)) => "i32.eq",
+            Some(Token::Symbol(Symbol::Lss))   => "i32.lt_s",
+            Some(Token::Symbol(Symbol::Gtr))   => "i32.gt_s",
+            Some(Token::Symbol(Symbol::NotEq)) => "i32.ne",
+            Some(Token::Symbol(Symbol::LssEq)) => "i32.le_s",
+            Some(Token::Symbol(Symbol::GtrEq)) => "i32.ge_s",
+            t => unreachable!("relational operator {:?}", t),
+        };
+        self.gen_expression(&children[0]);
+        self.gen_expression(&children[2]);
+        self.inst(op);
+    }
+
+    fn gen_expression(&mut self, node: &SyntaxNode) {
+        let children = node.get_ref_children();
+        let mut i = 0;
+        let mut negate = false;
+        if let Some(Token::Symbol(sym)) = children[0].get_ref_token() {
+            negate = *sym == Symbol::Minus;
+            i = 1;
+        }
+        if negate {
+            self.inst("i32.const 0");
+        }
+        self.gen_term(&children[i]);
+        if negate {
+            self.inst("i32.sub");
+        }
+        for pair in children[i + 1..].chunks(2) {
+            let op = match pair[0].get_ref_token() {
+                Some(Token::Symbol(Symbol::Plus))  => "i32.add",
+                Some(Token::Symbol(Symbol::Minus)) => "i32.sub",
+                t => unreachable!("additive operator {:?}", t),
+            };
+            self.gen_term(&pair[1]);
+            self.inst(op);
+        }
+    }
+
+    fn gen_term(&mut self, node: &SyntaxNode) {
+        let children = node.get_ref_children();
+        self.gen_factor(&children[0]);
+        for pair in children[1..].chunks(2) {
+            let op = match pair[0].get_ref_token() {
+                Some(Token::Symbol(Symbol::Mult)) => "i32.mul",
+                Some(Token::Symbol(Symbol::Div))  => "call $pl0_div",
+                Some(Token::Symbol(Symbol::Mod)) | Some(Token::Keyword(Keyword::Mod)) => "call $pl0_mod",
+                t => unreachable!("multiplicative operator {:?}", t),
+            };
+            self.gen_factor(&pair[1]);
+            self.inst(op);
+        }
+    }
+
+    fn gen_factor(&mut self, node: &SyntaxNode) {
+        let children = node.get_ref_children();
+        match children[0].get_ref_token() {
+            Some(Token::Identifier(name)) => {
+                if let Some(f) = self.table.search_intrinsic(name) {
+                    for arg in &children[1..] {
+                        if let Syntax::Expression = arg.get_ref_syntax() {
+                            self.gen_expression(arg);
+                        }
+                    }
+                    let f = match f {
+                        Intrinsic::Abs => "call $pl0_abs",
+                        Intrinsic::Min => "call $pl0_min",
+                        Intrinsic::Max => "call $pl0_max",
+                    };
+                    return self.inst(f);
+                }
+                let e = lookup(&self.table, name);
+                match e.kind {
+                    Kind::Var | Kind::Par => self.load(&e),
+                    Kind::Array => {
+                        self.gen_element(&e, &children[2]);
+                        let offset = self.offset(&e);
+                        self.inst(&format!("i32.load offset={}", offset));
+                    },
+                    Kind::Const => self.inst(&format!("i32.const {}", e.value)),
+                    Kind::Func => self.gen_call(&e, &children[1..]),
+                    Kind::Proc => unreachable!("unchecked syntax tree"),
+                }
+            },
+            Some(Token::Number(value)) => {
+                self.inst(&format!("i32.const {}", value));
+            },
+            _ => {
+                // ( expression )
+                self.gen_expression(&children[1]);
+            },
+        }
+    }
+
+    /// Leaves the address of an element of the array `e`, less the offset
+    /// of its first element, checking the index is within bounds.
+    fn gen_element(&mut self, e: &Entry, index: &SyntaxNode) {
+        self.gen_expression(index);
+        self.inst(&format!("i32.const {}", e.size));
+        self.inst("call $pl0_index");
+        self.inst("i32.const 4");
+        self.inst("i32.mul");
+        self.frame(e.level);
+        self.inst("i32.add");
+    }
+
+    /// Pushes the static link and the arguments among `args` and calls `e`.
+    fn gen_call(&mut self, e: &Entry, args: &[SyntaxNode]) {
+        // the callee is declared in the block whose frame it links to
+        self.frame(e.level);
+        for arg in args {
+            if let Syntax::Expression = arg.get_ref_syntax() {
+                self.gen_expression(arg);
+            }
+        }
+        self.inst(&format!("call {}", func_name("$", e)));
+    }
+
+    /// Calls the current function again in its own frame: the arguments
+    /// replace the parameters once all of them are known, the locals are
+    /// zeroed again, and the statement of its body starts over.
+    fn gen_tail_call(&mut self, args: &[&SyntaxNode]) {
+        // each argument goes on the stack above the address it is stored at
+        for arg in args {
+            self.inst("local.get $fp");
+            self.gen_expression(arg);
+        }
+        let pars = self.table.block_entries()[..args.len()].to_vec();
+        for par in pars.iter().rev() {
+            self.store(par);
+        }
+        let locals = self.table.frame_size() - FIRST_ADDR;
+        if locals > 0 {
+            self.inst("local.get $fp");
+            self.inst(&format!("i32.const {}", 4 + 4 * pars.len()));
+            self.inst("i32.add");
+            self.inst("i32.const 0");
+            self.inst(&format!("i32.const {}", 4 * locals));
+            self.inst("memory.fill");
+        }
+        self.inst("br $body");
+        self.tail_calls = true;
+    }
+
+    /// Restores the stack pointer of the caller.
+    fn pop_frame(&mut self) {
+        self.inst("local.get $saved");
+        self.inst("global.set $sp");
+    }
+
+    /// Follows the static links up to the frame of the block at `level`.
+    fn frame(&mut self, level: usize) {
+        self.inst("local.get $fp");
+        for _ in level..self.table.level() {
+            self.inst("i32.load");
+        }
+    }
+
+    /// Offset within its frame of the variable or parameter `e`, or of the
+    /// first element of the array `e`.
+    fn offset(&self, e: &Entry) -> i32 {
+        let pars = self.table.block_func(e.level).map_or(0, |f| self.table.get(f).pars) as i32;
+        let slot = if e.addr < 0 { e.addr + pars } else { pars + e.addr - FIRST_ADDR };
+        4 + 4 * slot
+    }
+
+    fn load(&mut self, e: &Entry) {
+        self.frame(e.level);
+        let offset = self.offset(e);
+        self.inst(&format!("i32.load offset={}", offset));
+    }
+
+    /// Stores the value on top of the stack into `e`, whose frame is below it.
+    fn store(&mut self, e: &Entry) {
+        let offset = self.offset(e);
+        self.inst(&format!("i32.store offset={}", offset));
+    }
+
+    /// Address of `s` in memory, which holds each string once.
+    fn string_addr(&mut self, s: &str) -> usize {
+        let i = intern(&mut self.strings, s);
+        self.strings[..i].iter().map(String::len).sum()
+    }
+
+    fn new_label(&mut self) -> usize {
+        self.labels += 1;
+        self.labels
+    }
+
+    /// Opens a structured instruction such as `block` or `if`.
+    fn open(&mut self, inst: &str) {
+        self.inst(inst);
+        self.indent += 1;
+    }
+
+    fn close(&mut self) {
+        self.indent -= 1;
+        self.inst("end");
+    }
+
+    fn inst(&mut self, inst: &str) {
+        writeln!(self.text, "{}{}", "  ".repeat(self.indent), inst).unwrap();
+    }
+}
+
+impl Default for WatGenerator {
+    fn default() -> Self {
+        WatGenerator::new()
+    }
+}
+
+/// Escapes `s` for a string of the text format.
+fn wat_string(s: &str) -> String {
+    let mut escaped = String::new();
+    for b in s.bytes() {
+        match b {
+            b'"' | b'\\' => write!(escaped, "\\{}", b as char).unwrap(),
+            0x20..=0x7e => escaped.push(b as char),
+            _ => write!(escaped, "\\{:02x}", b).unwrap(),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compile::*;
+
+    fn generate(src: &'static str) -> String {
+        let tree = analyze(src.as_bytes(), TokenizerOptions::default()).unwrap();
+        WatGenerator::new().generate(&tree)
+    }
+
+    /// Assembles `wat` and validates the module, as an engine would before
+    /// running it.
+    fn assert_valid(wat: &str) {
+        let wasm = wat::parse_str(wat).unwrap_or_else(|e| panic!("{}\n{}", e, wat));
+        wasmparser::Validator::new().validate_all(&wasm).unwrap_or_else(|e| panic!("{}\n{}", e, wat));
+    }
+
+    #[test]
+    fn test_module() {
+        let wat = generate("\
+            var x, a[3];
+            function f(n)
+                function g(m) return m + n + x;
+                return g(1);
+            begin
+                x := 1;
+                for x := 1 to 2 do a[x] := f(x);
+                while x > 0 do begin x := x - 1; if x = 1 then continue; write \"x\", x end;
+                repeat x := x + 1 until not (x < 3 or odd x)
+            end.");
+        assert_valid(&wat);
+        assert!(wat.starts_with("(module\n"));
+        assert!(wat.contains("(func $f1_f (param $up i32) (param $a0 i32) (result i32)\n"));
+        assert!(wat.contains("(func $f2_g (param $up i32) (param $a0 i32) (result i32)\n"));
+        assert!(wat.contains("(data (i32.const 0) \"x\")"));
+        assert!(wat.contains("(global $stack_limit i32 (i32.const 8))"));
+        assert!(wat.contains("(export \"main\" (func $main))"));
+        // g reaches `n` through one static link and `x` through two
+        assert!(wat.contains("    local.get $fp\n    i32.load\n    i32.load offset=4\n"));
+        assert!(wat.contains("    local.get $fp\n    i32.load\n    i32.load\n    i32.load offset=4\n"));
+        // every function called is defined or imported
+        for call in wat.lines().filter_map(|l| l.trim().strip_prefix("call ")) {
+            let defined = [" ", "\n", ")"].iter().any(|end| wat.contains(&format!("(func {}{}", call, end)));
+            assert!(defined, "{} is not defined", call);
+        }
+    }
+
+    #[test]
+    fn test_fixtures() {
+        for name in &["fact", "sort", "nested", "strings", "primes", "hanoi", "read"] {
+            let path = format!("{}/pl0/{}.pl0", env!("CARGO_MANIFEST_DIR"), name);
+            let source = std::fs::File::open(path).unwrap();
+            let tree = analyze(source, TokenizerOptions::default()).unwrap();
+            assert_valid(&WatGenerator::new().generate(&tree));
+        }
+    }
+
+    #[test]
+    fn test_wat_string() {
+        assert_eq!(wat_string("a \"b\"\\\n"), "a \\\"b\\\"\\\\\\0a");
+    }
+}
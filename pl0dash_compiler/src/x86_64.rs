@@ -0,0 +1,797 @@
+// x86-64 backend: translates a checked syntax tree into assembly for the GNU
+// assembler (AT&T syntax), to be linked with the C library by `cc`.
+//
+// Every block gets a frame of its own, addressed through %rbp:
+//
+//     16(%rbp) + 8*k   the parameter k places before the last one
+//      8(%rbp)         return address
+//      0(%rbp)         saved %rbp of the caller
+//     -8(%rbp)         static link, the frame of the enclosing block
+//    -8*a(%rbp)        the local at relative address `a`
+//
+// Arrays grow downwards from the address of their first element. The caller
+// pushes the arguments, passes the static link in %r10 and drops the
+// arguments after the call; results come back in %eax. Values are 32-bit,
+// as in the stack machine, and every slot holds one in its low half.
+
+use std::fmt::Write;
+use crate::tokenizer::*;
+use crate::keyword::*;
+use crate::symbol::*;
+use crate::parser::*;
+use crate::table::*;
+use crate::semantic::self_tail_call;
+use crate::backend::*;
+
+/// Routines the generated code calls for input, output and runtime errors.
+/// They realign the stack as the System V ABI wants before calling libc,
+/// and write the same messages as the stack machine.
+const RUNTIME: &str = r#"
+# sets the stack limit below %rdi, which takes 7/8 of the limit of the
+# process, or %rsi bytes when it has none
+pl0_init_stack:
+	pushq %rbp
+	movq %rsp, %rbp
+	pushq %rdi
+	pushq %rsi
+	subq $16, %rsp
+	andq $-16, %rsp
+# RLIMIT_STACK is 3
+	movl $3, %edi
+	leaq -32(%rbp), %rsi
+	call getrlimit@PLT
+	movq -8(%rbp), %rdi
+	movq -16(%rbp), %rcx
+	testl %eax, %eax
+	jne .Lstack_size
+# no limit is larger than any address
+	movq -32(%rbp), %rax
+	cmpq %rdi, %rax
+	jae .Lstack_size
+	movq %rax, %rcx
+	shrq $3, %rax
+	subq %rax, %rcx
+.Lstack_size:
+	subq %rcx, %rdi
+	movq %rdi, pl0_stack_limit(%rip)
+	leave
+	ret
+
+# writes %edi followed by a space
+pl0_write:
+	pushq %rbp
+	movq %rsp, %rbp
+	andq $-16, %rsp
+	movl %edi, %esi
+	leaq .Lfmt_int(%rip), %rdi
+	xorl %eax, %eax
+	call printf@PLT
+	leave
+	ret
+
+# writes the string at %rdi
+pl0_write_string:
+	pushq %rbp
+	movq %rsp, %rbp
+	andq $-16, %rsp
+	movq %rdi, %rsi
+	leaq .Lfmt_string(%rip), %rdi
+	xorl %eax, %eax
+	call printf@PLT
+	leave
+	ret
+
+pl0_writeln:
+	pushq %rbp
+	movq %rsp, %rbp
+	andq $-16, %rsp
+	movl $10, %edi
+	call putchar@PLT
+	leave
+	ret
+
+# reads the next whitespace separated word of stdin as an integer into %eax
+pl0_read:
+	pushq %rbp
+	movq %rsp, %rbp
+	subq $96, %rsp
+	andq $-16, %rsp
+	xorl %edi, %edi
+	call fflush@PLT
+	leaq .Lfmt_word(%rip), %rdi
+	leaq -80(%rbp), %rsi
+	xorl %eax, %eax
+	call scanf@PLT
+	cmpl $1, %eax
+	jne pl0_end_of_input
+	leaq -80(%rbp), %rdi
+	leaq -88(%rbp), %rsi
+	movl $10, %edx
+	call strtol@PLT
+	movq -88(%rbp), %rcx
+	leaq -80(%rbp), %rdx
+	cmpq %rdx, %rcx
+	je .Lmalformed
+	cmpb $0, (%rcx)
+	jne .Lmalformed
+# `strtol` saturates on overflow, which is out of range as well
+	movslq %eax, %rdx
+	cmpq %rax, %rdx
+	jne .Lmalformed
+	leave
+	ret
+.Lmalformed:
+	leaq .Lmsg_malformed(%rip), %rdi
+	leaq -80(%rbp), %rsi
+	jmp pl0_fail
+
+pl0_end_of_input:
+	leaq .Lmsg_end_of_input(%rip), %rdi
+	jmp pl0_fail
+
+pl0_division_by_zero:
+	leaq .Lmsg_division_by_zero(%rip), %rdi
+	jmp pl0_fail
+
+# the index is in %eax, the size of the array in %edx
+pl0_index_out_of_bounds:
+	movl %eax, %esi
+	leaq .Lmsg_index_out_of_bounds(%rip), %rdi
+	jmp pl0_fail
+
+pl0_stack_overflow:
+	leaq .Lmsg_stack_overflow(%rip), %rdi
+	jmp pl0_fail
+
+# writes the message formatted by %rdi with %rsi and %rdx to stderr and exits
+pl0_fail:
+	pushq %rbp
+	movq %rsp, %rbp
+	subq $32, %rsp
+	andq $-16, %rsp
+	movq %rdi, -8(%rbp)
+	movq %rsi, -16(%rbp)
+	movq %rdx, -24(%rbp)
+	xorl %edi, %edi
+	call fflush@PLT
+	movl $2, %edi
+	movq -8(%rbp), %rsi
+	movq -16(%rbp), %rdx
+	movq -24(%rbp), %rcx
+	xorl %eax, %eax
+	call dprintf@PLT
+	movl $1, %edi
+	call exit@PLT
+
+	.section .rodata
+.Lfmt_int:
+	.string "%d "
+.Lfmt_string:
+	.string "%s"
+.Lfmt_word:
+	.string "%63s"
+.Lmsg_malformed:
+	.string "runtime error: `read` expected an integer, found `%s`\n"
+.Lmsg_end_of_input:
+	.string "runtime error: `read` reached the end of the input\n"
+.Lmsg_division_by_zero:
+	.string "runtime error: division by zero\n"
+.Lmsg_index_out_of_bounds:
+	.string "runtime error: index %d is out of bounds for an array of size %d\n"
+.Lmsg_stack_overflow:
+	.string "runtime error: stack overflow\n"
+
+	.bss
+	.align 8
+pl0_stack_limit:
+	.zero 8
+
+	.section .note.GNU-stack,"",@progbits
+"#;
+
+/// Labels of the current loop that `break` and `continue` jump to.
+struct Loop {
+    break_label: usize,
+    continue_label: usize,
+}
+
+/// Translates a checked syntax tree into x86-64 assembly. Functions are
+/// entered in the table with the number of their label as address.
+pub struct X86Generator {
+    table: Table,
+    /// finished functions
+    out: String,
+    /// body of the function being generated
+    text: String,
+    strings: Vec<String>,
+    loops: Vec<Loop>,
+    labels: usize,
+}
+
+impl X86Generator {
+    pub fn new() -> Self {
+        X86Generator {
+            table: Table::new(),
+            out: String::new(),
+            text: String::new(),
+            strings: vec![],
+            loops: vec![],
+            labels: 0,
+        }
+    }
+
+    pub fn generate(mut self, tree: &SyntaxTree) -> String {
+        let program = tree.get_ref_root();
+        self.out += "\t.text\n";
+        self.table.block_begin(FIRST_ADDR);
+        self.gen_block(&program.get_ref_children()[0], None);
+        self.out += RUNTIME;
+        if !self.strings.is_empty() {
+            self.out += "\n\t.section .rodata\n";
+            for (i, s) in self.strings.iter().enumerate() {
+                writeln!(self.out, ".Ls{}:\n\t.string \"{}\"", i, asm_string(s)).unwrap();
+            }
+        }
+        self.out
+    }
+
+    fn gen_block(&mut self, node: &SyntaxNode, func: Option<usize>) {
+        for child in node.get_ref_children() {
+            match child.get_ref_syntax() {
+                Syntax::ConstDecl => enter_consts(&mut self.table, child),
+                Syntax::VarDecl => {
+                    enter_vars(&mut self.table, child);
+                },
+                Syntax::FuncDecl | Syntax::ProcDecl => self.gen_func_decl(child),
+                Syntax::Statement => self.gen_statement(child),
+                _ => unreachable!(),
+            }
+        }
+        let body = std::mem::take(&mut self.text);
+        // the frame is known once the statement had its temporaries
+        let locals = 8 * (self.table.frame_size() - FIRST_ADDR);
+        match func {
+            Some(f) => {
+                let e = self.table.get(f);
+                writeln!(self.out, "\n# function {}\n.Lf{}:", e.name, e.addr).unwrap();
+                self.out += "\tpushq %rbp\n\tmovq %rsp, %rbp\n";
+                self.out += "\tcmpq pl0_stack_limit(%rip), %rsp\n\tjb pl0_stack_overflow\n";
+                self.out += "\tpushq %r10\n";
+            },
+            None => {
+                self.out += "\n\t.globl main\nmain:\n";
+                self.out += "\tpushq %rbp\n\tmovq %rsp, %rbp\n";
+                writeln!(self.out, "\tmovq %rsp, %rdi\n\tmovq ${}, %rsi\n\tcall pl0_init_stack", STACK_LIMIT).unwrap();
+                self.out += "\tpushq $0\n";
+            },
+        }
+        if locals > 0 {
+            writeln!(self.out, "\tsubq ${}, %rsp", locals).unwrap();
+        }
+        if let Some(f) = func {
+            // where a self tail call starts the body over
+            writeln!(self.out, ".Lf{}_body:", self.table.get(f).addr).unwrap();
+        }
+        if locals > 0 {
+            // the locals start at zero, on every call
+            writeln!(self.out, "\tleaq -{}(%rbp), %rdi\n\tmovl ${}, %ecx\n\txorl %eax, %eax\n\trep stosq", 8 + locals, locals / 8).unwrap();
+        }
+        self.out += &body;
+        // procedures and the main block end without a `return`, functions
+        // usually with one
+        if !body.ends_with("\tret\n") {
+            if func.is_none_or(|f| self.table.get(f).kind == Kind::Proc) {
+                self.out += "\txorl %eax, %eax\n";
+            }
+            self.out += "\tleave\n\tret\n";
+        }
+        self.table.block_end();
+    }
+
+    fn gen_func_decl(&mut self, node: &SyntaxNode) {
+        let label = self.new_label() as i32;
+        let decl = enter_func(&mut self.table, node, label);
+        self.gen_block(decl.body, Some(decl.func));
+    }
+
+    fn gen_statement(&mut self, node: &SyntaxNode) {
+        let children = node.get_ref_children();
+        let first = match children.first() {
+            Some(first) => first,
+            None => return, // empty statement
+        };
+        match first.get_ref_token().unwrap() {
+            Token::Identifier(name) if children.len() > 1 && children[1].get_ref_token() == Some(&Token::Symbol(Symbol::Lparen)) => {
+                let e = lookup(&self.table, name);
+                self.gen_call(&e, &children[1..]);
+            },
+            Token::Keyword(Keyword::Call) => {
+                let e = lookup(&self.table, children[1].get_ref_identifier().unwrap());
+                self.gen_call(&e, &children[2..]);
+            },
+            Token::Identifier(name) => {
+                let e = lookup(&self.table, name);
+                if e.kind == Kind::Array {
+                    // ident [ index ] := value
+                    self.gen_index(&e, &children[2]);
+                    self.emit("pushq %rax");
+                    self.gen_expression(&children[5]);
+                    self.emit("popq %rdx");
+                    self.store_element(&e);
+                } else {
+                    self.gen_expression(&children[2]);
+                    self.store(&e);
+                }
+            },
+            Token::Keyword(Keyword::Begin) => {
+                for child in children {
+                    if let Syntax::Statement = child.get_ref_syntax() {
+                        self.gen_statement(child);
+                    }
+                }
+            },
+            Token::Keyword(Keyword::If) => {
+                let else_label = self.new_label();
+                self.gen_condition(&children[1], false, else_label);
+                self.gen_statement(&children[3]);
+                if children.len() > 4 {
+                    let next = self.new_label();
+                    self.emit(&format!("jmp .L{}", next));
+                    self.label(else_label);
+                    self.gen_statement(&children[5]);
+                    self.label(next);
+                } else {
+                    self.label(else_label);
+                }
+            },
+            Token::Keyword(Keyword::While) => {
+                let start = self.new_label();
+                let next = self.new_label();
+                self.label(start);
+                self.gen_condition(&children[1], false, next);
+                self.loops.push(Loop { break_label: next, continue_label: start });
+                self.gen_statement(&children[3]);
+                self.loops.pop();
+                self.emit(&format!("jmp .L{}", start));
+                self.label(next);
+            },
+            Token::Keyword(Keyword::For) => {
+                let var = lookup(&self.table, children[1].get_ref_identifier().unwrap());
+                let temp = self.table.enter_temp();
+                let limit = self.table.get(temp).clone();
+                let (exit, last, step) = match children[4].get_ref_token() {
+                    Some(Token::Keyword(Keyword::To)) => ("jg", "jge", "addl"),
+                    _ => ("jl", "jle", "subl"),
+                };
+                let (start, step_label, next) = (self.new_label(), self.new_label(), self.new_label());
+                self.gen_expression(&children[3]);
+                self.store(&var);
+                self.gen_expression(&children[5]);
+                self.store(&limit);
+                self.label(start);
+                self.load(&limit);
+                self.emit("movl %eax, %edx");
+                self.load(&var);
+                self.emit("cmpl %edx, %eax");
+                self.emit(&format!("{} .L{}", exit, next));
+                self.loops.push(Loop { break_label: next, continue_label: step_label });
+                self.gen_statement(&children[7]);
+                self.loops.pop();
+                self.label(step_label);
+                self.load(&limit);
+                self.emit("movl %eax, %edx");
+                self.load(&var);
+                self.emit("cmpl %edx, %eax");
+                self.emit(&format!("{} .L{}", last, next));
+                self.emit(&format!("{} $1, %eax", step));
+                self.store(&var);
+                self.emit(&format!("jmp .L{}", start));
+                self.label(next);
+            },
+            Token::Keyword(Keyword::Repeat) => {
+                let (start, cond, next) = (self.new_label(), self.new_label(), self.new_label());
+                self.label(start);
+                self.loops.push(Loop { break_label: next, continue_label: cond });
+                for child in children {
+                    if let Syntax::Statement = child.get_ref_syntax() {
+                        self.gen_statement(child);
+                    }
+                }
+                self.loops.pop();
+                self.label(cond);
+                self.gen_condition(children.last().unwrap(), false, start);
+                self.label(next);
+            },
+            Token::Keyword(Keyword::Break) => {
+                let target = self.loops.last().unwrap().break_label;
+                self.emit(&format!("jmp .L{}", target));
+            },
+            Token::Keyword(Keyword::Continue) => {
+                let target = self.loops.last().unwrap().continue_label;
+                self.emit(&format!("jmp .L{}", target));
+            },
+            Token::Keyword(Keyword::Ret) => {
+                if let Some(args) = self_tail_call(&self.table, &children[1]) {
+                    self.gen_tail_call(&args);
+                    return;
+                }
+                self.gen_expression(&children[1]);
+                self.emit("leave");
+                self.emit("ret");
+            },
+            Token::Keyword(Keyword::Write) => {
+                for arg in &children[1..] {
+                    if let Some(Token::String(s)) = arg.get_ref_token() {
+                        let i = intern(&mut self.strings, s);
+                        self.emit(&format!("leaq .Ls{}(%rip), %rdi", i));
+                        self.emit("call pl0_write_string");
+                    } else if let Syntax::Expression = arg.get_ref_syntax() {
+                        self.gen_expression(arg);
+                        self.emit("movl %eax, %edi");
+                        self.emit("call pl0_write");
+                    }
+                }
+            },
+            Token::Keyword(Keyword::WriteLn) => {
+                self.emit("call pl0_writeln");
+            },
+            Token::Keyword(Keyword::Read) => {
+                let e = lookup(&self.table, children[1].get_ref_identifier().unwrap());
+                if e.kind == Kind::Array {
+                    // read ident [ index ]
+                    self.gen_index(&e, &children[3]);
+                    self.emit("pushq %rax");
+                    self.emit("call pl0_read");
+                    self.emit("popq %rdx");
+                    self.store_element(&e);
+                } else {
+                    self.emit("call pl0_read");
+                    self.store(&e);
+                }
+            },
+            t => unreachable!("statement starting with {:?}", t),
+        }
+    }
+
+    /// Generates a condition that falls through when it holds, or when it
+    /// does not if `negate` is set, and otherwise jumps to `target`.
+    /// Operands of `and` and `or` are evaluated only as far as needed.
+    fn gen_condition(&mut self, node: &SyntaxNode, negate: bool, target: usize) {
+        let children = node.get_ref_children();
+        match children[0].get_ref_token() {
+            Some(Token::Keyword(Keyword::Not)) => {
+                return self.gen_condition(&children[1], !negate, target);
+            },
+            Some(Token::Keyword(Keyword::Odd)) => {
+                self.gen_expression(&children[1]);
+                self.emit("testl $1, %eax");
+                let jump = if negate { "jnz" } else { "jz" };
+                return self.emit(&format!("{} .L{}", jump, target));
+            },
+            Some(Token::Symbol(Symbol::Lparen)) => {
+                return self.gen_condition(&children[1], negate, target);
+            },
+            _ => (),
+        }
+        if let Syntax::Condition = children[0].get_ref_syntax() {
+            // c { and c } or c { or c }; a negated `and` is an `or` of the
+            // negated operands and the other way round
+            let is_or = children[1].get_ref_token() == Some(&Token::Keyword(Keyword::Or));
+            let operands: Vec<&SyntaxNode> = children.iter().step_by(2).collect();
+            if is_or == negate {
+                for c in operands {
+                    self.gen_condition(c, negate, target);
+                }
+                return;
+            }
+            let (last, init) = operands.split_last().unwrap();
+            let holds = self.new_label();
+            for c in init {
+                self.gen_condition(c, !negate, holds);
+            }
+            self.gen_condition(last, negate, target);
+            return self.label(holds);
+        }
+        self.gen_binary(&children[0], &children[2]);
+        self.emit("cmpl %ecx, %eax");
+        // the jump taken when the relation does not hold
+        let jump = match children[1].get_ref_token() {
+            Some(Token::Symbol(Symbol::Equal)) => "jne",
+            Some(Token::Symbol(Symbol::Lss))   => "jge",
+            Some(Token::Symbol(Symbol::Gtr))   => "jle",
+            Some(Token::Symbol(Symbol::NotEq)) => "je",
+            Some(Token::Symbol(Symbol::LssEq)) => "jg",
+            Some(Token::Symbol(Symbol::GtrEq)) => "jl",
+            t => unreachable!("relational operator {:?}", t),
+        };
+        let jump = if negate { complement(jump) } else { jump };
+        self.emit(&format!("{} .L{}", jump, target));
+    }
+
+    /// Evaluates two operands, the left one into %eax and the right one
+    /// into %ecx.
+    fn gen_binary(&mut self, left: &SyntaxNode, right: &SyntaxNode) {
+        self.gen_expression(left);
+        self.emit("pushq %rax");
+        self.gen_expression(right);
+        self.emit("movl %eax, %ecx");
+        self.emit("popq %rax");
+    }
+
+    /// Evaluates an expression into %eax.
+    fn gen_expression(&mut self, node: &SyntaxNode) {
+        let children = node.get_ref_children();
+        let mut i = 0;
+        let mut negate = false;
+        if let Some(Token::Symbol(sym)) = children[0].get_ref_token() {
+            negate = *sym == Symbol::Minus;
+            i = 1;
+        }
+        self.gen_term(&children[i]);
+        if negate {
+            self.emit("negl %eax");
+        }
+        for pair in children[i + 1..].chunks(2) {
+            let op = match pair[0].get_ref_token() {
+                Some(Token::Symbol(Symbol::Plus))  => "addl",
+                Some(Token::Symbol(Symbol::Minus)) => "subl",
+                t => unreachable!("additive operator {:?}", t),
+            };
+            self.emit("pushq %rax");
+            self.gen_term(&pair[1]);
+            self.emit("movl %eax, %ecx");
+            self.emit("popq %rax");
+            self.emit(&format!("{} %ecx, %eax", op));
+        }
+    }
+
+    fn gen_term(&mut self, node: &SyntaxNode) {
+        let children = node.get_ref_children();
+        self.gen_factor(&children[0]);
+        for pair in children[1..].chunks(2) {
+            self.emit("pushq %rax");
+            self.gen_factor(&pair[1]);
+            self.emit("movl %eax, %ecx");
+            self.emit("popq %rax");
+            match pair[0].get_ref_token() {
+                Some(Token::Symbol(Symbol::Mult)) => self.emit("imull %ecx, %eax"),
+                Some(Token::Symbol(Symbol::Div))  => self.gen_division(false),
+                Some(Token::Symbol(Symbol::Mod)) | Some(Token::Keyword(Keyword::Mod)) => self.gen_division(true),
+                t => unreachable!("multiplicative operator {:?}", t),
+            }
+        }
+    }
+
+    /// Divides %eax by %ecx, truncating, leaving the quotient or the
+    /// remainder in %eax. `idivl` traps on the quotient of the smallest
+    /// integer by -1, which wraps around in the stack machine instead.
+    fn gen_division(&mut self, remainder: bool) {
+        let (divide, next) = (self.new_label(), self.new_label());
+        self.emit("testl %ecx, %ecx");
+        self.emit("jz pl0_division_by_zero");
+        self.emit("cmpl $-1, %ecx");
+        self.emit(&format!("jne .L{}", divide));
+        self.emit(if remainder { "xorl %eax, %eax" } else { "negl %eax" });
+        self.emit(&format!("jmp .L{}", next));
+        self.label(divide);
+        self.emit("cltd");
+        self.emit("idivl %ecx");
+        if remainder {
+            self.emit("movl %edx, %eax");
+        }
+        self.label(next);
+    }
+
+    fn gen_factor(&mut self, node: &SyntaxNode) {
+        let children = node.get_ref_children();
+        match children[0].get_ref_token() {
+            Some(Token::Identifier(name)) => {
+                if let Some(f) = self.table.search_intrinsic(name) {
+                    match f {
+                        Intrinsic::Abs => {
+                            self.gen_expression(&children[2]);
+                            self.emit("movl %eax, %ecx");
+                            self.emit("negl %ecx");
+                            self.emit("cmovgl %ecx, %eax");
+                        },
+                        Intrinsic::Min | Intrinsic::Max => {
+                            self.gen_binary(&children[2], &children[4]);
+                            self.emit("cmpl %ecx, %eax");
+                            self.emit(if f == Intrinsic::Min { "cmovgl %ecx, %eax" } else { "cmovll %ecx, %eax" });
+                        },
+                    }
+                    return;
+                }
+                let e = lookup(&self.table, name);
+                match e.kind {
+                    Kind::Var | Kind::Par => self.load(&e),
+                    Kind::Array => {
+                        self.gen_index(&e, &children[2]);
+                        let base = self.frame(e.level, "%rcx");
+                        self.emit("negq %rax");
+                        self.emit(&format!("movl {}({},%rax,8), %eax", offset(e.addr), base));
+                    },
+                    Kind::Const => {
+                        self.emit(&format!("movl ${}, %eax", e.value));
+                    },
+                    Kind::Func => self.gen_call(&e, &children[1..]),
+                    Kind::Proc => unreachable!("unchecked syntax tree"),
+                }
+            },
+            Some(Token::Number(value)) => {
+                self.emit(&format!("movl ${}, %eax", value));
+            },
+            _ => {
+                // ( expression )
+                self.gen_expression(&children[1]);
+            },
+        }
+    }
+
+    /// Evaluates the index of an element of the array `e` into %rax,
+    /// checking it is within bounds.
+    fn gen_index(&mut self, e: &Entry, index: &SyntaxNode) {
+        self.gen_expression(index);
+        self.emit(&format!("movl ${}, %edx", e.size));
+        self.emit("cmpl %edx, %eax");
+        self.emit("jae pl0_index_out_of_bounds");
+    }
+
+    /// Pushes the arguments among `args` and calls `e`, which leaves its
+    /// value in %eax.
+    fn gen_call(&mut self, e: &Entry, args: &[SyntaxNode]) {
+        for arg in args {
+            if let Syntax::Expression = arg.get_ref_syntax() {
+                self.gen_expression(arg);
+                self.emit("pushq %rax");
+            }
+        }
+        // the callee is declared in the block whose frame it links to
+        let link = self.frame(e.level, "%r10");
+        if link != "%r10" {
+            self.emit(&format!("movq {}, %r10", link));
+        }
+        self.emit(&format!("call .Lf{}", e.addr));
+        if e.pars > 0 {
+            self.emit(&format!("addq ${}, %rsp", 8 * e.pars));
+        }
+    }
+
+    /// Calls the current function again in its own frame: the arguments
+    /// replace the parameters once all of them are known, and the body starts
+    /// over below the prologue, which has already pushed the static link and
+    /// made room for the locals, where they are zeroed again.
+    fn gen_tail_call(&mut self, args: &[&SyntaxNode]) {
+        for arg in args {
+            self.gen_expression(arg);
+            self.emit("pushq %rax");
+        }
+        let pars: Vec<i32> = self.table.block_entries()[..args.len()].iter().map(|e| e.addr).collect();
+        for addr in pars.into_iter().rev() {
+            self.emit("popq %rax");
+            self.emit(&format!("movl %eax, {}(%rbp)", offset(addr)));
+        }
+        let f = self.table.func_index().unwrap();
+        self.emit(&format!("jmp .Lf{}_body", self.table.get(f).addr));
+    }
+
+    /// Follows the static links up to the frame of the block at `level`,
+    /// using `reg` unless it is the frame of the current block. Returns the
+    /// register that holds the frame.
+    fn frame(&mut self, level: usize, reg: &'static str) -> &'static str {
+        let hops = self.table.level() - level;
+        if hops == 0 {
+            return "%rbp";
+        }
+        self.emit(&format!("movq -8(%rbp), {}", reg));
+        for _ in 1..hops {
+            self.emit(&format!("movq -8({}), {}", reg, reg));
+        }
+        reg
+    }
+
+    fn load(&mut self, e: &Entry) {
+        let base = self.frame(e.level, "%rcx");
+        self.emit(&format!("movl {}({}), %eax", offset(e.addr), base));
+    }
+
+    fn store(&mut self, e: &Entry) {
+        let base = self.frame(e.level, "%rcx");
+        self.emit(&format!("movl %eax, {}({})", offset(e.addr), base));
+    }
+
+    /// Stores %eax into the element of the array `e` whose index is in %rdx.
+    fn store_element(&mut self, e: &Entry) {
+        let base = self.frame(e.level, "%rcx");
+        self.emit("negq %rdx");
+        self.emit(&format!("movl %eax, {}({},%rdx,8)", offset(e.addr), base));
+    }
+
+    fn new_label(&mut self) -> usize {
+        self.labels += 1;
+        self.labels
+    }
+
+    fn label(&mut self, label: usize) {
+        writeln!(self.text, ".L{}:", label).unwrap();
+    }
+
+    fn emit(&mut self, inst: &str) {
+        writeln!(self.text, "\t{}", inst).unwrap();
+    }
+}
+
+impl Default for X86Generator {
+    fn default() -> Self {
+        X86Generator::new()
+    }
+}
+
+/// Offset from its frame of the name at relative address `addr`.
+fn offset(addr: i32) -> i32 {
+    if addr < 0 {
+        // above the return address and the saved %rbp
+        16 - 8 * (addr + 1)
+    } else {
+        -8 * addr
+    }
+}
+
+/// The conditional jump taken exactly when `jump` is not.
+fn complement(jump: &str) -> &'static str {
+    match jump {
+        "je"  => "jne",
+        "jne" => "je",
+        "jl"  => "jge",
+        "jge" => "jl",
+        "jg"  => "jle",
+        "jle" => "jg",
+        _ => unreachable!("conditional jump {}", jump),
+    }
+}
+
+/// Escapes `s` for a `.string` directive.
+fn asm_string(s: &str) -> String {
+    let mut escaped = String::new();
+    for b in s.bytes() {
+        match b {
+            b'"' => escaped += "\\\"",
+            b'\\' => escaped += "\\\\",
+            0x20..=0x7e => escaped.push(b as char),
+            _ => write!(escaped, "\\{:03o}", b).unwrap(),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compile::*;
+
+    fn generate(src: &'static str) -> String {
+        let tree = analyze(src.as_bytes(), TokenizerOptions::default()).unwrap();
+        X86Generator::new().generate(&tree)
+    }
+
+    #[test]
+    fn test_static_links() {
+        let asm = generate("\
+            var x;
+            function f(a)
+                function g(b) return a + b + x;
+                return g(1);
+            begin x := 1; write f(2) end.");
+        // f links to the frame of the main block, g to the one of f
+        assert!(asm.contains("\tmovq %rbp, %r10\n\tcall .Lf1\n\taddq $8, %rsp\n"));
+        assert!(asm.contains("\tmovq %rbp, %r10\n\tcall .Lf2\n"));
+        // g reaches `a` through one static link and `x` through two
+        assert!(asm.contains("\tmovq -8(%rbp), %rcx\n\tmovl 16(%rcx), %eax\n"));
+        assert!(asm.contains("\tmovq -8(%rbp), %rcx\n\tmovq -8(%rcx), %rcx\n\tmovl -16(%rcx), %eax\n"));
+    }
+
+    #[test]
+    fn test_asm_string() {
+        assert_eq!(asm_string("a \"b\"\\\n"), "a \\\"b\\\"\\\\\\012");
+    }
+}
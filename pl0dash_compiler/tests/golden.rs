@@ -0,0 +1,518 @@
+// Golden-file tests over the fixtures under `pl0/`.
+//
+// For every `pl0/**/NAME.pl0` the harness compares
+//   - the token dump           with `NAMET.xml`,
+//   - the syntax tree dump     with `NAME.xml`,
+//   - the diagnostics          with `NAME.err` (compile errors, or the
+//                                               runtime error of the run),
+//   - what the program writes  with `NAME.out`.
+// Programs run with `NAME.in`, if any, as the input of `read`.
+// A missing expectation file stands for empty output. Running the tests with
+// `PL0_BLESS=1` rewrites the expectation files from the current behaviour.
+//
+// The stack machine is the reference: programs built by the other backends
+// must write the same to stdout and stderr. Those tests are skipped when the
+// tools to build or run the programs are not installed, unless
+// `PL0_REQUIRE_TOOLS=1` makes a missing tool a failure, as on CI. The RISC-V
+// translations also run on the simulator of `riscv/`, which needs no tools.
+
+use pl0dash_compiler::tokenizer::*;
+use pl0dash_compiler::token_dump::*;
+use pl0dash_compiler::compile::*;
+use pl0dash_compiler::codegen::{Program, CodeGenerator};
+use pl0dash_compiler::vm::*;
+use pl0dash_compiler::peephole::peephole;
+use pl0dash_compiler::ir::Module;
+use pl0dash_compiler::interp::Interpreter;
+use pl0dash_compiler::ssa::{to_ssa, from_ssa};
+use pl0dash_compiler::opt::*;
+
+mod riscv;
+
+use std::env;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, Stdio};
+
+fn fixtures() -> Vec<PathBuf> {
+    fn walk(dir: &Path, found: &mut Vec<PathBuf>) {
+        for f in dir.read_dir().expect("read_dir call failed").flatten() {
+            let path = f.path();
+            if path.is_dir() {
+                walk(&path, found);
+            } else if path.extension().is_some_and(|e| e == "pl0") {
+                found.push(path);
+            }
+        }
+    }
+    let mut found = vec![];
+    walk(&Path::new(env!("CARGO_MANIFEST_DIR")).join("pl0"), &mut found);
+    found.sort();
+    assert!(!found.is_empty(), "no fixtures found");
+    found
+}
+
+fn blessing() -> bool {
+    env::var_os("PL0_BLESS").is_some()
+}
+
+/// `pl0/dir/NAME.pl0` -> `pl0/dir/NAME<suffix>`
+fn expectation(source: &Path, suffix: &str) -> PathBuf {
+    let stem = source.file_stem().unwrap().to_string_lossy();
+    source.with_file_name(format!("{}{}", stem, suffix))
+}
+
+/// Compares `actual` with the expectation file, or rewrites the file when
+/// blessing. Returns a description of the mismatch, if any.
+fn check(expected_path: &Path, actual: &str) -> Option<String> {
+    if blessing() {
+        if actual.is_empty() {
+            let _ = fs::remove_file(expected_path);
+        } else {
+            fs::write(expected_path, actual).expect("cannot write expectation file");
+        }
+        return None;
+    }
+    compare(expected_path, actual)
+}
+
+/// Compares `actual` with the expectation file.
+fn compare(expected_path: &Path, actual: &str) -> Option<String> {
+    let expected = fs::read_to_string(expected_path).unwrap_or_default();
+    if expected == actual {
+        return None;
+    }
+    let mut report = format!("{} differs\n", expected_path.display());
+    for (i, (e, a)) in expected.lines().zip(actual.lines()).enumerate() {
+        if e != a {
+            report += &format!("  first difference at line {}:\n  - {}\n  + {}\n", i + 1, e, a);
+            return Some(report);
+        }
+    }
+    report += &format!("  expected {} lines, got {}\n", expected.lines().count(), actual.lines().count());
+    Some(report)
+}
+
+fn run_golden(suffix: &str, render: impl Fn(&Path) -> String) {
+    let failures: Vec<String> = fixtures()
+        .iter()
+        .filter_map(|source| check(&expectation(source, suffix), &render(source)))
+        .collect();
+    assert!(failures.is_empty(), "\n{}\n(run with PL0_BLESS=1 to accept the new output)", failures.join("\n"));
+}
+
+fn open(source: &Path) -> File {
+    File::open(source).expect("cannot open fixture")
+}
+
+/// Runs the program of `source` on its `NAME.in`, or on no input at all.
+fn run(source: &Path, code: &Program, out: &mut Vec<u8>) -> Result<(), RuntimeError> {
+    let input_path = expectation(source, ".in");
+    let reader: Box<dyn std::io::Read> = match File::open(&input_path) {
+        Ok(f) => Box::new(f),
+        Err(_) => Box::new(std::io::empty()),
+    };
+    Vm::new(code).run_with_input(&mut TextInput::new(BufReader::new(reader)), out)
+}
+
+/// Runs the IR of `source` on its `NAME.in`, or on no input at all.
+fn run_ir(source: &Path, module: &Module, out: &mut Vec<u8>) -> Result<(), RuntimeError> {
+    let input_path = expectation(source, ".in");
+    let reader: Box<dyn std::io::Read> = match File::open(&input_path) {
+        Ok(f) => Box::new(f),
+        Err(_) => Box::new(std::io::empty()),
+    };
+    Interpreter::new(module).run_with_input(&mut TextInput::new(BufReader::new(reader)), out)
+}
+
+/// Whether `tool` can be run, for the backends that need one.
+fn available(tool: &str) -> bool {
+    Command::new(tool)
+        .arg("--version")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|s| s.success())
+}
+
+/// Whether any of `tools` cannot be run, so that the test needing them is
+/// skipped. Fails instead when `PL0_REQUIRE_TOOLS` is set.
+fn missing(tools: &[&str]) -> bool {
+    let missing: Vec<String> = tools.iter().filter(|t| !available(t)).map(|t| format!("`{}`", t)).collect();
+    if missing.is_empty() {
+        return false;
+    }
+    assert!(env::var_os("PL0_REQUIRE_TOOLS").is_none(), "missing {}", missing.join(", "));
+    eprintln!("skipped: needs {}", missing.join(", "));
+    true
+}
+
+/// A fresh directory for the files built from the fixtures.
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("pl0dash-{}-{}", name, process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).expect("cannot create scratch directory");
+    dir
+}
+
+/// Translates every fixture that compiles into `target`, builds it with
+/// `build` (given the translation and a path to build to, it returns the
+/// command that runs the result) and compares what it writes, on
+/// `NAME.in` if any, with `NAME.out` and `NAME.err`.
+fn run_golden_target(target: Target, build: impl Fn(&Path, &Path) -> Result<Command, String>) {
+    let dir = scratch_dir(&format!("{:?}", target).to_lowercase());
+    let mut failures = vec![];
+    for source in fixtures() {
+        let text = match emit(open(&source), TokenizerOptions::default(), target) {
+            Ok(text) => text,
+            Err(_) => continue,
+        };
+        let stem = source.file_stem().unwrap().to_string_lossy();
+        let translation = dir.join(format!("{}.{}", stem, target.extension()));
+        fs::write(&translation, text).expect("cannot write translation");
+        let mut command = match build(&translation, &dir.join(&*stem)) {
+            Ok(command) => command,
+            Err(e) => {
+                failures.push(format!("{}: cannot build {}\n{}", source.display(), translation.display(), e));
+                continue;
+            },
+        };
+        let input = match File::open(expectation(&source, ".in")) {
+            Ok(f) => Stdio::from(f),
+            Err(_) => Stdio::null(),
+        };
+        let output = command.stdin(input).output().expect("cannot run the built program");
+        failures.extend(compare(&expectation(&source, ".out"), &String::from_utf8_lossy(&output.stdout)));
+        failures.extend(compare(&expectation(&source, ".err"), &String::from_utf8_lossy(&output.stderr)));
+    }
+    let _ = fs::remove_dir_all(&dir);
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+/// Runs `command`, turning a failure into its diagnostics.
+fn build_with(command: &mut Command) -> Result<(), String> {
+    let output = command.output().map_err(|e| e.to_string())?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(String::from_utf8_lossy(&output.stderr).into_owned())
+    }
+}
+
+fn diagnostics(errors: &[CompileError]) -> String {
+    errors.iter().map(|e| format!("{}\n", e)).collect()
+}
+
+#[test]
+fn golden_tokens() {
+    run_golden("T.xml", |source| {
+        let mut t = Tokenizer::new(open(source));
+        let mut xml = vec![];
+        dump_tokens(&mut t, DumpFormat::Xml, &mut xml).unwrap();
+        String::from_utf8(xml).unwrap()
+    });
+}
+
+#[test]
+fn golden_trees() {
+    run_golden(".xml", |source| {
+        match analyze(open(source), TokenizerOptions::default()) {
+            Ok(tree) => {
+                let mut xml = vec![];
+                tree.write_xml(&mut xml).unwrap();
+                String::from_utf8(xml).unwrap()
+            },
+            Err(_) => String::new(),
+        }
+    });
+}
+
+#[test]
+fn golden_diagnostics() {
+    run_golden(".err", |source| {
+        match compile(open(source), TokenizerOptions::default()) {
+            Ok(code) => {
+                match run(source, &code, &mut vec![]) {
+                    Ok(()) => String::new(),
+                    Err(e) => format!("{}\n", e),
+                }
+            },
+            Err(errors) => diagnostics(&errors),
+        }
+    });
+}
+
+#[test]
+fn golden_output() {
+    run_golden(".out", |source| {
+        match compile(open(source), TokenizerOptions::default()) {
+            Ok(code) => {
+                let mut out = vec![];
+                let _ = run(source, &code, &mut out);
+                String::from_utf8(out).unwrap()
+            },
+            Err(_) => String::new(),
+        }
+    });
+}
+
+/// Every fixture must behave the same before and after the peephole pass,
+/// which never makes the code longer.
+#[test]
+fn golden_peephole() {
+    let mut failures = vec![];
+    for source in fixtures() {
+        let mut code = match compile(open(&source), TokenizerOptions::default()) {
+            Ok(code) => code,
+            Err(_) => continue,
+        };
+        for stage in ["before", "after"] {
+            if stage == "after" {
+                let before = code.code.len();
+                peephole(&mut code);
+                if code.code.len() > before {
+                    failures.push(format!("{}: {} instructions became {}", source.display(), before, code.code.len()));
+                }
+            }
+            let mut out = vec![];
+            let err = match run(&source, &code, &mut out) {
+                Ok(()) => String::new(),
+                Err(e) => format!("{}\n", e),
+            };
+            failures.extend(compare(&expectation(&source, ".out"), &String::from_utf8(out).unwrap())
+                .into_iter().map(|f| format!("({} the peephole pass) {}", stage, f)));
+            failures.extend(compare(&expectation(&source, ".err"), &err)
+                .into_iter().map(|f| format!("({} the peephole pass) {}", stage, f)));
+        }
+    }
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+/// Folding constants must not change what any fixture does.
+#[test]
+fn golden_folded() {
+    let mut failures = vec![];
+    for source in fixtures() {
+        if analyze(open(&source), TokenizerOptions::default()).is_err() {
+            continue;
+        }
+        let tree = match analyze_folded(open(&source), TokenizerOptions::default()) {
+            Ok(tree) => tree,
+            Err(errors) => {
+                failures.push(format!("{}: cannot fold\n{}", source.display(), diagnostics(&errors)));
+                continue;
+            },
+        };
+        let code = CodeGenerator::new().generate(&tree);
+        let mut out = vec![];
+        let err = match run(&source, &code, &mut out) {
+            Ok(()) => String::new(),
+            Err(e) => format!("{}\n", e),
+        };
+        failures.extend(compare(&expectation(&source, ".out"), &String::from_utf8(out).unwrap()));
+        failures.extend(compare(&expectation(&source, ".err"), &err));
+    }
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+/// The IR of every fixture must verify and behave as the stack machine code.
+#[test]
+fn golden_ir() {
+    let mut failures = vec![];
+    for source in fixtures() {
+        let module = match lower(open(&source), TokenizerOptions::default()) {
+            Ok(module) => module,
+            Err(_) => continue,
+        };
+        if let Err(errors) = module.verify() {
+            let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+            failures.push(format!("{}: malformed IR\n{}", source.display(), errors.join("\n")));
+            continue;
+        }
+        let mut out = vec![];
+        let err = match run_ir(&source, &module, &mut out) {
+            Ok(()) => String::new(),
+            Err(e) => format!("{}\n", e),
+        };
+        failures.extend(compare(&expectation(&source, ".out"), &String::from_utf8(out).unwrap()));
+        failures.extend(compare(&expectation(&source, ".err"), &err));
+    }
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+#[test]
+fn golden_optimized_ir() {
+    let all = Passes::all();
+    let combinations = [
+        Passes::none(),
+        Passes { sccp: true, ..Passes::none() },
+        Passes { gvn: true, ..Passes::none() },
+        Passes { dce: true, ..Passes::none() },
+        all,
+    ];
+    let mut failures = vec![];
+    for source in fixtures() {
+        let module = match lower(open(&source), TokenizerOptions::default()) {
+            Ok(module) => module,
+            Err(_) => continue,
+        };
+        // SSA form itself, before and without any pass
+        let mut ssa = module.clone();
+        to_ssa(&mut ssa);
+        let mut stages = vec![("ssa".to_string(), ssa.clone())];
+        from_ssa(&mut ssa);
+        stages.push(("out of ssa".to_string(), ssa));
+        for passes in combinations {
+            let mut optimized = module.clone();
+            optimize(&mut optimized, passes);
+            stages.push((format!("{:?}", passes), optimized));
+        }
+        for (stage, module) in stages {
+            if let Err(errors) = module.verify() {
+                let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+                failures.push(format!("{} ({}): malformed IR\n{}", source.display(), stage, errors.join("\n")));
+                continue;
+            }
+            let mut out = vec![];
+            let err = match run_ir(&source, &module, &mut out) {
+                Ok(()) => String::new(),
+                Err(e) => format!("{}\n", e),
+            };
+            failures.extend(compare(&expectation(&source, ".out"), &String::from_utf8(out).unwrap())
+                .into_iter().map(|f| format!("({}) {}", stage, f)));
+            failures.extend(compare(&expectation(&source, ".err"), &err)
+                .into_iter().map(|f| format!("({}) {}", stage, f)));
+        }
+    }
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+#[test]
+fn golden_x86_64() {
+    if !cfg!(all(target_arch = "x86_64", target_os = "linux")) {
+        eprintln!("skipped: needs x86-64 Linux");
+        return;
+    }
+    if missing(&["cc"]) {
+        return;
+    }
+    run_golden_target(Target::X86_64, |asm, exe| {
+        build_with(Command::new("cc").arg("-o").arg(exe).arg(asm))?;
+        Ok(Command::new(exe))
+    });
+}
+
+#[test]
+fn golden_c() {
+    if missing(&["cc"]) {
+        return;
+    }
+    run_golden_target(Target::C, |c, exe| {
+        build_with(Command::new("cc").args(["-std=c99", "-Wall", "-Werror", "-o"]).arg(exe).arg(c))?;
+        Ok(Command::new(exe))
+    });
+}
+
+/// Major version of the installed `llc`.
+fn llc_version() -> Option<u32> {
+    let output = Command::new("llc").arg("--version").output().ok()?;
+    let text = String::from_utf8_lossy(&output.stdout);
+    let version = text.split("LLVM version ").nth(1)?;
+    version.split('.').next()?.trim().parse().ok()
+}
+
+#[test]
+fn golden_llvm() {
+    if missing(&["llc", "cc"]) {
+        return;
+    }
+    let version = llc_version().expect("cannot tell the version of `llc`");
+    run_golden_target(Target::Llvm, |ir, exe| {
+        let asm = exe.with_extension("s");
+        let mut llc = Command::new("llc");
+        // opaque pointers are the default from LLVM 15 on
+        if version < 15 {
+            llc.arg("-opaque-pointers");
+        }
+        build_with(llc.arg("-relocation-model=pic").arg("-o").arg(&asm).arg(ir))?;
+        build_with(Command::new("cc").arg("-o").arg(exe).arg(&asm))?;
+        Ok(Command::new(exe))
+    });
+}
+
+#[test]
+fn golden_riscv64() {
+    if missing(&["riscv64-linux-gnu-gcc", "qemu-riscv64"]) {
+        return;
+    }
+    run_golden_target(Target::Riscv64, |asm, exe| {
+        build_with(Command::new("riscv64-linux-gnu-gcc").args(["-nostdlib", "-static", "-o"]).arg(exe).arg(asm))?;
+        let mut qemu = Command::new("qemu-riscv64");
+        qemu.arg(exe);
+        Ok(qemu)
+    });
+}
+
+/// Without a RISC-V machine to run on, checks at least that every
+/// translation assembles.
+#[test]
+fn assemble_riscv64() {
+    if missing(&["llvm-mc"]) {
+        return;
+    }
+    let dir = scratch_dir("riscv64-mc");
+    let mut failures = vec![];
+    for source in fixtures() {
+        let text = match emit(open(&source), TokenizerOptions::default(), Target::Riscv64) {
+            Ok(text) => text,
+            Err(_) => continue,
+        };
+        let asm = dir.join(source.file_stem().unwrap()).with_extension("s");
+        fs::write(&asm, text).expect("cannot write translation");
+        let mut mc = Command::new("llvm-mc");
+        mc.args(["-triple=riscv64", "-mattr=+m", "-filetype=obj", "-o"]).arg(asm.with_extension("o")).arg(&asm);
+        if let Err(e) = build_with(&mut mc) {
+            failures.push(format!("{}: cannot assemble {}\n{}", source.display(), asm.display(), e));
+        }
+    }
+    let _ = fs::remove_dir_all(&dir);
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+/// Runs every translation on the simulator of `riscv`, which needs no RISC-V
+/// machine.
+#[test]
+fn simulate_riscv64() {
+    let mut failures = vec![];
+    for source in fixtures() {
+        let text = match emit(open(&source), TokenizerOptions::default(), Target::Riscv64) {
+            Ok(text) => text,
+            Err(_) => continue,
+        };
+        let input = fs::read(expectation(&source, ".in")).unwrap_or_default();
+        match riscv::run(&text, &input) {
+            Ok(output) => {
+                failures.extend(compare(&expectation(&source, ".out"), &String::from_utf8_lossy(&output.stdout)));
+                failures.extend(compare(&expectation(&source, ".err"), &String::from_utf8_lossy(&output.stderr)));
+            },
+            Err(e) => failures.push(format!("{}: {}", source.display(), e)),
+        }
+    }
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+#[test]
+fn golden_wat() {
+    if missing(&["node"]) {
+        return;
+    }
+    let host = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/wasm_host.js");
+    run_golden_target(Target::Wat, |wat, exe| {
+        let wasm = exe.with_extension("wasm");
+        fs::write(&wasm, wat::parse_file(wat).map_err(|e| e.to_string())?).expect("cannot write module");
+        let mut node = Command::new("node");
+        node.arg(&host).arg(&wasm);
+        Ok(node)
+    });
+}
@@ -0,0 +1,552 @@
+// A simulator of the programs of the RISC-V backend, for checking what they
+// do where neither a RISC-V machine nor a user-mode QEMU is at hand.
+//
+// It reads the assembly the way the backend writes it: one instruction,
+// label or directive per line, the sections `.text`, `.rodata`, `.data` and
+// `.bss`, and the numeric local labels `1:` referred to as `1f` and `1b`.
+// Programs start at `_start` with the stack at the top of memory and talk to
+// the simulated Linux through `ecall`: `read` of stdin, `write` to stdout or
+// stderr, and `exit`.
+//
+// Instructions live apart from memory, at addresses from TEXT_BASE, so that
+// jumping or returning anywhere else is caught as an error. Branches and jumps
+// are held to the reach they have once assembled, with every
+// pseudo-instruction taking as many bytes as it may expand to.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+const MEMORY_SIZE: usize = 16 << 20;
+const DATA_BASE: u64 = 0x1_0000;
+const TEXT_BASE: u64 = 0x1_0000_0000;
+/// How far a conditional branch (B-type) and `j` or `jal` (J-type) reach,
+/// either way.
+const BRANCH_REACH: i64 = 1 << 12;
+const JUMP_REACH: i64 = 1 << 20;
+/// Instructions a program may execute before it is taken to loop forever.
+const STEP_LIMIT: u64 = 2_000_000_000;
+
+/// What a program wrote, and the status it exited with.
+pub struct Output {
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    pub status: i64,
+}
+
+#[derive(Copy, Clone, Debug)]
+enum Op {
+    Add, Sub, Addw, Subw,
+    Mul, Mulw, Div, Divu, Divw, Rem, Remu, Remw,
+    And, Or, Xor, Sll, Srl, Sra, Sllw, Sraw,
+    Slt, Sltu,
+}
+
+#[derive(Copy, Clone, Debug)]
+enum Cond {
+    Eq, Ne, Lt, Ge, Ltu, Geu,
+}
+
+#[derive(Copy, Clone, Debug)]
+enum Inst {
+    Li(usize, u64),
+    Alu(Op, usize, usize, usize),
+    AluImm(Op, usize, usize, u64),
+    /// rd, base, offset, size in bytes, whether sign extended
+    Load(usize, usize, u64, usize, bool),
+    /// rs, base, offset, size in bytes
+    Store(usize, usize, u64, usize),
+    Branch(Cond, usize, usize, u64),
+    Jump(u64),
+    Call(u64),
+    Jr(usize),
+    Ecall,
+}
+
+fn register(name: &str) -> Result<usize, String> {
+    let abi = [
+        "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "fp", "s1",
+        "a0", "a1", "a2", "a3", "a4", "a5", "a6", "a7",
+        "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11",
+        "t3", "t4", "t5", "t6",
+    ];
+    if name == "s0" {
+        return Ok(8);
+    }
+    if let Some(i) = abi.iter().position(|&r| r == name) {
+        return Ok(i);
+    }
+    match name.strip_prefix('x').and_then(|n| n.parse::<usize>().ok()) {
+        Some(i) if i < 32 => Ok(i),
+        _ => Err(format!("unknown register `{}`", name)),
+    }
+}
+
+fn immediate(s: &str) -> Result<u64, String> {
+    let (negative, digits) = match s.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, s),
+    };
+    let value = match digits.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => digits.parse::<u64>(),
+    }.map_err(|_| format!("bad immediate `{}`", s))?;
+    Ok(if negative { value.wrapping_neg() } else { value })
+}
+
+/// `offset(reg)` of loads and stores.
+fn address(s: &str) -> Result<(u64, usize), String> {
+    let (offset, rest) = s.split_once('(').ok_or_else(|| format!("bad address `{}`", s))?;
+    let base = rest.strip_suffix(')').ok_or_else(|| format!("bad address `{}`", s))?;
+    let offset = if offset.is_empty() { 0 } else { immediate(offset)? };
+    Ok((offset, register(base)?))
+}
+
+/// The bytes of the string literal of a `.string` directive.
+fn string_literal(s: &str) -> Result<Vec<u8>, String> {
+    let inner = s
+        .strip_prefix('"')
+        .and_then(|s| s.strip_suffix('"'))
+        .ok_or_else(|| format!("bad string `{}`", s))?;
+    let mut bytes = vec![];
+    let mut chars = inner.bytes().peekable();
+    while let Some(b) = chars.next() {
+        if b != b'\\' {
+            bytes.push(b);
+            continue;
+        }
+        match chars.next() {
+            Some(b'n') => bytes.push(b'\n'),
+            Some(b't') => bytes.push(b'\t'),
+            Some(d @ b'0'..=b'7') => {
+                let mut value = u32::from(d - b'0');
+                for _ in 0..2 {
+                    match chars.peek() {
+                        Some(&d @ b'0'..=b'7') => {
+                            value = value * 8 + u32::from(d - b'0');
+                            chars.next();
+                        },
+                        _ => break,
+                    }
+                }
+                bytes.push(value as u8);
+            },
+            Some(c) => bytes.push(c),
+            None => return Err(format!("bad string `{}`", s)),
+        }
+    }
+    bytes.push(0);
+    Ok(bytes)
+}
+
+/// The lines of `asm` that matter, without comments and blank lines.
+fn lines(asm: &str) -> impl Iterator<Item = &str> {
+    asm.lines().map(str::trim).filter(|l| !l.is_empty() && !l.starts_with('#'))
+}
+
+/// Bytes an assembler makes of the instruction `name` at most.
+fn size(name: &str, args: &str) -> Result<u64, String> {
+    Ok(match name {
+        "la" | "lla" | "call" => 8,
+        "li" => {
+            let value = immediate(args.rsplit(',').next().unwrap_or("").trim())? as i64;
+            if (-2048..2048).contains(&value) {
+                4
+            } else if i32::try_from(value).is_ok() {
+                8
+            } else {
+                32
+            }
+        },
+        _ => 4,
+    })
+}
+
+fn is_label(line: &str) -> Option<&str> {
+    line.strip_suffix(':').filter(|l| !l.contains(char::is_whitespace))
+}
+
+struct Program {
+    text: Vec<Inst>,
+    data: Vec<u8>,
+    start: u64,
+}
+
+/// Where the labels of `asm` point, with the numeric ones of `.text` apart
+/// as they may be defined many times.
+struct Labels {
+    global: HashMap<String, u64>,
+    local: Vec<(String, u64)>,
+    /// where each instruction of `.text` and its end lie once assembled
+    offsets: Vec<u64>,
+}
+
+impl Labels {
+    fn local<'a>(&'a self, name: &'a str) -> impl Iterator<Item = u64> + 'a {
+        self.local.iter().filter(move |(l, _)| l == name).map(|&(_, a)| a)
+    }
+
+    fn resolve(&self, name: &str, pc: u64) -> Result<u64, String> {
+        let target = if let Some(n) = name.strip_suffix('f').filter(|n| n.chars().all(|c| c.is_ascii_digit())) {
+            self.local(n).find(|&a| a > pc)
+        } else if let Some(n) = name.strip_suffix('b').filter(|n| n.chars().all(|c| c.is_ascii_digit())) {
+            self.local(n).filter(|&a| a <= pc).last()
+        } else {
+            self.global.get(name).copied()
+        };
+        target.ok_or_else(|| format!("undefined label `{}`", name))
+    }
+
+    /// Checks that the assembled instruction at `pc` reaches `target`.
+    fn reach(&self, pc: u64, target: u64, reach: i64) -> Result<u64, String> {
+        let offset = |addr: u64| {
+            addr.checked_sub(TEXT_BASE)
+                .and_then(|a| self.offsets.get(a as usize / 4))
+                .map(|&o| o as i64)
+                .ok_or_else(|| format!("jump to {:#x} outside `.text`", addr))
+        };
+        let distance = offset(target)? - offset(pc)?;
+        if (-reach..reach).contains(&distance) {
+            Ok(target)
+        } else {
+            Err(format!("target {} bytes away, beyond the reach of {}", distance, reach))
+        }
+    }
+}
+
+fn assemble(asm: &str) -> Result<Program, String> {
+    // where the labels point
+    let mut labels = Labels { global: HashMap::new(), local: vec![], offsets: vec![0] };
+    let mut data = vec![];
+    let mut count = 0u64;
+    let mut in_text = true;
+    for line in lines(asm) {
+        if let Some(label) = is_label(line) {
+            if in_text {
+                let addr = TEXT_BASE + 4 * count;
+                if label.chars().all(|c| c.is_ascii_digit()) {
+                    labels.local.push((label.to_string(), addr));
+                } else {
+                    labels.global.insert(label.to_string(), addr);
+                }
+            } else {
+                labels.global.insert(label.to_string(), DATA_BASE + data.len() as u64);
+            }
+            continue;
+        }
+        let (name, args) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let args = args.trim();
+        match name {
+            ".text" => in_text = true,
+            ".data" | ".bss" => in_text = false,
+            ".section" => in_text = args.starts_with(".text"),
+            ".globl" => (),
+            ".align" => {
+                let align = 1usize << immediate(args)?;
+                data.resize(data.len().div_ceil(align) * align, 0);
+            },
+            ".zero" => data.resize(data.len() + immediate(args)? as usize, 0),
+            ".word" => data.extend_from_slice(&(immediate(args)? as u32).to_le_bytes()),
+            ".dword" => data.extend_from_slice(&immediate(args)?.to_le_bytes()),
+            ".string" | ".asciz" => data.extend(string_literal(args)?),
+            _ if name.starts_with('.') => return Err(format!("unknown directive `{}`", line)),
+            _ if in_text => {
+                count += 1;
+                let end = labels.offsets.last().unwrap() + size(name, args)?;
+                labels.offsets.push(end);
+            },
+            _ => return Err(format!("instruction outside `.text`: `{}`", line)),
+        }
+    }
+
+    let mut text = vec![];
+    let mut in_text = true;
+    for line in lines(asm) {
+        if is_label(line).is_some() {
+            continue;
+        }
+        let (name, args) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        match name {
+            ".text" => in_text = true,
+            ".data" | ".bss" => in_text = false,
+            ".section" => in_text = args.trim().starts_with(".text"),
+            _ if name.starts_with('.') || !in_text => (),
+            _ => {
+                let pc = TEXT_BASE + 4 * text.len() as u64;
+                let inst = decode(name, args, pc, &labels).map_err(|e| format!("{} in `{}`", e, line))?;
+                text.push(inst);
+            },
+        }
+    }
+    let start = labels.resolve("_start", 0)?;
+    Ok(Program { text, data, start })
+}
+
+fn decode(name: &str, args: &str, pc: u64, labels: &Labels) -> Result<Inst, String> {
+    let args: Vec<&str> = args.split(',').map(str::trim).filter(|a| !a.is_empty()).collect();
+    let reg = |i: usize| -> Result<usize, String> {
+        register(args.get(i).ok_or("missing operand")?)
+    };
+    let imm = |i: usize| -> Result<u64, String> {
+        immediate(args.get(i).ok_or("missing operand")?)
+    };
+    let label = |i: usize| -> Result<u64, String> {
+        labels.resolve(args.get(i).ok_or("missing operand")?, pc)
+    };
+    let mem = |i: usize| -> Result<(u64, usize), String> {
+        address(args.get(i).ok_or("missing operand")?)
+    };
+    let alu = |op| Ok(Inst::Alu(op, reg(0)?, reg(1)?, reg(2)?));
+    let alu_imm = |op| Ok(Inst::AluImm(op, reg(0)?, reg(1)?, imm(2)?));
+    let load = |size, signed| {
+        let (offset, base) = mem(1)?;
+        Ok(Inst::Load(reg(0)?, base, offset, size, signed))
+    };
+    let store = |size| {
+        let (offset, base) = mem(1)?;
+        Ok(Inst::Store(reg(0)?, base, offset, size))
+    };
+    let branch = |cond, swap| {
+        let (a, b) = (reg(0)?, reg(1)?);
+        let (a, b) = if swap { (b, a) } else { (a, b) };
+        Ok(Inst::Branch(cond, a, b, labels.reach(pc, label(2)?, BRANCH_REACH)?))
+    };
+    // comparisons of a register with zero
+    let branch_zero = |cond, swap| {
+        let (a, b) = if swap { (0, reg(0)?) } else { (reg(0)?, 0) };
+        Ok(Inst::Branch(cond, a, b, labels.reach(pc, label(1)?, BRANCH_REACH)?))
+    };
+    match name {
+        "li" => Ok(Inst::Li(reg(0)?, imm(1)?)),
+        "la" | "lla" => Ok(Inst::Li(reg(0)?, labels.resolve(args.get(1).ok_or("missing operand")?, pc)?)),
+        "mv" => Ok(Inst::AluImm(Op::Add, reg(0)?, reg(1)?, 0)),
+        "not" => Ok(Inst::AluImm(Op::Xor, reg(0)?, reg(1)?, u64::MAX)),
+        "neg" => Ok(Inst::Alu(Op::Sub, reg(0)?, 0, reg(1)?)),
+        "negw" => Ok(Inst::Alu(Op::Subw, reg(0)?, 0, reg(1)?)),
+        "seqz" => Ok(Inst::AluImm(Op::Sltu, reg(0)?, reg(1)?, 1)),
+        "snez" => Ok(Inst::Alu(Op::Sltu, reg(0)?, 0, reg(1)?)),
+        "sext.w" => Ok(Inst::AluImm(Op::Addw, reg(0)?, reg(1)?, 0)),
+        "add" => alu(Op::Add),
+        "sub" => alu(Op::Sub),
+        "addw" => alu(Op::Addw),
+        "subw" => alu(Op::Subw),
+        "mul" => alu(Op::Mul),
+        "mulw" => alu(Op::Mulw),
+        "div" => alu(Op::Div),
+        "divu" => alu(Op::Divu),
+        "divw" => alu(Op::Divw),
+        "rem" => alu(Op::Rem),
+        "remu" => alu(Op::Remu),
+        "remw" => alu(Op::Remw),
+        "and" => alu(Op::And),
+        "or" => alu(Op::Or),
+        "xor" => alu(Op::Xor),
+        "sll" => alu(Op::Sll),
+        "srl" => alu(Op::Srl),
+        "sra" => alu(Op::Sra),
+        "sllw" => alu(Op::Sllw),
+        "sraw" => alu(Op::Sraw),
+        "slt" => alu(Op::Slt),
+        "sltu" => alu(Op::Sltu),
+        "addi" => alu_imm(Op::Add),
+        "addiw" => alu_imm(Op::Addw),
+        "andi" => alu_imm(Op::And),
+        "ori" => alu_imm(Op::Or),
+        "xori" => alu_imm(Op::Xor),
+        "slli" => alu_imm(Op::Sll),
+        "srli" => alu_imm(Op::Srl),
+        "srai" => alu_imm(Op::Sra),
+        "slliw" => alu_imm(Op::Sllw),
+        "sraiw" => alu_imm(Op::Sraw),
+        "slti" => alu_imm(Op::Slt),
+        "sltiu" => alu_imm(Op::Sltu),
+        "lb" => load(1, true),
+        "lbu" => load(1, false),
+        "lw" => load(4, true),
+        "lwu" => load(4, false),
+        "ld" => load(8, true),
+        "sb" => store(1),
+        "sw" => store(4),
+        "sd" => store(8),
+        "beq" => branch(Cond::Eq, false),
+        "bne" => branch(Cond::Ne, false),
+        "blt" => branch(Cond::Lt, false),
+        "bge" => branch(Cond::Ge, false),
+        "bltu" => branch(Cond::Ltu, false),
+        "bgeu" => branch(Cond::Geu, false),
+        "bgt" => branch(Cond::Lt, true),
+        "ble" => branch(Cond::Ge, true),
+        "bgtu" => branch(Cond::Ltu, true),
+        "bleu" => branch(Cond::Geu, true),
+        "beqz" => branch_zero(Cond::Eq, false),
+        "bnez" => branch_zero(Cond::Ne, false),
+        "bltz" => branch_zero(Cond::Lt, false),
+        "bgez" => branch_zero(Cond::Ge, false),
+        "blez" => branch_zero(Cond::Ge, true),
+        "bgtz" => branch_zero(Cond::Lt, true),
+        "j" => Ok(Inst::Jump(labels.reach(pc, label(0)?, JUMP_REACH)?)),
+        "jal" if args.len() == 1 => Ok(Inst::Call(labels.reach(pc, label(0)?, JUMP_REACH)?)),
+        "call" => Ok(Inst::Call(label(0)?)),
+        "jr" => Ok(Inst::Jr(reg(0)?)),
+        "ret" => Ok(Inst::Jr(1)),
+        "ecall" => Ok(Inst::Ecall),
+        _ => Err(format!("unknown instruction `{}`", name)),
+    }
+}
+
+fn compute(op: Op, a: u64, b: u64) -> u64 {
+    // the `w` instructions work on the low 32 bits and sign extend the result
+    let w = |v: i32| v as i64 as u64;
+    let (sa, sb) = (a as i64, b as i64);
+    let (wa, wb) = (a as i32, b as i32);
+    match op {
+        Op::Add => a.wrapping_add(b),
+        Op::Sub => a.wrapping_sub(b),
+        Op::Addw => w(wa.wrapping_add(wb)),
+        Op::Subw => w(wa.wrapping_sub(wb)),
+        Op::Mul => a.wrapping_mul(b),
+        Op::Mulw => w(wa.wrapping_mul(wb)),
+        // division by zero and overflow give what the M extension specifies
+        Op::Div => if b == 0 { u64::MAX } else { sa.wrapping_div(sb) as u64 },
+        Op::Divu => a.checked_div(b).unwrap_or(u64::MAX),
+        Op::Divw => if wb == 0 { u64::MAX } else { w(wa.wrapping_div(wb)) },
+        Op::Rem => if b == 0 { a } else { sa.wrapping_rem(sb) as u64 },
+        Op::Remu => a.checked_rem(b).unwrap_or(a),
+        Op::Remw => if wb == 0 { w(wa) } else { w(wa.wrapping_rem(wb)) },
+        Op::And => a & b,
+        Op::Or => a | b,
+        Op::Xor => a ^ b,
+        Op::Sll => a << (b & 63),
+        Op::Srl => a >> (b & 63),
+        Op::Sra => (sa >> (b & 63)) as u64,
+        Op::Sllw => w(wa << (b & 31)),
+        Op::Sraw => w(wa >> (b & 31)),
+        Op::Slt => (sa < sb) as u64,
+        Op::Sltu => (a < b) as u64,
+    }
+}
+
+struct Machine<'a> {
+    regs: [u64; 32],
+    memory: Vec<u8>,
+    input: &'a [u8],
+    output: Output,
+}
+
+impl Machine<'_> {
+    fn range(&self, addr: u64, size: usize) -> Result<std::ops::Range<usize>, String> {
+        match usize::try_from(addr) {
+            Ok(start) if start.checked_add(size).is_some_and(|end| end <= self.memory.len()) => Ok(start..start + size),
+            _ => Err(format!("access to {:#x} outside memory", addr)),
+        }
+    }
+
+    fn load(&self, addr: u64, size: usize, signed: bool) -> Result<u64, String> {
+        let mut bytes = [0; 8];
+        bytes[..size].copy_from_slice(&self.memory[self.range(addr, size)?]);
+        let value = u64::from_le_bytes(bytes);
+        let shift = 64 - 8 * size as u32;
+        Ok(if signed { ((value << shift) as i64 >> shift) as u64 } else { value })
+    }
+
+    fn store(&mut self, addr: u64, size: usize, value: u64) -> Result<(), String> {
+        let range = self.range(addr, size)?;
+        self.memory[range].copy_from_slice(&value.to_le_bytes()[..size]);
+        Ok(())
+    }
+
+    /// Carries out the system call in a7. Returns whether the program exited.
+    fn ecall(&mut self) -> Result<bool, String> {
+        let [a0, a1, a2] = [self.regs[10], self.regs[11], self.regs[12]];
+        match self.regs[17] {
+            // read
+            63 => {
+                if a0 != 0 {
+                    return Err(format!("`read` of file descriptor {}", a0));
+                }
+                let n = (a2 as usize).min(self.input.len());
+                let range = self.range(a1, n)?;
+                self.memory[range].copy_from_slice(&self.input[..n]);
+                self.input = &self.input[n..];
+                self.regs[10] = n as u64;
+            },
+            // write
+            64 => {
+                let bytes = self.memory[self.range(a1, a2 as usize)?].to_vec();
+                match a0 {
+                    1 => self.output.stdout.extend(bytes),
+                    2 => self.output.stderr.extend(bytes),
+                    _ => return Err(format!("`write` to file descriptor {}", a0)),
+                }
+                self.regs[10] = a2;
+            },
+            // exit
+            93 => {
+                self.output.status = a0 as i64;
+                return Ok(true);
+            },
+            n => return Err(format!("unknown system call {}", n)),
+        }
+        Ok(false)
+    }
+}
+
+/// Assembles `asm` and runs it on `input`.
+pub fn run(asm: &str, input: &[u8]) -> Result<Output, String> {
+    let program = assemble(asm)?;
+    let mut m = Machine {
+        regs: [0; 32],
+        memory: vec![0; MEMORY_SIZE],
+        input,
+        output: Output { stdout: vec![], stderr: vec![], status: 0 },
+    };
+    m.memory[DATA_BASE as usize..DATA_BASE as usize + program.data.len()].copy_from_slice(&program.data);
+    m.regs[2] = MEMORY_SIZE as u64;
+    let mut pc = program.start;
+    for _ in 0..STEP_LIMIT {
+        let inst = pc
+            .checked_sub(TEXT_BASE)
+            .map(|offset| offset / 4)
+            .and_then(|i| program.text.get(i as usize))
+            .ok_or_else(|| format!("jump to {:#x} outside the program", pc))?;
+        let mut next = pc + 4;
+        match *inst {
+            Inst::Li(rd, value) => m.regs[rd] = value,
+            Inst::Alu(op, rd, rs1, rs2) => m.regs[rd] = compute(op, m.regs[rs1], m.regs[rs2]),
+            Inst::AluImm(op, rd, rs1, imm) => m.regs[rd] = compute(op, m.regs[rs1], imm),
+            Inst::Load(rd, base, offset, size, signed) => {
+                m.regs[rd] = m.load(m.regs[base].wrapping_add(offset), size, signed)?;
+            },
+            Inst::Store(rs, base, offset, size) => {
+                m.store(m.regs[base].wrapping_add(offset), size, m.regs[rs])?;
+            },
+            Inst::Branch(cond, rs1, rs2, target) => {
+                let (a, b) = (m.regs[rs1], m.regs[rs2]);
+                let taken = match cond {
+                    Cond::Eq => a == b,
+                    Cond::Ne => a != b,
+                    Cond::Lt => (a as i64) < (b as i64),
+                    Cond::Ge => (a as i64) >= (b as i64),
+                    Cond::Ltu => a < b,
+                    Cond::Geu => a >= b,
+                };
+                if taken {
+                    next = target;
+                }
+            },
+            Inst::Jump(target) => next = target,
+            Inst::Call(target) => {
+                m.regs[1] = next;
+                next = target;
+            },
+            Inst::Jr(rs) => next = m.regs[rs],
+            Inst::Ecall => {
+                if m.ecall()? {
+                    return Ok(m.output);
+                }
+            },
+        }
+        m.regs[0] = 0;
+        pc = next;
+    }
+    Err(format!("still running after {} instructions", STEP_LIMIT))
+}
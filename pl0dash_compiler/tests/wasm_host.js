@@ -0,0 +1,87 @@
+// Runs a program built by the WebAssembly backend under Node.js:
+//
+//     node wasm_host.js PROGRAM.wasm < input
+//
+// Provides the imports of the module `env` with the behaviour of the stack
+// machine: runtime errors are written to stderr and exit with status 1. The
+// engine may run out of its own call stack before the program fills its frames
+// in linear memory, which is a stack overflow all the same.
+
+'use strict';
+
+const fs = require('fs');
+
+class RuntimeError extends Error {}
+
+let output = '';
+let words = null;
+let memory = null;
+
+function flush() {
+    fs.writeSync(1, Buffer.from(output, 'latin1'));
+    output = '';
+}
+
+function fail(message) {
+    throw new RuntimeError(`runtime error: ${message}`);
+}
+
+function nextWord() {
+    if (words === null) {
+        flush();
+        words = fs.readFileSync(0, 'latin1').split(/[ \t\n\v\f\r]+/).filter(w => w !== '');
+    }
+    return words.shift();
+}
+
+const env = {
+    write(n) {
+        output += `${n} `;
+    },
+    writeln() {
+        output += '\n';
+    },
+    write_string(addr, len) {
+        output += Buffer.from(memory.buffer, addr, len).toString('latin1');
+    },
+    read() {
+        const word = nextWord();
+        if (word === undefined) {
+            fail('`read` reached the end of the input');
+        }
+        const n = /^[+-]?[0-9]+$/.test(word) ? Number(word) : NaN;
+        if (!(n >= -2147483648 && n <= 2147483647)) {
+            fail(`\`read\` expected an integer, found \`${word}\``);
+        }
+        return n;
+    },
+    division_by_zero() {
+        fail('division by zero');
+    },
+    index_out_of_bounds(index, size) {
+        fail(`index ${index} is out of bounds for an array of size ${size}`);
+    },
+    stack_overflow() {
+        fail('stack overflow');
+    },
+};
+
+const program = new WebAssembly.Module(fs.readFileSync(process.argv[2]));
+const instance = new WebAssembly.Instance(program, { env });
+memory = instance.exports.memory;
+try {
+    instance.exports.main();
+    flush();
+} catch (e) {
+    flush();
+    let message;
+    if (e instanceof RuntimeError) {
+        message = e.message;
+    } else if (e instanceof RangeError && /call stack/.test(e.message)) {
+        message = 'runtime error: stack overflow';
+    } else {
+        throw e;
+    }
+    fs.writeSync(2, `${message}\n`);
+    process.exit(1);
+}